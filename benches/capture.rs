@@ -0,0 +1,75 @@
+//! Compares allocating captures with pooled and `capture_into` captures.
+//!
+//! Run with `cargo bench`. Every line reports the time and the number of heap allocations per capture.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use qshot::{CaptureManager, Frame, PixelFormat, Rect, Script, SyntheticBackend};
+
+/// Counts the allocations made by the benchmarked code.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const ITERATIONS: usize = 200;
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    // Warm up, so that pools and buffers are filled.
+    for _ in 0..10 {
+        f();
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed() / ITERATIONS as u32;
+    let allocations =
+        (ALLOCATIONS.load(Ordering::Relaxed) - allocations) as f64 / ITERATIONS as f64;
+    println!("{name:<24} {elapsed:>12.2?}/capture {allocations:>6.2} allocations/capture");
+}
+
+fn main() {
+    for format in [PixelFormat::Bgr24, PixelFormat::Rgba32] {
+        println!("{format:?}, 640x480");
+        let script = Script::new((1920, 1080)).solid([255, 0, 0], 1);
+        let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 640, 480))
+            .unwrap()
+            .with_format(format);
+
+        bench("capture", || {
+            black_box(manager.capture().unwrap());
+        });
+        bench("capture_pooled", || {
+            let frame = manager.capture_pooled().unwrap();
+            manager.pool().recycle(black_box(frame));
+        });
+        let mut frame = Frame::new(0, 0, format);
+        bench("capture_into", || {
+            manager.capture_into(&mut frame).unwrap();
+            black_box(&frame);
+        });
+    }
+}
@@ -0,0 +1,59 @@
+use crate::capture::CaptureData;
+use crate::error::Error;
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use crate::rect::Rect;
+
+/// A platform-specific source of screenshots driven by a [`CaptureManager`](crate::CaptureManager).
+///
+/// Every backend captures a rectangular area, given as a [`Rect`] relative to its target.
+/// Parts of the area that lie outside of the target are black.
+/// All backends report failures as [`Error`], keeping their own error types as its source.
+pub trait CaptureBackend: Sized {
+    /// Describes what should be captured, e.g. a window handle or a device path.
+    type Target;
+
+    /// Opens the backend for the given target and area.
+    ///
+    /// The [`CaptureManager`](crate::CaptureManager) only passes areas that are not empty.
+    fn open(target: Self::Target, area: Rect) -> Result<Self, Error>;
+
+    /// Captures the currently configured area.
+    fn capture(&self) -> Result<CaptureData, Error>;
+
+    /// Captures the currently configured area into an existing frame, reusing its buffer where possible.
+    ///
+    /// The frame takes on the size and format of the capture. The default implementation replaces the frame with
+    /// the result of [`capture`](CaptureBackend::capture), backends override it to avoid the allocation.
+    fn capture_into(&self, frame: &mut Frame) -> Result<(), Error> {
+        *frame = self.capture()?.into_owned();
+        Ok(())
+    }
+
+    /// Changes the position and size of the captured area.
+    fn resize(&mut self, area: Rect) -> Result<(), Error>;
+
+    /// Returns the currently captured area.
+    fn area(&self) -> Rect;
+
+    /// Returns the area that can be captured, e.g. the size of the targeted window, or `None` if it is not known.
+    ///
+    /// The [`CaptureManager`](crate::CaptureManager) uses it to reject areas that are entirely off-screen.
+    fn bounds(&self) -> Option<Rect> {
+        None
+    }
+
+    /// Asks the backend to produce captures in the given format.
+    ///
+    /// Returns `false` if the backend can't produce the format natively, in which case it keeps its previous format
+    /// and the [`CaptureManager`](crate::CaptureManager) converts the captures instead.
+    fn set_format(&mut self, format: PixelFormat) -> bool {
+        format == PixelFormat::Bgr24
+    }
+
+    /// Releases the resources held by the backend.
+    ///
+    /// This is called when the owning [`CaptureManager`](crate::CaptureManager) is dropped,
+    /// so implementations must tolerate being closed more than once.
+    fn close(&mut self) {}
+}
@@ -0,0 +1,438 @@
+//! Reading and writing of uncompressed BMP files.
+//!
+//! The pixels of a BMP file are stored the same way as the device-independent bitmaps the GDI backend captures
+//! into: \[B, G, R] or \[B, G, R, A] bytes, rows padded to a multiple of 4 bytes. [`encode`] therefore writes the
+//! bits of a [`PixelFormat::Bgr24`] or [`PixelFormat::Bgra32`] capture unchanged, and [`decode`] returns a frame
+//! whose bits are exactly those of the capture, including the stride. This makes BMP files handy as debugging dumps
+//! and as test fixtures, e.g. for [`Script::image_files`](crate::Script::image_files).
+//!
+//! Files are written top-down. [`PixelFormat::Bgra32`] images are written with a `BITMAPV4HEADER` describing the
+//! alpha channel, [`PixelFormat::Gray8`] images with a grayscale palette and RGB images are converted to BGR.
+//!
+//! # Examples
+//!
+//! ```
+//! use qshot::{bmp, CaptureManager, Rect, Script, SyntheticBackend};
+//!
+//! let script = Script::new((30, 20)).moving_rect([10, 20, 30], [200, 150, 100], (8, 8), (3, 4), (0, 0), 1);
+//! let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 30, 20)).unwrap();
+//! let capture = manager.capture().unwrap();
+//!
+//! let mut file = Vec::new();
+//! bmp::encode(&capture, &mut file).unwrap();
+//! let frame = bmp::decode(file.as_slice()).unwrap();
+//! assert!(frame.rows().eq((0..20).map(|y| capture.row(y))));
+//! assert_eq!(frame.stride(), 92);
+//! ```
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::convert::Converter;
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use crate::view::ImageView;
+
+const FILE_HEADER_SIZE: usize = 14;
+const INFO_HEADER_SIZE: usize = 40;
+const V4_HEADER_SIZE: usize = 108;
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+/// The `LCS_sRGB` color space, spelled `sRGB` backwards.
+const LCS_SRGB: u32 = 0x7352_4742;
+/// The red, green, blue and alpha masks of \[B, G, R, A] pixels.
+const BGRA_MASKS: [u32; 4] = [0x00FF_0000, 0x0000_FF00, 0x0000_00FF, 0xFF00_0000];
+
+/// Returns the length of a row of `width` pixels with `bits` bits each, padded to a multiple of 4 bytes.
+fn padded_stride(width: usize, bits: usize) -> usize {
+    (width * bits).div_ceil(32) * 4
+}
+
+/// Writes an image as a BMP file to `writer`.
+///
+/// # Errors
+///
+/// Returns an [`io::ErrorKind::InvalidInput`] error if the image is too large for a BMP file, and any error
+/// `writer` returns.
+pub fn encode<'a>(image: impl Into<ImageView<'a>>, mut writer: impl Write) -> io::Result<()> {
+    let image = image.into();
+    let format = match image.format() {
+        PixelFormat::Bgr24 | PixelFormat::Rgb24 => PixelFormat::Bgr24,
+        PixelFormat::Bgra32 | PixelFormat::Rgba32 => PixelFormat::Bgra32,
+        _ => PixelFormat::Gray8,
+    };
+    let bits = format.bytes_per_pixel() * 8;
+    let stride = padded_stride(image.width(), bits);
+    let (header_size, palette) = match format {
+        PixelFormat::Bgra32 => (V4_HEADER_SIZE, 0),
+        PixelFormat::Gray8 => (INFO_HEADER_SIZE, 256 * 4),
+        _ => (INFO_HEADER_SIZE, 0),
+    };
+    let offset = FILE_HEADER_SIZE + header_size + palette;
+    let size = (stride as u64 * image.height() as u64)
+        .checked_add(offset as u64)
+        .filter(|&size| size <= u32::MAX as u64);
+    let (Some(size), Ok(width), Ok(height)) = (
+        size,
+        i32::try_from(image.width()),
+        i32::try_from(image.height()),
+    ) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "image is too large for a BMP file",
+        ));
+    };
+
+    let mut header = Vec::with_capacity(offset);
+    header.extend_from_slice(b"BM");
+    header.extend_from_slice(&(size as u32).to_le_bytes());
+    header.extend_from_slice(&[0; 4]);
+    header.extend_from_slice(&(offset as u32).to_le_bytes());
+    header.extend_from_slice(&(header_size as u32).to_le_bytes());
+    header.extend_from_slice(&width.to_le_bytes());
+    // A negative height stores the rows from top to bottom, like the captures.
+    header.extend_from_slice(&(-height).to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&(bits as u16).to_le_bytes());
+    let compression = if format == PixelFormat::Bgra32 {
+        BI_BITFIELDS
+    } else {
+        BI_RGB
+    };
+    header.extend_from_slice(&compression.to_le_bytes());
+    header.extend_from_slice(&((size as usize - offset) as u32).to_le_bytes());
+    // 96 DPI, in pixels per meter.
+    header.extend_from_slice(&3780u32.to_le_bytes());
+    header.extend_from_slice(&3780u32.to_le_bytes());
+    let colors: u32 = if format == PixelFormat::Gray8 { 256 } else { 0 };
+    header.extend_from_slice(&colors.to_le_bytes());
+    header.extend_from_slice(&[0; 4]);
+    if format == PixelFormat::Bgra32 {
+        for mask in BGRA_MASKS {
+            header.extend_from_slice(&mask.to_le_bytes());
+        }
+        header.extend_from_slice(&LCS_SRGB.to_le_bytes());
+        // The endpoints and gamma values, which are ignored for sRGB.
+        header.extend_from_slice(&[0; 48]);
+    }
+    if format == PixelFormat::Gray8 {
+        for level in 0..=255 {
+            header.extend_from_slice(&[level, level, level, 0]);
+        }
+    }
+    writer.write_all(&header)?;
+
+    let converter = Converter::new();
+    let mut row = vec![0; stride];
+    for src in image.rows() {
+        converter.convert_row(src, image.format(), &mut row, format);
+        writer.write_all(&row)?;
+    }
+    writer.flush()
+}
+
+/// Reads an uncompressed BMP file from `reader`.
+///
+/// 24-bit files are returned as [`PixelFormat::Bgr24`], 32-bit files as [`PixelFormat::Bgra32`] and 8-bit files as
+/// [`PixelFormat::Gray8`] if their palette is a grayscale ramp and as [`PixelFormat::Bgr24`] otherwise. The frame
+/// keeps the rows padded to a multiple of 4 bytes, so a file written from a capture has the bits of the capture.
+/// The alpha channel of 32-bit files without a bit mask for it is returned as it is stored.
+///
+/// # Errors
+///
+/// Returns an [`io::ErrorKind::InvalidData`] error if the file is not a BMP file or uses a feature other than
+/// these, like compression or fewer bits per pixel, and any error `reader` returns.
+pub fn decode(mut reader: impl Read) -> io::Result<Frame> {
+    let mut file = Vec::new();
+    reader.read_to_end(&mut file)?;
+    parse(&file)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a supported BMP file"))
+}
+
+fn parse(file: &[u8]) -> Option<Frame> {
+    let u16_at = |at: usize| Some(u16::from_le_bytes(file.get(at..at + 2)?.try_into().ok()?));
+    let u32_at = |at: usize| Some(u32::from_le_bytes(file.get(at..at + 4)?.try_into().ok()?));
+    if file.get(..2)? != b"BM" {
+        return None;
+    }
+    let offset = u32_at(10)? as usize;
+    let header_size = u32_at(14)? as usize;
+    if header_size < INFO_HEADER_SIZE {
+        return None;
+    }
+    let width = usize::try_from(u32_at(18)? as i32).ok()?;
+    let height = u32_at(22)? as i32;
+    let bottom_up = height > 0;
+    let height = height.unsigned_abs() as usize;
+    let bits = u16_at(28)? as usize;
+    let compression = u32_at(30)?;
+
+    let format = match (bits, compression) {
+        (24, BI_RGB) | (8, BI_RGB) => PixelFormat::Bgr24,
+        (32, BI_RGB) => PixelFormat::Bgra32,
+        (32, BI_BITFIELDS) => {
+            // The masks follow the header, or are part of it for the later versions.
+            let masks = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+            let colors = [u32_at(masks)?, u32_at(masks + 4)?, u32_at(masks + 8)?];
+            let alpha = if header_size >= 56 {
+                u32_at(masks + 12)?
+            } else {
+                0
+            };
+            if colors != BGRA_MASKS[..3] || (alpha != 0 && alpha != BGRA_MASKS[3]) {
+                return None;
+            }
+            PixelFormat::Bgra32
+        }
+        _ => return None,
+    };
+    let palette = if bits == 8 {
+        let start = FILE_HEADER_SIZE + header_size;
+        let count = match u32_at(46)? {
+            0 => 256,
+            count => (count as usize).min(256),
+        };
+        let mut palette = [[0; 3]; 256];
+        for (i, color) in palette.iter_mut().take(count).enumerate() {
+            color.copy_from_slice(file.get(start + i * 4..start + i * 4 + 3)?);
+        }
+        Some(palette)
+    } else {
+        None
+    };
+
+    let src_stride = padded_stride(width, bits);
+    let data = file.get(offset..offset.checked_add(src_stride.checked_mul(height)?)?)?;
+    let src_rows = data.chunks_exact(src_stride.max(1)).take(height);
+    let frame = match palette {
+        None => {
+            let mut bits = data.to_vec();
+            if bottom_up {
+                let mut flipped = Vec::with_capacity(bits.len());
+                for row in bits.chunks_exact(src_stride.max(1)).rev() {
+                    flipped.extend_from_slice(row);
+                }
+                bits = flipped;
+            }
+            Frame::from_vec_with_stride(bits, width, height, src_stride, format)
+        }
+        Some(palette) => {
+            let gray = palette
+                .iter()
+                .enumerate()
+                .all(|(i, color)| *color == [i as u8; 3]);
+            let format = if gray {
+                PixelFormat::Gray8
+            } else {
+                PixelFormat::Bgr24
+            };
+            let stride = padded_stride(width, format.bytes_per_pixel() * 8);
+            let mut bits = vec![0; stride * height];
+            for (y, src) in src_rows.enumerate() {
+                let y = if bottom_up { height - 1 - y } else { y };
+                let row = &mut bits[y * stride..y * stride + width * format.bytes_per_pixel()];
+                if gray {
+                    row.copy_from_slice(&src[..width]);
+                } else {
+                    for (out, &index) in row.chunks_exact_mut(3).zip(src) {
+                        out.copy_from_slice(&palette[index as usize]);
+                    }
+                }
+            }
+            Frame::from_vec_with_stride(bits, width, height, stride, format)
+        }
+    };
+    Some(frame)
+}
+
+/// Saves an image as a BMP file, replacing the file if it exists, see [`encode`].
+pub fn save<'a>(image: impl Into<ImageView<'a>>, path: impl AsRef<Path>) -> io::Result<()> {
+    encode(image, BufWriter::new(File::create(path)?))
+}
+
+/// Loads a BMP file, see [`decode`].
+pub fn load(path: impl AsRef<Path>) -> io::Result<Frame> {
+    decode(File::open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::fixtures;
+
+    /// Builds a file with a `BITMAPINFOHEADER`, followed by `extra` (masks or a palette of `colors` entries) and the
+    /// pixel data.
+    fn file(
+        width: i32,
+        height: i32,
+        bits: u16,
+        compression: u32,
+        colors: u32,
+        extra: &[u8],
+        data: &[u8],
+    ) -> Vec<u8> {
+        let offset = (FILE_HEADER_SIZE + INFO_HEADER_SIZE + extra.len()) as u32;
+        let mut file = Vec::new();
+        file.extend_from_slice(b"BM");
+        file.extend_from_slice(&(offset + data.len() as u32).to_le_bytes());
+        file.extend_from_slice(&[0; 4]);
+        file.extend_from_slice(&offset.to_le_bytes());
+        file.extend_from_slice(&(INFO_HEADER_SIZE as u32).to_le_bytes());
+        file.extend_from_slice(&width.to_le_bytes());
+        file.extend_from_slice(&height.to_le_bytes());
+        file.extend_from_slice(&1u16.to_le_bytes());
+        file.extend_from_slice(&bits.to_le_bytes());
+        file.extend_from_slice(&compression.to_le_bytes());
+        file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        file.extend_from_slice(&[0; 8]);
+        file.extend_from_slice(&colors.to_le_bytes());
+        file.extend_from_slice(&[0; 4]);
+        file.extend_from_slice(extra);
+        file.extend_from_slice(data);
+        file
+    }
+
+    fn palette(colors: &[[u8; 3]]) -> Vec<u8> {
+        colors.iter().flat_map(|&[b, g, r]| [b, g, r, 0]).collect()
+    }
+
+    fn masks(masks: &[u32]) -> Vec<u8> {
+        masks.iter().flat_map(|mask| mask.to_le_bytes()).collect()
+    }
+
+    fn invalid(file: &[u8]) -> bool {
+        fixtures::fails_with(decode(file), io::ErrorKind::InvalidData)
+    }
+
+    #[test]
+    fn gray_palettes_decode_to_gray8() {
+        let ramp: Vec<[u8; 3]> = (0..=255).map(|level| [level; 3]).collect();
+        let file = file(
+            3,
+            -2,
+            8,
+            BI_RGB,
+            0,
+            &palette(&ramp),
+            &[1, 2, 3, 0, 4, 5, 6, 0],
+        );
+        let frame = decode(file.as_slice()).unwrap();
+        assert_eq!(frame.format(), PixelFormat::Gray8);
+        assert_eq!(frame.stride(), 4);
+        assert!(frame.rows().eq([&[1, 2, 3][..], &[4, 5, 6]]));
+    }
+
+    #[test]
+    fn color_palettes_decode_to_bgr24() {
+        let colors = palette(&[[10, 20, 30], [40, 50, 60], [70, 80, 90]]);
+        // Bottom-up, so the first row of the file is the last row of the image.
+        let file = file(3, 2, 8, BI_RGB, 3, &colors, &[0, 1, 2, 0, 2, 2, 0, 0]);
+        let frame = decode(file.as_slice()).unwrap();
+        assert_eq!(frame.format(), PixelFormat::Bgr24);
+        assert_eq!(frame.stride(), 12);
+        assert_eq!(frame.row(0), &[70, 80, 90, 70, 80, 90, 10, 20, 30]);
+        assert_eq!(frame.row(1), &[10, 20, 30, 40, 50, 60, 70, 80, 90]);
+    }
+
+    #[test]
+    fn bottom_up_rows_are_flipped() {
+        let data = [1, 2, 3, 4, 5, 6, 0, 0, 7, 8, 9, 10, 11, 12, 0, 0];
+        let frame = decode(file(2, 2, 24, BI_RGB, 0, &[], &data).as_slice()).unwrap();
+        assert_eq!(
+            frame.bits(),
+            &[7, 8, 9, 10, 11, 12, 0, 0, 1, 2, 3, 4, 5, 6, 0, 0]
+        );
+        let frame = decode(file(2, -2, 24, BI_RGB, 0, &[], &data).as_slice()).unwrap();
+        assert_eq!(frame.bits(), &data);
+    }
+
+    #[test]
+    fn encoded_images_round_trip() {
+        for (format, decoded) in [
+            (PixelFormat::Bgr24, PixelFormat::Bgr24),
+            (PixelFormat::Rgb24, PixelFormat::Bgr24),
+            (PixelFormat::Bgra32, PixelFormat::Bgra32),
+            (PixelFormat::Rgba32, PixelFormat::Bgra32),
+            (PixelFormat::Gray8, PixelFormat::Gray8),
+        ] {
+            let image = fixtures::padded(5, 3, 7, format, |x, y| {
+                [x as u8, y as u8, (x * y) as u8, 128]
+            });
+            let file = fixtures::encoded(|file| encode(&image, file));
+            let frame = decode(file.as_slice()).unwrap();
+            assert_eq!(frame.format(), decoded);
+            assert_eq!(
+                frame.stride(),
+                padded_stride(5, decoded.bytes_per_pixel() * 8)
+            );
+            assert!(
+                frame.rows().eq(image.to_format(decoded).rows()),
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn bitfields_must_describe_bgra() {
+        let data = [1, 2, 3, 4];
+        let bitfields = |colors: &[u32]| file(1, 1, 32, BI_BITFIELDS, 0, &masks(colors), &data);
+        let frame = decode(bitfields(&BGRA_MASKS[..3]).as_slice()).unwrap();
+        assert_eq!(
+            (frame.format(), frame.bits()),
+            (PixelFormat::Bgra32, &data[..])
+        );
+        assert!(invalid(&bitfields(&[0xFF, 0xFF00, 0xFF_0000])));
+        assert!(invalid(&bitfields(&[0x7C00, 0x03E0, 0x001F])));
+        assert!(invalid(&bitfields(&BGRA_MASKS[..2])));
+
+        // The alpha mask of a BITMAPV4HEADER may be absent or must be the fourth byte.
+        let image = Frame::from_vec(data.to_vec(), 1, 1, PixelFormat::Bgra32);
+        let mut v4 = fixtures::encoded(|file| encode(&image, file));
+        let alpha = FILE_HEADER_SIZE + INFO_HEADER_SIZE + 12;
+        assert!(decode(v4.as_slice()).is_ok());
+        v4[alpha..alpha + 4].copy_from_slice(&0u32.to_le_bytes());
+        assert!(decode(v4.as_slice()).is_ok());
+        v4[alpha..alpha + 4].copy_from_slice(&0xFFu32.to_le_bytes());
+        assert!(invalid(&v4));
+    }
+
+    #[test]
+    fn truncated_and_oversized_headers_are_rejected() {
+        let valid = file(2, 2, 24, BI_RGB, 0, &[], &[0; 16]);
+        assert!(decode(valid.as_slice()).is_ok());
+        for len in [0, 2, 13, 30, 53, 54, 69] {
+            assert!(invalid(&valid[..len]), "{len}");
+        }
+        assert!(invalid(&file(2, 2, 24, BI_RGB, 0, &[], &[0; 15])));
+        // The header claims far more pixels than the file holds.
+        assert!(invalid(&file(
+            i32::MAX,
+            i32::MAX,
+            32,
+            BI_RGB,
+            0,
+            &[],
+            &[0; 16]
+        )));
+        assert!(invalid(&file(
+            i32::MAX,
+            i32::MIN,
+            8,
+            BI_RGB,
+            0,
+            &palette(&[[0; 3]]),
+            &[0; 16]
+        )));
+        assert!(invalid(&file(-2, 2, 24, BI_RGB, 0, &[], &[0; 16])));
+        // A palette beyond the end of the file.
+        assert!(invalid(&file(2, 2, 8, BI_RGB, 256, &[], &[0; 8])));
+
+        let mut small_header = valid.clone();
+        small_header[14..18].copy_from_slice(&12u32.to_le_bytes());
+        assert!(invalid(&small_header));
+        assert!(invalid(&file(2, 2, 16, BI_RGB, 0, &[], &[0; 16])));
+        assert!(invalid(&file(2, 2, 8, 1, 0, &[], &[0; 16])));
+        assert!(invalid(&[b"MB", &valid[2..]].concat()));
+    }
+}
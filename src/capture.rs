@@ -1,46 +1,256 @@
-use windows::{
-    core::Error,
-    Win32::{Foundation, Graphics::Gdi},
-};
+use std::cell::RefCell;
+use std::time::Instant;
 
-/// A wrapper struct containing a slice.
-pub struct CaptureData {
-    bits: &'static [u8],
-    hbitmap: Gdi::HBITMAP,
+use crate::backend::CaptureBackend;
+use crate::convert;
+use crate::error::Error;
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+#[cfg(windows)]
+use crate::gdi::{Dib, GdiBackend};
+use crate::pool::FramePool;
+use crate::rect::Rect;
+use crate::scale::{Filter, Resolution, Scaler};
+
+enum Bits {
+    Owned(Vec<u8>),
+    #[cfg(windows)]
+    Dib(Dib),
 }
 
-impl Drop for CaptureData {
-    fn drop(&mut self) {
-        unsafe { Gdi::DeleteObject(self.hbitmap) };
+impl Bits {
+    /// Takes the bits out, copying them only if they are not owned yet.
+    fn into_vec(self) -> Vec<u8> {
+        match self {
+            Bits::Owned(bits) => bits,
+            #[cfg(windows)]
+            Bits::Dib(dib) => dib.bits().to_vec(),
+        }
     }
 }
 
+/// A wrapper struct containing a slice.
+///
+/// The slice holds `height` rows of `width` pixels. Every row starts `stride` bytes after the previous one,
+/// so rows may be followed by padding bytes (24-bit DIB rows are for example padded to a multiple of 4 bytes).
+pub struct CaptureData {
+    bits: Bits,
+    width: usize,
+    height: usize,
+    stride: usize,
+    format: PixelFormat,
+    timestamp: Instant,
+}
+
 impl CaptureData {
+    /// Wraps tightly packed bits that were copied by a [`CaptureBackend`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bits` is shorter than `width * height` pixels.
+    pub fn from_vec(
+        bits: Vec<u8>,
+        width: usize,
+        height: usize,
+        format: PixelFormat,
+    ) -> CaptureData {
+        let stride = width * format.bytes_per_pixel();
+        CaptureData::from_vec_with_stride(bits, width, height, stride, format)
+    }
+
+    /// Wraps bits that were copied by a [`CaptureBackend`], in which every row is `stride` bytes long.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `stride` is smaller than a row of pixels or `bits` is too short to hold `height` rows.
+    pub fn from_vec_with_stride(
+        bits: Vec<u8>,
+        width: usize,
+        height: usize,
+        stride: usize,
+        format: PixelFormat,
+    ) -> CaptureData {
+        assert!(
+            stride >= width * format.bytes_per_pixel(),
+            "stride is too small"
+        );
+        assert!(bits.len() >= stride * height, "buffer is too small");
+        CaptureData {
+            bits: Bits::Owned(bits),
+            width,
+            height,
+            stride,
+            format,
+            timestamp: Instant::now(),
+        }
+    }
+
+    #[cfg(windows)]
+    pub(crate) fn from_dib(
+        dib: Dib,
+        width: usize,
+        height: usize,
+        stride: usize,
+        format: PixelFormat,
+    ) -> CaptureData {
+        CaptureData {
+            bits: Bits::Dib(dib),
+            width,
+            height,
+            stride,
+            format,
+            timestamp: Instant::now(),
+        }
+    }
+
+    fn from_frame(frame: Frame) -> CaptureData {
+        let (width, height, stride) = (frame.width(), frame.height(), frame.stride());
+        let (format, timestamp) = (frame.format(), frame.timestamp());
+        CaptureData {
+            bits: Bits::Owned(frame.into_vec()),
+            width,
+            height,
+            stride,
+            format,
+            timestamp,
+        }
+    }
+
     /// Returns a raw slice containing copied bitmap bit values.
     ///
-    /// The bits are stored as a one-dimensional array, in which one pixel consists of 3 adjacent \[B, G, R] values.
+    /// The bits are stored as a one-dimensional array of [`height`](CaptureData::height) rows,
+    /// each [`stride`](CaptureData::stride) bytes long.
+    /// The layout of a single pixel is described by [`format`](CaptureData::format),
+    /// e.g. one pixel consists of 3 adjacent \[B, G, R] values for [`PixelFormat::Bgr24`].
     pub fn get_bits(&self) -> &[u8] {
-        self.bits
+        match &self.bits {
+            Bits::Owned(bits) => bits,
+            #[cfg(windows)]
+            Bits::Dib(dib) => dib.bits(),
+        }
+    }
+
+    /// Returns the width of the captured area in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of the captured area in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the distance between the starts of two consecutive rows in bytes.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Returns the layout of a single pixel.
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Returns the moment the capture was taken.
+    pub fn timestamp(&self) -> Instant {
+        self.timestamp
+    }
+
+    /// Returns the pixels of the `y`-th row, without the padding.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `y` is not smaller than the height.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qshot::{CaptureData, PixelFormat};
+    ///
+    /// // Two rows of a single BGR pixel, each padded to 4 bytes.
+    /// let data = CaptureData::from_vec_with_stride(vec![1, 2, 3, 0, 4, 5, 6, 0], 1, 2, 4, PixelFormat::Bgr24);
+    /// assert_eq!(data.row(1), &[4, 5, 6]);
+    /// ```
+    pub fn row(&self, y: usize) -> &[u8] {
+        assert!(y < self.height, "row out of bounds");
+        let start = y * self.stride;
+        &self.get_bits()[start..start + self.width * self.format.bytes_per_pixel()]
+    }
+
+    /// Returns an iterator over the rows, without the padding.
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        (0..self.height).map(move |y| self.row(y))
+    }
+
+    /// Converts the pixels into another format. The returned data is tightly packed.
+    ///
+    /// See the [`convert`](crate::convert) module for conversions on plain buffers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qshot::{CaptureData, PixelFormat};
+    ///
+    /// let data = CaptureData::from_vec(vec![1, 2, 3], 1, 1, PixelFormat::Bgr24);
+    /// assert_eq!(data.to_format(PixelFormat::Rgba32).get_bits(), &[3, 2, 1, 255]);
+    /// ```
+    pub fn to_format(&self, format: PixelFormat) -> CaptureData {
+        let mut data = convert::convert(self, format);
+        data.timestamp = self.timestamp;
+        data
+    }
+
+    /// Turns the capture into an owned [`Frame`].
+    ///
+    /// Bits that were copied by the backend are moved into the frame, only bits that are still owned by the
+    /// platform (like a GDI bitmap) have to be copied.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qshot::{CaptureData, PixelFormat};
+    ///
+    /// let frame = CaptureData::from_vec(vec![1, 2, 3], 1, 1, PixelFormat::Bgr24).into_owned();
+    /// let bits = std::thread::spawn(move || frame.into_vec()).join().unwrap();
+    /// assert_eq!(bits, [1, 2, 3]);
+    /// ```
+    pub fn into_owned(self) -> Frame {
+        Frame::from_parts(
+            self.bits.into_vec(),
+            self.width,
+            self.height,
+            self.stride,
+            self.format,
+            self.timestamp,
+        )
     }
 }
+
 /// A struct that contains and manages information required for screen capturing
 ///
-/// This struct must be first initialized using the "new" constructor.
-pub struct CaptureManager {
-    top_left: (i32, i32),
-    wh: (i32, i32),
-    dc: Gdi::HDC,
-    dc_mem: Gdi::HDC,
-    window_handle: Foundation::HWND,
-    bitmap_info: Gdi::BITMAPINFO,
+/// The actual capturing is done by a [`CaptureBackend`].
+/// This struct must be first initialized using one of the constructors.
+pub struct CaptureManager<B: CaptureBackend> {
+    pub(crate) backend: B,
+    format: PixelFormat,
+    pool: FramePool,
+    /// Receives the captures that have to be converted or scaled before they are returned.
+    scratch: RefCell<Frame>,
+    /// Receives the scaled captures that still have to be converted.
+    scaled: RefCell<Frame>,
+    /// Receives the area around all regions captured by [`capture_regions`](CaptureManager::capture_regions).
+    pub(crate) bounding: Frame,
+    output_size: Option<Resolution>,
+    scaler: RefCell<Scaler>,
 }
 
-impl CaptureManager {
-    /// Creates a new capture manager.
+#[cfg(windows)]
+impl CaptureManager<GdiBackend> {
+    /// Creates a new capture manager using the GDI backend.
     ///
     /// # Errors
     ///
-    /// This method will usually return an error if window_handle is invalid.
+    /// This method will usually return [`Error::WindowGone`] if window_handle is invalid.
+    /// It fails in the same cases as [`open`](CaptureManager::open) as well.
     /// Other errors are also possible but very unlikely to happen.
     ///
     /// # Examples
@@ -48,79 +258,181 @@ impl CaptureManager {
     /// Let's assume that the screen resolution is 1000x1000px. In this example, the captured area will be a 500x500px square in the center of the screen.
     /// ```no_run
     /// use std::error::Error;
-    /// use qshot::CaptureManager;
+    /// use qshot::{CaptureManager, Rect};
     ///
     /// fn main() -> Result<(), Box<dyn Error>> {
     ///     let window_handle = 0; // A handle to a window that should be captured (0 to capture the entire screen).
-    ///     let top_left = (250, 250); // X and Y coordinates of the upper-left corner of the screen/window.
-    ///     let wh = (500, 500); // Width and height of the area which should be captured.
-    ///     
-    ///     let manager = CaptureManager::new(window_handle, top_left, wh)?;
+    ///     let area = Rect::new(250, 250, 500, 500); // X and Y coordinates of the upper-left corner, width and height.
+    ///
+    ///     let manager = CaptureManager::new(window_handle, area)?;
     ///
     ///     Ok(())
     /// }
     /// ```
-    pub fn new(
-        window_handle: isize,
-        top_left: (i32, i32),
-        wh: (i32, i32),
-    ) -> Result<CaptureManager, Error> {
-        let window_handle = Foundation::HWND(window_handle);
-        let dc = unsafe { Gdi::GetDC(window_handle) };
-        if dc.is_invalid() {
-            return Err(Error::from_win32());
-        }
-        let dc_mem = unsafe { Gdi::CreateCompatibleDC(dc) };
-        if dc_mem.is_invalid() {
-            return Err(windows::core::Error::from_win32());
-        }
+    pub fn new(window_handle: isize, area: Rect) -> Result<CaptureManager<GdiBackend>, Error> {
+        CaptureManager::open(window_handle, area)
+    }
+}
 
-        let bitmap_info = Gdi::BITMAPINFO {
-            bmiHeader: Gdi::BITMAPINFOHEADER {
-                biSize: std::mem::size_of::<Gdi::BITMAPINFOHEADER>() as u32,
-                biWidth: wh.0,
-                biHeight: -wh.1,
-                biBitCount: 24,
-                biCompression: 0,
-                biPlanes: 1,
-                biSizeImage: 0,
-                biClrImportant: 0,
-                biClrUsed: 0,
-                biXPelsPerMeter: 0,
-                biYPelsPerMeter: 0,
-            },
-            bmiColors: [Gdi::RGBQUAD {
-                rgbRed: 0,
-                rgbGreen: 0,
-                rgbBlue: 0,
-                rgbReserved: 0,
-            }],
+impl<B: CaptureBackend> CaptureManager<B> {
+    /// Creates a new capture manager by opening the backend `B` for the given target.
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if the backend fails to open the target.
+    /// It returns [`Error::InvalidRegion`] if the area is empty and [`Error::OffScreen`] if it lies entirely outside
+    /// of the target's [`bounds`](CaptureBackend::bounds). Areas that are only partially off-screen are fine,
+    /// the parts outside of the target are black.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qshot::{CaptureManager, Error, Rect, Script, SyntheticBackend};
+    ///
+    /// let open = |area| CaptureManager::<SyntheticBackend>::open(Script::new((100, 100)), area);
+    ///
+    /// assert!(open(Rect::new(90, -10, 20, 20)).is_ok());
+    /// assert!(matches!(open(Rect::new(0, 0, 0, 10)), Err(Error::InvalidRegion(_))));
+    /// assert!(matches!(open(Rect::new(100, 0, 10, 10)), Err(Error::OffScreen { .. })));
+    /// ```
+    pub fn open(target: B::Target, area: Rect) -> Result<CaptureManager<B>, Error> {
+        Error::check_region(area, None)?;
+        let manager = CaptureManager::with_backend(B::open(target, area)?);
+        Error::check_region(area, manager.backend.bounds())?;
+        Ok(manager)
+    }
+
+    /// Creates a new capture manager from an already opened backend.
+    ///
+    /// Captures are returned as [`PixelFormat::Bgr24`] until a different format is set.
+    pub fn with_backend(backend: B) -> CaptureManager<B> {
+        let mut manager = CaptureManager {
+            backend,
+            format: PixelFormat::Bgr24,
+            pool: FramePool::new(4),
+            scratch: RefCell::new(Frame::new(0, 0, PixelFormat::Bgr24)),
+            scaled: RefCell::new(Frame::new(0, 0, PixelFormat::Bgr24)),
+            bounding: Frame::new(0, 0, PixelFormat::Bgr24),
+            output_size: None,
+            scaler: RefCell::new(Scaler::new(Filter::default())),
         };
+        manager.set_format(PixelFormat::Bgr24);
+        manager
+    }
 
-        Ok(CaptureManager {
-            top_left,
-            wh,
-            bitmap_info,
-            dc,
-            dc_mem,
-            window_handle,
-        })
+    /// Sets the format of the captures returned by [`capture`](CaptureManager::capture).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qshot::{CaptureManager, PixelFormat, Rect, Script, SyntheticBackend};
+    ///
+    /// let script = Script::new((100, 100)).solid([255, 0, 0], 1);
+    /// let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 10, 10))
+    ///     .unwrap()
+    ///     .with_format(PixelFormat::Rgba32);
+    ///
+    /// let res = manager.capture().unwrap();
+    /// assert_eq!(res.format(), PixelFormat::Rgba32);
+    /// assert_eq!(&res.get_bits()[..4], &[0, 0, 255, 255]);
+    /// ```
+    pub fn with_format(mut self, format: PixelFormat) -> CaptureManager<B> {
+        self.set_format(format);
+        self
     }
+
+    /// Changes the format of the captures returned by [`capture`](CaptureManager::capture).
+    ///
+    /// Formats the backend can't produce natively are converted after every capture.
+    pub fn set_format(&mut self, format: PixelFormat) {
+        self.format = format;
+        self.backend.set_format(format);
+        self.pool.invalidate();
+    }
+
+    /// Returns the format of the captures returned by [`capture`](CaptureManager::capture).
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Makes the captures come out at the given resolution, see
+    /// [`set_output_size`](CaptureManager::set_output_size).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qshot::scale::{Filter, Resolution};
+    /// use qshot::{CaptureManager, Rect, Script, SyntheticBackend};
+    ///
+    /// let script = Script::new((1920, 1080)).solid([255, 0, 0], 1);
+    /// let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 1920, 1080))
+    ///     .unwrap()
+    ///     .with_output_size(Resolution::Size(320, 180), Filter::Box);
+    ///
+    /// let frame = manager.capture_frame().unwrap();
+    /// assert_eq!((frame.width(), frame.height()), (320, 180));
+    /// assert_eq!(&frame.bits()[..3], &[255, 0, 0]);
+    /// ```
+    pub fn with_output_size(mut self, size: Resolution, filter: Filter) -> CaptureManager<B> {
+        self.set_output_size(size, filter);
+        self
+    }
+
+    /// Makes the captures come out at the given resolution instead of the size of the captured area.
+    ///
+    /// Every capture is scaled with `filter` right after it was taken, before it is converted into the manager's
+    /// format. The full resolution capture and the scaled one go through buffers kept by the manager, so
+    /// [`capture_into`](CaptureManager::capture_into) doesn't allocate. Regions captured by [`capture_regions`](CaptureManager::capture_regions) are not scaled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is `Resolution::Fraction(0)`.
+    pub fn set_output_size(&mut self, size: Resolution, filter: Filter) {
+        size.resolve(0, 0);
+        self.output_size = Some(size);
+        if self.scaler.get_mut().filter() != filter {
+            *self.scaler.get_mut() = Scaler::new(filter);
+        }
+        self.pool.invalidate();
+    }
+
+    /// Makes the captures come out at the size of the captured area again.
+    pub fn reset_output_size(&mut self) {
+        self.output_size = None;
+        self.pool.invalidate();
+    }
+
+    /// Returns the resolution the captures are scaled to, `None` if they are not scaled.
+    pub fn output_size(&self) -> Option<Resolution> {
+        self.output_size
+    }
+
+    /// Returns a reference to the underlying backend.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Returns a mutable reference to the underlying backend.
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
+
     /// Captures the screen using the information provided in the constructor. Returns [`CaptureData`] if succeed.
     ///
     /// # Errors
     ///
-    /// This method will return an error if the targeted window is closed.
+    /// This method will return [`Error::WindowGone`] if the targeted window is closed.
     /// In some cases, incorrect coordinates will also cause the method to fail.
     ///
     /// # Examples
     ///
-    /// ```no_run
+    /// ```
     /// use std::error::Error;
-    /// use qshot::CaptureManager;
+    /// use qshot::{CaptureManager, Rect, Script, SyntheticBackend};
     ///
     /// fn main() -> Result<(), Box<dyn Error>> {
-    ///     let manager = CaptureManager::new(0, (250, 250), (500, 500))?;
+    ///     let script = Script::new((1000, 1000)).solid([255, 0, 0], 1);
+    ///     let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(250, 250, 500, 500))?;
     ///
     ///     let res = manager.capture()?;
     ///     assert_eq!(res.get_bits().len(), 500 * 500 * 3);
@@ -129,75 +441,214 @@ impl CaptureManager {
     /// }
     /// ```
     pub fn capture(&self) -> Result<CaptureData, Error> {
-        unsafe {
-            let mut bits = std::mem::MaybeUninit::<*mut u8>::uninit();
-            let hbitmap = Gdi::CreateDIBSection(
-                self.dc,
-                &self.bitmap_info,
-                Gdi::DIB_RGB_COLORS,
-                bits.as_mut_ptr() as *mut *mut std::ffi::c_void,
-                None,
-                0,
-            )?;
-            let bits = bits.assume_init();
-
-            Gdi::SelectObject(self.dc_mem, hbitmap);
-
-            Gdi::BitBlt(
-                self.dc_mem,
-                0,
-                0,
-                self.wh.0,
-                self.wh.1,
-                self.dc,
-                self.top_left.0,
-                self.top_left.1,
-                Gdi::SRCCOPY,
-            )?;
-
-            let slice = std::slice::from_raw_parts(bits, (self.wh.0 * self.wh.1 * 3) as usize);
-
-            Ok(CaptureData {
-                bits: slice,
-                hbitmap,
-            })
+        self.capture_as(self.format)
+    }
+
+    /// Captures the screen like [`capture`](CaptureManager::capture), but returns the data in the given format.
+    ///
+    /// # Errors
+    ///
+    /// This method fails in the same cases as [`capture`](CaptureManager::capture).
+    pub fn capture_as(&self, format: PixelFormat) -> Result<CaptureData, Error> {
+        if let Some(size) = self.output_size {
+            let mut frame = Frame::new(0, 0, format);
+            self.capture_scaled(size, &mut frame, format)?;
+            return Ok(CaptureData::from_frame(frame));
+        }
+        let data = self.backend.capture()?;
+        if data.format() == format {
+            Ok(data)
+        } else {
+            Ok(data.to_format(format))
+        }
+    }
+
+    /// Captures the screen like [`capture`](CaptureManager::capture), but returns an owned [`Frame`]
+    /// that can be sent to other threads.
+    ///
+    /// # Errors
+    ///
+    /// This method fails in the same cases as [`capture`](CaptureManager::capture).
+    pub fn capture_frame(&self) -> Result<Frame, Error> {
+        Ok(self.capture()?.into_owned())
+    }
+
+    /// Captures the screen into an existing frame, reusing its buffer instead of allocating a new one.
+    ///
+    /// The frame takes on the size and format of the capture.
+    ///
+    /// # Errors
+    ///
+    /// This method fails in the same cases as [`capture`](CaptureManager::capture).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qshot::{CaptureManager, Frame, PixelFormat, Rect, Script, SyntheticBackend};
+    ///
+    /// let script = Script::new((100, 100)).solid([255, 0, 0], 1);
+    /// let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 10, 10)).unwrap();
+    ///
+    /// let mut frame = Frame::new(10, 10, PixelFormat::Bgr24);
+    /// let ptr = frame.bits().as_ptr();
+    /// manager.capture_into(&mut frame).unwrap();
+    /// assert_eq!(frame.bits().as_ptr(), ptr);
+    /// assert_eq!(&frame.bits()[..3], &[255, 0, 0]);
+    /// ```
+    pub fn capture_into(&self, frame: &mut Frame) -> Result<(), Error> {
+        if let Some(size) = self.output_size {
+            return self.capture_scaled(size, frame, self.format);
+        }
+        self.backend.capture_into(frame)?;
+        if frame.format() != self.format {
+            let mut scratch = self.scratch.borrow_mut();
+            std::mem::swap(&mut *scratch, frame);
+            scratch.convert_into(frame, self.format);
+        }
+        Ok(())
+    }
+
+    /// Captures into the scratch frame and scales it into `frame`, converting the scaled capture if needed.
+    fn capture_scaled(
+        &self,
+        size: Resolution,
+        frame: &mut Frame,
+        format: PixelFormat,
+    ) -> Result<(), Error> {
+        let mut scratch = self.scratch.borrow_mut();
+        self.backend.capture_into(&mut scratch)?;
+        let (width, height) = size.resolve(scratch.width(), scratch.height());
+        let mut scaler = self.scaler.borrow_mut();
+        if scratch.format() == format {
+            scaler.scale_into(&*scratch, frame, width, height);
+        } else {
+            let mut scaled = self.scaled.borrow_mut();
+            scaler.scale_into(&*scratch, &mut scaled, width, height);
+            scaled.convert_into(frame, format);
         }
+        frame.set_timestamp(scratch.timestamp());
+        Ok(())
     }
+
+    /// Captures the screen into a frame taken from the manager's [`pool`](CaptureManager::pool).
+    ///
+    /// Frames returned to the pool with [`FramePool::recycle`] are reused by later captures,
+    /// so that a steady stream of captures does not allocate.
+    ///
+    /// # Errors
+    ///
+    /// This method fails in the same cases as [`capture`](CaptureManager::capture).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qshot::{CaptureManager, Rect, Script, SyntheticBackend};
+    ///
+    /// let script = Script::new((100, 100)).solid([255, 0, 0], 1);
+    /// let mut manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 10, 10)).unwrap();
+    ///
+    /// let frame = manager.capture_pooled().unwrap();
+    /// manager.pool().recycle(frame);
+    /// assert_eq!(manager.pool().len(), 1);
+    ///
+    /// // Frames of the previous size are no longer useful.
+    /// manager.change_size(Rect::new(0, 0, 20, 20)).unwrap();
+    /// assert!(manager.pool().is_empty());
+    /// ```
+    pub fn capture_pooled(&self) -> Result<Frame, Error> {
+        let mut frame = self.pool.get();
+        let res = self.capture_into(&mut frame);
+        self.pool
+            .set_shape(frame.width(), frame.height(), frame.format());
+        res.map(|_| frame)
+    }
+
+    /// Returns the pool used by [`capture_pooled`](CaptureManager::capture_pooled).
+    ///
+    /// The pool can be cloned and moved to the threads consuming the frames, so that they can be recycled there.
+    pub fn pool(&self) -> &FramePool {
+        &self.pool
+    }
+
+    /// Replaces the pool used by [`capture_pooled`](CaptureManager::capture_pooled).
+    #[cfg(feature = "async")]
+    pub(crate) fn set_pool(&mut self, pool: FramePool) {
+        self.pool = pool;
+    }
+
     /// Modifies information associated with the screenshot size and position without the need to call the constructor again.
     ///
+    /// # Errors
+    ///
+    /// This method fails in the same cases as [`open`](CaptureManager::open), the captured area is left unchanged
+    /// if the new area is empty or off-screen.
+    ///
     /// # Examples
     ///
-    /// ```no_run
+    /// ```
     /// use std::error::Error;
-    /// use qshot::CaptureManager;
+    /// use qshot::{CaptureManager, Rect, Script, SyntheticBackend};
     ///
     /// fn main() -> Result<(), Box<dyn Error>> {
-    ///     let mut manager = CaptureManager::new(0, (250, 250), (500, 500))?;
+    ///     let script = Script::new((1000, 1000)).solid([255, 0, 0], 1);
+    ///     let mut manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(250, 250, 500, 500))?;
     ///
     ///     let res = manager.capture()?;
     ///     assert_eq!(res.get_bits().len(), 500 * 500 * 3);
-    ///     
-    ///     manager.change_size((100, 100), (100, 250));
-    ///     
+    ///
+    ///     manager.change_size(Rect::new(100, 100, 100, 250))?;
+    ///
     ///     let res1 = manager.capture()?;
     ///     assert_eq!(res1.get_bits().len(), 100 * 250 * 3);
     ///
     ///     Ok(())
     /// }
     /// ```
-    pub fn change_size(&mut self, top_left: (i32, i32), wh: (i32, i32)) {
-        self.wh = wh;
-        self.top_left = top_left;
-        self.bitmap_info.bmiHeader.biHeight = wh.0;
-        self.bitmap_info.bmiHeader.biHeight = -wh.1;
+    pub fn change_size(&mut self, area: Rect) -> Result<(), Error> {
+        Error::check_region(area, self.backend.bounds())?;
+        self.pool.invalidate();
+        self.backend.resize(area)
+    }
+
+    /// Returns the captured area.
+    pub fn area(&self) -> Rect {
+        self.backend.area()
     }
 }
 
-impl Drop for CaptureManager {
+impl<B: CaptureBackend> Drop for CaptureManager<B> {
     fn drop(&mut self) {
-        unsafe {
-            Gdi::ReleaseDC(self.window_handle, self.dc);
-            Gdi::DeleteDC(self.dc_mem);
-        }
+        self.backend.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scale::{Filter, Resolution};
+    use crate::{CaptureManager, Frame, PixelFormat, Rect, Script, SyntheticBackend};
+
+    #[test]
+    fn scaled_captures_reuse_the_frame() {
+        let script = Script::new((100, 100)).solid([255, 0, 0], 1);
+        let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 40, 20))
+            .unwrap()
+            .with_output_size(Resolution::Fraction(2), Filter::Box)
+            .with_format(PixelFormat::Rgba32);
+
+        let mut frame = Frame::new(20, 10, PixelFormat::Rgba32);
+        let ptr = frame.bits().as_ptr();
+        manager.capture_into(&mut frame).unwrap();
+        assert_eq!(frame.bits().as_ptr(), ptr);
+        assert_eq!(
+            (frame.width(), frame.height(), frame.format()),
+            (20, 10, PixelFormat::Rgba32)
+        );
+        assert_eq!(&frame.bits()[..4], &[0, 0, 255, 255]);
+
+        let data = manager.capture_as(PixelFormat::Gray8).unwrap();
+        assert_eq!(
+            (data.width(), data.height(), data.format()),
+            (20, 10, PixelFormat::Gray8)
+        );
     }
 }
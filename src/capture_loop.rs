@@ -0,0 +1,495 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{SyncSender, TrySendError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::backend::CaptureBackend;
+use crate::capture::CaptureManager;
+use crate::error::Error;
+use crate::frame::Frame;
+
+/// The longest a [`CaptureLoop`] sleeps before checking whether it was paused or stopped.
+const MAX_SLEEP: Duration = Duration::from_millis(10);
+
+/// A monotonic source of time used by a [`CaptureLoop`] to pace its captures.
+pub trait Clock {
+    /// Returns the time elapsed since some fixed point in the past. It must never go backwards.
+    fn now(&self) -> Duration;
+
+    /// Blocks the current thread for the given duration.
+    fn sleep(&self, duration: Duration);
+}
+
+/// The system's monotonic clock.
+#[derive(Clone, Copy, Debug)]
+pub struct MonotonicClock {
+    start: Instant,
+}
+
+impl Default for MonotonicClock {
+    fn default() -> MonotonicClock {
+        MonotonicClock::new()
+    }
+}
+
+impl MonotonicClock {
+    /// Creates a clock that starts at zero now.
+    pub fn new() -> MonotonicClock {
+        MonotonicClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// A clock that only moves when told to, for testing code built on a [`CaptureLoop`].
+///
+/// Sleeping returns immediately after advancing the clock. Clones share the same time,
+/// so a clone can be used to simulate slow captures from within a callback.
+#[derive(Clone, Debug, Default)]
+pub struct MockClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl MockClock {
+    /// Creates a clock starting at zero.
+    pub fn new() -> MockClock {
+        MockClock::default()
+    }
+
+    /// Moves the clock forward.
+    pub fn advance(&self, duration: Duration) {
+        self.nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// Statistics collected by a [`CaptureLoop`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LoopStats {
+    /// Frames that were captured and delivered.
+    pub frames: u64,
+    /// Frames whose capture and delivery took longer than the frame period.
+    pub late: u64,
+    /// Frames that were skipped because the loop fell behind by a whole period or more,
+    /// or that were captured but could not be delivered because the channel was full.
+    pub dropped: u64,
+    /// The achieved number of frames per second, `0.0` until two frames were delivered.
+    pub fps: f64,
+    /// The standard deviation of the intervals between delivered frames.
+    pub jitter: Duration,
+}
+
+/// Accumulates the intervals between frames using Welford's algorithm.
+#[derive(Default)]
+struct Intervals {
+    count: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Intervals {
+    fn push(&mut self, interval: Duration) {
+        let x = interval.as_secs_f64();
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    fn fill(&self, stats: &mut LoopStats) {
+        if self.count > 0 && self.mean > 0.0 {
+            stats.fps = 1.0 / self.mean;
+            stats.jitter = Duration::from_secs_f64((self.m2 / self.count as f64).sqrt());
+        }
+    }
+}
+
+#[derive(Default)]
+struct ControlState {
+    paused: bool,
+    stopped: bool,
+}
+
+#[derive(Default)]
+struct Control {
+    state: Mutex<ControlState>,
+    changed: Condvar,
+    stats: Mutex<LoopStats>,
+}
+
+impl Control {
+    fn state(&self) -> MutexGuard<'_, ControlState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Controls a running [`CaptureLoop`] from any thread.
+#[derive(Clone, Default)]
+pub struct LoopHandle {
+    control: Arc<Control>,
+}
+
+impl LoopHandle {
+    /// Pauses the loop after the current frame. Paused time does not count towards the statistics.
+    pub fn pause(&self) {
+        self.control.state().paused = true;
+        self.control.changed.notify_all();
+    }
+
+    /// Resumes a paused loop, the frame schedule restarts from the moment it is resumed.
+    pub fn resume(&self) {
+        self.control.state().paused = false;
+        self.control.changed.notify_all();
+    }
+
+    /// Stops the loop after the current frame, which makes [`CaptureLoop::run`] return.
+    pub fn stop(&self) {
+        self.control.state().stopped = true;
+        self.control.changed.notify_all();
+    }
+
+    /// Returns whether the loop was paused and not resumed since.
+    pub fn is_paused(&self) -> bool {
+        self.control.state().paused
+    }
+
+    /// Returns whether the loop was asked to stop and was not run again since.
+    pub fn is_stopped(&self) -> bool {
+        self.control.state().stopped
+    }
+
+    /// Returns the statistics of the loop so far.
+    pub fn stats(&self) -> LoopStats {
+        *self
+            .control
+            .stats
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// What happened to a captured frame.
+pub(crate) enum Delivery {
+    Delivered,
+    Dropped,
+    Closed,
+}
+
+/// Returns the frame period for `fps`, panicking if it is not a positive number of nanoseconds.
+pub(crate) fn period(fps: f64) -> Duration {
+    assert!(fps > 0.0 && fps.is_finite(), "fps must be positive");
+    match Duration::try_from_secs_f64(1.0 / fps) {
+        Ok(period) if !period.is_zero() => period,
+        _ => panic!("fps out of range"),
+    }
+}
+
+/// Captures frames at a fixed rate and hands them to a callback or a channel.
+///
+/// Frames are scheduled on a fixed grid of `1 / fps` periods. When a frame takes longer than its period, the next one
+/// starts right away, and when the loop falls behind by whole periods these frames are dropped instead of being
+/// captured in a burst. The loop is controlled through a [`LoopHandle`], which can be moved to other threads.
+///
+/// # Examples
+///
+/// ```
+/// use std::time::Duration;
+/// use qshot::{CaptureLoop, CaptureManager, MockClock, Rect, Script, SyntheticBackend};
+///
+/// let script = Script::new((100, 100)).solid([255, 0, 0], 1);
+/// let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 10, 10)).unwrap();
+/// let clock = MockClock::new();
+///
+/// let capture_loop = CaptureLoop::new(50.0).with_clock(clock.clone()).max_frames(100);
+/// let stats = capture_loop.run(&manager, |_frame| {}).unwrap();
+/// assert_eq!((stats.frames, stats.late, stats.dropped), (100, 0, 0));
+/// assert!((stats.fps - 50.0).abs() < 1e-6);
+///
+/// // Every frame now takes 30ms, which doesn't fit into the 20ms period.
+/// let handle = capture_loop.handle();
+/// let stats = capture_loop
+///     .run(&manager, |_frame| {
+///         clock.advance(Duration::from_millis(30));
+///         if handle.stats().frames == 9 {
+///             handle.stop();
+///         }
+///     })
+///     .unwrap();
+/// assert_eq!((stats.frames, stats.late), (10, 10));
+/// assert!(stats.dropped > 0);
+/// ```
+pub struct CaptureLoop<C: Clock = MonotonicClock> {
+    period: Duration,
+    clock: C,
+    max_frames: Option<u64>,
+    handle: LoopHandle,
+}
+
+impl CaptureLoop {
+    /// Creates a loop targeting the given number of frames per second, paced by the [`MonotonicClock`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fps` is not a positive number, or if its period is shorter than a nanosecond or too long for a
+    /// [`Duration`].
+    pub fn new(fps: f64) -> CaptureLoop {
+        CaptureLoop {
+            period: period(fps),
+            clock: MonotonicClock::new(),
+            max_frames: None,
+            handle: LoopHandle::default(),
+        }
+    }
+}
+
+impl<C: Clock> CaptureLoop<C> {
+    /// Paces the loop with another clock.
+    pub fn with_clock<D: Clock>(self, clock: D) -> CaptureLoop<D> {
+        CaptureLoop {
+            period: self.period,
+            clock,
+            max_frames: self.max_frames,
+            handle: self.handle,
+        }
+    }
+
+    /// Stops every run of the loop after the given number of delivered frames.
+    pub fn max_frames(mut self, frames: u64) -> CaptureLoop<C> {
+        self.max_frames = Some(frames);
+        self
+    }
+
+    /// Returns a handle that controls the loop.
+    pub fn handle(&self) -> LoopHandle {
+        self.handle.clone()
+    }
+
+    /// Runs the loop, passing every frame to `callback`, until it is stopped.
+    ///
+    /// The frame is reused between calls, so capturing does not allocate.
+    /// A stopped loop can be run again, which clears the stop request.
+    ///
+    /// # Errors
+    ///
+    /// Stops and returns the error if a capture fails.
+    pub fn run<B: CaptureBackend>(
+        &self,
+        manager: &CaptureManager<B>,
+        mut callback: impl FnMut(&Frame),
+    ) -> Result<LoopStats, Error> {
+        let mut frame = Frame::new(0, 0, manager.format());
+        self.restart();
+        self.drive(|| {
+            manager.capture_into(&mut frame)?;
+            callback(&frame);
+            Ok(Delivery::Delivered)
+        })
+    }
+
+    /// Runs the loop, sending every frame into a channel, until it is stopped or the receiver is dropped.
+    ///
+    /// Frames that don't fit into the channel are dropped. Frames are taken from the manager's
+    /// [`pool`](CaptureManager::pool), so receivers should recycle them once done.
+    ///
+    /// # Errors
+    ///
+    /// Stops and returns the error if a capture fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::mpsc;
+    /// use qshot::{CaptureLoop, CaptureManager, MockClock, Rect, Script, SyntheticBackend};
+    ///
+    /// let script = Script::new((100, 100)).solid([255, 0, 0], 1);
+    /// let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 10, 10)).unwrap();
+    ///
+    /// let (tx, rx) = mpsc::sync_channel(4);
+    /// let capture_loop = CaptureLoop::new(30.0).with_clock(MockClock::new()).max_frames(3);
+    /// assert_eq!(capture_loop.run_with_sender(&manager, &tx).unwrap().frames, 3);
+    ///
+    /// let pool = manager.pool().clone();
+    /// rx.try_iter().for_each(|frame| pool.recycle(frame));
+    /// assert_eq!(pool.len(), 3);
+    /// ```
+    pub fn run_with_sender<B: CaptureBackend>(
+        &self,
+        manager: &CaptureManager<B>,
+        sender: &SyncSender<Frame>,
+    ) -> Result<LoopStats, Error> {
+        self.restart();
+        self.drive(|| match sender.try_send(manager.capture_pooled()?) {
+            Ok(()) => Ok(Delivery::Delivered),
+            Err(TrySendError::Full(frame)) => {
+                manager.pool().recycle(frame);
+                Ok(Delivery::Dropped)
+            }
+            Err(TrySendError::Disconnected(_)) => Ok(Delivery::Closed),
+        })
+    }
+
+    /// Clears the stop request of a previous run.
+    ///
+    /// This happens on the caller's thread, so that a stop issued before the loop starts on another thread is kept.
+    fn restart(&self) {
+        self.handle.control.state().stopped = false;
+    }
+
+    pub(crate) fn drive<E>(
+        &self,
+        mut capture: impl FnMut() -> Result<Delivery, E>,
+    ) -> Result<LoopStats, E> {
+        let control = &self.handle.control;
+        let mut stats = LoopStats::default();
+        let mut intervals = Intervals::default();
+        self.publish(&stats);
+
+        let mut anchor = self.clock.now();
+        let mut tick: u32 = 0;
+        let mut previous = None;
+        loop {
+            {
+                let mut state = control.state();
+                if state.paused && !state.stopped {
+                    while state.paused && !state.stopped {
+                        state = control
+                            .changed
+                            .wait(state)
+                            .unwrap_or_else(|err| err.into_inner());
+                    }
+                    anchor = self.clock.now();
+                    tick = 0;
+                    previous = None;
+                }
+                if state.stopped || self.max_frames.is_some_and(|max| stats.frames >= max) {
+                    break;
+                }
+            }
+
+            // Moving the anchor keeps the tick from overflowing in long running loops.
+            if tick >= 1 << 20 {
+                anchor += self.period * tick;
+                tick = 0;
+            }
+            let deadline = anchor + self.period * tick;
+            let now = self.clock.now();
+            if now < deadline {
+                self.clock.sleep((deadline - now).min(MAX_SLEEP));
+                continue;
+            }
+            let behind = (now - deadline).as_nanos() / self.period.as_nanos();
+            stats.dropped = stats
+                .dropped
+                .saturating_add(behind.try_into().unwrap_or(u64::MAX));
+            if behind >= 1 << 20 {
+                // After a long stall the schedule starts over, so the tick never has to count the missed periods.
+                anchor = now;
+                tick = 1;
+            } else {
+                tick += behind as u32 + 1;
+            }
+
+            match capture()? {
+                Delivery::Delivered => {
+                    stats.frames += 1;
+                    if let Some(previous) = previous {
+                        intervals.push(now - previous);
+                    }
+                    previous = Some(now);
+                }
+                Delivery::Dropped => stats.dropped += 1,
+                Delivery::Closed => break,
+            }
+            if self.clock.now() > anchor + self.period * tick {
+                stats.late += 1;
+            }
+            intervals.fill(&mut stats);
+            self.publish(&stats);
+        }
+        Ok(stats)
+    }
+
+    fn publish(&self, stats: &LoopStats) {
+        *self
+            .handle
+            .control
+            .stats
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()) = *stats;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stop_before_drive_is_kept() {
+        let capture_loop = CaptureLoop::new(30.0).with_clock(MockClock::new());
+        capture_loop.handle().stop();
+        let stats = capture_loop
+            .drive(|| Ok::<_, ()>(Delivery::Delivered))
+            .unwrap();
+        assert_eq!(stats.frames, 0);
+    }
+
+    #[test]
+    fn huge_stalls_restart_the_schedule() {
+        let clock = MockClock::new();
+        let capture_loop = CaptureLoop::new(1e9)
+            .with_clock(clock.clone())
+            .max_frames(3);
+        let mut captures = 0;
+        let stats = capture_loop
+            .drive(|| {
+                captures += 1;
+                if captures == 1 {
+                    // Far more nanosecond periods than fit in a u32.
+                    clock.advance(Duration::from_secs(10));
+                }
+                Ok::<_, ()>(Delivery::Delivered)
+            })
+            .unwrap();
+        assert_eq!(stats.frames, 3);
+        assert_eq!(stats.dropped, 9_999_999_999);
+        assert_eq!(stats.late, 1);
+        assert_eq!(clock.now(), Duration::from_nanos(10_000_000_001));
+    }
+
+    #[test]
+    fn shortest_period_is_a_nanosecond() {
+        assert_eq!(CaptureLoop::new(1e9).period, Duration::from_nanos(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "fps out of range")]
+    fn sub_nanosecond_period_panics() {
+        CaptureLoop::new(1e10);
+    }
+
+    #[test]
+    #[should_panic(expected = "fps out of range")]
+    fn overlong_period_panics() {
+        CaptureLoop::new(1e-300);
+    }
+}
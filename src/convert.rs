@@ -0,0 +1,578 @@
+//! Pixel format conversions for [`CaptureData`] buffers.
+//!
+//! Every conversion has a scalar implementation and, where it pays off, SSE2/SSSE3/AVX2 (x86_64) or NEON (aarch64)
+//! implementations. The fastest one supported by the CPU is picked at runtime, see [`Isa::detect`].
+//! The free functions in this module use the detected instruction set, [`Converter`] allows choosing one explicitly.
+
+use std::sync::OnceLock;
+
+use crate::capture::CaptureData;
+use crate::format::PixelFormat;
+
+#[cfg(target_arch = "aarch64")]
+mod neon;
+#[cfg(target_arch = "x86_64")]
+mod x86;
+
+/// An instruction set a [`Converter`] can use.
+///
+/// Every level also uses the levels below it for conversions it has no dedicated implementation for,
+/// e.g. [`Isa::Avx2`] falls back to SSSE3 or SSE2 code where there is no AVX2 code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Isa {
+    /// Plain Rust, available everywhere.
+    Scalar,
+    /// SSE2 (x86_64).
+    Sse2,
+    /// SSSE3 (x86_64).
+    Ssse3,
+    /// AVX2 (x86_64).
+    Avx2,
+    /// NEON (aarch64).
+    Neon,
+}
+
+impl Isa {
+    /// Returns the fastest instruction set supported by the CPU.
+    pub fn detect() -> Isa {
+        static DETECTED: OnceLock<Isa> = OnceLock::new();
+        *DETECTED.get_or_init(|| {
+            Isa::available()
+                .into_iter()
+                .max_by_key(|isa| match isa {
+                    Isa::Neon => 1,
+                    isa => *isa as u8,
+                })
+                .unwrap_or(Isa::Scalar)
+        })
+    }
+
+    /// Returns all instruction sets supported by the CPU.
+    pub fn available() -> Vec<Isa> {
+        [Isa::Scalar, Isa::Sse2, Isa::Ssse3, Isa::Avx2, Isa::Neon]
+            .into_iter()
+            .filter(|isa| isa.is_available())
+            .collect()
+    }
+
+    /// Returns `true` if the CPU supports the instruction set.
+    pub fn is_available(self) -> bool {
+        match self {
+            Isa::Scalar => true,
+            #[cfg(target_arch = "x86_64")]
+            Isa::Sse2 => is_x86_feature_detected!("sse2"),
+            #[cfg(target_arch = "x86_64")]
+            Isa::Ssse3 => is_x86_feature_detected!("ssse3"),
+            #[cfg(target_arch = "x86_64")]
+            Isa::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(target_arch = "aarch64")]
+            Isa::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+            _ => false,
+        }
+    }
+}
+
+/// The weights used to compute the luma of a pixel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Luma {
+    /// ITU-R BT.601 (SD video, JPEG).
+    #[default]
+    Bt601,
+    /// ITU-R BT.709 (HD video).
+    Bt709,
+}
+
+impl Luma {
+    /// Returns the \[R, G, B] weights as 8-bit fixed point numbers, they always add up to 256.
+    fn weights(self) -> [u32; 3] {
+        match self {
+            Luma::Bt601 => [77, 150, 29],
+            Luma::Bt709 => [54, 183, 19],
+        }
+    }
+}
+
+/// Converts pixels using a fixed instruction set.
+///
+/// Every instruction set produces exactly the same results as the scalar code.
+///
+/// # Examples
+///
+/// ```
+/// use qshot::convert::{Converter, Isa};
+/// use qshot::PixelFormat;
+///
+/// let converter = Converter::with_isa(Isa::Scalar).unwrap();
+/// let mut rgba = [0; 8];
+/// converter.convert_row(&[1, 2, 3, 4, 5, 6], PixelFormat::Bgr24, &mut rgba, PixelFormat::Rgba32);
+/// assert_eq!(rgba, [3, 2, 1, 255, 6, 5, 4, 255]);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Converter {
+    isa: Isa,
+}
+
+impl Default for Converter {
+    fn default() -> Converter {
+        Converter::new()
+    }
+}
+
+impl Converter {
+    /// Creates a converter using the fastest instruction set supported by the CPU.
+    pub fn new() -> Converter {
+        Converter { isa: Isa::detect() }
+    }
+
+    /// Creates a converter using the given instruction set, or `None` if the CPU does not support it.
+    pub fn with_isa(isa: Isa) -> Option<Converter> {
+        isa.is_available().then_some(Converter { isa })
+    }
+
+    /// Returns the instruction set used by the converter.
+    pub fn isa(&self) -> Isa {
+        self.isa
+    }
+
+    /// Converts \[B, G, R] pixels into \[R, G, B] pixels (or the other way around).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `dst` is shorter than `src`.
+    pub fn bgr24_to_rgb24(&self, src: &[u8], dst: &mut [u8]) {
+        let len = src.len() / 3 * 3;
+        assert!(dst.len() >= len, "destination is too small");
+        let (src, dst) = (&src[..len], &mut dst[..len]);
+        match self.isa {
+            #[cfg(target_arch = "x86_64")]
+            Isa::Avx2 => unsafe { x86::swap_rb24_avx2(src, dst) },
+            #[cfg(target_arch = "x86_64")]
+            Isa::Ssse3 => unsafe { x86::swap_rb24_ssse3(src, dst) },
+            #[cfg(target_arch = "aarch64")]
+            Isa::Neon => unsafe { neon::swap_rb24(src, dst) },
+            _ => scalar::swap_rb24(src, dst),
+        }
+    }
+
+    /// Converts \[B, G, R] pixels into \[R, G, B, A] pixels with an opaque alpha channel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `dst` can't hold as many 4-byte pixels as there are 3-byte pixels in `src`.
+    pub fn bgr24_to_rgba32(&self, src: &[u8], dst: &mut [u8]) {
+        self.expand24(src, dst, true)
+    }
+
+    /// Converts \[B, G, R] pixels into \[B, G, R, A] pixels with an opaque alpha channel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `dst` can't hold as many 4-byte pixels as there are 3-byte pixels in `src`.
+    pub fn bgr24_to_bgra32(&self, src: &[u8], dst: &mut [u8]) {
+        self.expand24(src, dst, false)
+    }
+
+    fn expand24(&self, src: &[u8], dst: &mut [u8], swap: bool) {
+        let pixels = src.len() / 3;
+        assert!(dst.len() >= pixels * 4, "destination is too small");
+        let (src, dst) = (&src[..pixels * 3], &mut dst[..pixels * 4]);
+        match self.isa {
+            #[cfg(target_arch = "x86_64")]
+            Isa::Avx2 => unsafe { x86::expand24_avx2(src, dst, swap) },
+            #[cfg(target_arch = "x86_64")]
+            Isa::Ssse3 => unsafe { x86::expand24_ssse3(src, dst, swap) },
+            #[cfg(target_arch = "aarch64")]
+            Isa::Neon => unsafe { neon::expand24(src, dst, swap) },
+            _ => scalar::expand24(src, dst, swap),
+        }
+    }
+
+    /// Converts \[B, G, R, A] pixels into \[R, G, B, A] pixels (or the other way around).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `dst` is shorter than `src`.
+    pub fn bgra32_to_rgba32(&self, src: &[u8], dst: &mut [u8]) {
+        let len = src.len() / 4 * 4;
+        assert!(dst.len() >= len, "destination is too small");
+        let (src, dst) = (&src[..len], &mut dst[..len]);
+        match self.isa {
+            #[cfg(target_arch = "x86_64")]
+            Isa::Avx2 => unsafe { x86::swap_rb32_avx2(src, dst) },
+            #[cfg(target_arch = "x86_64")]
+            Isa::Sse2 | Isa::Ssse3 => unsafe { x86::swap_rb32_sse2(src, dst) },
+            #[cfg(target_arch = "aarch64")]
+            Isa::Neon => unsafe { neon::swap_rb32(src, dst) },
+            _ => scalar::swap_rb32(src, dst),
+        }
+    }
+
+    /// Converts \[B, G, R, A] pixels into their luma, ignoring the alpha channel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `dst` is shorter than the number of pixels in `src`.
+    pub fn bgra32_to_gray8(&self, src: &[u8], dst: &mut [u8], luma: Luma) {
+        let [r, g, b] = luma.weights();
+        self.gray32(src, dst, [b, g, r])
+    }
+
+    /// Converts \[R, G, B, A] pixels into their luma, ignoring the alpha channel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `dst` is shorter than the number of pixels in `src`.
+    pub fn rgba32_to_gray8(&self, src: &[u8], dst: &mut [u8], luma: Luma) {
+        self.gray32(src, dst, luma.weights())
+    }
+
+    fn gray32(&self, src: &[u8], dst: &mut [u8], weights: [u32; 3]) {
+        let pixels = src.len() / 4;
+        assert!(dst.len() >= pixels, "destination is too small");
+        let (src, dst) = (&src[..pixels * 4], &mut dst[..pixels]);
+        match self.isa {
+            #[cfg(target_arch = "x86_64")]
+            Isa::Avx2 => unsafe { x86::gray32_avx2(src, dst, weights) },
+            #[cfg(target_arch = "x86_64")]
+            Isa::Sse2 | Isa::Ssse3 => unsafe { x86::gray32_sse2(src, dst, weights) },
+            #[cfg(target_arch = "aarch64")]
+            Isa::Neon => unsafe { neon::gray32(src, dst, weights) },
+            _ => scalar::gray32(src, dst, weights),
+        }
+    }
+
+    /// Multiplies the color channels of 32-bit pixels by their alpha channel, in place.
+    ///
+    /// The alpha channel must be the fourth byte of every pixel, i.e. the buffer is either
+    /// [`PixelFormat::Bgra32`] or [`PixelFormat::Rgba32`].
+    pub fn premultiply_alpha(&self, buf: &mut [u8]) {
+        let len = buf.len() / 4 * 4;
+        let buf = &mut buf[..len];
+        match self.isa {
+            #[cfg(target_arch = "x86_64")]
+            Isa::Avx2 => unsafe { x86::premultiply_avx2(buf) },
+            #[cfg(target_arch = "x86_64")]
+            Isa::Sse2 | Isa::Ssse3 => unsafe { x86::premultiply_sse2(buf) },
+            #[cfg(target_arch = "aarch64")]
+            Isa::Neon => unsafe { neon::premultiply(buf) },
+            _ => scalar::premultiply(buf),
+        }
+    }
+
+    /// Reverts [`premultiply_alpha`](Converter::premultiply_alpha), in place.
+    ///
+    /// Fully transparent pixels become transparent black.
+    pub fn unpremultiply_alpha(&self, buf: &mut [u8]) {
+        let len = buf.len() / 4 * 4;
+        let buf = &mut buf[..len];
+        match self.isa {
+            #[cfg(target_arch = "x86_64")]
+            Isa::Avx2 => unsafe { x86::unpremultiply_avx2(buf) },
+            #[cfg(target_arch = "x86_64")]
+            Isa::Sse2 | Isa::Ssse3 => unsafe { x86::unpremultiply_sse2(buf) },
+            #[cfg(target_arch = "aarch64")]
+            Isa::Neon => unsafe { neon::unpremultiply(buf) },
+            _ => scalar::unpremultiply(buf),
+        }
+    }
+
+    /// Converts a row of pixels from one format into another.
+    ///
+    /// Conversions into [`PixelFormat::Gray8`] use the BT.601 weights.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `dst` can't hold as many pixels as there are in `src`.
+    pub fn convert_row(&self, src: &[u8], from: PixelFormat, dst: &mut [u8], to: PixelFormat) {
+        use PixelFormat::*;
+
+        let pixels = src.len() / from.bytes_per_pixel();
+        assert!(
+            dst.len() >= pixels * to.bytes_per_pixel(),
+            "destination is too small"
+        );
+        match (from, to) {
+            (Bgr24, Bgr24)
+            | (Bgra32, Bgra32)
+            | (Rgb24, Rgb24)
+            | (Rgba32, Rgba32)
+            | (Gray8, Gray8) => dst[..src.len()].copy_from_slice(src),
+            (Bgr24, Rgb24) | (Rgb24, Bgr24) => self.bgr24_to_rgb24(src, dst),
+            (Bgr24, Bgra32) | (Rgb24, Rgba32) => self.bgr24_to_bgra32(src, dst),
+            (Bgr24, Rgba32) | (Rgb24, Bgra32) => self.bgr24_to_rgba32(src, dst),
+            (Bgra32, Rgba32) | (Rgba32, Bgra32) => self.bgra32_to_rgba32(src, dst),
+            (Bgra32, Gray8) => self.bgra32_to_gray8(src, dst, Luma::Bt601),
+            (Rgba32, Gray8) => self.rgba32_to_gray8(src, dst, Luma::Bt601),
+            (Bgra32, Bgr24) | (Rgba32, Rgb24) => {
+                scalar::map::<4, 3>(src, dst, |s, d| d.copy_from_slice(&s[..3]))
+            }
+            (Bgra32, Rgb24) | (Rgba32, Bgr24) => {
+                scalar::map::<4, 3>(src, dst, |s, d| d.copy_from_slice(&[s[2], s[1], s[0]]))
+            }
+            (Bgr24, Gray8) | (Rgb24, Gray8) => {
+                let [r, g, b] = Luma::Bt601.weights();
+                let weights = if from == Bgr24 { [b, g, r] } else { [r, g, b] };
+                scalar::map::<3, 1>(src, dst, |s, d| d[0] = scalar::weigh(s, weights))
+            }
+            (Gray8, Bgr24) | (Gray8, Rgb24) => scalar::map::<1, 3>(src, dst, |s, d| d.fill(s[0])),
+            (Gray8, Bgra32) | (Gray8, Rgba32) => {
+                scalar::map::<1, 4>(src, dst, |s, d| d.copy_from_slice(&[s[0], s[0], s[0], 255]))
+            }
+        }
+    }
+
+    /// Converts captured data into another format. The returned data is tightly packed.
+    pub fn convert(&self, data: &CaptureData, to: PixelFormat) -> CaptureData {
+        let stride = data.width() * to.bytes_per_pixel();
+        let mut bits = vec![0; stride * data.height()];
+        if stride > 0 {
+            for (src, dst) in data.rows().zip(bits.chunks_exact_mut(stride)) {
+                self.convert_row(src, data.format(), dst, to);
+            }
+        }
+        CaptureData::from_vec(bits, data.width(), data.height(), to)
+    }
+}
+
+/// Converts \[B, G, R] pixels into \[R, G, B] pixels (or the other way around), see [`Converter::bgr24_to_rgb24`].
+pub fn bgr24_to_rgb24(src: &[u8], dst: &mut [u8]) {
+    Converter::new().bgr24_to_rgb24(src, dst)
+}
+
+/// Converts \[B, G, R] pixels into opaque \[R, G, B, A] pixels, see [`Converter::bgr24_to_rgba32`].
+pub fn bgr24_to_rgba32(src: &[u8], dst: &mut [u8]) {
+    Converter::new().bgr24_to_rgba32(src, dst)
+}
+
+/// Converts \[B, G, R, A] pixels into \[R, G, B, A] pixels (or the other way around), see [`Converter::bgra32_to_rgba32`].
+pub fn bgra32_to_rgba32(src: &[u8], dst: &mut [u8]) {
+    Converter::new().bgra32_to_rgba32(src, dst)
+}
+
+/// Converts \[B, G, R, A] pixels into their luma, see [`Converter::bgra32_to_gray8`].
+///
+/// # Examples
+///
+/// ```
+/// use qshot::convert::{self, Luma};
+///
+/// let mut gray = [0; 2];
+/// convert::bgra32_to_gray8(&[255, 255, 255, 255, 0, 0, 255, 255], &mut gray, Luma::Bt709);
+/// assert_eq!(gray, [255, 54]);
+/// ```
+pub fn bgra32_to_gray8(src: &[u8], dst: &mut [u8], luma: Luma) {
+    Converter::new().bgra32_to_gray8(src, dst, luma)
+}
+
+/// Multiplies the color channels of 32-bit pixels by their alpha channel, see [`Converter::premultiply_alpha`].
+pub fn premultiply_alpha(buf: &mut [u8]) {
+    Converter::new().premultiply_alpha(buf)
+}
+
+/// Reverts [`premultiply_alpha`], see [`Converter::unpremultiply_alpha`].
+pub fn unpremultiply_alpha(buf: &mut [u8]) {
+    Converter::new().unpremultiply_alpha(buf)
+}
+
+/// Converts captured data into another format, see [`Converter::convert`].
+pub fn convert(data: &CaptureData, to: PixelFormat) -> CaptureData {
+    Converter::new().convert(data, to)
+}
+
+/// The reference implementations, which the SIMD code also uses for the remainders.
+mod scalar {
+    #[inline(always)]
+    pub(super) fn map<const S: usize, const D: usize>(
+        src: &[u8],
+        dst: &mut [u8],
+        f: impl Fn(&[u8], &mut [u8]),
+    ) {
+        for (s, d) in src.chunks_exact(S).zip(dst.chunks_exact_mut(D)) {
+            f(s, d);
+        }
+    }
+
+    /// Computes the weighted sum of the first three bytes of a pixel, with the weights adding up to 256.
+    #[inline(always)]
+    pub(super) fn weigh(pixel: &[u8], weights: [u32; 3]) -> u8 {
+        let sum = weights[0] * pixel[0] as u32
+            + weights[1] * pixel[1] as u32
+            + weights[2] * pixel[2] as u32;
+        ((sum + 128) >> 8) as u8
+    }
+
+    pub(super) fn swap_rb24(src: &[u8], dst: &mut [u8]) {
+        map::<3, 3>(src, dst, |s, d| d.copy_from_slice(&[s[2], s[1], s[0]]))
+    }
+
+    pub(super) fn expand24(src: &[u8], dst: &mut [u8], swap: bool) {
+        if swap {
+            map::<3, 4>(src, dst, |s, d| d.copy_from_slice(&[s[2], s[1], s[0], 255]))
+        } else {
+            map::<3, 4>(src, dst, |s, d| d.copy_from_slice(&[s[0], s[1], s[2], 255]))
+        }
+    }
+
+    pub(super) fn swap_rb32(src: &[u8], dst: &mut [u8]) {
+        map::<4, 4>(src, dst, |s, d| {
+            d.copy_from_slice(&[s[2], s[1], s[0], s[3]])
+        })
+    }
+
+    pub(super) fn gray32(src: &[u8], dst: &mut [u8], weights: [u32; 3]) {
+        map::<4, 1>(src, dst, |s, d| d[0] = weigh(s, weights))
+    }
+
+    pub(super) fn premultiply(buf: &mut [u8]) {
+        for pixel in buf.chunks_exact_mut(4) {
+            let a = pixel[3] as u32;
+            for c in &mut pixel[..3] {
+                let t = *c as u32 * a + 128;
+                *c = ((t + (t >> 8)) >> 8) as u8;
+            }
+        }
+    }
+
+    pub(super) fn unpremultiply(buf: &mut [u8]) {
+        for pixel in buf.chunks_exact_mut(4) {
+            let a = pixel[3] as u32;
+            if a == 0 {
+                pixel.fill(0);
+                continue;
+            }
+            for c in &mut pixel[..3] {
+                *c = ((*c as u32 * 255 + a / 2) / a).min(255) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut seed = 0x2545_f491_u32;
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect()
+    }
+
+    /// Checks that every available instruction set computes the same output as the scalar code, for buffers of
+    /// various lengths starting at aligned and unaligned addresses.
+    fn check(name: &str, f: impl Fn(&Converter, &[u8], &mut [u8])) {
+        let data = random_bytes(4099);
+        let scalar = Converter::with_isa(Isa::Scalar).unwrap();
+        for isa in Isa::available() {
+            let simd = Converter::with_isa(isa).unwrap();
+            for start in 0..3 {
+                for len in [0, 1, 7, 48, 333, 1021, 4096] {
+                    let src = &data[start..start + len];
+                    let (mut a, mut b) = (vec![0; len * 4], vec![0; len * 4]);
+                    f(&scalar, src, &mut a);
+                    f(&simd, src, &mut b);
+                    assert_eq!(a, b, "{name} {isa:?} {start} {len}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn swap_rb24() {
+        check("bgr24_to_rgb24", |c, src, dst| c.bgr24_to_rgb24(src, dst));
+    }
+
+    #[test]
+    fn expand24() {
+        check("bgr24_to_rgba32", |c, src, dst| c.bgr24_to_rgba32(src, dst));
+        check("bgr24_to_bgra32", |c, src, dst| c.bgr24_to_bgra32(src, dst));
+    }
+
+    #[test]
+    fn swap_rb32() {
+        check("bgra32_to_rgba32", |c, src, dst| {
+            c.bgra32_to_rgba32(src, dst)
+        });
+    }
+
+    #[test]
+    fn gray32() {
+        for luma in [Luma::Bt601, Luma::Bt709] {
+            check("bgra32_to_gray8", |c, src, dst| {
+                c.bgra32_to_gray8(src, dst, luma)
+            });
+            check("rgba32_to_gray8", |c, src, dst| {
+                c.rgba32_to_gray8(src, dst, luma)
+            });
+        }
+    }
+
+    /// Returns every combination of a color and an alpha value, as \[B, G, R, A] pixels.
+    fn all_alphas() -> Vec<u8> {
+        (0..=255u8)
+            .flat_map(|a| (0..=255u8).flat_map(move |c| [c, 255 - c, c / 2, a]))
+            .collect()
+    }
+
+    #[test]
+    fn premultiply() {
+        check("premultiply_alpha", |c, src, dst| {
+            dst[..src.len()].copy_from_slice(src);
+            c.premultiply_alpha(&mut dst[..src.len()]);
+        });
+        let scalar = Converter::with_isa(Isa::Scalar).unwrap();
+        let mut expected = all_alphas();
+        scalar.premultiply_alpha(&mut expected);
+        for isa in Isa::available() {
+            let mut buf = all_alphas();
+            Converter::with_isa(isa)
+                .unwrap()
+                .premultiply_alpha(&mut buf);
+            assert!(buf == expected, "premultiply_alpha {isa:?}");
+        }
+        assert_eq!(expected[255 * 4 * 256 + 200 * 4], 200);
+        assert_eq!(&expected[128 * 4 * 256 + 255 * 4..][..4], [128, 0, 64, 128]);
+    }
+
+    #[test]
+    fn unpremultiply() {
+        check("unpremultiply_alpha", |c, src, dst| {
+            dst[..src.len()].copy_from_slice(src);
+            c.unpremultiply_alpha(&mut dst[..src.len()]);
+        });
+        let scalar = Converter::with_isa(Isa::Scalar).unwrap();
+        let mut expected = all_alphas();
+        scalar.unpremultiply_alpha(&mut expected);
+        for isa in Isa::available() {
+            let mut buf = all_alphas();
+            Converter::with_isa(isa)
+                .unwrap()
+                .unpremultiply_alpha(&mut buf);
+            assert!(buf == expected, "unpremultiply_alpha {isa:?}");
+        }
+        // Fully transparent pixels become transparent black, colors above the alpha saturate.
+        assert!(expected[..256 * 4].iter().all(|&b| b == 0));
+        assert_eq!(
+            &expected[128 * 4 * 256 + 255 * 4..][..4],
+            [255, 0, 253, 128]
+        );
+    }
+
+    #[test]
+    fn premultiply_round_trip() {
+        let mut buf = all_alphas();
+        premultiply_alpha(&mut buf);
+        unpremultiply_alpha(&mut buf);
+        for (pixel, original) in buf.chunks_exact(4).zip(all_alphas().chunks_exact(4)) {
+            let tolerance = 255 / original[3].max(1) as i32;
+            for c in 0..3 {
+                assert!((pixel[c] as i32 - original[c] as i32).abs() <= tolerance);
+            }
+        }
+    }
+}
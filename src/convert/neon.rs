@@ -0,0 +1,134 @@
+use std::arch::aarch64::*;
+
+use super::scalar;
+
+#[target_feature(enable = "neon")]
+pub(super) unsafe fn swap_rb24(src: &[u8], dst: &mut [u8]) {
+    let mut i = 0;
+    while i + 48 <= src.len() {
+        let v = vld3q_u8(src.as_ptr().add(i));
+        vst3q_u8(dst.as_mut_ptr().add(i), uint8x16x3_t(v.2, v.1, v.0));
+        i += 48;
+    }
+    scalar::swap_rb24(&src[i..], &mut dst[i..]);
+}
+
+#[target_feature(enable = "neon")]
+pub(super) unsafe fn expand24(src: &[u8], dst: &mut [u8], swap: bool) {
+    let alpha = vdupq_n_u8(255);
+    let (mut i, mut j) = (0, 0);
+    while i + 48 <= src.len() {
+        let v = vld3q_u8(src.as_ptr().add(i));
+        let out = if swap {
+            uint8x16x4_t(v.2, v.1, v.0, alpha)
+        } else {
+            uint8x16x4_t(v.0, v.1, v.2, alpha)
+        };
+        vst4q_u8(dst.as_mut_ptr().add(j), out);
+        i += 48;
+        j += 64;
+    }
+    scalar::expand24(&src[i..], &mut dst[j..], swap);
+}
+
+#[target_feature(enable = "neon")]
+pub(super) unsafe fn swap_rb32(src: &[u8], dst: &mut [u8]) {
+    let mut i = 0;
+    while i + 64 <= src.len() {
+        let v = vld4q_u8(src.as_ptr().add(i));
+        vst4q_u8(dst.as_mut_ptr().add(i), uint8x16x4_t(v.2, v.1, v.0, v.3));
+        i += 64;
+    }
+    scalar::swap_rb32(&src[i..], &mut dst[i..]);
+}
+
+#[target_feature(enable = "neon")]
+pub(super) unsafe fn gray32(src: &[u8], dst: &mut [u8], weights: [u32; 3]) {
+    let [w0, w1, w2] = weights.map(|w| vdup_n_u8(w as u8));
+    let (mut i, mut j) = (0, 0);
+    while i + 64 <= src.len() {
+        let v = vld4q_u8(src.as_ptr().add(i));
+        let lo = vmull_u8(vget_low_u8(v.0), w0);
+        let lo = vmlal_u8(lo, vget_low_u8(v.1), w1);
+        let lo = vmlal_u8(lo, vget_low_u8(v.2), w2);
+        let hi = vmull_u8(vget_high_u8(v.0), w0);
+        let hi = vmlal_u8(hi, vget_high_u8(v.1), w1);
+        let hi = vmlal_u8(hi, vget_high_u8(v.2), w2);
+        // The rounding narrowing shift computes (sum + 128) >> 8.
+        let y = vcombine_u8(vrshrn_n_u16::<8>(lo), vrshrn_n_u16::<8>(hi));
+        vst1q_u8(dst.as_mut_ptr().add(j), y);
+        i += 64;
+        j += 16;
+    }
+    scalar::gray32(&src[i..], &mut dst[j..], weights);
+}
+
+/// Computes `c * a / 255` rounded to the nearest integer, like the scalar code.
+#[inline(always)]
+unsafe fn mul_div255(c: uint8x16_t, a: uint8x16_t) -> uint8x16_t {
+    let lo = vmull_u8(vget_low_u8(c), vget_low_u8(a));
+    let hi = vmull_u8(vget_high_u8(c), vget_high_u8(a));
+    let lo = vrshrn_n_u16::<8>(vrsraq_n_u16::<8>(lo, lo));
+    let hi = vrshrn_n_u16::<8>(vrsraq_n_u16::<8>(hi, hi));
+    vcombine_u8(lo, hi)
+}
+
+#[target_feature(enable = "neon")]
+pub(super) unsafe fn premultiply(buf: &mut [u8]) {
+    let mut i = 0;
+    while i + 64 <= buf.len() {
+        let v = vld4q_u8(buf.as_ptr().add(i));
+        let out = uint8x16x4_t(
+            mul_div255(v.0, v.3),
+            mul_div255(v.1, v.3),
+            mul_div255(v.2, v.3),
+            v.3,
+        );
+        vst4q_u8(buf.as_mut_ptr().add(i), out);
+        i += 64;
+    }
+    scalar::premultiply(&mut buf[i..]);
+}
+
+/// Computes `(c * 255 + a / 2) / a` saturated to 255 for 8 pixels, like the scalar code.
+///
+/// The division is done in single precision, which is exact for these small integers.
+#[inline(always)]
+unsafe fn div_alpha(c: uint8x8_t, a: uint8x8_t) -> uint8x8_t {
+    let n = vmlal_u8(vmovl_u8(vshr_n_u8::<1>(a)), c, vdup_n_u8(255));
+    let a = vmovl_u8(a);
+    let quotient = |n: uint16x4_t, a: uint16x4_t| {
+        let q = vdivq_f32(vcvtq_f32_u32(vmovl_u16(n)), vcvtq_f32_u32(vmovl_u16(a)));
+        vqmovn_u32(vcvtq_u32_f32(q))
+    };
+    let lo = quotient(vget_low_u16(n), vget_low_u16(a));
+    let hi = quotient(vget_high_u16(n), vget_high_u16(a));
+    vqmovn_u16(vcombine_u16(lo, hi))
+}
+
+/// Unpremultiplies a channel of 16 pixels, the channels of fully transparent pixels become zero.
+#[inline(always)]
+unsafe fn unpremultiply16(c: uint8x16_t, a: uint8x16_t) -> uint8x16_t {
+    let out = vcombine_u8(
+        div_alpha(vget_low_u8(c), vget_low_u8(a)),
+        div_alpha(vget_high_u8(c), vget_high_u8(a)),
+    );
+    vbicq_u8(out, vceqzq_u8(a))
+}
+
+#[target_feature(enable = "neon")]
+pub(super) unsafe fn unpremultiply(buf: &mut [u8]) {
+    let mut i = 0;
+    while i + 64 <= buf.len() {
+        let v = vld4q_u8(buf.as_ptr().add(i));
+        let out = uint8x16x4_t(
+            unpremultiply16(v.0, v.3),
+            unpremultiply16(v.1, v.3),
+            unpremultiply16(v.2, v.3),
+            v.3,
+        );
+        vst4q_u8(buf.as_mut_ptr().add(i), out);
+        i += 64;
+    }
+    scalar::unpremultiply(&mut buf[i..]);
+}
@@ -0,0 +1,324 @@
+use std::arch::x86_64::*;
+
+use super::scalar;
+
+#[inline(always)]
+unsafe fn load128(src: &[u8], i: usize) -> __m128i {
+    debug_assert!(i + 16 <= src.len());
+    _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i)
+}
+
+#[inline(always)]
+unsafe fn store128(dst: &mut [u8], i: usize, v: __m128i) {
+    debug_assert!(i + 16 <= dst.len());
+    _mm_storeu_si128(dst.as_mut_ptr().add(i) as *mut __m128i, v)
+}
+
+#[inline(always)]
+unsafe fn load256(src: &[u8], i: usize) -> __m256i {
+    debug_assert!(i + 32 <= src.len());
+    _mm256_loadu_si256(src.as_ptr().add(i) as *const __m256i)
+}
+
+#[inline(always)]
+unsafe fn store256(dst: &mut [u8], i: usize, v: __m256i) {
+    debug_assert!(i + 32 <= dst.len());
+    _mm256_storeu_si256(dst.as_mut_ptr().add(i) as *mut __m256i, v)
+}
+
+/// Swaps the first and third byte of the 5 pixels in the low 15 bytes, the last byte is kept.
+#[inline(always)]
+unsafe fn swap_rb24_mask() -> __m128i {
+    _mm_setr_epi8(2, 1, 0, 5, 4, 3, 8, 7, 6, 11, 10, 9, 14, 13, 12, 15)
+}
+
+/// Expands 4 pixels in the low 12 bytes to 4 bytes each, leaving the alpha byte zeroed.
+#[inline(always)]
+unsafe fn expand24_mask(swap: bool) -> __m128i {
+    if swap {
+        _mm_setr_epi8(2, 1, 0, -1, 5, 4, 3, -1, 8, 7, 6, -1, 11, 10, 9, -1)
+    } else {
+        _mm_setr_epi8(0, 1, 2, -1, 3, 4, 5, -1, 6, 7, 8, -1, 9, 10, 11, -1)
+    }
+}
+
+#[target_feature(enable = "ssse3")]
+pub(super) unsafe fn swap_rb24_ssse3(src: &[u8], dst: &mut [u8]) {
+    let mask = swap_rb24_mask();
+    let mut i = 0;
+    // Every store writes one byte too many, it is overwritten by the next iteration.
+    while i + 16 <= src.len() {
+        store128(dst, i, _mm_shuffle_epi8(load128(src, i), mask));
+        i += 15;
+    }
+    scalar::swap_rb24(&src[i..], &mut dst[i..]);
+}
+
+#[target_feature(enable = "avx2")]
+pub(super) unsafe fn swap_rb24_avx2(src: &[u8], dst: &mut [u8]) {
+    let mask = _mm256_setr_epi8(
+        2, 1, 0, 5, 4, 3, 8, 7, 6, 11, 10, 9, -1, -1, -1, -1, //
+        2, 1, 0, 5, 4, 3, 8, 7, 6, 11, 10, 9, -1, -1, -1, -1,
+    );
+    // Moves 4 pixels into each 128-bit lane and back again.
+    let spread = _mm256_setr_epi32(0, 1, 2, 3, 3, 4, 5, 6);
+    let gather = _mm256_setr_epi32(0, 1, 2, 4, 5, 6, 7, 7);
+    let mut i = 0;
+    // Every store writes 8 bytes too many, they are overwritten by the next iteration.
+    while i + 32 <= src.len() {
+        let v = _mm256_permutevar8x32_epi32(load256(src, i), spread);
+        let v = _mm256_shuffle_epi8(v, mask);
+        store256(dst, i, _mm256_permutevar8x32_epi32(v, gather));
+        i += 24;
+    }
+    swap_rb24_ssse3(&src[i..], &mut dst[i..]);
+}
+
+#[target_feature(enable = "ssse3")]
+pub(super) unsafe fn expand24_ssse3(src: &[u8], dst: &mut [u8], swap: bool) {
+    let mask = expand24_mask(swap);
+    let alpha = _mm_set1_epi32(0xff00_0000_u32 as i32);
+    let (mut i, mut j) = (0, 0);
+    while i + 16 <= src.len() {
+        let v = _mm_shuffle_epi8(load128(src, i), mask);
+        store128(dst, j, _mm_or_si128(v, alpha));
+        i += 12;
+        j += 16;
+    }
+    scalar::expand24(&src[i..], &mut dst[j..], swap);
+}
+
+#[target_feature(enable = "avx2")]
+pub(super) unsafe fn expand24_avx2(src: &[u8], dst: &mut [u8], swap: bool) {
+    let mask = _mm256_broadcastsi128_si256(expand24_mask(swap));
+    let alpha = _mm256_set1_epi32(0xff00_0000_u32 as i32);
+    let spread = _mm256_setr_epi32(0, 1, 2, 3, 3, 4, 5, 6);
+    let (mut i, mut j) = (0, 0);
+    while i + 32 <= src.len() {
+        let v = _mm256_permutevar8x32_epi32(load256(src, i), spread);
+        let v = _mm256_shuffle_epi8(v, mask);
+        store256(dst, j, _mm256_or_si256(v, alpha));
+        i += 24;
+        j += 32;
+    }
+    expand24_ssse3(&src[i..], &mut dst[j..], swap);
+}
+
+#[target_feature(enable = "sse2")]
+pub(super) unsafe fn swap_rb32_sse2(src: &[u8], dst: &mut [u8]) {
+    let ga = _mm_set1_epi32(0xff00_ff00_u32 as i32);
+    let mut i = 0;
+    while i + 16 <= src.len() {
+        let v = load128(src, i);
+        let rb = _mm_andnot_si128(ga, v);
+        let rb = _mm_or_si128(_mm_slli_epi32(rb, 16), _mm_srli_epi32(rb, 16));
+        store128(dst, i, _mm_or_si128(_mm_and_si128(v, ga), rb));
+        i += 16;
+    }
+    scalar::swap_rb32(&src[i..], &mut dst[i..]);
+}
+
+#[target_feature(enable = "avx2")]
+pub(super) unsafe fn swap_rb32_avx2(src: &[u8], dst: &mut [u8]) {
+    let mask = _mm256_setr_epi8(
+        2, 1, 0, 3, 6, 5, 4, 7, 10, 9, 8, 11, 14, 13, 12, 15, //
+        2, 1, 0, 3, 6, 5, 4, 7, 10, 9, 8, 11, 14, 13, 12, 15,
+    );
+    let mut i = 0;
+    while i + 32 <= src.len() {
+        store256(dst, i, _mm256_shuffle_epi8(load256(src, i), mask));
+        i += 32;
+    }
+    swap_rb32_sse2(&src[i..], &mut dst[i..]);
+}
+
+/// Computes the luma of 4 pixels, leaving it in the low byte of every 32-bit lane.
+#[inline(always)]
+unsafe fn luma4(v: __m128i, w: [__m128i; 3]) -> __m128i {
+    // The products and their sum never exceed 16 bits, so 16-bit multiplies are enough.
+    let byte = _mm_set1_epi32(0xff);
+    let c0 = _mm_mullo_epi16(_mm_and_si128(v, byte), w[0]);
+    let c1 = _mm_mullo_epi16(_mm_and_si128(_mm_srli_epi32(v, 8), byte), w[1]);
+    let c2 = _mm_mullo_epi16(_mm_and_si128(_mm_srli_epi32(v, 16), byte), w[2]);
+    let sum = _mm_add_epi32(
+        _mm_add_epi32(c0, c1),
+        _mm_add_epi32(c2, _mm_set1_epi32(128)),
+    );
+    _mm_srli_epi32(sum, 8)
+}
+
+#[target_feature(enable = "sse2")]
+pub(super) unsafe fn gray32_sse2(src: &[u8], dst: &mut [u8], weights: [u32; 3]) {
+    let w = weights.map(|w| _mm_set1_epi32(w as i32));
+    let (mut i, mut j) = (0, 0);
+    while i + 64 <= src.len() {
+        let y0 = luma4(load128(src, i), w);
+        let y1 = luma4(load128(src, i + 16), w);
+        let y2 = luma4(load128(src, i + 32), w);
+        let y3 = luma4(load128(src, i + 48), w);
+        let y = _mm_packus_epi16(_mm_packs_epi32(y0, y1), _mm_packs_epi32(y2, y3));
+        store128(dst, j, y);
+        i += 64;
+        j += 16;
+    }
+    scalar::gray32(&src[i..], &mut dst[j..], weights);
+}
+
+#[inline(always)]
+unsafe fn luma8(v: __m256i, w: [__m256i; 3]) -> __m256i {
+    let byte = _mm256_set1_epi32(0xff);
+    let c0 = _mm256_mullo_epi16(_mm256_and_si256(v, byte), w[0]);
+    let c1 = _mm256_mullo_epi16(_mm256_and_si256(_mm256_srli_epi32(v, 8), byte), w[1]);
+    let c2 = _mm256_mullo_epi16(_mm256_and_si256(_mm256_srli_epi32(v, 16), byte), w[2]);
+    let sum = _mm256_add_epi32(
+        _mm256_add_epi32(c0, c1),
+        _mm256_add_epi32(c2, _mm256_set1_epi32(128)),
+    );
+    _mm256_srli_epi32(sum, 8)
+}
+
+#[target_feature(enable = "avx2")]
+pub(super) unsafe fn gray32_avx2(src: &[u8], dst: &mut [u8], weights: [u32; 3]) {
+    let w = weights.map(|w| _mm256_set1_epi32(w as i32));
+    // The packs work within 128-bit lanes, this puts the 32-bit groups back in order.
+    let order = _mm256_setr_epi32(0, 4, 1, 5, 2, 6, 3, 7);
+    let (mut i, mut j) = (0, 0);
+    while i + 128 <= src.len() {
+        let y0 = luma8(load256(src, i), w);
+        let y1 = luma8(load256(src, i + 32), w);
+        let y2 = luma8(load256(src, i + 64), w);
+        let y3 = luma8(load256(src, i + 96), w);
+        let y = _mm256_packus_epi16(_mm256_packs_epi32(y0, y1), _mm256_packs_epi32(y2, y3));
+        store256(dst, j, _mm256_permutevar8x32_epi32(y, order));
+        i += 128;
+        j += 32;
+    }
+    gray32_sse2(&src[i..], &mut dst[j..], weights);
+}
+
+/// Premultiplies 2 pixels widened to 16 bits per channel.
+#[inline(always)]
+unsafe fn premultiply2(v: __m128i) -> __m128i {
+    let alpha = _mm_shufflehi_epi16(_mm_shufflelo_epi16(v, 0xff), 0xff);
+    let t = _mm_add_epi16(_mm_mullo_epi16(v, alpha), _mm_set1_epi16(128));
+    let t = _mm_srli_epi16(_mm_add_epi16(t, _mm_srli_epi16(t, 8)), 8);
+    let mask = _mm_setr_epi16(0, 0, 0, -1, 0, 0, 0, -1);
+    _mm_or_si128(_mm_andnot_si128(mask, t), _mm_and_si128(mask, v))
+}
+
+#[target_feature(enable = "sse2")]
+pub(super) unsafe fn premultiply_sse2(buf: &mut [u8]) {
+    let zero = _mm_setzero_si128();
+    let mut i = 0;
+    while i + 16 <= buf.len() {
+        let v = load128(buf, i);
+        let lo = premultiply2(_mm_unpacklo_epi8(v, zero));
+        let hi = premultiply2(_mm_unpackhi_epi8(v, zero));
+        store128(buf, i, _mm_packus_epi16(lo, hi));
+        i += 16;
+    }
+    scalar::premultiply(&mut buf[i..]);
+}
+
+#[inline(always)]
+unsafe fn premultiply4(v: __m256i) -> __m256i {
+    let alpha = _mm256_shufflehi_epi16(_mm256_shufflelo_epi16(v, 0xff), 0xff);
+    let t = _mm256_add_epi16(_mm256_mullo_epi16(v, alpha), _mm256_set1_epi16(128));
+    let t = _mm256_srli_epi16(_mm256_add_epi16(t, _mm256_srli_epi16(t, 8)), 8);
+    let mask = _mm256_setr_epi16(0, 0, 0, -1, 0, 0, 0, -1, 0, 0, 0, -1, 0, 0, 0, -1);
+    _mm256_or_si256(_mm256_andnot_si256(mask, t), _mm256_and_si256(mask, v))
+}
+
+#[target_feature(enable = "avx2")]
+pub(super) unsafe fn premultiply_avx2(buf: &mut [u8]) {
+    let zero = _mm256_setzero_si256();
+    let mut i = 0;
+    // Unpacking and packing both work within 128-bit lanes, so the pixel order is preserved.
+    while i + 32 <= buf.len() {
+        let v = load256(buf, i);
+        let lo = premultiply4(_mm256_unpacklo_epi8(v, zero));
+        let hi = premultiply4(_mm256_unpackhi_epi8(v, zero));
+        store256(buf, i, _mm256_packus_epi16(lo, hi));
+        i += 32;
+    }
+    premultiply_sse2(&mut buf[i..]);
+}
+
+/// Unpremultiplies a single pixel widened to 32 bits per channel.
+///
+/// The division is done in single precision, which is exact for these small integers. A zero alpha makes the
+/// conversion return `i32::MIN`, which the packs later saturate to zero.
+#[inline(always)]
+unsafe fn unpremultiply1(v: __m128i) -> __m128i {
+    let alpha = _mm_shuffle_epi32(v, 0xff);
+    let n = _mm_sub_epi32(_mm_slli_epi32(v, 8), v);
+    let n = _mm_add_epi32(n, _mm_srli_epi32(alpha, 1));
+    _mm_cvttps_epi32(_mm_div_ps(_mm_cvtepi32_ps(n), _mm_cvtepi32_ps(alpha)))
+}
+
+#[target_feature(enable = "sse2")]
+pub(super) unsafe fn unpremultiply_sse2(buf: &mut [u8]) {
+    let zero = _mm_setzero_si128();
+    let alpha = _mm_set1_epi32(0xff00_0000_u32 as i32);
+    let mut i = 0;
+    while i + 16 <= buf.len() {
+        let v = load128(buf, i);
+        let lo = _mm_unpacklo_epi8(v, zero);
+        let hi = _mm_unpackhi_epi8(v, zero);
+        let lo = _mm_packs_epi32(
+            unpremultiply1(_mm_unpacklo_epi16(lo, zero)),
+            unpremultiply1(_mm_unpackhi_epi16(lo, zero)),
+        );
+        let hi = _mm_packs_epi32(
+            unpremultiply1(_mm_unpacklo_epi16(hi, zero)),
+            unpremultiply1(_mm_unpackhi_epi16(hi, zero)),
+        );
+        let out = _mm_packus_epi16(lo, hi);
+        store128(
+            buf,
+            i,
+            _mm_or_si128(_mm_andnot_si128(alpha, out), _mm_and_si128(alpha, v)),
+        );
+        i += 16;
+    }
+    scalar::unpremultiply(&mut buf[i..]);
+}
+
+#[inline(always)]
+unsafe fn unpremultiply2(v: __m256i) -> __m256i {
+    let alpha = _mm256_shuffle_epi32(v, 0xff);
+    let n = _mm256_sub_epi32(_mm256_slli_epi32(v, 8), v);
+    let n = _mm256_add_epi32(n, _mm256_srli_epi32(alpha, 1));
+    _mm256_cvttps_epi32(_mm256_div_ps(
+        _mm256_cvtepi32_ps(n),
+        _mm256_cvtepi32_ps(alpha),
+    ))
+}
+
+#[target_feature(enable = "avx2")]
+pub(super) unsafe fn unpremultiply_avx2(buf: &mut [u8]) {
+    let zero = _mm256_setzero_si256();
+    let alpha = _mm256_set1_epi32(0xff00_0000_u32 as i32);
+    let mut i = 0;
+    while i + 32 <= buf.len() {
+        let v = load256(buf, i);
+        let lo = _mm256_unpacklo_epi8(v, zero);
+        let hi = _mm256_unpackhi_epi8(v, zero);
+        let lo = _mm256_packs_epi32(
+            unpremultiply2(_mm256_unpacklo_epi16(lo, zero)),
+            unpremultiply2(_mm256_unpackhi_epi16(lo, zero)),
+        );
+        let hi = _mm256_packs_epi32(
+            unpremultiply2(_mm256_unpacklo_epi16(hi, zero)),
+            unpremultiply2(_mm256_unpackhi_epi16(hi, zero)),
+        );
+        let out = _mm256_packus_epi16(lo, hi);
+        store256(
+            buf,
+            i,
+            _mm256_or_si256(_mm256_andnot_si256(alpha, out), _mm256_and_si256(alpha, v)),
+        );
+        i += 32;
+    }
+    unpremultiply_sse2(&mut buf[i..]);
+}
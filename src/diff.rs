@@ -0,0 +1,245 @@
+//! Detection of the parts of an image that changed between two captures.
+//!
+//! A [`Differ`] splits both images into square tiles and compares them tile by tile, so only tiles containing a
+//! change have to be processed or uploaded. Rows that are identical in both images are skipped with a single
+//! comparison, which makes comparing captures of a mostly static screen cheap.
+//!
+//! All functions take anything that converts into an [`ImageView`], e.g. the [`CaptureData`](crate::CaptureData)
+//! returned by [`CaptureManager::capture`](crate::CaptureManager::capture) or a [`Frame`](crate::Frame).
+
+use crate::rect::Rect;
+use crate::view::ImageView;
+
+/// Compares images tile by tile.
+///
+/// Two pixels are considered different if any of their channels, including alpha, differs by more than the
+/// [`threshold`](Differ::threshold), which defaults to 0. Tiles are 32 pixels wide and high by default, tiles at the
+/// right and bottom edges are cut off by the edges of the image.
+///
+/// # Examples
+///
+/// ```
+/// use qshot::diff::Differ;
+/// use qshot::{CaptureManager, Rect, Script, SyntheticBackend};
+///
+/// // A 10x10 square moving 5 pixels to the right every frame.
+/// let script = Script::new((100, 100)).moving_rect([0; 3], [255; 3], (10, 10), (0, 0), (5, 0), 2);
+/// let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 100, 100)).unwrap();
+/// let before = manager.capture_frame().unwrap();
+/// let after = manager.capture().unwrap();
+///
+/// let differ = Differ::new().tile_size(8);
+/// assert!(differ.changed(&before, &after));
+/// assert_eq!(differ.dirty_rects(&before, &after), vec![Rect::new(0, 0, 16, 16)]);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Differ {
+    tile_size: usize,
+    threshold: u8,
+}
+
+impl Default for Differ {
+    fn default() -> Differ {
+        Differ::new()
+    }
+}
+
+impl Differ {
+    /// Creates a differ with 32x32 pixel tiles that reports any change.
+    pub fn new() -> Differ {
+        Differ {
+            tile_size: 32,
+            threshold: 0,
+        }
+    }
+
+    /// Sets the width and height of the tiles in pixels.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    pub fn tile_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "tile size must not be zero");
+        self.tile_size = size;
+        self
+    }
+
+    /// Sets the largest difference of a single channel that is not considered a change.
+    ///
+    /// A small threshold ignores noise like dithering or compression artifacts of video.
+    pub fn threshold(mut self, threshold: u8) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Returns `true` if any pixel differs between the images.
+    ///
+    /// This stops at the first difference and doesn't look at tiles.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the images differ in size or format.
+    pub fn changed<'a, 'b>(
+        &self,
+        a: impl Into<ImageView<'a>>,
+        b: impl Into<ImageView<'b>>,
+    ) -> bool {
+        let (a, b) = (a.into(), b.into());
+        check_shape(&a, &b);
+        a.rows()
+            .zip(b.rows())
+            .any(|(a, b)| differs(a, b, self.threshold))
+    }
+
+    /// Returns the tiles containing at least one changed pixel, from left to right and top to bottom.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the images differ in size or format.
+    pub fn changed_tiles<'a, 'b>(
+        &self,
+        a: impl Into<ImageView<'a>>,
+        b: impl Into<ImageView<'b>>,
+    ) -> Vec<Rect> {
+        let (a, b) = (a.into(), b.into());
+        let bounds = a.bounds();
+        let mut tiles = Vec::new();
+        self.scan(a, b, |band, dirty| {
+            for (col, _) in dirty.iter().enumerate().filter(|(_, &dirty)| dirty) {
+                tiles.push(self.tile(band, col, col + 1).clamp(&bounds));
+            }
+        });
+        tiles
+    }
+
+    /// Returns the changed tiles merged into as few rectangles as possible.
+    ///
+    /// Horizontally adjacent changed tiles are merged first, rectangles spanning the same columns are then merged
+    /// with the rectangles right below them. The rectangles don't overlap and cover exactly the changed tiles.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the images differ in size or format.
+    pub fn dirty_rects<'a, 'b>(
+        &self,
+        a: impl Into<ImageView<'a>>,
+        b: impl Into<ImageView<'b>>,
+    ) -> Vec<Rect> {
+        let (a, b) = (a.into(), b.into());
+        let bounds = a.bounds();
+        let mut rects: Vec<Rect> = Vec::new();
+        // The rectangles ending right above the current row of tiles, which may still grow downwards.
+        let mut open: Vec<usize> = Vec::new();
+        let mut next_open = Vec::new();
+        self.scan(a, b, |band, dirty| {
+            let mut col = 0;
+            while col < dirty.len() {
+                if !dirty[col] {
+                    col += 1;
+                    continue;
+                }
+                let start = col;
+                while col < dirty.len() && dirty[col] {
+                    col += 1;
+                }
+                let run = self.tile(band, start, col).clamp(&bounds);
+                let above = open
+                    .iter()
+                    .copied()
+                    .find(|&i| (rects[i].x, rects[i].width) == (run.x, run.width));
+                match above {
+                    Some(i) => {
+                        rects[i].height += run.height;
+                        next_open.push(i);
+                    }
+                    None => {
+                        next_open.push(rects.len());
+                        rects.push(run);
+                    }
+                }
+            }
+            std::mem::swap(&mut open, &mut next_open);
+            next_open.clear();
+        });
+        rects
+    }
+
+    /// Compares the images and calls `band` with the index of every row of tiles and whether each tile in it changed.
+    fn scan(&self, a: ImageView, b: ImageView, mut band: impl FnMut(usize, &[bool])) {
+        check_shape(&a, &b);
+        let bpp = a.format().bytes_per_pixel();
+        let span = self.tile_size * bpp;
+        let cols = a.width().div_ceil(self.tile_size);
+        let mut dirty = vec![false; cols];
+        for (index, first) in (0..a.height()).step_by(self.tile_size).enumerate() {
+            dirty.fill(false);
+            for y in first..(first + self.tile_size).min(a.height()) {
+                let (a, b) = (a.row(y), b.row(y));
+                if !differs(a, b, self.threshold) {
+                    continue;
+                }
+                let tiles = a.chunks(span).zip(b.chunks(span));
+                for (dirty, (a, b)) in dirty.iter_mut().zip(tiles) {
+                    *dirty = *dirty || differs(a, b, self.threshold);
+                }
+            }
+            band(index, &dirty);
+        }
+    }
+
+    /// Returns the area covered by the tiles `start..end` of the `band`-th row of tiles, ignoring the edges of the
+    /// image.
+    fn tile(&self, band: usize, start: usize, end: usize) -> Rect {
+        let size = self.tile_size;
+        Rect::new(
+            (start * size) as i32,
+            (band * size) as i32,
+            ((end - start) * size) as i32,
+            size as i32,
+        )
+    }
+}
+
+fn check_shape(a: &ImageView, b: &ImageView) {
+    assert!(
+        (a.width(), a.height(), a.format()) == (b.width(), b.height(), b.format()),
+        "images differ in size or format"
+    );
+}
+
+/// Returns `true` if any byte differs by more than `threshold`.
+fn differs(a: &[u8], b: &[u8], threshold: u8) -> bool {
+    if threshold == 0 {
+        return a != b;
+    }
+    // Finding the largest difference of a whole chunk, rather than stopping at the first one, can be vectorized.
+    a.chunks(64).zip(b.chunks(64)).any(|(a, b)| {
+        let largest = a
+            .iter()
+            .zip(b)
+            .fold(0, |max, (a, b)| max.max(a.abs_diff(*b)));
+        largest > threshold
+    })
+}
+
+/// Returns `true` if any pixel differs between the images, see [`Differ::changed`].
+///
+/// # Panics
+///
+/// Panics if the images differ in size or format.
+///
+/// # Examples
+///
+/// ```
+/// use qshot::{diff, Frame, PixelFormat};
+///
+/// let a = Frame::new(64, 64, PixelFormat::Bgra32);
+/// let mut b = a.clone();
+/// assert!(!diff::changed(&a, &b));
+///
+/// b.bits_mut()[1000] ^= 1;
+/// assert!(diff::changed(&a, &b));
+/// ```
+pub fn changed<'a, 'b>(a: impl Into<ImageView<'a>>, b: impl Into<ImageView<'b>>) -> bool {
+    Differ::new().changed(a, b)
+}
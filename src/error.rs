@@ -0,0 +1,104 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
+use crate::rect::Rect;
+
+/// The error type returned by [`CaptureManager`](crate::CaptureManager) and every [`CaptureBackend`](crate::CaptureBackend).
+///
+/// Backend-specific errors, e.g. [`X11Error`](crate::X11Error), are kept as the [`source`](StdError::source) of the
+/// error, so they can still be inspected with [`downcast_ref`](StdError::downcast_ref).
+///
+/// # Examples
+///
+/// ```
+/// use std::error::Error as _;
+/// use qshot::{CaptureManager, Error, Rect, Script, SyntheticBackend, SyntheticError};
+///
+/// let script = Script::new((100, 100)).error("device lost");
+/// let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 10, 10)).unwrap();
+///
+/// let err = manager.capture_frame().unwrap_err();
+/// assert!(matches!(err, Error::Platform(_)));
+///
+/// let source = err.source().unwrap().downcast_ref::<SyntheticError>().unwrap();
+/// assert_eq!(source, &SyntheticError::Injected("device lost".to_owned()));
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The captured area is empty, i.e. its width or height is not positive.
+    InvalidRegion(Rect),
+    /// The captured area lies entirely outside of the area the backend can capture.
+    OffScreen {
+        /// The rejected area.
+        region: Rect,
+        /// The area the backend can capture, see [`CaptureBackend::bounds`](crate::CaptureBackend::bounds).
+        bounds: Rect,
+    },
+    /// The targeted window no longer exists.
+    WindowGone,
+    /// The backend can't be used, e.g. because there is no display server to connect to.
+    BackendUnavailable(Box<dyn StdError + Send + Sync>),
+    /// The pixel layout of the target is not supported.
+    UnsupportedFormat(String),
+    /// Reading from or writing to a device or file failed.
+    Io(io::Error),
+    /// The platform reported an error, e.g. a failed system call.
+    Platform(Box<dyn StdError + Send + Sync>),
+}
+
+impl Error {
+    /// Wraps a backend-specific error as [`Error::Platform`].
+    pub(crate) fn platform(err: impl StdError + Send + Sync + 'static) -> Error {
+        Error::Platform(Box::new(err))
+    }
+
+    /// Wraps a backend-specific error as [`Error::BackendUnavailable`].
+    pub(crate) fn unavailable(err: impl StdError + Send + Sync + 'static) -> Error {
+        Error::BackendUnavailable(Box::new(err))
+    }
+
+    /// Checks that the area is not empty and, if the bounds are known, that at least part of it lies within them.
+    pub(crate) fn check_region(region: Rect, bounds: Option<Rect>) -> Result<(), Error> {
+        if region.is_empty() {
+            return Err(Error::InvalidRegion(region));
+        }
+        match bounds {
+            Some(bounds) if !region.intersects(&bounds) => Err(Error::OffScreen { region, bounds }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidRegion(region) => write!(f, "empty capture area: {region}"),
+            Error::OffScreen { region, bounds } => {
+                write!(f, "capture area {region} lies outside of {bounds}")
+            }
+            Error::WindowGone => write!(f, "the targeted window no longer exists"),
+            Error::BackendUnavailable(err) => write!(f, "capture backend unavailable: {err}"),
+            Error::UnsupportedFormat(what) => write!(f, "unsupported pixel format: {what}"),
+            Error::Io(err) => write!(f, "I/O error: {err}"),
+            Error::Platform(err) => write!(f, "platform error: {err}"),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::BackendUnavailable(err) | Error::Platform(err) => Some(&**err),
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
@@ -0,0 +1,561 @@
+use std::cell::RefCell;
+use std::ffi::c_int;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+use crate::backend::CaptureBackend;
+use crate::capture::CaptureData;
+use crate::error::Error;
+use crate::format::{PixelFormat, OPAQUE_BLACK};
+use crate::frame::Frame;
+use crate::rect::Rect;
+
+mod sys {
+    use std::ffi::{c_int, c_ulong};
+
+    pub const FBIOGET_VSCREENINFO: c_ulong = 0x4600;
+    pub const FBIOGET_FSCREENINFO: c_ulong = 0x4602;
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct FbBitfield {
+        pub offset: u32,
+        pub length: u32,
+        pub msb_right: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct FbVarScreeninfo {
+        pub xres: u32,
+        pub yres: u32,
+        pub xres_virtual: u32,
+        pub yres_virtual: u32,
+        pub xoffset: u32,
+        pub yoffset: u32,
+        pub bits_per_pixel: u32,
+        pub grayscale: u32,
+        pub red: FbBitfield,
+        pub green: FbBitfield,
+        pub blue: FbBitfield,
+        pub transp: FbBitfield,
+        pub nonstd: u32,
+        pub activate: u32,
+        pub height: u32,
+        pub width: u32,
+        pub accel_flags: u32,
+        pub pixclock: u32,
+        pub left_margin: u32,
+        pub right_margin: u32,
+        pub upper_margin: u32,
+        pub lower_margin: u32,
+        pub hsync_len: u32,
+        pub vsync_len: u32,
+        pub sync: u32,
+        pub vmode: u32,
+        pub rotate: u32,
+        pub colorspace: u32,
+        pub reserved: [u32; 4],
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct FbFixScreeninfo {
+        pub id: [u8; 16],
+        pub smem_start: c_ulong,
+        pub smem_len: u32,
+        pub kind: u32,
+        pub type_aux: u32,
+        pub visual: u32,
+        pub xpanstep: u16,
+        pub ypanstep: u16,
+        pub ywrapstep: u16,
+        pub line_length: u32,
+        pub mmio_start: c_ulong,
+        pub mmio_len: u32,
+        pub accel: u32,
+        pub capabilities: u16,
+        pub reserved: [u16; 2],
+    }
+
+    extern "C" {
+        pub fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+}
+
+/// The position and size of a color channel within a framebuffer pixel, in bits.
+///
+/// Pixels are read as little-endian integers of [`FbLayout::bits_per_pixel`] bits, like the `fb_bitfield`s of the
+/// kernel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Channel {
+    /// Position of the least significant bit of the channel, counted from the least significant bit of the pixel.
+    pub offset: u32,
+    /// Number of bits of the channel, 0 if the channel is absent.
+    pub length: u32,
+}
+
+impl Channel {
+    /// Extracts the channel from a pixel value and scales it to 8 bits.
+    fn extract(self, pixel: u32) -> u8 {
+        if self.length == 0 || self.offset >= 32 {
+            return 0;
+        }
+        let length = self.length.min(32 - self.offset);
+        let value = (pixel >> self.offset) & (u32::MAX >> (32 - length));
+        if length >= 8 {
+            (value >> (length - 8)) as u8
+        } else {
+            (value * 255 / ((1 << length) - 1)) as u8
+        }
+    }
+}
+
+/// Describes how the pixels of a framebuffer are laid out in memory.
+///
+/// For framebuffer devices this is queried with the `FBIOGET_FSCREENINFO` and `FBIOGET_VSCREENINFO` ioctls,
+/// for plain files it has to be given explicitly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FbLayout {
+    /// Visible width in pixels.
+    pub width: u32,
+    /// Visible height in pixels.
+    pub height: u32,
+    /// Length of a line in bytes.
+    pub stride: u32,
+    /// Size of a pixel in bits, 16, 24 and 32 are supported.
+    pub bits_per_pixel: u32,
+    /// Offset (x, y) of the visible area within the virtual resolution, in pixels, i.e. the `xoffset` and
+    /// `yoffset` the framebuffer is panned to.
+    pub offset: (u32, u32),
+    /// Position of the red channel within a pixel.
+    pub red: Channel,
+    /// Position of the green channel within a pixel.
+    pub green: Channel,
+    /// Position of the blue channel within a pixel.
+    pub blue: Channel,
+}
+
+impl FbLayout {
+    /// A 32 bits per pixel layout storing pixels as \[B, G, R, X].
+    pub fn bgrx8888(width: u32, height: u32) -> FbLayout {
+        FbLayout {
+            width,
+            height,
+            stride: width * 4,
+            bits_per_pixel: 32,
+            offset: (0, 0),
+            red: Channel {
+                offset: 16,
+                length: 8,
+            },
+            green: Channel {
+                offset: 8,
+                length: 8,
+            },
+            blue: Channel {
+                offset: 0,
+                length: 8,
+            },
+        }
+    }
+
+    /// A 16 bits per pixel RGB565 layout.
+    pub fn rgb565(width: u32, height: u32) -> FbLayout {
+        FbLayout {
+            width,
+            height,
+            stride: width * 2,
+            bits_per_pixel: 16,
+            offset: (0, 0),
+            red: Channel {
+                offset: 11,
+                length: 5,
+            },
+            green: Channel {
+                offset: 5,
+                length: 6,
+            },
+            blue: Channel {
+                offset: 0,
+                length: 5,
+            },
+        }
+    }
+
+    fn query(file: &File) -> io::Result<FbLayout> {
+        let mut fix = sys::FbFixScreeninfo::default();
+        let mut var = sys::FbVarScreeninfo::default();
+        let fd: c_int = file.as_raw_fd();
+        unsafe {
+            if sys::ioctl(fd, sys::FBIOGET_FSCREENINFO, &mut fix) < 0
+                || sys::ioctl(fd, sys::FBIOGET_VSCREENINFO, &mut var) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        let channel = |field: &sys::FbBitfield| Channel {
+            offset: field.offset,
+            length: field.length,
+        };
+        Ok(FbLayout {
+            width: var.xres,
+            height: var.yres,
+            stride: fix.line_length,
+            bits_per_pixel: var.bits_per_pixel,
+            offset: (var.xoffset, var.yoffset),
+            red: channel(&var.red),
+            green: channel(&var.green),
+            blue: channel(&var.blue),
+        })
+    }
+
+    fn is_bgrx8888(&self) -> bool {
+        let byte = |offset| Channel { offset, length: 8 };
+        self.bits_per_pixel == 32
+            && (self.red, self.green, self.blue) == (byte(16), byte(8), byte(0))
+    }
+}
+
+/// Describes the framebuffer that should be captured.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FbdevTarget {
+    path: PathBuf,
+    layout: Option<FbLayout>,
+}
+
+impl Default for FbdevTarget {
+    fn default() -> FbdevTarget {
+        FbdevTarget::new("/dev/fb0")
+    }
+}
+
+impl FbdevTarget {
+    /// Targets the framebuffer device (or file) at the given path.
+    pub fn new(path: impl Into<PathBuf>) -> FbdevTarget {
+        FbdevTarget {
+            path: path.into(),
+            layout: None,
+        }
+    }
+
+    /// Uses the given layout instead of querying the device, which makes it possible to capture plain files.
+    pub fn layout(mut self, layout: FbLayout) -> FbdevTarget {
+        self.layout = Some(layout);
+        self
+    }
+}
+
+/// A capture backend reading the Linux framebuffer device (`/dev/fb0`).
+///
+/// Both [`PixelFormat::Bgr24`] and [`PixelFormat::Bgra32`] are produced natively.
+/// Parts of the captured area lying outside of the visible screen are black.
+///
+/// # Examples
+///
+/// ```
+/// use qshot::{CaptureManager, FbLayout, FbdevBackend, FbdevTarget, Rect};
+///
+/// // A fake 4x2 framebuffer in which every pixel is [B, G, R, X] = [1, 2, 3, 0].
+/// let path = std::env::temp_dir().join(format!("qshot-fbdev-example-{}", std::process::id()));
+/// std::fs::write(&path, [1, 2, 3, 0].repeat(4 * 2)).unwrap();
+///
+/// let target = FbdevTarget::new(&path).layout(FbLayout::bgrx8888(4, 2));
+/// let manager = CaptureManager::<FbdevBackend>::open(target, Rect::new(3, 1, 2, 1)).unwrap();
+/// assert_eq!(manager.capture().unwrap().get_bits(), &[1, 2, 3, 0, 0, 0]);
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub struct FbdevBackend {
+    file: File,
+    layout: FbLayout,
+    fixed_layout: bool,
+    top_left: (i32, i32),
+    wh: (i32, i32),
+    format: PixelFormat,
+    /// The lines read from the framebuffer, kept around so that they don't have to be reallocated for every capture.
+    data: RefCell<Vec<u8>>,
+}
+
+impl FbdevBackend {
+    /// Returns the layout of the framebuffer.
+    pub fn layout(&self) -> &FbLayout {
+        &self.layout
+    }
+
+    fn grab(&self, bits: &mut [u8]) -> Result<(), Error> {
+        let layout = &self.layout;
+        if !matches!(layout.bits_per_pixel, 16 | 24 | 32) {
+            return Err(Error::UnsupportedFormat(format!(
+                "{} bits per pixel framebuffer",
+                layout.bits_per_pixel
+            )));
+        }
+        let bpp = layout.bits_per_pixel as usize / 8;
+        let area = self.area();
+        let left = area.x.max(0);
+        let top = area.y.max(0);
+        let right = area.right().min(layout.width as i32);
+        let bottom = area.bottom().min(layout.height as i32);
+        if right <= left || bottom <= top {
+            return Ok(());
+        }
+        let (w, h) = ((right - left) as usize, (bottom - top) as usize);
+
+        // Read all the affected lines at once, one syscall is much cheaper than one per line.
+        let stride = layout.stride as usize;
+        let first = (layout.offset.1 as usize + top as usize) * stride
+            + (layout.offset.0 as usize + left as usize) * bpp;
+        let mut data = self.data.borrow_mut();
+        data.resize((h - 1) * stride + w * bpp, 0);
+        self.file.read_exact_at(&mut data, first as u64)?;
+
+        let out_bpp = self.format.bytes_per_pixel();
+        let out_stride = self.wh.0 as usize * out_bpp;
+        let fast = layout.is_bgrx8888();
+        for y in 0..h {
+            let src = &data[y * stride..y * stride + w * bpp];
+            let start = (top - self.top_left.1) as usize + y;
+            let start = start * out_stride + (left - self.top_left.0) as usize * out_bpp;
+            let dst = &mut bits[start..start + w * out_bpp];
+            if fast {
+                for (d, s) in dst.chunks_exact_mut(out_bpp).zip(src.chunks_exact(4)) {
+                    d[..3].copy_from_slice(&s[..3]);
+                }
+                continue;
+            }
+            for (d, s) in dst.chunks_exact_mut(out_bpp).zip(src.chunks_exact(bpp)) {
+                let pixel = s
+                    .iter()
+                    .rev()
+                    .fold(0u32, |pixel, &b| (pixel << 8) | b as u32);
+                d[0] = layout.blue.extract(pixel);
+                d[1] = layout.green.extract(pixel);
+                d[2] = layout.red.extract(pixel);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CaptureBackend for FbdevBackend {
+    type Target = FbdevTarget;
+    fn open(target: FbdevTarget, area: Rect) -> Result<Self, Error> {
+        let (top_left, wh) = (area.top_left(), area.wh());
+        let file = File::open(&target.path).map_err(Error::unavailable)?;
+        let layout = match target.layout {
+            Some(layout) => layout,
+            None => FbLayout::query(&file)?,
+        };
+        Ok(FbdevBackend {
+            file,
+            layout,
+            fixed_layout: target.layout.is_some(),
+            top_left,
+            wh,
+            format: PixelFormat::Bgr24,
+            data: RefCell::new(Vec::new()),
+        })
+    }
+
+    fn capture(&self) -> Result<CaptureData, Error> {
+        let (width, height) = (self.wh.0.max(0) as usize, self.wh.1.max(0) as usize);
+        let mut bits = OPAQUE_BLACK[..self.format.bytes_per_pixel()].repeat(width * height);
+        self.grab(&mut bits)?;
+        Ok(CaptureData::from_vec(bits, width, height, self.format))
+    }
+
+    fn capture_into(&self, frame: &mut Frame) -> Result<(), Error> {
+        let (width, height) = (self.wh.0.max(0) as usize, self.wh.1.max(0) as usize);
+        frame.reset(width, height, self.format);
+        self.grab(frame.bits_mut())
+    }
+
+    fn set_format(&mut self, format: PixelFormat) -> bool {
+        if matches!(format, PixelFormat::Bgr24 | PixelFormat::Bgra32) {
+            self.format = format;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn resize(&mut self, area: Rect) -> Result<(), Error> {
+        // The resolution may have changed since the device was opened.
+        if !self.fixed_layout {
+            self.layout = FbLayout::query(&self.file)?;
+        }
+        self.top_left = area.top_left();
+        self.wh = area.wh();
+        Ok(())
+    }
+
+    fn area(&self) -> Rect {
+        Rect::from_parts(self.top_left, self.wh)
+    }
+
+    fn bounds(&self) -> Option<Rect> {
+        Some(Rect::new(
+            0,
+            0,
+            self.layout.width as i32,
+            self.layout.height as i32,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// A file in the temporary directory that is removed when dropped, named after the test using it.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, contents: &[u8]) -> TempFile {
+            let path =
+                std::env::temp_dir().join(format!("qshot-fbdev-{}-{name}", std::process::id()));
+            std::fs::write(&path, contents).unwrap();
+            TempFile(path)
+        }
+
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn open(file: &TempFile, layout: FbLayout, area: Rect) -> FbdevBackend {
+        FbdevBackend::open(FbdevTarget::new(file.path()).layout(layout), area).unwrap()
+    }
+
+    #[test]
+    fn channels_are_scaled_to_8_bits() {
+        let red = FbLayout::rgb565(1, 1).red;
+        assert_eq!(red.extract(0xF800), 255);
+        assert_eq!(red.extract(0x07FF), 0);
+        assert_eq!(red.extract(16 << 11), 131);
+        let green = FbLayout::rgb565(1, 1).green;
+        assert_eq!(green.extract(32 << 5), 129);
+        assert_eq!(green.extract(0x07E0), 255);
+        assert_eq!(
+            Channel {
+                offset: 8,
+                length: 10
+            }
+            .extract(0x3FF00),
+            255
+        );
+        assert_eq!(
+            Channel {
+                offset: 8,
+                length: 10
+            }
+            .extract(0x20000),
+            128
+        );
+        // Channels reaching past the pixel are cut, absent ones are black.
+        assert_eq!(
+            Channel {
+                offset: 28,
+                length: 8
+            }
+            .extract(0xF000_0000),
+            255
+        );
+        assert_eq!(
+            Channel {
+                offset: 32,
+                length: 8
+            }
+            .extract(u32::MAX),
+            0
+        );
+        assert_eq!(Channel::default().extract(u32::MAX), 0);
+    }
+
+    #[test]
+    fn rgb565_pixels_are_expanded() {
+        // Half red, half green and full blue, then pure red, stored little-endian.
+        let file = TempFile::new("rgb565", &[0x1F, 0x84, 0x00, 0xF8]);
+        let backend = open(&file, FbLayout::rgb565(2, 1), Rect::new(0, 0, 2, 1));
+        assert_eq!(
+            backend.capture().unwrap().get_bits(),
+            &[255, 129, 131, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn bgr24_lines_are_read_with_their_stride() {
+        // A 3x2 framebuffer storing pixels as [R, G, B], with 2 bytes of padding after every line.
+        let pixels = |y: u8| (0..3).flat_map(move |x| [10 * x + y, 100 + x, 200 + y]);
+        let file: Vec<u8> = (0..2).flat_map(|y| pixels(y).chain([0xEE; 2])).collect();
+        let file = TempFile::new("bgr24", &file);
+        let byte = |offset| Channel { offset, length: 8 };
+        let layout = FbLayout {
+            width: 3,
+            height: 2,
+            stride: 11,
+            bits_per_pixel: 24,
+            offset: (0, 0),
+            red: byte(0),
+            green: byte(8),
+            blue: byte(16),
+        };
+        let mut backend = open(&file, layout, Rect::new(1, -1, 3, 2));
+        assert!(backend.set_format(PixelFormat::Bgra32));
+        let capture = backend.capture().unwrap();
+        assert_eq!(capture.row(0), &[0, 0, 0, 255].repeat(3)[..]);
+        assert_eq!(
+            capture.row(1),
+            &[200, 101, 10, 255, 200, 102, 20, 255, 0, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn panned_framebuffers_are_read_at_their_offset() {
+        let file: Vec<u8> = (0..4 * 3).flat_map(|i| [i, i, i, 0]).collect();
+        let file = TempFile::new("panned", &file);
+        // The visible 2x2 pixels are panned to (1, 1) of a 4x3 virtual resolution.
+        let layout = FbLayout {
+            stride: 16,
+            offset: (1, 1),
+            ..FbLayout::bgrx8888(2, 2)
+        };
+        let backend = open(&file, layout, Rect::new(0, 0, 2, 2));
+        assert_eq!(
+            backend.capture().unwrap().get_bits(),
+            &[5, 5, 5, 6, 6, 6, 9, 9, 9, 10, 10, 10]
+        );
+    }
+
+    #[test]
+    fn areas_at_the_end_of_the_coordinates_are_black() {
+        let file = TempFile::new("far", &[7; 16]);
+        let backend = open(
+            &file,
+            FbLayout::bgrx8888(2, 2),
+            Rect::new(i32::MAX - 1, 0, 4, 1),
+        );
+        assert_eq!(backend.capture().unwrap().get_bits(), &[0; 12]);
+    }
+
+    #[test]
+    fn failed_resize_keeps_the_area() {
+        let file = TempFile::new("resize", &[0; 16]);
+        let mut backend = open(&file, FbLayout::bgrx8888(2, 2), Rect::new(0, 0, 2, 2));
+        // Plain files don't answer the ioctls, like a device that went away.
+        backend.fixed_layout = false;
+        assert!(backend.resize(Rect::new(1, 1, 1, 1)).is_err());
+        assert_eq!(backend.area(), Rect::new(0, 0, 2, 2));
+        assert_eq!(backend.capture().unwrap().get_bits().len(), 2 * 2 * 3);
+    }
+}
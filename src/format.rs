@@ -0,0 +1,43 @@
+/// An opaque black pixel in any of the BGR(A) formats, backends fill areas they can't capture with it.
+pub(crate) const OPAQUE_BLACK: [u8; 4] = [0, 0, 0, 255];
+
+/// Describes how a single pixel is stored in a [`CaptureData`](crate::CaptureData) buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PixelFormat {
+    /// 3 bytes per pixel, stored as \[B, G, R].
+    Bgr24,
+    /// 4 bytes per pixel, stored as \[B, G, R, A].
+    Bgra32,
+    /// 3 bytes per pixel, stored as \[R, G, B].
+    Rgb24,
+    /// 4 bytes per pixel, stored as \[R, G, B, A].
+    Rgba32,
+    /// 1 byte per pixel containing the BT.601 luma.
+    Gray8,
+}
+
+impl PixelFormat {
+    /// Returns the number of bytes a single pixel occupies.
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Bgr24 | PixelFormat::Rgb24 => 3,
+            PixelFormat::Bgra32 | PixelFormat::Rgba32 => 4,
+            PixelFormat::Gray8 => 1,
+        }
+    }
+
+    /// Returns `true` if the format has an alpha channel.
+    pub fn has_alpha(self) -> bool {
+        matches!(self, PixelFormat::Bgra32 | PixelFormat::Rgba32)
+    }
+
+    /// Returns the offsets of the red, green and blue channels within a pixel, all 0 for [`PixelFormat::Gray8`].
+    pub(crate) fn rgb_offsets(self) -> [usize; 3] {
+        match self {
+            PixelFormat::Bgr24 | PixelFormat::Bgra32 => [2, 1, 0],
+            PixelFormat::Rgb24 | PixelFormat::Rgba32 => [0, 1, 2],
+            PixelFormat::Gray8 => [0, 0, 0],
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use std::time::Instant;
+
+use crate::capture::CaptureData;
+use crate::convert;
+use crate::format::{PixelFormat, OPAQUE_BLACK};
+use crate::rect::Rect;
+
+/// An owned capture that can be sent to and shared between threads.
+///
+/// Unlike [`CaptureData`], a frame never borrows platform resources. It is usually created with
+/// [`CaptureData::into_owned`] or [`CaptureManager::capture_frame`](crate::CaptureManager::capture_frame).
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::mpsc;
+/// use qshot::{CaptureManager, Frame, Rect, Script, SyntheticBackend};
+///
+/// let script = Script::new((100, 100)).solid([255, 0, 0], 1);
+/// let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 10, 10)).unwrap();
+///
+/// let (tx, rx) = mpsc::channel::<Frame>();
+/// let worker = std::thread::spawn(move || rx.recv().unwrap().row(0)[..3].to_vec());
+/// tx.send(manager.capture_frame().unwrap()).unwrap();
+/// assert_eq!(worker.join().unwrap(), [255, 0, 0]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    bits: Vec<u8>,
+    width: usize,
+    height: usize,
+    stride: usize,
+    format: PixelFormat,
+    timestamp: Instant,
+}
+
+impl Frame {
+    /// Creates an opaque black frame, timestamped with the current time.
+    ///
+    /// This is mostly useful as a buffer for [`CaptureManager::capture_into`](crate::CaptureManager::capture_into),
+    /// which changes the size and format of the frame as needed.
+    pub fn new(width: usize, height: usize, format: PixelFormat) -> Frame {
+        let bits = OPAQUE_BLACK[..format.bytes_per_pixel()].repeat(width * height);
+        Frame::from_vec(bits, width, height, format)
+    }
+
+    /// Creates a frame from tightly packed bits, timestamped with the current time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bits` is shorter than `width * height` pixels.
+    pub fn from_vec(bits: Vec<u8>, width: usize, height: usize, format: PixelFormat) -> Frame {
+        let stride = width * format.bytes_per_pixel();
+        Frame::from_vec_with_stride(bits, width, height, stride, format)
+    }
+
+    /// Creates a frame from bits in which every row is `stride` bytes long, timestamped with the current time.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `stride` is smaller than a row of pixels or `bits` is too short to hold `height` rows.
+    pub fn from_vec_with_stride(
+        bits: Vec<u8>,
+        width: usize,
+        height: usize,
+        stride: usize,
+        format: PixelFormat,
+    ) -> Frame {
+        assert!(
+            stride >= width * format.bytes_per_pixel(),
+            "stride is too small"
+        );
+        assert!(bits.len() >= stride * height, "buffer is too small");
+        Frame::from_parts(bits, width, height, stride, format, Instant::now())
+    }
+
+    pub(crate) fn from_parts(
+        bits: Vec<u8>,
+        width: usize,
+        height: usize,
+        stride: usize,
+        format: PixelFormat,
+        timestamp: Instant,
+    ) -> Frame {
+        Frame {
+            bits,
+            width,
+            height,
+            stride,
+            format,
+            timestamp,
+        }
+    }
+
+    /// Changes the shape of the frame and timestamps it with the current time, keeping the allocation if possible.
+    ///
+    /// The contents of the frame are unspecified afterwards.
+    pub(crate) fn reshape(
+        &mut self,
+        width: usize,
+        height: usize,
+        stride: usize,
+        format: PixelFormat,
+    ) {
+        self.bits.resize(stride * height, 0);
+        self.width = width;
+        self.height = height;
+        self.stride = stride;
+        self.format = format;
+        self.timestamp = Instant::now();
+    }
+
+    pub(crate) fn set_timestamp(&mut self, timestamp: Instant) {
+        self.timestamp = timestamp;
+    }
+
+    /// Reshapes the frame into tightly packed rows and makes it opaque black, like a fresh [`Frame::new`].
+    pub(crate) fn reset(&mut self, width: usize, height: usize, format: PixelFormat) {
+        let bpp = format.bytes_per_pixel();
+        self.reshape(width, height, width * bpp, format);
+        for pixel in self.bits.chunks_exact_mut(bpp) {
+            pixel.copy_from_slice(&OPAQUE_BLACK[..bpp]);
+        }
+    }
+
+    /// Returns the bits of the frame, laid out like [`CaptureData::get_bits`].
+    pub fn bits(&self) -> &[u8] {
+        &self.bits
+    }
+
+    /// Returns the bits of the frame for modification.
+    pub fn bits_mut(&mut self) -> &mut [u8] {
+        &mut self.bits
+    }
+
+    /// Returns the bits of the frame, including any padding.
+    pub fn into_vec(self) -> Vec<u8> {
+        self.bits
+    }
+
+    /// Returns the width of the frame in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of the frame in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the distance between the starts of two consecutive rows in bytes.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Returns the layout of a single pixel.
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Returns the moment the frame was captured.
+    pub fn timestamp(&self) -> Instant {
+        self.timestamp
+    }
+
+    /// Returns the pixels of the `y`-th row, without the padding.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `y` is not smaller than the height.
+    pub fn row(&self, y: usize) -> &[u8] {
+        assert!(y < self.height, "row out of bounds");
+        let start = y * self.stride;
+        &self.bits[start..start + self.width * self.format.bytes_per_pixel()]
+    }
+
+    /// Returns an iterator over the rows, without the padding.
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        (0..self.height).map(move |y| self.row(y))
+    }
+
+    /// Returns a copy of the part of the frame within `area`, which is clamped to the frame first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qshot::{Frame, PixelFormat, Rect};
+    ///
+    /// let frame = Frame::new(10, 10, PixelFormat::Bgra32);
+    /// let part = frame.crop(Rect::new(8, -2, 4, 4));
+    /// assert_eq!((part.width(), part.height()), (2, 2));
+    /// ```
+    pub fn crop(&self, area: Rect) -> Frame {
+        let area = area.clamp(&Rect::new(0, 0, self.width as i32, self.height as i32));
+        let (width, height) = (area.width as usize, area.height as usize);
+        let stride = width * self.format.bytes_per_pixel();
+        let mut frame = Frame::from_parts(
+            vec![0; stride * height],
+            width,
+            height,
+            stride,
+            self.format,
+            self.timestamp,
+        );
+        self.copy_area(area, &mut frame, (0, 0));
+        frame
+    }
+
+    /// Copies the pixels within `area` into `dst` with their upper-left corner at `at`, converting them into the
+    /// format of `dst`. Both areas must lie within their frames.
+    pub(crate) fn copy_area(&self, area: Rect, dst: &mut Frame, at: (usize, usize)) {
+        let converter = convert::Converter::new();
+        let (x, y, width) = (area.x as usize, area.y as usize, area.width as usize);
+        let (bpp, dst_bpp) = (self.format.bytes_per_pixel(), dst.format.bytes_per_pixel());
+        for row in 0..area.height.max(0) as usize {
+            let src = &self.row(y + row)[x * bpp..(x + width) * bpp];
+            let start = (at.1 + row) * dst.stride + at.0 * dst_bpp;
+            let out = &mut dst.bits[start..start + width * dst_bpp];
+            converter.convert_row(src, self.format, out, dst.format);
+        }
+    }
+
+    /// Converts the pixels into another format. The returned frame is tightly packed.
+    pub fn to_format(&self, format: PixelFormat) -> Frame {
+        let mut frame = Frame::from_parts(Vec::new(), 0, 0, 0, format, self.timestamp);
+        self.convert_into(&mut frame, format);
+        frame
+    }
+
+    /// Converts the pixels into another format, writing them into `dst` whose buffer is reused.
+    ///
+    /// `dst` ends up tightly packed and with the timestamp of this frame.
+    pub fn convert_into(&self, dst: &mut Frame, format: PixelFormat) {
+        let converter = convert::Converter::new();
+        let stride = self.width * format.bytes_per_pixel();
+        dst.reshape(self.width, self.height, stride, format);
+        dst.timestamp = self.timestamp;
+        if stride > 0 {
+            for (src, out) in self.rows().zip(dst.bits.chunks_exact_mut(stride)) {
+                converter.convert_row(src, self.format, out, format);
+            }
+        }
+    }
+}
+
+impl From<CaptureData> for Frame {
+    fn from(data: CaptureData) -> Frame {
+        data.into_owned()
+    }
+}
+
+/// Fixtures shared by the tests of the codecs and filters.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use std::io;
+
+    use super::*;
+
+    /// Returns a frame whose rows are padded by `padding` bytes of `0xAA`, with every pixel taken from the first
+    /// samples of `pixel(x, y)`.
+    pub(crate) fn padded(
+        width: usize,
+        height: usize,
+        padding: usize,
+        format: PixelFormat,
+        mut pixel: impl FnMut(usize, usize) -> [u8; 4],
+    ) -> Frame {
+        let bpp = format.bytes_per_pixel();
+        let stride = width * bpp + padding;
+        let mut bits = vec![0xAA; stride * height];
+        for (y, row) in bits.chunks_exact_mut(stride.max(1)).enumerate() {
+            for (x, samples) in row[..width * bpp].chunks_exact_mut(bpp).enumerate() {
+                samples.copy_from_slice(&pixel(x, y)[..bpp]);
+            }
+        }
+        Frame::from_vec_with_stride(bits, width, height, stride, format)
+    }
+
+    /// Returns the bytes written by `encode`, which must succeed.
+    pub(crate) fn encoded(encode: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> Vec<u8> {
+        let mut file = Vec::new();
+        encode(&mut file).unwrap();
+        file
+    }
+
+    /// Returns whether `result` is an error of the given kind.
+    pub(crate) fn fails_with<T>(result: io::Result<T>, kind: io::ErrorKind) -> bool {
+        result.is_err_and(|err| err.kind() == kind)
+    }
+}
@@ -0,0 +1,272 @@
+use std::cell::RefCell;
+
+use windows::{
+    core::Error as WinError,
+    Win32::{Foundation, Graphics::Gdi, UI::WindowsAndMessaging},
+};
+
+use crate::backend::CaptureBackend;
+use crate::capture::CaptureData;
+use crate::error::Error;
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use crate::rect::Rect;
+
+/// A DIB section holding the bits of a single capture.
+pub(crate) struct Dib {
+    bits: *mut u8,
+    len: usize,
+    hbitmap: Gdi::HBITMAP,
+}
+
+impl Dib {
+    pub(crate) fn bits(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.bits, self.len) }
+    }
+
+    fn bits_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.bits, self.len) }
+    }
+}
+
+impl Drop for Dib {
+    fn drop(&mut self) {
+        unsafe { Gdi::DeleteObject(self.hbitmap) };
+    }
+}
+
+impl From<WinError> for Error {
+    fn from(err: WinError) -> Error {
+        if err.code() == Foundation::ERROR_INVALID_WINDOW_HANDLE.to_hresult() {
+            Error::WindowGone
+        } else {
+            Error::platform(err)
+        }
+    }
+}
+
+/// Returns the length of a DIB row, which is always padded to a multiple of 4 bytes.
+fn dib_stride(width: usize, format: PixelFormat) -> usize {
+    (width * format.bytes_per_pixel() + 3) & !3
+}
+
+/// A capture backend built on top of GDI's `BitBlt`.
+///
+/// The target is a window handle, `0` captures the entire screen. Screen coordinates span all monitors, with the
+/// upper-left corner of the primary monitor at the origin.
+/// Both [`PixelFormat::Bgr24`] and [`PixelFormat::Bgra32`] are produced natively.
+pub struct GdiBackend {
+    top_left: (i32, i32),
+    wh: (i32, i32),
+    format: PixelFormat,
+    dc: Gdi::HDC,
+    dc_mem: Gdi::HDC,
+    window_handle: Foundation::HWND,
+    bitmap_info: Gdi::BITMAPINFO,
+    /// The DIB section reused by `capture_into`, recreated whenever the size or format changes.
+    dib: RefCell<Option<Dib>>,
+    /// The DIB section of the previous size, so that switching back and forth between two sizes doesn't
+    /// reallocate, e.g. for [`CaptureManager::capture_regions`](crate::CaptureManager::capture_regions).
+    spare: Option<((i32, i32), Dib)>,
+}
+
+impl GdiBackend {
+    fn create_dib(&self) -> Result<Dib, WinError> {
+        let mut bits = std::mem::MaybeUninit::<*mut u8>::uninit();
+        let hbitmap = unsafe {
+            Gdi::CreateDIBSection(
+                self.dc,
+                &self.bitmap_info,
+                Gdi::DIB_RGB_COLORS,
+                bits.as_mut_ptr() as *mut *mut std::ffi::c_void,
+                None,
+                0,
+            )?
+        };
+        let (width, height) = self.size();
+        Ok(Dib {
+            bits: unsafe { bits.assume_init() },
+            len: dib_stride(width, self.format) * height,
+            hbitmap,
+        })
+    }
+
+    fn size(&self) -> (usize, usize) {
+        (self.wh.0.max(0) as usize, self.wh.1.max(0) as usize)
+    }
+
+    /// Copies the captured area into the DIB.
+    fn blit(&self, dib: &mut Dib) -> Result<(), WinError> {
+        unsafe {
+            Gdi::SelectObject(self.dc_mem, dib.hbitmap);
+            Gdi::BitBlt(
+                self.dc_mem,
+                0,
+                0,
+                self.wh.0,
+                self.wh.1,
+                self.dc,
+                self.top_left.0,
+                self.top_left.1,
+                Gdi::SRCCOPY,
+            )?;
+        }
+        if self.format == PixelFormat::Bgra32 {
+            // BitBlt leaves the fourth byte of every pixel zeroed.
+            for pixel in dib.bits_mut().chunks_exact_mut(4) {
+                pixel[3] = 255;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CaptureBackend for GdiBackend {
+    type Target = isize;
+    fn open(window_handle: isize, area: Rect) -> Result<Self, Error> {
+        let (top_left, wh) = (area.top_left(), area.wh());
+        let window_handle = Foundation::HWND(window_handle);
+        let dc = unsafe { Gdi::GetDC(window_handle) };
+        if dc.is_invalid() {
+            return Err(WinError::from_win32().into());
+        }
+        let dc_mem = unsafe { Gdi::CreateCompatibleDC(dc) };
+        if dc_mem.is_invalid() {
+            let err = WinError::from_win32();
+            unsafe { Gdi::ReleaseDC(window_handle, dc) };
+            return Err(err.into());
+        }
+
+        let bitmap_info = Gdi::BITMAPINFO {
+            bmiHeader: Gdi::BITMAPINFOHEADER {
+                biSize: std::mem::size_of::<Gdi::BITMAPINFOHEADER>() as u32,
+                biWidth: wh.0,
+                biHeight: -wh.1,
+                biBitCount: 24,
+                biCompression: 0,
+                biPlanes: 1,
+                biSizeImage: 0,
+                biClrImportant: 0,
+                biClrUsed: 0,
+                biXPelsPerMeter: 0,
+                biYPelsPerMeter: 0,
+            },
+            bmiColors: [Gdi::RGBQUAD {
+                rgbRed: 0,
+                rgbGreen: 0,
+                rgbBlue: 0,
+                rgbReserved: 0,
+            }],
+        };
+
+        Ok(GdiBackend {
+            top_left,
+            wh,
+            format: PixelFormat::Bgr24,
+            bitmap_info,
+            dc,
+            dc_mem,
+            window_handle,
+            dib: RefCell::new(None),
+            spare: None,
+        })
+    }
+
+    fn capture(&self) -> Result<CaptureData, Error> {
+        // Owning the bitmap right away makes sure it gets deleted if `BitBlt` fails.
+        let mut dib = self.create_dib()?;
+        self.blit(&mut dib)?;
+        let (width, height) = self.size();
+        Ok(CaptureData::from_dib(
+            dib,
+            width,
+            height,
+            dib_stride(width, self.format),
+            self.format,
+        ))
+    }
+
+    fn capture_into(&self, frame: &mut Frame) -> Result<(), Error> {
+        let mut dib = self.dib.borrow_mut();
+        let dib = match &mut *dib {
+            Some(dib) => dib,
+            dib => dib.insert(self.create_dib()?),
+        };
+        self.blit(dib)?;
+        let (width, height) = self.size();
+        frame.reshape(width, height, dib_stride(width, self.format), self.format);
+        frame.bits_mut().copy_from_slice(dib.bits());
+        Ok(())
+    }
+
+    fn resize(&mut self, area: Rect) -> Result<(), Error> {
+        let (top_left, wh) = (area.top_left(), area.wh());
+        if wh != self.wh {
+            let previous = self.dib.get_mut().take().map(|dib| (self.wh, dib));
+            let spare = std::mem::replace(&mut self.spare, previous);
+            *self.dib.get_mut() = spare.filter(|(size, _)| *size == wh).map(|(_, dib)| dib);
+        }
+        self.wh = wh;
+        self.top_left = top_left;
+        self.bitmap_info.bmiHeader.biWidth = wh.0;
+        self.bitmap_info.bmiHeader.biHeight = -wh.1;
+        Ok(())
+    }
+
+    fn area(&self) -> Rect {
+        Rect::from_parts(self.top_left, self.wh)
+    }
+
+    fn bounds(&self) -> Option<Rect> {
+        if self.window_handle.0 == 0 {
+            // The screen DC spans all monitors, with the primary monitor's upper-left corner at the origin.
+            let metric = |index| unsafe { WindowsAndMessaging::GetSystemMetrics(index) };
+            return Some(Rect::new(
+                metric(WindowsAndMessaging::SM_XVIRTUALSCREEN),
+                metric(WindowsAndMessaging::SM_YVIRTUALSCREEN),
+                metric(WindowsAndMessaging::SM_CXVIRTUALSCREEN),
+                metric(WindowsAndMessaging::SM_CYVIRTUALSCREEN),
+            ));
+        }
+        let mut rect = Foundation::RECT::default();
+        unsafe { WindowsAndMessaging::GetClientRect(self.window_handle, &mut rect) }.ok()?;
+        Some(Rect::from_corners(
+            (rect.left, rect.top),
+            (rect.right, rect.bottom),
+        ))
+    }
+
+    fn set_format(&mut self, format: PixelFormat) -> bool {
+        let bit_count = match format {
+            PixelFormat::Bgr24 => 24,
+            PixelFormat::Bgra32 => 32,
+            _ => return false,
+        };
+        self.format = format;
+        self.bitmap_info.bmiHeader.biBitCount = bit_count;
+        *self.dib.get_mut() = None;
+        self.spare = None;
+        true
+    }
+
+    fn close(&mut self) {
+        *self.dib.get_mut() = None;
+        self.spare = None;
+        unsafe {
+            if !self.dc.is_invalid() {
+                Gdi::ReleaseDC(self.window_handle, self.dc);
+                self.dc = Gdi::HDC(0);
+            }
+            if !self.dc_mem.is_invalid() {
+                Gdi::DeleteDC(self.dc_mem);
+                self.dc_mem = Gdi::HDC(0);
+            }
+        }
+    }
+}
+
+impl Drop for GdiBackend {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
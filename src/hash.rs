@@ -0,0 +1,308 @@
+//! Hashing of captured images.
+//!
+//! [`content_hash`] identifies images with exactly the same pixels, e.g. to drop duplicate screenshots. It hashes
+//! the pixels row by row with [`Xxh64`], skipping the padding at the end of the rows, so the same pixels have the
+//! same hash regardless of the stride of the buffer they are stored in.
+//!
+//! The perceptual hashes [`average_hash`], [`difference_hash`] and [`perceptual_hash`] identify images that look
+//! alike. They reduce an image to a tiny grayscale version and derive 64 bits from it, so similar images have
+//! hashes differing in few bits, see [`ImageHash::distance`]. In increasing order of cost and robustness:
+//!
+//! - [`average_hash`] compares every pixel of an 8x8 version with the mean brightness.
+//! - [`difference_hash`] compares horizontally adjacent pixels of a 9x8 version, which ignores changes of the
+//!   overall brightness.
+//! - [`perceptual_hash`] compares the low frequencies of the discrete cosine transform of a 32x32 version, which
+//!   is the least affected by small shifts, scaling and noise.
+
+use std::f64::consts::PI;
+use std::fmt;
+use std::hash::Hasher;
+
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use crate::scale::{self, Filter};
+use crate::view::ImageView;
+
+const PRIME1: u64 = 0x9E37_79B1_85EB_CA87;
+const PRIME2: u64 = 0xC2B2_AE3D_27D4_EB4F;
+const PRIME3: u64 = 0x1656_67B1_9E37_79F9;
+const PRIME4: u64 = 0x85EB_CA77_C2B2_AE63;
+const PRIME5: u64 = 0x27D4_EB2F_1656_67C5;
+
+/// A streaming implementation of the 64-bit xxHash algorithm (XXH64).
+///
+/// The hashes are the same as those of other XXH64 implementations, as long as they use the same seed.
+///
+/// # Examples
+///
+/// ```
+/// use std::hash::Hasher;
+/// use qshot::hash::Xxh64;
+///
+/// let mut hasher = Xxh64::new(0);
+/// hasher.write(b"ab");
+/// hasher.write(b"c");
+/// assert_eq!(hasher.finish(), 0x44BC_2CF5_AD77_0999);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Xxh64 {
+    seed: u64,
+    lanes: [u64; 4],
+    /// Input that doesn't fill a whole stripe of 32 bytes yet.
+    buffer: [u8; 32],
+    buffered: usize,
+    len: u64,
+}
+
+impl Xxh64 {
+    /// Creates a hasher using the given seed.
+    pub fn new(seed: u64) -> Xxh64 {
+        Xxh64 {
+            seed,
+            lanes: [
+                seed.wrapping_add(PRIME1).wrapping_add(PRIME2),
+                seed.wrapping_add(PRIME2),
+                seed,
+                seed.wrapping_sub(PRIME1),
+            ],
+            buffer: [0; 32],
+            buffered: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns the hash of `bytes`, using the given seed.
+    pub fn hash(bytes: &[u8], seed: u64) -> u64 {
+        let mut hasher = Xxh64::new(seed);
+        hasher.write(bytes);
+        hasher.finish()
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (lane, bytes) in self.lanes.iter_mut().zip(stripe.chunks_exact(8)) {
+            *lane = round(*lane, read_u64(bytes));
+        }
+    }
+}
+
+impl Default for Xxh64 {
+    fn default() -> Xxh64 {
+        Xxh64::new(0)
+    }
+}
+
+impl Hasher for Xxh64 {
+    fn write(&mut self, mut bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+        if self.buffered > 0 {
+            let take = bytes.len().min(32 - self.buffered);
+            self.buffer[self.buffered..self.buffered + take].copy_from_slice(&bytes[..take]);
+            self.buffered += take;
+            bytes = &bytes[take..];
+            if self.buffered < 32 {
+                return;
+            }
+            let buffer = self.buffer;
+            self.stripe(&buffer);
+            self.buffered = 0;
+        }
+        let mut stripes = bytes.chunks_exact(32);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+        let rest = stripes.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    fn finish(&self) -> u64 {
+        let mut hash = if self.len >= 32 {
+            let [a, b, c, d] = self.lanes;
+            let mut hash = a
+                .rotate_left(1)
+                .wrapping_add(b.rotate_left(7))
+                .wrapping_add(c.rotate_left(12))
+                .wrapping_add(d.rotate_left(18));
+            for lane in self.lanes {
+                hash = (hash ^ round(0, lane))
+                    .wrapping_mul(PRIME1)
+                    .wrapping_add(PRIME4);
+            }
+            hash
+        } else {
+            self.seed.wrapping_add(PRIME5)
+        };
+        hash = hash.wrapping_add(self.len);
+
+        let mut rest = &self.buffer[..self.buffered];
+        while rest.len() >= 8 {
+            hash ^= round(0, read_u64(rest));
+            hash = hash
+                .rotate_left(27)
+                .wrapping_mul(PRIME1)
+                .wrapping_add(PRIME4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let word = u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64;
+            hash ^= word.wrapping_mul(PRIME1);
+            hash = hash
+                .rotate_left(23)
+                .wrapping_mul(PRIME2)
+                .wrapping_add(PRIME3);
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            hash ^= (byte as u64).wrapping_mul(PRIME5);
+            hash = hash.rotate_left(11).wrapping_mul(PRIME1);
+        }
+
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(PRIME2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(PRIME3);
+        hash ^ (hash >> 32)
+    }
+}
+
+fn round(lane: u64, input: u64) -> u64 {
+    lane.wrapping_add(input.wrapping_mul(PRIME2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME1)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+/// Returns the XXH64 hash of the pixels of an image, ignoring the padding at the end of the rows.
+///
+/// The hash equals the hash of the tightly packed pixels with a seed of 0. It doesn't include the size or format
+/// of the image, so images of different shapes containing the same bytes have the same hash.
+///
+/// # Examples
+///
+/// ```
+/// use qshot::{hash, Frame, PixelFormat};
+///
+/// let packed = Frame::from_vec(vec![7; 2 * 2 * 3], 2, 2, PixelFormat::Bgr24);
+/// let padded = Frame::from_vec_with_stride([7, 7, 7, 7, 7, 7, 0, 0].repeat(2), 2, 2, 8, PixelFormat::Bgr24);
+/// assert_eq!(hash::content_hash(&packed), hash::content_hash(&padded));
+/// ```
+pub fn content_hash<'a>(image: impl Into<ImageView<'a>>) -> u64 {
+    let image = image.into();
+    let mut hasher = Xxh64::new(0);
+    for row in image.rows() {
+        hasher.write(row);
+    }
+    hasher.finish()
+}
+
+/// A 64-bit perceptual hash of an image.
+///
+/// The hash is displayed as 16 hexadecimal digits.
+///
+/// # Examples
+///
+/// ```
+/// use qshot::{hash, CaptureManager, Rect, Script, SyntheticBackend};
+///
+/// // A window that moves a little and then jumps to the other side of the screen.
+/// let (background, window) = ([40, 40, 40], [250, 250, 250]);
+/// let script = Script::new((200, 100))
+///     .moving_rect(background, window, (60, 40), (20, 20), (2, 1), 2)
+///     .moving_rect(background, window, (60, 40), (120, 50), (0, 0), 1);
+/// let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 200, 100)).unwrap();
+///
+/// let original = hash::perceptual_hash(&manager.capture().unwrap());
+/// let moved = hash::perceptual_hash(&manager.capture().unwrap());
+/// let elsewhere = hash::perceptual_hash(&manager.capture().unwrap());
+/// assert!(original.distance(moved) <= 10);
+/// assert!(original.distance(elsewhere) > 20);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ImageHash(pub u64);
+
+impl ImageHash {
+    /// Returns the number of bits in which the hashes differ, from 0 for images that look alike to 64.
+    pub fn distance(self, other: ImageHash) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+}
+
+impl fmt::Display for ImageHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Reduces an image to a grayscale version of the given size.
+fn shrink(image: ImageView, width: usize, height: usize) -> Frame {
+    scale::scale(image, width, height, Filter::Box).to_format(PixelFormat::Gray8)
+}
+
+/// Sets the `i`-th bit of the hash for every `true` item.
+fn collect_bits(bits: impl Iterator<Item = bool>) -> ImageHash {
+    ImageHash(
+        bits.enumerate()
+            .fold(0, |hash, (i, set)| hash | ((set as u64) << i)),
+    )
+}
+
+/// Returns the average hash (aHash) of an image.
+///
+/// Every bit tells whether a pixel of an 8x8 grayscale version of the image is brighter than the mean.
+pub fn average_hash<'a>(image: impl Into<ImageView<'a>>) -> ImageHash {
+    let small = shrink(image.into(), 8, 8);
+    let mean = small.bits().iter().map(|&p| p as u32).sum::<u32>() / 64;
+    collect_bits(small.bits().iter().map(|&p| p as u32 > mean))
+}
+
+/// Returns the difference hash (dHash) of an image.
+///
+/// Every bit tells whether a pixel of a 9x8 grayscale version of the image is brighter than its left neighbor.
+pub fn difference_hash<'a>(image: impl Into<ImageView<'a>>) -> ImageHash {
+    let small = shrink(image.into(), 9, 8);
+    collect_bits(
+        small
+            .rows()
+            .flat_map(|row| row.windows(2).map(|pair| pair[1] > pair[0])),
+    )
+}
+
+/// Returns the perceptual hash (pHash) of an image.
+///
+/// Every bit tells whether one of the 8x8 lowest frequencies of the discrete cosine transform of a 32x32 grayscale
+/// version of the image is above their median.
+pub fn perceptual_hash<'a>(image: impl Into<ImageView<'a>>) -> ImageHash {
+    const SIZE: usize = 32;
+    const LOW: usize = 8;
+    let small = shrink(image.into(), SIZE, SIZE);
+
+    let mut cos = [[0.0; SIZE]; LOW];
+    for (u, cos) in cos.iter_mut().enumerate() {
+        for (x, cos) in cos.iter_mut().enumerate() {
+            *cos = ((2 * x + 1) as f64 * u as f64 * PI / (2 * SIZE) as f64).cos();
+        }
+    }
+
+    // The transform is separable, so the rows are transformed first and the result is transformed along the
+    // columns. Only the low frequencies are needed and computed.
+    let mut rows = [[0.0; LOW]; SIZE];
+    for (row, out) in small.rows().zip(&mut rows) {
+        for (out, cos) in out.iter_mut().zip(&cos) {
+            *out = row.iter().zip(cos).map(|(&p, c)| p as f64 * c).sum();
+        }
+    }
+    let mut coefficients = [0.0; LOW * LOW];
+    for (v, out) in coefficients.chunks_exact_mut(LOW).enumerate() {
+        for (u, out) in out.iter_mut().enumerate() {
+            *out = rows.iter().zip(&cos[v]).map(|(row, c)| row[u] * c).sum();
+        }
+    }
+
+    let mut sorted = coefficients;
+    sorted.sort_by(f64::total_cmp);
+    let median = (sorted[LOW * LOW / 2 - 1] + sorted[LOW * LOW / 2]) / 2.0;
+    collect_bits(coefficients.iter().map(|&c| c > median))
+}
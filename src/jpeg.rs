@@ -0,0 +1,1013 @@
+//! JPEG encoding of captured images.
+//!
+//! A [`JpegEncoder`] writes baseline JPEG files (JFIF) with the standard quantization and Huffman tables of the
+//! JPEG specification, scaled by a [`quality`](JpegEncoder::quality) like most image tools do. Color images are
+//! stored as YCbCr with full or halved chroma resolution, see [`Subsampling`], [`PixelFormat::Gray8`] images as
+//! grayscale. The alpha channel is ignored.
+//!
+//! The encoder keeps its buffers between images, so encoding a stream of frames with the same encoder doesn't
+//! allocate once the first frame is done.
+//!
+//! # Examples
+//!
+//! ```
+//! use qshot::jpeg::{JpegEncoder, Subsampling};
+//! use qshot::{CaptureManager, Rect, Script, SyntheticBackend};
+//!
+//! let script = Script::new((320, 240)).moving_rect([240, 240, 240], [40, 90, 200], (60, 30), (20, 20), (10, 5), 3);
+//! let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 320, 240)).unwrap();
+//!
+//! let mut encoder = JpegEncoder::new().quality(80).subsampling(Subsampling::Chroma444);
+//! let mut file = Vec::new();
+//! for _ in 0..3 {
+//!     file.clear();
+//!     encoder.encode(&manager.capture().unwrap(), &mut file).unwrap();
+//!     assert_eq!(&file[..2], &[0xFF, 0xD8]);
+//!     assert!(file.len() < 320 * 240 * 3 / 20);
+//! }
+//! ```
+
+use std::f32::consts::PI;
+use std::io::{self, Write};
+
+use crate::format::PixelFormat;
+use crate::view::ImageView;
+
+/// The position of every coefficient of the zigzag sequence in a block stored row by row.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// The luminance quantization table of Annex K of the specification, for a quality of 50.
+const LUMA_QUANTIZATION: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+/// The chrominance quantization table of Annex K of the specification, for a quality of 50.
+const CHROMA_QUANTIZATION: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+/// The number of codes of every length from 1 to 16 bits, followed by the symbols in the order of their codes.
+struct HuffmanSpec<const N: usize> {
+    counts: [u8; 16],
+    symbols: [u8; N],
+}
+
+const LUMA_DC: HuffmanSpec<12> = HuffmanSpec {
+    counts: [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0],
+    symbols: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+};
+
+const CHROMA_DC: HuffmanSpec<12> = HuffmanSpec {
+    counts: [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0],
+    symbols: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+};
+
+const LUMA_AC: HuffmanSpec<162> = HuffmanSpec {
+    counts: [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7D],
+    symbols: [
+        0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61,
+        0x07, 0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xA1, 0x08, 0x23, 0x42, 0xB1, 0xC1, 0x15, 0x52,
+        0xD1, 0xF0, 0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0A, 0x16, 0x17, 0x18, 0x19, 0x1A, 0x25,
+        0x26, 0x27, 0x28, 0x29, 0x2A, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3A, 0x43, 0x44, 0x45,
+        0x46, 0x47, 0x48, 0x49, 0x4A, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0x63, 0x64,
+        0x65, 0x66, 0x67, 0x68, 0x69, 0x6A, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0x83,
+        0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8A, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99,
+        0x9A, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7, 0xA8, 0xA9, 0xAA, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6,
+        0xB7, 0xB8, 0xB9, 0xBA, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA, 0xD2, 0xD3,
+        0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA, 0xE1, 0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8,
+        0xE9, 0xEA, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA,
+    ],
+};
+
+const CHROMA_AC: HuffmanSpec<162> = HuffmanSpec {
+    counts: [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77],
+    symbols: [
+        0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61,
+        0x71, 0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xA1, 0xB1, 0xC1, 0x09, 0x23, 0x33,
+        0x52, 0xF0, 0x15, 0x62, 0x72, 0xD1, 0x0A, 0x16, 0x24, 0x34, 0xE1, 0x25, 0xF1, 0x17, 0x18,
+        0x19, 0x1A, 0x26, 0x27, 0x28, 0x29, 0x2A, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3A, 0x43, 0x44,
+        0x45, 0x46, 0x47, 0x48, 0x49, 0x4A, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0x63,
+        0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6A, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A,
+        0x82, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89, 0x8A, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97,
+        0x98, 0x99, 0x9A, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7, 0xA8, 0xA9, 0xAA, 0xB2, 0xB3, 0xB4,
+        0xB5, 0xB6, 0xB7, 0xB8, 0xB9, 0xBA, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA,
+        0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA, 0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7,
+        0xE8, 0xE9, 0xEA, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA,
+    ],
+};
+
+impl<const N: usize> HuffmanSpec<N> {
+    /// Returns the code and its length in bits for every symbol.
+    fn codes(&self) -> [(u16, u8); 256] {
+        let mut codes = [(0, 0); 256];
+        let mut symbols = self.symbols.iter();
+        let mut code = 0u16;
+        for (len, &count) in (1..=16).zip(&self.counts) {
+            for &symbol in symbols.by_ref().take(count as usize) {
+                codes[symbol as usize] = (code, len);
+                code += 1;
+            }
+            code <<= 1;
+        }
+        codes
+    }
+
+    fn write_segment(&self, class_and_id: u8, out: &mut Vec<u8>) {
+        out.push(class_and_id);
+        out.extend_from_slice(&self.counts);
+        out.extend_from_slice(&self.symbols);
+    }
+}
+
+/// The resolution of the chroma components of color images.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Subsampling {
+    /// Chroma at full resolution, which keeps colored text and thin lines sharp.
+    Chroma444,
+    /// Chroma at half the resolution in both directions, for noticeably smaller files of photos and video.
+    #[default]
+    Chroma420,
+}
+
+impl Subsampling {
+    /// Returns how many luma samples a chroma sample covers in each direction.
+    fn factor(self) -> usize {
+        match self {
+            Subsampling::Chroma444 => 1,
+            Subsampling::Chroma420 => 2,
+        }
+    }
+}
+
+/// A quantization table, stored in zigzag order as in the file.
+struct Quantization {
+    table: [u8; 64],
+    /// The reciprocal of every entry, in the order of the coefficients within a block.
+    scale: [f32; 64],
+}
+
+impl Quantization {
+    fn new(base: &[u16; 64], quality: u8) -> Quantization {
+        // The scaling of the Independent JPEG Group, used by most encoders.
+        let quality = quality.clamp(1, 100) as u32;
+        let factor = if quality < 50 {
+            5000 / quality
+        } else {
+            200 - quality * 2
+        };
+        let mut table = [0; 64];
+        let mut scale = [0.0; 64];
+        for (i, &pos) in ZIGZAG.iter().enumerate() {
+            let value = ((base[pos] as u32 * factor + 50) / 100).clamp(1, 255);
+            table[i] = value as u8;
+            scale[pos] = 1.0 / value as f32;
+        }
+        Quantization { table, scale }
+    }
+}
+
+/// Encodes images as baseline JPEG, reusing its buffers from one image to the next.
+pub struct JpegEncoder {
+    quality: u8,
+    subsampling: Subsampling,
+    restart_interval: u16,
+    luma: Quantization,
+    chroma: Quantization,
+    /// The basis of the DCT, `cos[u][x]` already multiplied by the normalization of frequency `u`.
+    cos: [[f32; 8]; 8],
+    luma_dc: [(u16, u8); 256],
+    luma_ac: [(u16, u8); 256],
+    chroma_dc: [(u16, u8); 256],
+    chroma_ac: [(u16, u8); 256],
+    /// The Y, Cb and Cr samples of one row of MCUs at full resolution, shifted to be centered around 0.
+    planes: [Vec<f32>; 3],
+    output: Vec<u8>,
+}
+
+impl Default for JpegEncoder {
+    fn default() -> JpegEncoder {
+        JpegEncoder::new()
+    }
+}
+
+impl JpegEncoder {
+    /// Creates an encoder with a quality of 75, 4:2:0 subsampling and no restart markers.
+    pub fn new() -> JpegEncoder {
+        let mut cos = [[0.0; 8]; 8];
+        for (u, row) in cos.iter_mut().enumerate() {
+            let norm = if u == 0 { 0.5 / 2f32.sqrt() } else { 0.5 };
+            for (x, c) in row.iter_mut().enumerate() {
+                *c = norm * ((2 * x + 1) as f32 * u as f32 * PI / 16.0).cos();
+            }
+        }
+        JpegEncoder {
+            quality: 75,
+            subsampling: Subsampling::default(),
+            restart_interval: 0,
+            luma: Quantization::new(&LUMA_QUANTIZATION, 75),
+            chroma: Quantization::new(&CHROMA_QUANTIZATION, 75),
+            cos,
+            luma_dc: LUMA_DC.codes(),
+            luma_ac: LUMA_AC.codes(),
+            chroma_dc: CHROMA_DC.codes(),
+            chroma_ac: CHROMA_AC.codes(),
+            planes: [Vec::new(), Vec::new(), Vec::new()],
+            output: Vec::new(),
+        }
+    }
+
+    /// Sets the quality from 1 (smallest files) to 100 (least loss), values outside are clamped.
+    pub fn quality(mut self, quality: u8) -> Self {
+        self.quality = quality.clamp(1, 100);
+        self.luma = Quantization::new(&LUMA_QUANTIZATION, self.quality);
+        self.chroma = Quantization::new(&CHROMA_QUANTIZATION, self.quality);
+        self
+    }
+
+    /// Sets the resolution of the chroma components, which doesn't affect grayscale images.
+    pub fn subsampling(mut self, subsampling: Subsampling) -> Self {
+        self.subsampling = subsampling;
+        self
+    }
+
+    /// Inserts a restart marker after every `interval` MCUs (blocks of 8x8 or 16x16 pixels), 0 disables them.
+    ///
+    /// Decoders can resynchronize at the markers, so a corrupted byte only damages the pixels up to the next one.
+    /// Every marker costs a few bytes.
+    pub fn restart_interval(mut self, interval: u16) -> Self {
+        self.restart_interval = interval;
+        self
+    }
+
+    /// Writes an image as a complete JPEG file to `writer`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::ErrorKind::InvalidInput`] error if the image is empty or larger than 65535 pixels in
+    /// either direction, and any error `writer` returns.
+    pub fn encode<'a>(
+        &mut self,
+        image: impl Into<ImageView<'a>>,
+        mut writer: impl Write,
+    ) -> io::Result<()> {
+        let image = image.into();
+        let size = (u16::try_from(image.width()), u16::try_from(image.height()));
+        let (Ok(width @ 1..), Ok(height @ 1..)) = size else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "JPEG images must be between 1 and 65535 pixels wide and high",
+            ));
+        };
+        let gray = image.format() == PixelFormat::Gray8;
+        let factor = if gray { 1 } else { self.subsampling.factor() };
+
+        self.output.clear();
+        self.write_headers(width, height, gray, factor);
+        self.scan(image, gray, factor);
+        self.output.extend_from_slice(&[0xFF, 0xD9]);
+        writer.write_all(&self.output)?;
+        writer.flush()
+    }
+
+    fn write_headers(&mut self, width: u16, height: u16, gray: bool, factor: usize) {
+        let out = &mut self.output;
+        out.extend_from_slice(&[0xFF, 0xD8]);
+        // JFIF 1.1 without a physical resolution (square pixels) or thumbnail.
+        marker(out, 0xE0, |out| {
+            out.extend_from_slice(b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0")
+        });
+
+        let tables: &[&Quantization] = if gray {
+            &[&self.luma]
+        } else {
+            &[&self.luma, &self.chroma]
+        };
+        marker(out, 0xDB, |out| {
+            for (id, table) in tables.iter().enumerate() {
+                out.push(id as u8);
+                out.extend_from_slice(&table.table);
+            }
+        });
+
+        // Baseline, 8 bits per sample. Components are numbered from 1: Y, Cb and Cr.
+        let luma_sampling = (factor as u8) << 4 | factor as u8;
+        marker(out, 0xC0, |out| {
+            out.push(8);
+            out.extend_from_slice(&height.to_be_bytes());
+            out.extend_from_slice(&width.to_be_bytes());
+            if gray {
+                out.extend_from_slice(&[1, 1, 0x11, 0]);
+            } else {
+                out.extend_from_slice(&[3, 1, luma_sampling, 0, 2, 0x11, 1, 3, 0x11, 1]);
+            }
+        });
+
+        marker(out, 0xC4, |out| {
+            LUMA_DC.write_segment(0x00, out);
+            LUMA_AC.write_segment(0x10, out);
+            if !gray {
+                CHROMA_DC.write_segment(0x01, out);
+                CHROMA_AC.write_segment(0x11, out);
+            }
+        });
+
+        if self.restart_interval > 0 {
+            let interval = self.restart_interval;
+            marker(out, 0xDD, |out| {
+                out.extend_from_slice(&interval.to_be_bytes())
+            });
+        }
+
+        // A single scan of all coefficients, the chroma components use the second pair of Huffman tables.
+        marker(out, 0xDA, |out| {
+            if gray {
+                out.extend_from_slice(&[1, 1, 0x00]);
+            } else {
+                out.extend_from_slice(&[3, 1, 0x00, 2, 0x11, 3, 0x11]);
+            }
+            out.extend_from_slice(&[0, 63, 0]);
+        });
+    }
+
+    /// Encodes the image MCU by MCU, writing the entropy-coded data.
+    fn scan(&mut self, image: ImageView, gray: bool, factor: usize) {
+        let mcu = 8 * factor;
+        let (width, height) = (image.width(), image.height());
+        let plane_width = width.div_ceil(mcu) * mcu;
+        let components = if gray { 1 } else { 3 };
+        for plane in &mut self.planes[..components] {
+            plane.clear();
+            plane.resize(plane_width * mcu, 0.0);
+        }
+
+        let mut bits = BitWriter::default();
+        let mut predictions = [0; 3];
+        // The MCUs left until the next restart marker, which is only written between two MCUs.
+        let mut until_restart = self.restart_interval;
+        let mut restarts = 0u8;
+        for top in (0..height).step_by(mcu) {
+            self.load_rows(image, top, mcu, plane_width, gray);
+            for left in (0..plane_width).step_by(mcu) {
+                if self.restart_interval > 0 {
+                    if until_restart == 0 {
+                        bits.flush(&mut self.output);
+                        self.output.extend_from_slice(&[0xFF, 0xD0 + restarts]);
+                        restarts = (restarts + 1) % 8;
+                        predictions = [0; 3];
+                        until_restart = self.restart_interval;
+                    }
+                    until_restart -= 1;
+                }
+
+                let mut block = [0.0; 64];
+                for by in (0..mcu).step_by(8) {
+                    for bx in (0..mcu).step_by(8) {
+                        let plane = &self.planes[0];
+                        for (row, out) in block.chunks_exact_mut(8).enumerate() {
+                            let start = (by + row) * plane_width + left + bx;
+                            out.copy_from_slice(&plane[start..start + 8]);
+                        }
+                        self.encode_block(&mut block, 0, &mut predictions[0], &mut bits);
+                    }
+                }
+                for (component, prediction) in
+                    predictions.iter_mut().enumerate().take(components).skip(1)
+                {
+                    self.chroma_block(component, left, factor, plane_width, &mut block);
+                    self.encode_block(&mut block, component, prediction, &mut bits);
+                }
+            }
+        }
+        bits.flush(&mut self.output);
+    }
+
+    /// Converts the rows `top..top + count` into the planes, repeating the last row and column of the image to fill
+    /// whole MCUs.
+    fn load_rows(
+        &mut self,
+        image: ImageView,
+        top: usize,
+        count: usize,
+        plane_width: usize,
+        gray: bool,
+    ) {
+        let width = image.width();
+        let (bpp, [r, g, b]) = (
+            image.format().bytes_per_pixel(),
+            image.format().rgb_offsets(),
+        );
+        let [luma, cb, cr] = &mut self.planes;
+        for row in 0..count {
+            let src = image.row((top + row).min(image.height() - 1));
+            let range = row * plane_width..(row + 1) * plane_width;
+            let luma = &mut luma[range.clone()];
+            if gray {
+                for (out, &p) in luma.iter_mut().zip(src) {
+                    *out = p as f32 - 128.0;
+                }
+            } else {
+                let (cb, cr) = (&mut cb[range.clone()], &mut cr[range]);
+                for (x, p) in src.chunks_exact(bpp).enumerate() {
+                    let (r, g, b) = (p[r] as f32, p[g] as f32, p[b] as f32);
+                    // BT.601 with full range, as JFIF prescribes.
+                    luma[x] = 0.299 * r + 0.587 * g + 0.114 * b - 128.0;
+                    cb[x] = -0.168_736 * r - 0.331_264 * g + 0.5 * b;
+                    cr[x] = 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+                }
+                let last = cb[width - 1];
+                cb[width..].fill(last);
+                let last = cr[width - 1];
+                cr[width..].fill(last);
+            }
+            let last = luma[width - 1];
+            luma[width..].fill(last);
+        }
+    }
+
+    /// Fills `block` with the chroma samples of the MCU starting at `left`, averaging `factor`x`factor` samples.
+    fn chroma_block(
+        &self,
+        component: usize,
+        left: usize,
+        factor: usize,
+        plane_width: usize,
+        block: &mut [f32; 64],
+    ) {
+        let plane = &self.planes[component];
+        let weight = 1.0 / (factor * factor) as f32;
+        for (i, out) in block.iter_mut().enumerate() {
+            let (x, y) = (left + i % 8 * factor, i / 8 * factor);
+            let mut sum = 0.0;
+            for dy in 0..factor {
+                let start = (y + dy) * plane_width + x;
+                sum += plane[start..start + factor].iter().sum::<f32>();
+            }
+            *out = sum * weight;
+        }
+    }
+
+    /// Transforms, quantizes and writes a block of samples.
+    fn encode_block(
+        &mut self,
+        block: &mut [f32; 64],
+        component: usize,
+        prediction: &mut i32,
+        bits: &mut BitWriter,
+    ) {
+        self.fdct(block);
+        let (quantization, dc, ac) = if component == 0 {
+            (&self.luma, &self.luma_dc, &self.luma_ac)
+        } else {
+            (&self.chroma, &self.chroma_dc, &self.chroma_ac)
+        };
+        let mut coefficients = [0i32; 64];
+        for (out, &pos) in coefficients.iter_mut().zip(&ZIGZAG) {
+            *out = (block[pos] * quantization.scale[pos]).round() as i32;
+        }
+        // The standard tables only have codes for magnitudes up to 2047 (DC differences) and 1023 (AC), which the
+        // highest qualities can exceed in extreme blocks.
+        coefficients[0] = coefficients[0].clamp(-1023, 1023);
+        let diff = coefficients[0] - *prediction;
+        *prediction = coefficients[0];
+        let category = magnitude_bits(diff);
+        bits.write_code(dc[category as usize], &mut self.output);
+        bits.write_value(diff, category, &mut self.output);
+
+        let mut run = 0;
+        for &value in &coefficients[1..] {
+            if value == 0 {
+                run += 1;
+                continue;
+            }
+            while run > 15 {
+                bits.write_code(ac[0xF0], &mut self.output);
+                run -= 16;
+            }
+            let value = value.clamp(-1023, 1023);
+            let category = magnitude_bits(value);
+            bits.write_code(ac[(run << 4 | category) as usize], &mut self.output);
+            bits.write_value(value, category, &mut self.output);
+            run = 0;
+        }
+        if run > 0 {
+            bits.write_code(ac[0x00], &mut self.output);
+        }
+    }
+
+    /// Applies the two-dimensional discrete cosine transform in place, as two passes of 8 one-dimensional ones.
+    fn fdct(&self, block: &mut [f32; 64]) {
+        let mut rows = [0.0; 64];
+        for (src, out) in block.chunks_exact(8).zip(rows.chunks_exact_mut(8)) {
+            for (out, cos) in out.iter_mut().zip(&self.cos) {
+                *out = src.iter().zip(cos).map(|(s, c)| s * c).sum();
+            }
+        }
+        for u in 0..8 {
+            for (v, cos) in self.cos.iter().enumerate() {
+                block[v * 8 + u] = (0..8).map(|y| rows[y * 8 + u] * cos[y]).sum();
+            }
+        }
+    }
+}
+
+/// Appends a marker segment to `out`, with the length of the payload that `payload` writes.
+fn marker(out: &mut Vec<u8>, marker: u8, payload: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    out.extend_from_slice(&[0xFF, marker, 0, 0]);
+    payload(out);
+    let len = (out.len() - start - 2) as u16;
+    out[start + 2..start + 4].copy_from_slice(&len.to_be_bytes());
+}
+
+/// Returns the number of bits needed for the magnitude of `value`, which JPEG calls its category.
+fn magnitude_bits(value: i32) -> u8 {
+    (32 - value.unsigned_abs().leading_zeros()) as u8
+}
+
+/// Writes bits starting at the most significant bit of every byte, stuffing a zero byte after every 0xFF byte so
+/// it can't be mistaken for a marker.
+#[derive(Default)]
+struct BitWriter {
+    acc: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u8, out: &mut Vec<u8>) {
+        self.acc = self.acc << count | value & ((1 << count) - 1);
+        self.count += count as u32;
+        while self.count >= 8 {
+            self.count -= 8;
+            let byte = (self.acc >> self.count) as u8;
+            out.push(byte);
+            if byte == 0xFF {
+                out.push(0);
+            }
+        }
+        self.acc &= (1 << self.count) - 1;
+    }
+
+    fn write_code(&mut self, (code, len): (u16, u8), out: &mut Vec<u8>) {
+        self.write(code as u32, len, out);
+    }
+
+    /// Writes the low `category` bits of `value`, or of `value - 1` for negative values, as JPEG stores them.
+    fn write_value(&mut self, value: i32, category: u8, out: &mut Vec<u8>) {
+        let bits = if value < 0 { value - 1 } else { value };
+        self.write(bits as u32, category, out);
+    }
+
+    /// Pads the written bits with ones to a whole byte.
+    fn flush(&mut self, out: &mut Vec<u8>) {
+        if self.count > 0 {
+            let pad = 8 - self.count as u8;
+            self.write((1 << pad) - 1, pad, out);
+        }
+    }
+}
+
+/// Writes an image as a JPEG file with the given quality and 4:2:0 subsampling to `writer`, see
+/// [`JpegEncoder::encode`].
+pub fn encode<'a>(
+    image: impl Into<ImageView<'a>>,
+    writer: impl Write,
+    quality: u8,
+) -> io::Result<()> {
+    JpegEncoder::new().quality(quality).encode(image, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{fixtures, Frame};
+
+    /// Reads the bits of entropy-coded data, with the stuffed zero bytes already removed.
+    struct BitReader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bits(&mut self, count: u8) -> i32 {
+            let mut value = 0;
+            for _ in 0..count {
+                let bit = self.data[self.pos / 8] >> (7 - self.pos % 8) & 1;
+                value = value << 1 | bit as i32;
+                self.pos += 1;
+            }
+            value
+        }
+
+        /// Reads a value of the given category, negative values are stored as `value - 1`.
+        fn value(&mut self, category: u8) -> i32 {
+            let bits = self.bits(category);
+            if category > 0 && bits < 1 << (category - 1) {
+                bits - (1 << category) + 1
+            } else {
+                bits
+            }
+        }
+    }
+
+    /// A Huffman table as stored in a DHT segment, decoded one bit at a time.
+    struct Table {
+        class_and_id: u8,
+        counts: [u8; 16],
+        symbols: Vec<u8>,
+    }
+
+    impl Table {
+        fn decode(&self, bits: &mut BitReader) -> u8 {
+            let (mut code, mut first, mut index) = (0, 0, 0);
+            for &count in &self.counts {
+                code |= bits.bits(1);
+                if code - first < count as i32 {
+                    return self.symbols[index + (code - first) as usize];
+                }
+                index += count as usize;
+                first = (first + count as i32) << 1;
+                code <<= 1;
+            }
+            panic!("invalid Huffman code");
+        }
+    }
+
+    /// The parts of a file the tests look at, besides the decoded image.
+    struct Decoded {
+        frame: Frame,
+        /// The quantization tables in zigzag order.
+        quantization: Vec<[u8; 64]>,
+        restart_markers: usize,
+    }
+
+    /// Splits the entropy-coded data into the intervals between restart markers, removing the stuffed bytes.
+    fn intervals(data: &[u8]) -> Vec<Vec<u8>> {
+        let mut intervals = vec![Vec::new()];
+        let mut i = 0;
+        loop {
+            if data[i] != 0xFF {
+                intervals.last_mut().unwrap().push(data[i]);
+                i += 1;
+                continue;
+            }
+            match data[i + 1] {
+                0x00 => intervals.last_mut().unwrap().push(0xFF),
+                marker @ 0xD0..=0xD7 => {
+                    assert_eq!(marker, 0xD0 + (intervals.len() - 1) as u8 % 8);
+                    intervals.push(Vec::new());
+                }
+                0xD9 => {
+                    assert_eq!(i + 2, data.len(), "data after the end of the image");
+                    return intervals;
+                }
+                marker => panic!("unexpected marker {marker:02X}"),
+            }
+            i += 2;
+        }
+    }
+
+    /// Decodes a block into `plane`, whose rows are `stride` samples long.
+    #[allow(clippy::too_many_arguments)]
+    fn decode_block(
+        bits: &mut BitReader,
+        dc: &Table,
+        ac: &Table,
+        quantization: &[u8; 64],
+        prediction: &mut i32,
+        plane: &mut [f32],
+        stride: usize,
+        (x, y): (usize, usize),
+    ) {
+        let category = dc.decode(bits);
+        *prediction += bits.value(category);
+        let mut coefficients = [0.0f32; 64];
+        coefficients[0] = (*prediction * quantization[0] as i32) as f32;
+        let mut k = 1;
+        while k < 64 {
+            let symbol = ac.decode(bits);
+            if symbol == 0x00 {
+                break;
+            }
+            k += (symbol >> 4) as usize;
+            let category = symbol & 0xF;
+            if category > 0 {
+                coefficients[ZIGZAG[k]] = (bits.value(category) * quantization[k] as i32) as f32;
+            }
+            k += 1;
+        }
+        assert!(k <= 64, "coefficients past the end of the block");
+
+        // The basis is orthonormal, so the inverse transform uses its transpose.
+        let cos = JpegEncoder::new().cos;
+        for row in 0..8 {
+            for col in 0..8 {
+                let mut sum = 0.0;
+                for v in 0..8 {
+                    for u in 0..8 {
+                        sum += cos[v][row] * cos[u][col] * coefficients[v * 8 + u];
+                    }
+                }
+                plane[(y + row) * stride + x + col] = sum;
+            }
+        }
+    }
+
+    /// Decodes a file written by the encoder, checking its structure on the way.
+    fn decode(file: &[u8]) -> Decoded {
+        assert_eq!(&file[..2], &[0xFF, 0xD8]);
+        let mut pos = 2;
+        let mut quantization = Vec::new();
+        let mut tables = Vec::new();
+        let (mut width, mut height, mut factor, mut components) = (0, 0, 1, 0);
+        let mut interval = 0;
+        loop {
+            assert_eq!(file[pos], 0xFF);
+            let marker = file[pos + 1];
+            let len = u16::from_be_bytes([file[pos + 2], file[pos + 3]]) as usize;
+            let payload = &file[pos + 4..pos + 2 + len];
+            pos += 2 + len;
+            match marker {
+                0xE0 => assert_eq!(&payload[..5], b"JFIF\0"),
+                0xDB => {
+                    for (id, table) in payload.chunks(65).enumerate() {
+                        assert_eq!(table[0] as usize, id);
+                        quantization.push(table[1..].try_into().unwrap());
+                    }
+                }
+                0xC0 => {
+                    assert_eq!(payload[0], 8);
+                    height = u16::from_be_bytes([payload[1], payload[2]]) as usize;
+                    width = u16::from_be_bytes([payload[3], payload[4]]) as usize;
+                    components = payload[5] as usize;
+                    factor = (payload[7] >> 4) as usize;
+                    assert_eq!(payload[7], (factor << 4 | factor) as u8);
+                }
+                0xC4 => {
+                    let mut rest = payload;
+                    while !rest.is_empty() {
+                        let counts: [u8; 16] = rest[1..17].try_into().unwrap();
+                        let count = counts.iter().map(|&c| c as usize).sum::<usize>();
+                        tables.push(Table {
+                            class_and_id: rest[0],
+                            counts,
+                            symbols: rest[17..17 + count].to_vec(),
+                        });
+                        rest = &rest[17 + count..];
+                    }
+                }
+                0xDD => interval = u16::from_be_bytes([payload[0], payload[1]]) as usize,
+                0xDA => {
+                    assert_eq!(payload[0] as usize, components);
+                    break;
+                }
+                marker => panic!("unexpected marker {marker:02X}"),
+            }
+        }
+        let table = |class_and_id| {
+            tables
+                .iter()
+                .find(|t| t.class_and_id == class_and_id)
+                .unwrap()
+        };
+
+        let mcu = 8 * factor;
+        let (mcus_x, mcus_y) = (width.div_ceil(mcu), height.div_ceil(mcu));
+        let stride = mcus_x * mcu;
+        let mut planes = vec![vec![0.0; stride * mcus_y * mcu]; components];
+        let total = mcus_x * mcus_y;
+        let per_interval = if interval > 0 { interval } else { total };
+        let intervals = intervals(&file[pos..]);
+        assert_eq!(intervals.len(), total.div_ceil(per_interval));
+        for (n, data) in intervals.iter().enumerate() {
+            let mut bits = BitReader { data, pos: 0 };
+            let mut predictions = [0; 3];
+            for m in n * per_interval..((n + 1) * per_interval).min(total) {
+                let (left, top) = (m % mcus_x * mcu, m / mcus_x * mcu);
+                for by in (0..mcu).step_by(8) {
+                    for bx in (0..mcu).step_by(8) {
+                        let at = (left + bx, top + by);
+                        let (dc, ac) = (table(0x00), table(0x10));
+                        let (q, p) = (&quantization[0], &mut predictions[0]);
+                        decode_block(&mut bits, dc, ac, q, p, &mut planes[0], stride, at);
+                    }
+                }
+                for c in 1..components {
+                    let at = (left / factor, top / factor);
+                    let (dc, ac) = (table(0x01), table(0x11));
+                    let (q, p) = (&quantization[1], &mut predictions[c]);
+                    decode_block(&mut bits, dc, ac, q, p, &mut planes[c], stride / factor, at);
+                }
+            }
+            // Only the padding of ones is left.
+            let left = data.len() * 8 - bits.pos;
+            assert!(left < 8);
+            assert_eq!(bits.bits(left as u8), (1 << left) - 1);
+        }
+
+        let sample = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+        let mut bits = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let luma = planes[0][y * stride + x] + 128.0;
+                if components == 1 {
+                    bits.push(sample(luma));
+                    continue;
+                }
+                let chroma = (y / factor) * (stride / factor) + x / factor;
+                let (cb, cr) = (planes[1][chroma], planes[2][chroma]);
+                bits.push(sample(luma + 1.772 * cb));
+                bits.push(sample(luma - 0.344_136 * cb - 0.714_136 * cr));
+                bits.push(sample(luma + 1.402 * cr));
+            }
+        }
+        let format = if components == 1 {
+            PixelFormat::Gray8
+        } else {
+            PixelFormat::Bgr24
+        };
+        Decoded {
+            frame: Frame::from_vec(bits, width, height, format),
+            quantization,
+            restart_markers: intervals.len() - 1,
+        }
+    }
+
+    /// Returns a frame of smooth gradients, whose rows are padded by `padding` bytes.
+    fn gradient(width: usize, height: usize, padding: usize, format: PixelFormat) -> Frame {
+        fixtures::padded(width, height, padding, format, |x, y| {
+            [(x * 4) as u8, (y * 3) as u8, ((x + y) * 2) as u8, 255]
+        })
+    }
+
+    fn encoded(encoder: &mut JpegEncoder, image: &Frame) -> Vec<u8> {
+        fixtures::encoded(|file| encoder.encode(image, file))
+    }
+
+    /// Returns the largest and the mean absolute difference between the samples of two frames.
+    fn error(image: &Frame, decoded: &Frame) -> (u8, f64) {
+        let expected = image.to_format(decoded.format());
+        let diffs: Vec<u8> = expected
+            .bits()
+            .iter()
+            .zip(decoded.bits())
+            .map(|(a, b)| a.abs_diff(*b))
+            .collect();
+        let mean = diffs.iter().map(|&d| d as f64).sum::<f64>() / diffs.len() as f64;
+        (diffs.iter().copied().max().unwrap(), mean)
+    }
+
+    #[test]
+    fn round_trips_all_formats_and_odd_sizes() {
+        let formats = [
+            PixelFormat::Bgr24,
+            PixelFormat::Bgra32,
+            PixelFormat::Rgb24,
+            PixelFormat::Rgba32,
+            PixelFormat::Gray8,
+        ];
+        for format in formats {
+            for (width, height) in [(1, 1), (7, 3), (17, 9), (33, 20)] {
+                for padding in [0, 3] {
+                    let image = gradient(width, height, padding, format);
+                    let mut encoder = JpegEncoder::new()
+                        .quality(100)
+                        .subsampling(Subsampling::Chroma444);
+                    let decoded = decode(&encoded(&mut encoder, &image)).frame;
+                    assert_eq!((decoded.width(), decoded.height()), (width, height));
+                    let (max, mean) = error(&image, &decoded);
+                    assert!(
+                        max <= 4 && mean < 1.0,
+                        "{format:?} {width}x{height}: {max} {mean}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn subsampled_chroma_stays_close() {
+        for (width, height) in [(1, 1), (15, 17), (40, 33)] {
+            let image = gradient(width, height, 0, PixelFormat::Bgra32);
+            let decoded = decode(&encoded(&mut JpegEncoder::new().quality(90), &image)).frame;
+            let (max, mean) = error(&image, &decoded);
+            assert!(max <= 16 && mean < 3.0, "{width}x{height}: {max} {mean}");
+        }
+    }
+
+    #[test]
+    fn extreme_blocks_stay_within_the_standard_tables() {
+        // Alternating black and white pixels have the largest high-frequency coefficients.
+        let bits = (0..32 * 16 * 3)
+            .map(|i| if (i / 3 + i / 96) % 2 == 0 { 0 } else { 255 })
+            .collect();
+        let image = Frame::from_vec(bits, 32, 16, PixelFormat::Bgr24);
+        for quality in [1, 50, 100] {
+            let mut encoder = JpegEncoder::new()
+                .quality(quality)
+                .subsampling(Subsampling::Chroma444);
+            let decoded = decode(&encoded(&mut encoder, &image)).frame;
+            if quality == 100 {
+                assert!(error(&image, &decoded).1 < 2.0);
+            }
+        }
+    }
+
+    #[test]
+    fn restart_markers_cycle_between_mcus() {
+        // 5x3 MCUs of 16x16 pixels.
+        let image = gradient(80, 48, 0, PixelFormat::Bgr24);
+        for (interval, markers) in [(0, 0), (1, 14), (3, 4), (4, 3), (15, 0), (100, 0)] {
+            let mut encoder = JpegEncoder::new().restart_interval(interval);
+            let decoded = decode(&encoded(&mut encoder, &image));
+            assert_eq!(decoded.restart_markers, markers, "interval {interval}");
+            assert!(error(&image, &decoded.frame).1 < 3.0);
+        }
+    }
+
+    #[test]
+    fn quantization_tables_follow_the_quality() {
+        let image = gradient(8, 8, 0, PixelFormat::Bgr24);
+        let tables = |quality| {
+            decode(&encoded(&mut JpegEncoder::new().quality(quality), &image)).quantization
+        };
+        let zigzag = |table: &[u16; 64]| ZIGZAG.map(|pos| table[pos] as u8);
+        assert_eq!(
+            tables(50),
+            [zigzag(&LUMA_QUANTIZATION), zigzag(&CHROMA_QUANTIZATION)]
+        );
+        assert_eq!(tables(100), [[1; 64], [1; 64]]);
+        assert_eq!(tables(1), [[255; 64], [255; 64]]);
+        let gray = gradient(8, 8, 0, PixelFormat::Gray8);
+        assert_eq!(
+            decode(&encoded(&mut JpegEncoder::new(), &gray))
+                .quantization
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn encoder_output_does_not_depend_on_earlier_images_or_padding() {
+        let mut encoder = JpegEncoder::new();
+        let first = encoded(&mut encoder, &gradient(21, 13, 0, PixelFormat::Bgr24));
+        encoded(&mut encoder, &gradient(64, 64, 0, PixelFormat::Gray8));
+        assert_eq!(
+            encoded(&mut encoder, &gradient(21, 13, 7, PixelFormat::Bgr24)),
+            first
+        );
+    }
+
+    #[test]
+    fn unsupported_sizes_are_rejected() {
+        let invalid = |image: &Frame| {
+            fixtures::fails_with(
+                JpegEncoder::new().encode(image, io::sink()),
+                io::ErrorKind::InvalidInput,
+            )
+        };
+        assert!(invalid(&Frame::new(0, 0, PixelFormat::Bgr24)));
+        assert!(invalid(&Frame::new(0, 5, PixelFormat::Bgr24)));
+        assert!(invalid(&Frame::new(65536, 1, PixelFormat::Gray8)));
+    }
+
+    #[test]
+    fn huffman_codes_are_canonical() {
+        // Annex K.3 of the specification.
+        let dc = LUMA_DC.codes();
+        assert_eq!(dc[0], (0b00, 2));
+        assert_eq!(dc[1], (0b010, 3));
+        assert_eq!(dc[5], (0b110, 3));
+        assert_eq!(dc[6], (0b1110, 4));
+        assert_eq!(dc[11], (0b1_1111_1110, 9));
+        let ac = LUMA_AC.codes();
+        assert_eq!(ac[0x00], (0b1010, 4));
+        assert_eq!(ac[0x01], (0b00, 2));
+        assert_eq!(ac[0xF0], (0b111_1111_1001, 11));
+    }
+
+    #[test]
+    fn magnitude_categories() {
+        let cases = [
+            (0, 0),
+            (1, 1),
+            (-1, 1),
+            (2, 2),
+            (-3, 2),
+            (4, 3),
+            (1023, 10),
+            (-1024, 11),
+            (2047, 11),
+        ];
+        for (value, category) in cases {
+            assert_eq!(magnitude_bits(value), category, "{value}");
+        }
+    }
+}
@@ -1,6 +1,60 @@
-#![cfg(windows)]
-
+mod backend;
+pub mod bmp;
 mod capture;
+mod capture_loop;
+pub mod convert;
+pub mod diff;
+mod error;
+#[cfg(target_os = "linux")]
+mod fbdev;
+mod format;
+mod frame;
+#[cfg(windows)]
+mod gdi;
+pub mod hash;
+#[cfg(feature = "jpeg")]
+pub mod jpeg;
+pub mod locate;
+#[cfg(feature = "png")]
+pub mod png;
+pub mod pnm;
+mod pool;
+pub mod qoi;
+mod rect;
+mod regions;
+pub mod scale;
+pub mod search;
+#[cfg(feature = "async")]
+mod stream;
+mod synthetic;
+mod view;
+#[cfg(all(target_os = "linux", feature = "wayland"))]
+mod wayland;
+#[cfg(all(unix, feature = "x11"))]
+mod x11;
+pub mod yuv;
 
+pub use crate::backend::CaptureBackend;
 pub use crate::capture::CaptureData;
 pub use crate::capture::CaptureManager;
+pub use crate::capture_loop::{
+    CaptureLoop, Clock, LoopHandle, LoopStats, MockClock, MonotonicClock,
+};
+pub use crate::error::Error;
+#[cfg(target_os = "linux")]
+pub use crate::fbdev::{Channel, FbLayout, FbdevBackend, FbdevTarget};
+pub use crate::format::PixelFormat;
+pub use crate::frame::Frame;
+#[cfg(windows)]
+pub use crate::gdi::GdiBackend;
+pub use crate::pool::FramePool;
+pub use crate::rect::Rect;
+pub use crate::regions::Atlas;
+#[cfg(feature = "async")]
+pub use crate::stream::{Backpressure, FrameStream, Next};
+pub use crate::synthetic::{Direction, Script, SyntheticBackend, SyntheticError};
+pub use crate::view::ImageView;
+#[cfg(all(target_os = "linux", feature = "wayland"))]
+pub use crate::wayland::{OutputSelector, WaylandBackend, WaylandError, WaylandTarget};
+#[cfg(all(unix, feature = "x11"))]
+pub use crate::x11::{X11Backend, X11Error, X11Target};
@@ -0,0 +1,720 @@
+//! Locating a smaller image, the needle, within a captured image, the haystack.
+//!
+//! A [`Locator`] compares the needle with every position of the haystack and scores how well it matches, either by
+//! the [squared differences](Method::SquaredDifference) of their pixels or by their
+//! [normalized cross-correlation](Method::CrossCorrelation). Scores are turned into a confidence between 0 and 1,
+//! where 1 is a perfect match.
+//!
+//! Comparing every position at full resolution is slow for large needles, so both images are first reduced by the
+//! same factor, chosen such that the needle keeps about 8 pixels along its shorter side. The best positions of the
+//! reduced images are then refined at full resolution. The reduced search can be done in grayscale with
+//! [`Locator::grayscale`], which is faster but can't tell apart colors of the same brightness.
+//!
+//! Needles that appear at a different size, e.g. on a display with another scale factor, are found by searching at
+//! several [`scales`](Locator::scales).
+
+use crate::convert::Converter;
+#[cfg(target_arch = "x86_64")]
+use crate::convert::Isa;
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use crate::rect::Rect;
+use crate::scale::{self, Filter};
+use crate::view::ImageView;
+
+/// The number of pixels kept along the shorter side of the needle in the reduced search.
+const REDUCED_SIZE: usize = 8;
+
+/// The way the needle is compared with the haystack.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Method {
+    /// The sum of the squared differences of all channels. The confidence is 1 minus the root mean square of the
+    /// differences relative to 255.
+    ///
+    /// Best for finding exact copies of the needle, e.g. icons or buttons, but sensitive to changes of brightness.
+    #[default]
+    SquaredDifference,
+    /// The zero-mean normalized cross-correlation of all channels, i.e. how similar the patterns of light and dark
+    /// areas are regardless of their overall brightness and contrast. Negative correlations have a confidence of 0.
+    ///
+    /// Areas without any contrast only match needles without any contrast of about the same brightness.
+    CrossCorrelation,
+}
+
+/// A position at which the needle was found.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Match {
+    /// The area of the haystack covered by the needle, in the coordinates of the whole haystack.
+    pub area: Rect,
+    /// How well the needle matches, from 0 to 1.
+    pub confidence: f64,
+    /// The scale the needle was found at, see [`Locator::scales`].
+    pub scale: f64,
+}
+
+/// The sums of a needle's values needed for scoring.
+struct Needle {
+    frame: Frame,
+    sum: u64,
+    sum_sq: u64,
+}
+
+impl Needle {
+    fn new(frame: Frame) -> Needle {
+        let (mut sum, mut sum_sq) = (0, 0);
+        for row in frame.rows() {
+            sum += row.iter().map(|&v| v as u64).sum::<u64>();
+            sum_sq += row.iter().map(|&v| v as u64 * v as u64).sum::<u64>();
+        }
+        Needle { frame, sum, sum_sq }
+    }
+
+    fn width(&self) -> usize {
+        self.frame.width()
+    }
+
+    fn height(&self) -> usize {
+        self.frame.height()
+    }
+}
+
+/// A reusable template matcher.
+///
+/// By default it looks for exact copies of the needle using [`Method::SquaredDifference`] in color, at the
+/// original scale, and accepts matches with a confidence of at least 0.9.
+///
+/// # Examples
+///
+/// ```
+/// use qshot::locate::{Locator, Method};
+/// use qshot::{Frame, PixelFormat, Rect};
+///
+/// let bits = (0..320 * 200)
+///     .flat_map(|i| {
+///         let (x, y) = (i % 320, i / 320);
+///         [(x * 7 ^ y * 13) as u8, (x + y) as u8, (x * y / 64) as u8, 255]
+///     })
+///     .collect();
+/// let screen = Frame::from_vec(bits, 320, 200, PixelFormat::Bgra32);
+///
+/// // Cut out a part of the screen, brighten it and find it again.
+/// let mut needle = screen.crop(Rect::new(100, 50, 24, 16)).to_format(PixelFormat::Bgr24);
+/// needle.bits_mut().iter_mut().for_each(|v| *v = v.saturating_add(10));
+///
+/// let found = Locator::new().locate(&screen, &needle).unwrap();
+/// assert_eq!(found.area, Rect::new(100, 50, 24, 16));
+///
+/// let correlation = Locator::new().method(Method::CrossCorrelation);
+/// let found = correlation.locate(&screen, &needle).unwrap();
+/// assert_eq!(found.area, Rect::new(100, 50, 24, 16));
+/// assert!(found.confidence > 0.95);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Locator {
+    method: Method,
+    min_confidence: f64,
+    limit: usize,
+    grayscale: bool,
+    scales: Vec<f64>,
+    area: Option<Rect>,
+}
+
+impl Default for Locator {
+    fn default() -> Locator {
+        Locator::new()
+    }
+}
+
+impl Locator {
+    /// Creates a locator with the default settings.
+    pub fn new() -> Locator {
+        Locator {
+            method: Method::SquaredDifference,
+            min_confidence: 0.9,
+            limit: 16,
+            grayscale: false,
+            scales: vec![1.0],
+            area: None,
+        }
+    }
+
+    /// Sets the way the needle is compared with the haystack.
+    pub fn method(mut self, method: Method) -> Self {
+        self.method = method;
+        self
+    }
+
+    /// Sets the lowest confidence of the returned matches.
+    pub fn min_confidence(mut self, confidence: f64) -> Self {
+        self.min_confidence = confidence;
+        self
+    }
+
+    /// Sets the largest number of matches returned by [`locate_all`](Locator::locate_all), 16 by default.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Sets whether the reduced search, which picks the positions compared at full resolution, is done in
+    /// grayscale instead of color.
+    pub fn grayscale(mut self, grayscale: bool) -> Self {
+        self.grayscale = grayscale;
+        self
+    }
+
+    /// Sets the scales at which the needle is searched for, e.g. `[1.0, 1.25, 1.5]` to also find it on displays
+    /// scaled to 125% and 150%.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no scale is given or a scale is not positive.
+    pub fn scales(mut self, scales: &[f64]) -> Self {
+        assert!(!scales.is_empty(), "no scales given");
+        assert!(scales.iter().all(|&s| s > 0.0), "scales must be positive");
+        self.scales = scales.to_vec();
+        self
+    }
+
+    /// Restricts the search to the part of the haystack within `area`.
+    pub fn within(mut self, area: Rect) -> Self {
+        self.area = Some(area);
+        self
+    }
+
+    /// Returns the best match of the needle in the haystack, or `None` if there is none with a high enough
+    /// confidence.
+    pub fn locate<'a, 'b>(
+        &self,
+        haystack: impl Into<ImageView<'a>>,
+        needle: impl Into<ImageView<'b>>,
+    ) -> Option<Match> {
+        self.search(haystack.into(), needle.into(), 1)
+            .into_iter()
+            .next()
+    }
+
+    /// Returns the best matches of the needle in the haystack, ordered by decreasing confidence.
+    ///
+    /// Matches don't overlap each other, a match is dropped in favor of a better match covering part of the same
+    /// area. At most [`limit`](Locator::limit) matches with a high enough confidence are returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qshot::locate::Locator;
+    /// use qshot::{Frame, PixelFormat, Rect};
+    ///
+    /// // Three squares of decreasing brightness on black.
+    /// let mut haystack = Frame::new(200, 100, PixelFormat::Bgr24);
+    /// for (x, y, value) in [(20, 20, 255), (120, 60, 240), (150, 10, 230)] {
+    ///     for row in y..y + 10 {
+    ///         let start = row * haystack.stride() + x * 3;
+    ///         haystack.bits_mut()[start..start + 30].fill(value);
+    ///     }
+    /// }
+    /// let needle = haystack.crop(Rect::new(15, 15, 20, 20));
+    ///
+    /// let matches = Locator::new().limit(3).locate_all(&haystack, &needle);
+    /// assert_eq!(matches.len(), 3);
+    /// assert_eq!(matches[0].area, Rect::new(15, 15, 20, 20));
+    /// assert_eq!(matches[1].area, Rect::new(115, 55, 20, 20));
+    /// assert_eq!(matches[2].area, Rect::new(145, 5, 20, 20));
+    /// assert!(matches[2].confidence < matches[1].confidence);
+    /// ```
+    pub fn locate_all<'a, 'b>(
+        &self,
+        haystack: impl Into<ImageView<'a>>,
+        needle: impl Into<ImageView<'b>>,
+    ) -> Vec<Match> {
+        self.search(haystack.into(), needle.into(), self.limit)
+    }
+
+    fn search(&self, haystack: ImageView, needle: ImageView, limit: usize) -> Vec<Match> {
+        let bounds = haystack.bounds();
+        let within = self.area.map_or(bounds, |area| area.clamp(&bounds));
+        let haystack = packed(haystack.sub_view(within), PixelFormat::Bgr24);
+        let needle = packed(needle, PixelFormat::Bgr24);
+        if limit == 0 || needle.width() == 0 || needle.height() == 0 {
+            return Vec::new();
+        }
+
+        let mut reduced: Vec<(usize, Frame)> = Vec::new();
+        let mut found = Vec::new();
+        for &scale in &self.scales {
+            let width = (needle.width() as f64 * scale).round() as usize;
+            let height = (needle.height() as f64 * scale).round() as usize;
+            if width == 0 || height == 0 || width > haystack.width() || height > haystack.height() {
+                continue;
+            }
+            let filter = if scale < 1.0 {
+                Filter::Box
+            } else {
+                Filter::Bilinear
+            };
+            let scaled = if scale == 1.0 {
+                needle.clone()
+            } else {
+                scale::scale(&needle, width, height, filter)
+            };
+
+            let factor = (width.min(height) / REDUCED_SIZE).max(1);
+            let index = match reduced.iter().position(|(f, _)| *f == factor) {
+                Some(index) => index,
+                None => {
+                    let (width, height) = (haystack.width() / factor, haystack.height() / factor);
+                    reduced.push((factor, self.reduce(&haystack, width, height)));
+                    reduced.len() - 1
+                }
+            };
+            let full = (haystack.width(), haystack.height());
+            let candidates = self.candidates(&reduced[index].1, full, &scaled, factor, limit);
+
+            let needle = Needle::new(scaled);
+            for (x, y) in candidates {
+                let (confidence, (x, y)) = self.refine(&haystack, &needle, (x, y), factor);
+                if confidence >= self.min_confidence {
+                    let area = Rect::new(x as i32, y as i32, width as i32, height as i32);
+                    found.push(Match {
+                        area: area.offset(within.x, within.y),
+                        confidence,
+                        scale,
+                    });
+                }
+            }
+        }
+        suppress(found, limit)
+    }
+
+    /// Reduces an image to the given size, converting it to grayscale for a grayscale search.
+    fn reduce(&self, image: &Frame, width: usize, height: usize) -> Frame {
+        let (width, height) = (width.max(1), height.max(1));
+        let reduced = if (width, height) == (image.width(), image.height()) {
+            image.clone()
+        } else {
+            scale::scale(image, width, height, Filter::Box)
+        };
+        if self.grayscale {
+            reduced.to_format(PixelFormat::Gray8)
+        } else {
+            reduced
+        }
+    }
+
+    /// Returns the most promising positions of the needle at full resolution, found by comparing the needle with the
+    /// haystack reduced by about `factor` from its `full` size.
+    ///
+    /// The reduced needle only lines up with the reduced haystack at multiples of the factor, so it is also compared
+    /// with its first rows and columns cut off by half a factor.
+    fn candidates(
+        &self,
+        haystack: &Frame,
+        full: (usize, usize),
+        needle: &Frame,
+        factor: usize,
+        limit: usize,
+    ) -> Vec<(usize, usize)> {
+        // The exact factors, which differ from `factor` if the full size is not a multiple of it.
+        let ratio_x = full.0 as f64 / haystack.width() as f64;
+        let ratio_y = full.1 as f64 / haystack.height() as f64;
+        let shifts: &[usize] = if factor > 1 { &[0, factor / 2] } else { &[0] };
+        let planes = Planes::new(haystack, self.method == Method::CrossCorrelation);
+
+        // The reduced images only approximate the real ones, so more positions than needed are refined. Positions
+        // within the refined area of a better one are skipped, at most that many per picked position.
+        let wanted = limit.saturating_mul(4).max(16);
+        let radius = refine_radius(factor);
+        let best = wanted.saturating_mul((2 * radius + 1).pow(2));
+        let by_score = |a: &(f64, usize, usize), b: &(f64, usize, usize)| b.0.total_cmp(&a.0);
+
+        // Only the best positions are kept, positions below the worst of them are dropped right away.
+        let mut scores = Vec::new();
+        let mut floor = f64::NEG_INFINITY;
+        for &dy in shifts {
+            for &dx in shifts {
+                let cut = Rect::new(
+                    dx as i32,
+                    dy as i32,
+                    (needle.width() - dx) as i32,
+                    (needle.height() - dy) as i32,
+                );
+                let width = (cut.width as f64 / ratio_x).round() as usize;
+                let height = (cut.height as f64 / ratio_y).round() as usize;
+                let small = Needle::new(self.reduce(&needle.crop(cut), width, height));
+                self.score_all(&planes, &small, |x, y, confidence| {
+                    let fx = ((x as f64 * ratio_x).round() as usize).checked_sub(dx);
+                    let fy = ((y as f64 * ratio_y).round() as usize).checked_sub(dy);
+                    if let (Some(fx), Some(fy)) = (fx, fy) {
+                        if confidence > floor {
+                            scores.push((confidence, fx, fy));
+                        }
+                        if scores.len() == 2 * best {
+                            scores.select_nth_unstable_by(best - 1, by_score);
+                            scores.truncate(best);
+                            floor = scores[best - 1].0;
+                        }
+                    }
+                });
+            }
+        }
+        scores.sort_unstable_by(by_score);
+
+        let mut picked: Vec<(usize, usize)> = Vec::new();
+        for (_, x, y) in scores {
+            if picked.len() == wanted {
+                break;
+            }
+            if picked
+                .iter()
+                .all(|&(px, py)| px.abs_diff(x) > radius || py.abs_diff(y) > radius)
+            {
+                picked.push((x, y));
+            }
+        }
+        picked
+    }
+
+    /// Finds the best position around the given one at full resolution.
+    fn refine(
+        &self,
+        haystack: &Frame,
+        needle: &Needle,
+        (x, y): (usize, usize),
+        factor: usize,
+    ) -> (f64, (usize, usize)) {
+        let (max_x, max_y) = (
+            haystack.width() - needle.width(),
+            haystack.height() - needle.height(),
+        );
+        let radius = refine_radius(factor);
+        let mut best = (f64::NEG_INFINITY, (x.min(max_x), y.min(max_y)));
+        for y in y.saturating_sub(radius)..=(y + radius).min(max_y) {
+            for x in x.saturating_sub(radius)..=(x + radius).min(max_x) {
+                let confidence = self.score(haystack, needle, x, y);
+                if confidence > best.0 {
+                    best = (confidence, (x, y));
+                }
+            }
+        }
+        best
+    }
+
+    /// Returns the confidence of the needle matching the haystack with its upper-left corner at the given position.
+    fn score(&self, haystack: &Frame, needle: &Needle, x: usize, y: usize) -> f64 {
+        let bpp = needle.frame.format().bytes_per_pixel();
+        let span = x * bpp..(x + needle.width()) * bpp;
+        let (bits, stride) = (haystack.bits(), haystack.stride());
+        let rows = (y..y + needle.height()).map(|y| &bits[y * stride..][span.clone()]);
+        let mut window = Window::default();
+        for (a, b) in rows.zip(needle.frame.rows()) {
+            window.ssd += a
+                .iter()
+                .zip(b)
+                .map(|(&a, &b)| (a.abs_diff(b) as u32).pow(2))
+                .sum::<u32>() as u64;
+            if self.method == Method::CrossCorrelation {
+                window.sum += a.iter().map(|&a| a as u32).sum::<u32>() as u64;
+                window.sum_sq += a.iter().map(|&a| (a as u32).pow(2)).sum::<u32>() as u64;
+            }
+        }
+        self.confidence(needle, window)
+    }
+
+    /// Calls `score` with the position and confidence of every position of the needle in the haystack.
+    ///
+    /// Rather than comparing one position after another, every value of the needle is compared with a whole row of
+    /// positions at once, which can be vectorized.
+    fn score_all(
+        &self,
+        haystack: &Planes,
+        needle: &Needle,
+        mut score: impl FnMut(usize, usize, f64),
+    ) {
+        let (w, h) = (needle.width(), needle.height());
+        if w > haystack.width || h > haystack.height {
+            return;
+        }
+        let needle_planes = Planes::new(&needle.frame, false);
+        let columns = haystack.width - w + 1;
+        let mut row = vec![0u32; columns];
+        let mut ssd = vec![0u64; columns];
+        for y in 0..=haystack.height - h {
+            ssd.fill(0);
+            for j in 0..h {
+                row.fill(0);
+                for (plane, values) in haystack.planes.iter().zip(&needle_planes.planes) {
+                    let plane = &plane[(y + j) * haystack.width..][..haystack.width];
+                    for (i, &value) in values[j * w..(j + 1) * w].iter().enumerate() {
+                        accumulate(&mut row, &plane[i..i + columns], value);
+                    }
+                }
+                for (ssd, &row) in ssd.iter_mut().zip(&row) {
+                    *ssd += row as u64;
+                }
+            }
+            for (x, &ssd) in ssd.iter().enumerate() {
+                let (sum, sum_sq) = haystack.window_sums(x, y, w, h);
+                score(x, y, self.confidence(needle, Window { ssd, sum, sum_sq }));
+            }
+        }
+    }
+
+    fn confidence(&self, needle: &Needle, window: Window) -> f64 {
+        let count = needle.frame.bits().len() as f64;
+        match self.method {
+            Method::SquaredDifference => 1.0 - (window.ssd as f64 / count).sqrt() / 255.0,
+            Method::CrossCorrelation => {
+                let Window { ssd, sum, sum_sq } = window;
+                let cross = (sum_sq + needle.sum_sq - ssd) / 2;
+                let variance = sum_sq as f64 - (sum as f64).powi(2) / count;
+                let needle_variance = needle.sum_sq as f64 - (needle.sum as f64).powi(2) / count;
+                let covariance = cross as f64 - sum as f64 * needle.sum as f64 / count;
+                // Rounding errors can make the variance of flat areas slightly positive.
+                let flat = |variance: f64| variance < 0.5;
+                match (flat(variance), flat(needle_variance)) {
+                    (true, true) => 1.0 - (sum.abs_diff(needle.sum) as f64 / count) / 255.0,
+                    (false, false) => {
+                        (covariance / (variance * needle_variance).sqrt()).clamp(0.0, 1.0)
+                    }
+                    _ => 0.0,
+                }
+            }
+        }
+    }
+}
+
+/// The sums over the values of the haystack covered by the needle at one position.
+#[derive(Clone, Copy, Debug, Default)]
+struct Window {
+    /// The sum of the squared differences to the needle.
+    ssd: u64,
+    /// The sum of the values and their squares, only computed for the cross-correlation.
+    sum: u64,
+    sum_sq: u64,
+}
+
+/// A tightly packed image split into one plane per channel.
+struct Planes {
+    width: usize,
+    height: usize,
+    planes: Vec<Vec<u8>>,
+    /// The sums of all values, and their squares, above and left of every position, with an extra leading row and
+    /// column of zeros. Only computed for the cross-correlation.
+    integral: Vec<(u64, u64)>,
+}
+
+impl Planes {
+    fn new(image: &Frame, integral: bool) -> Planes {
+        let (width, height) = (image.width(), image.height());
+        let bpp = image.format().bytes_per_pixel();
+        let planes = (0..bpp)
+            .map(|c| image.bits().iter().skip(c).step_by(bpp).copied().collect())
+            .collect();
+        let mut planes = Planes {
+            width,
+            height,
+            planes,
+            integral: Vec::new(),
+        };
+        if integral {
+            planes.integral = vec![(0, 0); (width + 1) * (height + 1)];
+            for (y, row) in image.bits().chunks_exact(width * bpp).enumerate() {
+                let (mut sum, mut sum_sq) = (0, 0);
+                for (x, pixel) in row.chunks_exact(bpp).enumerate() {
+                    sum += pixel.iter().map(|&v| v as u64).sum::<u64>();
+                    sum_sq += pixel.iter().map(|&v| (v as u64).pow(2)).sum::<u64>();
+                    let above = planes.integral[y * (width + 1) + x + 1];
+                    planes.integral[(y + 1) * (width + 1) + x + 1] =
+                        (above.0 + sum, above.1 + sum_sq);
+                }
+            }
+        }
+        planes
+    }
+
+    /// Returns the sum of all values, and of their squares, within the given area, or zeros without an integral.
+    fn window_sums(&self, x: usize, y: usize, width: usize, height: usize) -> (u64, u64) {
+        if self.integral.is_empty() {
+            return (0, 0);
+        }
+        let at = |x: usize, y: usize| self.integral[y * (self.width + 1) + x];
+        let (a, b, c, d) = (
+            at(x, y),
+            at(x + width, y),
+            at(x, y + height),
+            at(x + width, y + height),
+        );
+        (d.0 + a.0 - b.0 - c.0, d.1 + a.1 - b.1 - c.1)
+    }
+}
+
+/// Adds the squared differences between every value of `row` and `value` to `acc`.
+fn accumulate(acc: &mut [u32], row: &[u8], value: u8) {
+    // The multiplications are vectorized much better with AVX2.
+    #[cfg(target_arch = "x86_64")]
+    if Isa::detect() == Isa::Avx2 {
+        return unsafe { accumulate_avx2(acc, row, value) };
+    }
+    accumulate_generic(acc, row, value)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn accumulate_avx2(acc: &mut [u32], row: &[u8], value: u8) {
+    accumulate_generic(acc, row, value)
+}
+
+#[inline(always)]
+fn accumulate_generic(acc: &mut [u32], row: &[u8], value: u8) {
+    for (acc, &v) in acc.iter_mut().zip(row) {
+        let diff = v.abs_diff(value) as u16;
+        *acc += (diff * diff) as u32;
+    }
+}
+
+/// Returns how far the position of a match may be off after comparing images reduced by `factor`.
+///
+/// The needle is compared at offsets of half a factor, so it is never off by more than a quarter factor and
+/// rounding errors of the reduced sizes.
+fn refine_radius(factor: usize) -> usize {
+    factor / 2 + 1
+}
+
+/// Copies an image into a tightly packed frame of the given format.
+fn packed(image: ImageView, format: PixelFormat) -> Frame {
+    let stride = image.width() * format.bytes_per_pixel();
+    let mut bits = vec![0; stride * image.height()];
+    if stride > 0 {
+        let converter = Converter::new();
+        for (src, dst) in image.rows().zip(bits.chunks_exact_mut(stride)) {
+            converter.convert_row(src, image.format(), dst, format);
+        }
+    }
+    Frame::from_vec(bits, image.width(), image.height(), format)
+}
+
+/// Drops matches overlapping a better match and returns the best `limit` of the rest, best first.
+fn suppress(mut matches: Vec<Match>, limit: usize) -> Vec<Match> {
+    matches.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    let mut kept: Vec<Match> = Vec::new();
+    for m in matches {
+        if kept.len() == limit {
+            break;
+        }
+        if kept.iter().all(|k| !k.area.intersects(&m.area)) {
+            kept.push(m);
+        }
+    }
+    kept
+}
+
+/// Returns the best match of the needle in the haystack, see [`Locator::locate`].
+pub fn locate<'a, 'b>(
+    haystack: impl Into<ImageView<'a>>,
+    needle: impl Into<ImageView<'b>>,
+) -> Option<Match> {
+    Locator::new().locate(haystack, needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::fixtures;
+
+    /// Returns a 320x200 haystack of smooth noise, in which every 24x16 area looks different.
+    fn haystack() -> Frame {
+        let mut state = 0x9E37_79B9u32;
+        let noise: Vec<u8> = (0..40 * 25)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        // Blocks of 8x8 pixels with gradients, so that scaled copies still look alike.
+        fixtures::padded(320, 200, 4, PixelFormat::Bgra32, |x, y| {
+            let block = noise[y / 8 * 40 + x / 8];
+            [
+                block,
+                block.wrapping_add((x % 8 * 8) as u8),
+                (y % 8 * 16) as u8 ^ block,
+                255,
+            ]
+        })
+    }
+
+    const AREA: Rect = Rect::new(100, 50, 24, 16);
+
+    #[test]
+    fn exact_copies_are_found() {
+        let haystack = haystack();
+        let found = Locator::new()
+            .locate(&haystack, &haystack.crop(AREA))
+            .unwrap();
+        assert_eq!(
+            (found.area, found.confidence, found.scale),
+            (AREA, 1.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn grayscale_search_refines_in_color() {
+        let haystack = haystack();
+        let needle = haystack.crop(AREA).to_format(PixelFormat::Bgr24);
+        let found = Locator::new()
+            .grayscale(true)
+            .locate(&haystack, &needle)
+            .unwrap();
+        assert_eq!((found.area, found.confidence), (AREA, 1.0));
+    }
+
+    #[test]
+    fn needles_are_found_at_other_scales() {
+        let haystack = haystack();
+        // The needle as it looks on a display at 100%, while the haystack was captured at 150%.
+        let large = Rect::new(100, 48, 36, 24);
+        let needle = scale::scale(&haystack.crop(large), 24, 16, Filter::Box);
+        let locator = Locator::new().scales(&[1.0, 1.5]).min_confidence(0.8);
+        let found = locator.locate(&haystack, &needle).unwrap();
+        assert_eq!((found.area, found.scale), (large, 1.5));
+        assert!(Locator::new().locate(&haystack, &needle).is_none());
+    }
+
+    #[test]
+    fn matches_within_an_area_use_haystack_coordinates() {
+        let haystack = haystack();
+        let needle = haystack.crop(AREA);
+        for area in [
+            Rect::new(80, 40, 100, 60),
+            Rect::new(-50, -50, 200, 150),
+            AREA,
+        ] {
+            let found = Locator::new().within(area).locate(&haystack, &needle);
+            assert_eq!(found.map(|m| m.area), Some(AREA), "{area:?}");
+        }
+        let elsewhere = Locator::new().within(Rect::new(150, 0, 170, 200));
+        assert!(elsewhere.locate(&haystack, &needle).is_none());
+    }
+
+    #[test]
+    fn oversized_needles_find_nothing() {
+        let haystack = haystack();
+        let wide = Frame::new(321, 10, PixelFormat::Bgr24);
+        assert!(Locator::new()
+            .min_confidence(0.0)
+            .locate(&haystack, &wide)
+            .is_none());
+        let needle = haystack.crop(AREA);
+        let off_image = Locator::new().within(Rect::new(400, 300, 50, 50));
+        assert!(off_image.locate(&haystack, &needle).is_none());
+        let narrow = Locator::new().within(Rect::new(100, 50, 23, 16));
+        assert!(narrow.locate(&haystack, &needle).is_none());
+        let empty = Frame::new(0, 0, PixelFormat::Bgr24);
+        assert!(Locator::new().locate(&haystack, &empty).is_none());
+        assert!(Locator::new()
+            .limit(0)
+            .locate_all(&haystack, &needle)
+            .is_empty());
+    }
+}
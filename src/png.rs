@@ -0,0 +1,582 @@
+//! PNG encoding of captured images.
+//!
+//! [`Frame::save_png`] and [`CaptureData::save_png`] write a capture to a file, [`encode_png`](Frame::encode_png)
+//! writes it to any [`Write`]. They convert the pixels from the BGR order of the backends and skip the padding of
+//! the rows, so the bits can be used as they are. Images without alpha are stored as RGB, [`PixelFormat::Gray8`]
+//! images as grayscale.
+//!
+//! A [`PngEncoder`] chooses the [`Compression`] and keeps its buffers between images, which matters when dumping
+//! many frames. The encoder is self-contained, including the deflate compression.
+//!
+//! # Examples
+//!
+//! ```
+//! use qshot::png::{Compression, PngEncoder};
+//! use qshot::{Frame, PixelFormat};
+//!
+//! let frame = Frame::new(64, 48, PixelFormat::Bgra32);
+//! let mut png = Vec::new();
+//! frame.encode_png(&mut png).unwrap();
+//! assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+//!
+//! let mut encoder = PngEncoder::new(Compression::Fast);
+//! let mut fast = Vec::new();
+//! encoder.encode(&frame, &mut fast).unwrap();
+//! assert!(fast.len() < 64 * 48 * 4);
+//! ```
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::capture::CaptureData;
+use crate::convert::Converter;
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use crate::view::ImageView;
+
+mod deflate;
+
+use self::deflate::Deflater;
+
+const SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+/// The largest amount of compressed data written in a single IDAT chunk.
+const MAX_CHUNK: usize = 1 << 20;
+
+/// How hard a [`PngEncoder`] compresses images.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Compression {
+    /// Doesn't filter the rows and compresses them at level 1, for dumping frames at a high rate.
+    Fast,
+    /// Chooses a filter for every row and compresses at level 6, like most image tools.
+    #[default]
+    Default,
+    /// Chooses a filter for every row and compresses at level 9, for the smallest files.
+    Best,
+    /// Compresses at a level from 0 (no compression) to 9 (best compression), larger levels are treated as 9.
+    ///
+    /// Rows are filtered at all levels but 0.
+    Level(u8),
+}
+
+impl Compression {
+    fn level(self) -> u8 {
+        match self {
+            Compression::Fast => 1,
+            Compression::Default => 6,
+            Compression::Best => 9,
+            Compression::Level(level) => level.min(9),
+        }
+    }
+
+    fn filters(self) -> bool {
+        !matches!(self, Compression::Fast | Compression::Level(0))
+    }
+}
+
+/// Encodes images as PNG, reusing its buffers from one image to the next.
+pub struct PngEncoder {
+    compression: Compression,
+    converter: Converter,
+    deflater: Deflater,
+    /// The previous and the current row in the byte order of the PNG.
+    rows: [Vec<u8>; 2],
+    /// The current row with every filter applied, each preceded by its filter type.
+    candidates: Vec<u8>,
+    filtered: Vec<u8>,
+    compressed: Vec<u8>,
+}
+
+impl Default for PngEncoder {
+    fn default() -> PngEncoder {
+        PngEncoder::new(Compression::Default)
+    }
+}
+
+impl PngEncoder {
+    /// Creates an encoder compressing images as hard as `compression` says.
+    pub fn new(compression: Compression) -> PngEncoder {
+        PngEncoder {
+            compression,
+            converter: Converter::new(),
+            deflater: Deflater::new(compression.level()),
+            rows: [Vec::new(), Vec::new()],
+            candidates: Vec::new(),
+            filtered: Vec::new(),
+            compressed: Vec::new(),
+        }
+    }
+
+    /// Returns how hard the encoder compresses images.
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Writes an image as a complete PNG file to `writer`.
+    ///
+    /// Nothing is written until the image is compressed, so a failed write leaves at most a truncated file.
+    pub fn encode<'a>(
+        &mut self,
+        image: impl Into<ImageView<'a>>,
+        mut writer: impl Write,
+    ) -> io::Result<()> {
+        let image = image.into();
+        let (format, color_type) = match image.format() {
+            PixelFormat::Bgr24 | PixelFormat::Rgb24 => (PixelFormat::Rgb24, 2),
+            PixelFormat::Bgra32 | PixelFormat::Rgba32 => (PixelFormat::Rgba32, 6),
+            _ => (PixelFormat::Gray8, 0),
+        };
+        let width = u32::try_from(image.width())
+            .ok()
+            .filter(|&w| w > 0 && w <= i32::MAX as u32);
+        let height = u32::try_from(image.height())
+            .ok()
+            .filter(|&h| h > 0 && h <= i32::MAX as u32);
+        let (Some(width), Some(height)) = (width, height) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "PNG images must be between 1 and 2^31 - 1 pixels wide and high",
+            ));
+        };
+
+        self.filter(image, format);
+        self.compressed.clear();
+        self.deflater.compress(&self.filtered, &mut self.compressed);
+
+        let mut header = [0; 13];
+        header[..4].copy_from_slice(&width.to_be_bytes());
+        header[4..8].copy_from_slice(&height.to_be_bytes());
+        // 8 bits per channel, followed by the default compression, filter and interlace methods.
+        header[8..10].copy_from_slice(&[8, color_type]);
+        writer.write_all(SIGNATURE)?;
+        write_chunk(&mut writer, b"IHDR", &header)?;
+        for data in self.compressed.chunks(MAX_CHUNK) {
+            write_chunk(&mut writer, b"IDAT", data)?;
+        }
+        write_chunk(&mut writer, b"IEND", &[])?;
+        writer.flush()
+    }
+
+    /// Converts the rows into `format` and filters them into `self.filtered`.
+    fn filter(&mut self, image: ImageView, format: PixelFormat) {
+        let bpp = format.bytes_per_pixel();
+        let len = image.width() * bpp;
+        self.filtered.clear();
+        self.filtered.reserve((len + 1) * image.height());
+        for row in &mut self.rows {
+            row.clear();
+            row.resize(len, 0);
+        }
+        self.candidates.resize((len + 1) * 5, 0);
+        for src in image.rows() {
+            self.rows.swap(0, 1);
+            let [prev, row] = &mut self.rows;
+            self.converter.convert_row(src, image.format(), row, format);
+            if !self.compression.filters() {
+                self.filtered.push(0);
+                self.filtered.extend_from_slice(row);
+                continue;
+            }
+            // The filter whose output has the smallest sum of absolute values (as signed bytes) tends to compress
+            // best.
+            let best = self
+                .candidates
+                .chunks_exact_mut(len + 1)
+                .enumerate()
+                .map(|(filter, out)| {
+                    out[0] = filter as u8;
+                    apply_filter(filter as u8, row, prev, bpp, &mut out[1..]);
+                    let score: u64 = out[1..]
+                        .iter()
+                        .map(|&b| (b as i8).unsigned_abs() as u64)
+                        .sum();
+                    (score, filter)
+                })
+                .min()
+                .map(|(_, filter)| filter)
+                .unwrap();
+            self.filtered
+                .extend_from_slice(&self.candidates[best * (len + 1)..(best + 1) * (len + 1)]);
+        }
+    }
+}
+
+/// Applies one of the PNG filters (0 to 4: none, sub, up, average, Paeth) to `row`.
+fn apply_filter(filter: u8, row: &[u8], prev: &[u8], bpp: usize, out: &mut [u8]) {
+    let left = |i: usize| if i >= bpp { row[i - bpp] } else { 0 };
+    let up_left = |i: usize| if i >= bpp { prev[i - bpp] } else { 0 };
+    match filter {
+        0 => out.copy_from_slice(row),
+        1 => {
+            out[..bpp].copy_from_slice(&row[..bpp]);
+            for i in bpp..row.len() {
+                out[i] = row[i].wrapping_sub(row[i - bpp]);
+            }
+        }
+        2 => {
+            for ((out, &x), &up) in out.iter_mut().zip(row).zip(prev) {
+                *out = x.wrapping_sub(up);
+            }
+        }
+        3 => {
+            for i in 0..row.len() {
+                let average = (left(i) as u16 + prev[i] as u16) / 2;
+                out[i] = row[i].wrapping_sub(average as u8);
+            }
+        }
+        _ => {
+            for i in 0..row.len() {
+                out[i] = row[i].wrapping_sub(paeth(left(i), prev[i], up_left(i)));
+            }
+        }
+    }
+}
+
+/// Returns whichever of the left, upper and upper left neighbors is closest to `left + up - up_left`.
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let (a, b, c) = (left as i16, up as i16, up_left as i16);
+    let p = a + b - c;
+    let (pa, pb, pc) = ((p - a).abs(), (p - b).abs(), (p - c).abs());
+    if pa <= pb && pa <= pc {
+        left
+    } else if pb <= pc {
+        up
+    } else {
+        up_left
+    }
+}
+
+fn write_chunk(writer: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    let crc = crc32(crc32(!0, kind), data);
+    writer.write_all(&(!crc).to_be_bytes())
+}
+
+const CRC_TABLE: [[u32; 256]; 8] = crc_table();
+
+const fn crc_table() -> [[u32; 256]; 8] {
+    let mut table = [[0; 256]; 8];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[0][i] = crc;
+        i += 1;
+    }
+    // The further tables advance a CRC by one more zero byte each, so 8 bytes can be processed at once.
+    let mut i = 0;
+    while i < 256 {
+        let mut k = 1;
+        while k < 8 {
+            let prev = table[k - 1][i];
+            table[k][i] = (prev >> 8) ^ table[0][(prev & 0xFF) as usize];
+            k += 1;
+        }
+        i += 1;
+    }
+    table
+}
+
+/// Updates a CRC-32 (without the final inversion) with `data`.
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let low = crc ^ u32::from_le_bytes(chunk[..4].try_into().unwrap());
+        let t = &CRC_TABLE;
+        crc = t[7][(low & 0xFF) as usize]
+            ^ t[6][(low >> 8 & 0xFF) as usize]
+            ^ t[5][(low >> 16 & 0xFF) as usize]
+            ^ t[4][(low >> 24) as usize]
+            ^ t[3][chunk[4] as usize]
+            ^ t[2][chunk[5] as usize]
+            ^ t[1][chunk[6] as usize]
+            ^ t[0][chunk[7] as usize];
+    }
+    for &byte in chunks.remainder() {
+        crc = (crc >> 8) ^ CRC_TABLE[0][((crc ^ byte as u32) & 0xFF) as usize];
+    }
+    crc
+}
+
+fn save(image: ImageView, path: &Path) -> io::Result<()> {
+    PngEncoder::default().encode(image, BufWriter::new(File::create(path)?))
+}
+
+impl Frame {
+    /// Writes the frame as a PNG file with [`Compression::Default`] to `writer`, see [`PngEncoder`].
+    pub fn encode_png(&self, writer: impl Write) -> io::Result<()> {
+        PngEncoder::default().encode(self, writer)
+    }
+
+    /// Saves the frame as a PNG file with [`Compression::Default`], replacing the file if it exists.
+    pub fn save_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        save(self.into(), path.as_ref())
+    }
+}
+
+impl CaptureData {
+    /// Writes the capture as a PNG file with [`Compression::Default`] to `writer`, see [`PngEncoder`].
+    pub fn encode_png(&self, writer: impl Write) -> io::Result<()> {
+        PngEncoder::default().encode(self, writer)
+    }
+
+    /// Saves the capture as a PNG file with [`Compression::Default`], replacing the file if it exists.
+    pub fn save_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        save(self.into(), path.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::fixtures;
+    use crate::png::deflate::tests::{inflate, noise};
+
+    const FORMATS: [PixelFormat; 5] = [
+        PixelFormat::Bgr24,
+        PixelFormat::Bgra32,
+        PixelFormat::Rgb24,
+        PixelFormat::Rgba32,
+        PixelFormat::Gray8,
+    ];
+
+    const COMPRESSIONS: [Compression; 5] = [
+        Compression::Fast,
+        Compression::Default,
+        Compression::Best,
+        Compression::Level(0),
+        Compression::Level(3),
+    ];
+
+    /// A decoded file, with the pixels in the byte order of the PNG.
+    struct Decoded {
+        header: [u8; 13],
+        idat_sizes: Vec<usize>,
+        filters: Vec<u8>,
+        pixels: Vec<u8>,
+    }
+
+    /// Splits a file into its chunks, checking the signature and the CRC of every chunk.
+    fn chunks(file: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        assert_eq!(&file[..8], SIGNATURE);
+        let mut chunks = Vec::new();
+        let mut rest = &file[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let kind: [u8; 4] = rest[4..8].try_into().unwrap();
+            let data = &rest[8..8 + len];
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(!crc32(crc32(!0, &kind), data), crc);
+            chunks.push((kind, data));
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+
+    /// Inflates the image data and reverses the filters, assuming 8 bits per channel.
+    fn decode(file: &[u8]) -> Decoded {
+        let chunks = chunks(file);
+        let kinds: Vec<_> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds.first(), Some(&b"IHDR"));
+        assert_eq!(kinds.last(), Some(&b"IEND"));
+        assert!(kinds[1..kinds.len() - 1]
+            .iter()
+            .all(|&kind| kind == b"IDAT"));
+        let header: [u8; 13] = chunks[0].1.try_into().unwrap();
+        let width = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        let bpp = match header[9] {
+            0 => 1,
+            2 => 3,
+            6 => 4,
+            kind => panic!("unexpected color type {kind}"),
+        };
+        let idat: Vec<&[u8]> = chunks[1..chunks.len() - 1]
+            .iter()
+            .map(|(_, data)| *data)
+            .collect();
+        let filtered = inflate(&idat.concat());
+
+        let len = width * bpp;
+        let (mut filters, mut pixels) = (Vec::new(), Vec::new());
+        let mut prev = vec![0; len];
+        for line in filtered.chunks_exact(len + 1) {
+            let (filter, line) = (line[0], &line[1..]);
+            let mut row = vec![0u8; len];
+            for i in 0..len {
+                let left = if i >= bpp { row[i - bpp] } else { 0 };
+                let up_left = if i >= bpp { prev[i - bpp] } else { 0 };
+                let predicted = match filter {
+                    0 => 0,
+                    1 => left,
+                    2 => prev[i],
+                    3 => ((left as u16 + prev[i] as u16) / 2) as u8,
+                    4 => paeth(left, prev[i], up_left),
+                    _ => panic!("unexpected filter {filter}"),
+                };
+                row[i] = line[i].wrapping_add(predicted);
+            }
+            filters.push(filter);
+            pixels.extend_from_slice(&row);
+            prev = row;
+        }
+        Decoded {
+            header,
+            idat_sizes: idat.iter().map(|data| data.len()).collect(),
+            filters,
+            pixels,
+        }
+    }
+
+    /// Returns a frame mixing flat areas, gradients and noise, so that every filter gets picked.
+    fn image(width: usize, height: usize, padding: usize, format: PixelFormat) -> Frame {
+        let noise = noise(width * height * 4);
+        fixtures::padded(width, height, padding, format, |x, y| match (y / 4) % 3 {
+            0 => [40, 80, 120, 255],
+            1 => [(x * 3) as u8, (y * 5) as u8, (x + y) as u8, (x * 7) as u8],
+            _ => noise[(y * width + x) * 4..][..4].try_into().unwrap(),
+        })
+    }
+
+    fn encoded(encoder: &mut PngEncoder, image: &Frame) -> Vec<u8> {
+        fixtures::encoded(|file| encoder.encode(image, file))
+    }
+
+    #[test]
+    fn crc32_of_known_strings() {
+        assert_eq!(!crc32(!0, b""), 0);
+        assert_eq!(!crc32(!0, b"123456789"), 0xCBF4_3926);
+        assert_eq!(
+            !crc32(!0, b"The quick brown fox jumps over the lazy dog"),
+            0x414F_A339
+        );
+        assert_eq!(!crc32(!0, b"IEND"), 0xAE42_6082);
+    }
+
+    #[test]
+    fn crc32_matches_the_bytewise_loop() {
+        let data = noise(1001);
+        for len in [1, 7, 8, 9, 63, 1001] {
+            let expected = data[..len].iter().fold(!0u32, |crc, &byte| {
+                (0..8).fold(crc ^ byte as u32, |crc, _| {
+                    (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+                })
+            });
+            assert_eq!(crc32(!0, &data[..len]), expected, "{len}");
+        }
+    }
+
+    #[test]
+    fn paeth_picks_the_closest_neighbor() {
+        assert_eq!(paeth(20, 10, 10), 20);
+        assert_eq!(paeth(10, 20, 10), 20);
+        assert_eq!(paeth(10, 20, 15), 15);
+        assert_eq!(paeth(100, 200, 0), 200);
+        assert_eq!(paeth(10, 0, 5), 5);
+        // Ties go to the left, then to the upper neighbor.
+        assert_eq!(paeth(10, 10, 10), 10);
+        assert_eq!(paeth(0, 255, 255), 0);
+        assert_eq!(paeth(5, 5, 9), 5);
+        assert_eq!(paeth(0, 6, 2), 6);
+    }
+
+    #[test]
+    fn filters_of_a_known_row() {
+        let (row, prev) = ([10, 20, 30, 40], [5, 5, 50, 50]);
+        let filtered = |filter| {
+            let mut out = [0; 4];
+            apply_filter(filter, &row, &prev, 2, &mut out);
+            out
+        };
+        assert_eq!(filtered(0), [10, 20, 30, 40]);
+        assert_eq!(filtered(1), [10, 20, 20, 20]);
+        assert_eq!(filtered(2), [5, 15, 236, 246]);
+        assert_eq!(filtered(3), [8, 18, 0, 5]);
+        assert_eq!(filtered(4), [5, 15, 236, 246]);
+    }
+
+    #[test]
+    fn headers_describe_the_image() {
+        for (format, color_type) in [
+            (PixelFormat::Bgr24, 2),
+            (PixelFormat::Rgb24, 2),
+            (PixelFormat::Bgra32, 6),
+            (PixelFormat::Rgba32, 6),
+            (PixelFormat::Gray8, 0),
+        ] {
+            let file = encoded(&mut PngEncoder::default(), &image(300, 2, 0, format));
+            let header = decode(&file).header;
+            assert_eq!(header[..8], [0, 0, 1, 44, 0, 0, 0, 2]);
+            assert_eq!(header[8..], [8, color_type, 0, 0, 0], "{format:?}");
+        }
+    }
+
+    #[test]
+    fn pixels_round_trip() {
+        for compression in COMPRESSIONS {
+            let mut encoder = PngEncoder::new(compression);
+            for format in FORMATS {
+                for (width, height, padding) in [(1, 1, 0), (37, 29, 3), (64, 13, 0)] {
+                    let image = image(width, height, padding, format);
+                    let decoded = decode(&encoded(&mut encoder, &image));
+                    let expected = match format {
+                        PixelFormat::Bgr24 | PixelFormat::Rgb24 => PixelFormat::Rgb24,
+                        PixelFormat::Bgra32 | PixelFormat::Rgba32 => PixelFormat::Rgba32,
+                        _ => PixelFormat::Gray8,
+                    };
+                    assert!(
+                        decoded.pixels == image.to_format(expected).into_vec(),
+                        "{compression:?} {format:?} {width}x{height}"
+                    );
+                    assert_eq!(decoded.filters.len(), height);
+                    if !compression.filters() {
+                        assert!(decoded.filters.iter().all(|&filter| filter == 0));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_filter_is_chosen() {
+        let decoded = decode(&encoded(
+            &mut PngEncoder::default(),
+            &image(64, 48, 0, PixelFormat::Bgr24),
+        ));
+        for filter in 0..5 {
+            assert!(decoded.filters.contains(&filter), "{filter}");
+        }
+    }
+
+    #[test]
+    fn large_images_span_several_idat_chunks() {
+        let image = Frame::from_vec(noise(700 * 500 * 4), 700, 500, PixelFormat::Rgba32);
+        let decoded = decode(&encoded(
+            &mut PngEncoder::new(Compression::Level(0)),
+            &image,
+        ));
+        assert_eq!(decoded.idat_sizes.len(), 2);
+        assert_eq!(decoded.idat_sizes[0], MAX_CHUNK);
+        assert!(decoded.pixels == image.into_vec());
+    }
+
+    #[test]
+    fn empty_images_are_rejected() {
+        for (width, height) in [(0, 0), (0, 5), (5, 0)] {
+            let image = Frame::new(width, height, PixelFormat::Bgr24);
+            assert!(fixtures::fails_with(
+                PngEncoder::default().encode(&image, io::sink()),
+                io::ErrorKind::InvalidInput
+            ));
+        }
+    }
+}
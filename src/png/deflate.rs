@@ -0,0 +1,890 @@
+//! A zlib stream (RFC 1950) holding deflate data (RFC 1951), as stored in the IDAT chunks of a PNG file.
+
+use std::collections::BinaryHeap;
+
+const WINDOW: usize = 1 << 15;
+const HASH_BITS: u32 = 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// The number of symbols gathered before a block is written, each block gets its own Huffman codes.
+const BLOCK_SYMBOLS: usize = 1 << 15;
+/// The largest amount of bytes a stored block can hold.
+const MAX_STORED: usize = 65535;
+const END_OF_BLOCK: usize = 256;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order in which the lengths of the code length codes are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// How hard the matcher looks for repeated strings at a compression level.
+#[derive(Clone, Copy, Debug)]
+struct Effort {
+    /// The number of earlier positions with the same hash that are tried.
+    chain: usize,
+    /// A match at least this long ends the search.
+    nice: usize,
+    /// Whether a match is only taken if the next position doesn't start a longer one.
+    lazy: bool,
+}
+
+const EFFORT: [Effort; 10] = [
+    Effort {
+        chain: 0,
+        nice: 0,
+        lazy: false,
+    },
+    Effort {
+        chain: 4,
+        nice: 8,
+        lazy: false,
+    },
+    Effort {
+        chain: 8,
+        nice: 16,
+        lazy: false,
+    },
+    Effort {
+        chain: 16,
+        nice: 32,
+        lazy: false,
+    },
+    Effort {
+        chain: 16,
+        nice: 16,
+        lazy: true,
+    },
+    Effort {
+        chain: 32,
+        nice: 32,
+        lazy: true,
+    },
+    Effort {
+        chain: 128,
+        nice: 128,
+        lazy: true,
+    },
+    Effort {
+        chain: 256,
+        nice: 128,
+        lazy: true,
+    },
+    Effort {
+        chain: 1024,
+        nice: 258,
+        lazy: true,
+    },
+    Effort {
+        chain: 4096,
+        nice: 258,
+        lazy: true,
+    },
+];
+
+/// A literal byte (`dist == 0`) or a reference to `len` bytes `dist` bytes back.
+#[derive(Clone, Copy, Debug)]
+struct Symbol {
+    len: u16,
+    dist: u16,
+}
+
+/// Compresses data into zlib streams, keeping its tables for the next stream.
+pub(super) struct Deflater {
+    level: u8,
+    /// The last position with each hash plus 1, 0 if there is none.
+    head: Vec<u32>,
+    /// The previous position with the same hash as each position in the window, plus 1.
+    prev: Vec<u32>,
+    symbols: Vec<Symbol>,
+    length_code: [u8; MAX_MATCH + 1],
+}
+
+impl Deflater {
+    /// Creates a deflater for a level from 0 (store only) to 9 (best compression).
+    pub(super) fn new(level: u8) -> Deflater {
+        let mut length_code = [0; MAX_MATCH + 1];
+        for (len, code) in length_code.iter_mut().enumerate().skip(MIN_MATCH) {
+            *code = (LENGTH_BASE.partition_point(|&base| base as usize <= len) - 1) as u8;
+        }
+        Deflater {
+            level: level.min(9),
+            head: Vec::new(),
+            prev: Vec::new(),
+            symbols: Vec::with_capacity(BLOCK_SYMBOLS),
+            length_code,
+        }
+    }
+
+    /// Appends the zlib stream of `data` to `out`.
+    pub(super) fn compress(&mut self, data: &[u8], out: &mut Vec<u8>) {
+        // The window is 32 KiB (CINFO = 7), FLEVEL tells the level roughly, the header is a multiple of 31.
+        let header: u16 = match self.level {
+            0 | 1 => 0x7801,
+            2..=5 => 0x785E,
+            6 => 0x789C,
+            _ => 0x78DA,
+        };
+        out.extend_from_slice(&header.to_be_bytes());
+        let mut bits = BitWriter {
+            out,
+            acc: 0,
+            count: 0,
+        };
+        if self.level == 0 {
+            write_stored(&mut bits, data, true);
+        } else {
+            self.matches(data, &mut bits);
+        }
+        bits.flush();
+        out.extend_from_slice(&adler32(data).to_be_bytes());
+    }
+
+    /// Finds repeated strings with hash chains and writes the resulting blocks.
+    fn matches(&mut self, data: &[u8], bits: &mut BitWriter) {
+        let effort = EFFORT[self.level as usize];
+        self.head.clear();
+        self.head.resize(1 << HASH_BITS, 0);
+        self.prev.resize(WINDOW, 0);
+        self.symbols.clear();
+
+        let mut block_start = 0;
+        let mut pos = 0;
+        // A match starting at the previous position, waiting for the lazy evaluation of this one.
+        let mut pending: Option<(usize, usize)> = None;
+        while pos < data.len() {
+            let found = self.longest_match(data, pos, effort);
+            self.insert(data, pos);
+            match (pending.take(), found) {
+                (Some((len, _)), Some(next)) if next.0 > len => {
+                    self.symbols.push(literal(data[pos - 1]));
+                    pending = Some(next);
+                    pos += 1;
+                }
+                (Some((len, dist)), _) => {
+                    // The match covers `pos - 1..pos - 1 + len`, of which `pos - 1` and `pos` are hashed already.
+                    self.symbols.push(reference(len, dist));
+                    for p in pos + 1..pos - 1 + len {
+                        self.insert(data, p);
+                    }
+                    pos += len - 1;
+                }
+                (None, Some((len, dist))) if effort.lazy && len < effort.nice => {
+                    pending = Some((len, dist));
+                    pos += 1;
+                }
+                (None, Some((len, dist))) => {
+                    self.symbols.push(reference(len, dist));
+                    for p in pos + 1..pos + len {
+                        self.insert(data, p);
+                    }
+                    pos += len;
+                }
+                (None, None) => {
+                    self.symbols.push(literal(data[pos]));
+                    pos += 1;
+                }
+            }
+            if pending.is_none() && self.symbols.len() >= BLOCK_SYMBOLS {
+                self.write_block(bits, &data[block_start..pos], false);
+                block_start = pos;
+            }
+        }
+        if let Some((len, dist)) = pending {
+            self.symbols.push(reference(len, dist));
+        }
+        self.write_block(bits, &data[block_start..], true);
+    }
+
+    fn hash(data: &[u8], pos: usize) -> usize {
+        let word = u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], 0]);
+        (word.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, data: &[u8], pos: usize) {
+        if pos + MIN_MATCH <= data.len() {
+            let hash = Self::hash(data, pos);
+            self.prev[pos % WINDOW] = self.head[hash];
+            self.head[hash] = pos as u32 + 1;
+        }
+    }
+
+    /// Returns the length and distance of the longest earlier string matching the data at `pos`.
+    fn longest_match(&self, data: &[u8], pos: usize, effort: Effort) -> Option<(usize, usize)> {
+        if pos + MIN_MATCH > data.len() {
+            return None;
+        }
+        let max = (data.len() - pos).min(MAX_MATCH);
+        let mut best: Option<(usize, usize)> = None;
+        let mut candidate = self.head[Self::hash(data, pos)] as usize;
+        for _ in 0..effort.chain {
+            if candidate == 0 || pos - (candidate - 1) > WINDOW {
+                break;
+            }
+            let start = candidate - 1;
+            let best_len = best.map_or(MIN_MATCH - 1, |(len, _)| len);
+            // Most candidates fail at the byte that would make them longer than the best match so far.
+            if data[start + best_len] == data[pos + best_len] {
+                let len = match_length(&data[start..], &data[pos..], max);
+                if len > best_len {
+                    best = Some((len, pos - start));
+                    if len >= effort.nice || len == max {
+                        break;
+                    }
+                }
+            }
+            let next = self.prev[start % WINDOW] as usize;
+            if next >= candidate {
+                // The slot was overwritten by a later position, the chain left the window.
+                break;
+            }
+            candidate = next;
+        }
+        best
+    }
+
+    /// Writes the gathered symbols, which encode `raw`, as the smallest of a stored, fixed or dynamic block.
+    fn write_block(&mut self, bits: &mut BitWriter, raw: &[u8], last: bool) {
+        self.symbols.push(Symbol {
+            len: END_OF_BLOCK as u16,
+            dist: 0,
+        });
+        let mut lit_freqs = [0u32; 286];
+        let mut dist_freqs = [0u32; 30];
+        let mut extra_bits = 0;
+        for symbol in &self.symbols {
+            if symbol.dist == 0 {
+                lit_freqs[symbol.len as usize] += 1;
+            } else {
+                let code = self.length_code[symbol.len as usize] as usize;
+                let dist = dist_code(symbol.dist);
+                lit_freqs[257 + code] += 1;
+                dist_freqs[dist] += 1;
+                extra_bits += LENGTH_EXTRA[code] as u64 + DIST_EXTRA[dist] as u64;
+            }
+        }
+
+        let dynamic = DynamicHeader::new(&lit_freqs, &dist_freqs);
+        let (fixed_lit, fixed_dist) = fixed_lengths();
+        let dynamic_size =
+            3 + dynamic.size() + cost(&lit_freqs, &dynamic.lit) + cost(&dist_freqs, &dynamic.dist);
+        let fixed_size = 3 + cost(&lit_freqs, &fixed_lit) + cost(&dist_freqs, &fixed_dist);
+        let stored_size = (raw.len().div_ceil(MAX_STORED).max(1) * 40 + raw.len() * 8) as u64 + 7;
+
+        if stored_size <= dynamic_size.min(fixed_size) + extra_bits {
+            write_stored(bits, raw, last);
+        } else if fixed_size <= dynamic_size {
+            bits.write(last as u32 | 1 << 1, 3);
+            self.write_symbols(bits, &fixed_lit, &fixed_dist);
+        } else {
+            bits.write(last as u32 | 2 << 1, 3);
+            dynamic.write(bits);
+            self.write_symbols(bits, &dynamic.lit, &dynamic.dist);
+        }
+        self.symbols.clear();
+    }
+
+    fn write_symbols(&self, bits: &mut BitWriter, lit_lengths: &[u8], dist_lengths: &[u8]) {
+        let lit_codes = canonical_codes(lit_lengths);
+        let dist_codes = canonical_codes(dist_lengths);
+        for symbol in &self.symbols {
+            if symbol.dist == 0 {
+                let lit = symbol.len as usize;
+                bits.write(lit_codes[lit] as u32, lit_lengths[lit] as u32);
+                continue;
+            }
+            let code = self.length_code[symbol.len as usize] as usize;
+            bits.write(lit_codes[257 + code] as u32, lit_lengths[257 + code] as u32);
+            bits.write(
+                (symbol.len - LENGTH_BASE[code]) as u32,
+                LENGTH_EXTRA[code] as u32,
+            );
+            let dist = dist_code(symbol.dist);
+            bits.write(dist_codes[dist] as u32, dist_lengths[dist] as u32);
+            bits.write(
+                (symbol.dist - DIST_BASE[dist]) as u32,
+                DIST_EXTRA[dist] as u32,
+            );
+        }
+    }
+}
+
+fn literal(byte: u8) -> Symbol {
+    Symbol {
+        len: byte as u16,
+        dist: 0,
+    }
+}
+
+fn reference(len: usize, dist: usize) -> Symbol {
+    Symbol {
+        len: len as u16,
+        dist: dist as u16,
+    }
+}
+
+fn dist_code(dist: u16) -> usize {
+    DIST_BASE.partition_point(|&base| base <= dist) - 1
+}
+
+/// Returns the number of equal leading bytes of `a` and `b`, up to `max`.
+fn match_length(a: &[u8], b: &[u8], max: usize) -> usize {
+    let mut len = 0;
+    while len + 8 <= max {
+        let x = u64::from_le_bytes(a[len..len + 8].try_into().unwrap());
+        let y = u64::from_le_bytes(b[len..len + 8].try_into().unwrap());
+        if x != y {
+            return len + ((x ^ y).trailing_zeros() / 8) as usize;
+        }
+        len += 8;
+    }
+    while len < max && a[len] == b[len] {
+        len += 1;
+    }
+    len
+}
+
+/// Returns the number of bits the symbols with the given frequencies take with the given code lengths.
+fn cost(freqs: &[u32], lengths: &[u8]) -> u64 {
+    freqs
+        .iter()
+        .zip(lengths)
+        .map(|(&freq, &len)| freq as u64 * len as u64)
+        .sum()
+}
+
+fn write_stored(bits: &mut BitWriter, raw: &[u8], last: bool) {
+    let mut chunks = raw.chunks(MAX_STORED).peekable();
+    if chunks.peek().is_none() {
+        bits.write(last as u32, 3);
+        bits.align();
+        bits.out.extend_from_slice(&[0, 0, 0xFF, 0xFF]);
+        return;
+    }
+    while let Some(chunk) = chunks.next() {
+        let final_chunk = last && chunks.peek().is_none();
+        bits.write(final_chunk as u32, 3);
+        bits.align();
+        let len = chunk.len() as u16;
+        bits.out.extend_from_slice(&len.to_le_bytes());
+        bits.out.extend_from_slice(&(!len).to_le_bytes());
+        bits.out.extend_from_slice(chunk);
+    }
+}
+
+/// Returns the code lengths of the fixed Huffman codes.
+fn fixed_lengths() -> ([u8; 288], [u8; 30]) {
+    let mut lit = [8; 288];
+    lit[144..256].fill(9);
+    lit[256..280].fill(7);
+    (lit, [5; 30])
+}
+
+/// The code lengths of a dynamic block and their run-length encoding in its header.
+struct DynamicHeader {
+    lit: Vec<u8>,
+    dist: Vec<u8>,
+    /// The code length symbols (0 to 18) with their extra bits.
+    runs: Vec<(u8, u8)>,
+    code_lengths: Vec<u8>,
+    /// The number of code length code lengths stored, in [`CODE_LENGTH_ORDER`].
+    stored_code_lengths: usize,
+}
+
+impl DynamicHeader {
+    fn new(lit_freqs: &[u32], dist_freqs: &[u32]) -> DynamicHeader {
+        let mut lit = code_lengths(lit_freqs, 15);
+        let mut dist = code_lengths(dist_freqs, 15);
+        let lit_count = 257.max(lit.iter().rposition(|&len| len > 0).map_or(0, |i| i + 1));
+        let dist_count = 1.max(dist.iter().rposition(|&len| len > 0).map_or(0, |i| i + 1));
+        lit.truncate(lit_count);
+        dist.truncate(dist_count);
+
+        let runs = run_lengths(&[lit.as_slice(), dist.as_slice()].concat());
+        let mut freqs = [0u32; 19];
+        for &(symbol, _) in &runs {
+            freqs[symbol as usize] += 1;
+        }
+        let code_lengths = code_lengths(&freqs, 7);
+        let stored_code_lengths = 4.max(
+            CODE_LENGTH_ORDER
+                .iter()
+                .rposition(|&symbol| code_lengths[symbol] > 0)
+                .map_or(0, |i| i + 1),
+        );
+        DynamicHeader {
+            lit,
+            dist,
+            runs,
+            code_lengths,
+            stored_code_lengths,
+        }
+    }
+
+    /// Returns the size of the header in bits.
+    fn size(&self) -> u64 {
+        let runs: u64 = self
+            .runs
+            .iter()
+            .map(|&(symbol, _)| {
+                self.code_lengths[symbol as usize] as u64 + run_extra_bits(symbol) as u64
+            })
+            .sum();
+        5 + 5 + 4 + 3 * self.stored_code_lengths as u64 + runs
+    }
+
+    fn write(&self, bits: &mut BitWriter) {
+        bits.write(self.lit.len() as u32 - 257, 5);
+        bits.write(self.dist.len() as u32 - 1, 5);
+        bits.write(self.stored_code_lengths as u32 - 4, 4);
+        for &symbol in &CODE_LENGTH_ORDER[..self.stored_code_lengths] {
+            bits.write(self.code_lengths[symbol] as u32, 3);
+        }
+        let codes = canonical_codes(&self.code_lengths);
+        for &(symbol, extra) in &self.runs {
+            let symbol = symbol as usize;
+            bits.write(codes[symbol] as u32, self.code_lengths[symbol] as u32);
+            bits.write(extra as u32, run_extra_bits(symbol as u8));
+        }
+    }
+}
+
+fn run_extra_bits(symbol: u8) -> u32 {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+/// Encodes code lengths with the repeat symbols 16 (previous length 3-6 times), 17 (zero 3-10 times) and 18 (zero
+/// 11-138 times).
+fn run_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let len = lengths[i];
+        let mut count = 1;
+        while i + count < lengths.len() && lengths[i + count] == len {
+            count += 1;
+        }
+        i += count;
+        if len == 0 {
+            while count >= 11 {
+                let n = count.min(138);
+                runs.push((18, (n - 11) as u8));
+                count -= n;
+            }
+            if count >= 3 {
+                runs.push((17, (count - 3) as u8));
+                count = 0;
+            }
+        } else {
+            runs.push((len, 0));
+            count -= 1;
+            while count >= 3 {
+                let n = count.min(6);
+                runs.push((16, (n - 3) as u8));
+                count -= n;
+            }
+        }
+        runs.extend(std::iter::repeat_n((len, 0), count));
+    }
+    runs
+}
+
+/// Returns the lengths of a Huffman code for symbols with the given frequencies, none longer than `limit`.
+///
+/// At least two symbols get a code, so that the code is complete as some decoders require.
+fn code_lengths(freqs: &[u32], limit: u8) -> Vec<u8> {
+    let mut freqs = freqs.to_vec();
+    for _ in freqs.iter().filter(|&&freq| freq > 0).count()..2 {
+        if let Some(freq) = freqs.iter_mut().find(|freq| **freq == 0) {
+            *freq = 1;
+        }
+    }
+    loop {
+        let lengths = huffman_lengths(&freqs);
+        if lengths.iter().all(|&len| len <= limit) {
+            return lengths;
+        }
+        // Flattening the distribution shortens the longest codes, a few rounds are rarely needed.
+        for freq in freqs.iter_mut().filter(|freq| **freq > 0) {
+            *freq = freq.div_ceil(2);
+        }
+    }
+}
+
+fn huffman_lengths(freqs: &[u32]) -> Vec<u8> {
+    // Leaves come first, every node merged from two others is appended with its children pointing to it.
+    let mut parent = Vec::with_capacity(freqs.len() * 2);
+    let mut heap = BinaryHeap::new();
+    let mut leaves = Vec::new();
+    for (symbol, &freq) in freqs.iter().enumerate().filter(|(_, &freq)| freq > 0) {
+        heap.push(std::cmp::Reverse((freq as u64, leaves.len())));
+        leaves.push(symbol);
+        parent.push(usize::MAX);
+    }
+    while heap.len() > 1 {
+        let std::cmp::Reverse((a, i)) = heap.pop().unwrap();
+        let std::cmp::Reverse((b, j)) = heap.pop().unwrap();
+        let node = parent.len();
+        parent.push(usize::MAX);
+        parent[i] = node;
+        parent[j] = node;
+        heap.push(std::cmp::Reverse((a + b, node)));
+    }
+    // Parents come after their children, so depths can be computed from the root down.
+    let mut depth = vec![0u32; parent.len()];
+    for node in (0..parent.len()).rev() {
+        if parent[node] != usize::MAX {
+            depth[node] = depth[parent[node]] + 1;
+        }
+    }
+    let mut lengths = vec![0; freqs.len()];
+    for (leaf, &symbol) in leaves.iter().enumerate() {
+        lengths[symbol] = depth[leaf].min(u8::MAX as u32) as u8;
+    }
+    lengths
+}
+
+/// Returns the canonical Huffman codes for the given lengths, bit-reversed as deflate writes them.
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut count = [0u16; 16];
+    for &len in lengths {
+        count[len as usize] += 1;
+    }
+    count[0] = 0;
+    let mut next = [0u16; 16];
+    let mut code = 0;
+    for bits in 1..16 {
+        code = (code + count[bits - 1]) << 1;
+        next[bits] = code;
+    }
+    lengths
+        .iter()
+        .map(|&len| {
+            if len == 0 {
+                return 0;
+            }
+            let code = next[len as usize];
+            next[len as usize] += 1;
+            code.reverse_bits() >> (16 - len)
+        })
+        .collect()
+}
+
+/// Writes bits starting at the least significant bit of every byte.
+struct BitWriter<'a> {
+    out: &'a mut Vec<u8>,
+    acc: u64,
+    count: u32,
+}
+
+impl BitWriter<'_> {
+    /// Writes the lowest `count` bits of `value`, at most 32.
+    fn write(&mut self, value: u32, count: u32) {
+        self.acc |= (value as u64) << self.count;
+        self.count += count;
+        if self.count >= 32 {
+            self.out.extend_from_slice(&(self.acc as u32).to_le_bytes());
+            self.acc >>= 32;
+            self.count -= 32;
+        }
+    }
+
+    /// Pads the written bits to a whole byte and writes all pending bytes.
+    fn align(&mut self) {
+        let bytes = self.count.div_ceil(8);
+        self.out
+            .extend_from_slice(&self.acc.to_le_bytes()[..bytes as usize]);
+        self.acc = 0;
+        self.count = 0;
+    }
+
+    fn flush(&mut self) {
+        self.align();
+    }
+}
+
+/// Returns the Adler-32 checksum of `data`.
+fn adler32(data: &[u8]) -> u32 {
+    // The sums can't overflow within 5552 bytes before they are reduced.
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// Reads bits starting at the least significant bit of every byte.
+    struct BitReader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bits(&mut self, count: u32) -> usize {
+            let mut value = 0;
+            for i in 0..count {
+                let bit = self.data[self.pos / 8] >> (self.pos % 8) & 1;
+                value |= (bit as usize) << i;
+                self.pos += 1;
+            }
+            value
+        }
+    }
+
+    /// A canonical Huffman code, decoded one bit at a time.
+    struct Huffman {
+        count: [usize; 16],
+        symbols: Vec<usize>,
+    }
+
+    impl Huffman {
+        fn new(lengths: &[u8]) -> Huffman {
+            let mut count = [0; 16];
+            for &len in lengths {
+                count[len as usize] += 1;
+            }
+            count[0] = 0;
+            let mut symbols: Vec<usize> = (0..lengths.len()).filter(|&s| lengths[s] > 0).collect();
+            symbols.sort_by_key(|&s| lengths[s]);
+            Huffman { count, symbols }
+        }
+
+        fn decode(&self, bits: &mut BitReader) -> usize {
+            let (mut code, mut first, mut index) = (0, 0, 0);
+            for len in 1..16 {
+                code |= bits.bits(1);
+                if code < first + self.count[len] {
+                    return self.symbols[index + code - first];
+                }
+                index += self.count[len];
+                first = (first + self.count[len]) << 1;
+                code <<= 1;
+            }
+            panic!("invalid code");
+        }
+    }
+
+    fn inflate_block(bits: &mut BitReader, out: &mut Vec<u8>, lit: &Huffman, dist: &Huffman) {
+        loop {
+            let symbol = lit.decode(bits);
+            if symbol < END_OF_BLOCK {
+                out.push(symbol as u8);
+                continue;
+            }
+            if symbol == END_OF_BLOCK {
+                return;
+            }
+            let code = symbol - 257;
+            let len = LENGTH_BASE[code] as usize + bits.bits(LENGTH_EXTRA[code] as u32);
+            let code = dist.decode(bits);
+            let dist = DIST_BASE[code] as usize + bits.bits(DIST_EXTRA[code] as u32);
+            assert!(dist <= out.len().min(WINDOW));
+            for _ in 0..len {
+                out.push(out[out.len() - dist]);
+            }
+        }
+    }
+
+    /// Decompresses a zlib stream, checking its header and checksum.
+    pub(crate) fn inflate(stream: &[u8]) -> Vec<u8> {
+        assert_eq!(u16::from_be_bytes([stream[0], stream[1]]) % 31, 0);
+        assert_eq!(stream[0], 0x78);
+        let mut bits = BitReader {
+            data: &stream[2..],
+            pos: 0,
+        };
+        let mut out = Vec::new();
+        loop {
+            let last = bits.bits(1) == 1;
+            match bits.bits(2) {
+                0 => {
+                    bits.pos = bits.pos.div_ceil(8) * 8;
+                    let len = bits.bits(16);
+                    assert_eq!(bits.bits(16), len ^ 0xFFFF);
+                    let start = bits.pos / 8;
+                    out.extend_from_slice(&bits.data[start..start + len]);
+                    bits.pos += len * 8;
+                }
+                1 => {
+                    let (lit, dist) = fixed_lengths();
+                    inflate_block(
+                        &mut bits,
+                        &mut out,
+                        &Huffman::new(&lit),
+                        &Huffman::new(&dist),
+                    );
+                }
+                2 => {
+                    let lit_count = bits.bits(5) + 257;
+                    let dist_count = bits.bits(5) + 1;
+                    let mut code_lengths = [0; 19];
+                    for &symbol in &CODE_LENGTH_ORDER[..bits.bits(4) + 4] {
+                        code_lengths[symbol] = bits.bits(3) as u8;
+                    }
+                    let code = Huffman::new(&code_lengths);
+                    let mut lengths = Vec::new();
+                    while lengths.len() < lit_count + dist_count {
+                        match code.decode(&mut bits) {
+                            16 => {
+                                let previous = *lengths.last().unwrap();
+                                let count = 3 + bits.bits(2);
+                                lengths.extend(std::iter::repeat_n(previous, count));
+                            }
+                            17 => lengths.extend(std::iter::repeat_n(0, 3 + bits.bits(3))),
+                            18 => lengths.extend(std::iter::repeat_n(0, 11 + bits.bits(7))),
+                            len => lengths.push(len as u8),
+                        }
+                    }
+                    assert_eq!(lengths.len(), lit_count + dist_count);
+                    let (lit, dist) = lengths.split_at(lit_count);
+                    inflate_block(&mut bits, &mut out, &Huffman::new(lit), &Huffman::new(dist));
+                }
+                _ => panic!("reserved block type"),
+            }
+            if last {
+                break;
+            }
+        }
+        let end = bits.pos.div_ceil(8);
+        assert_eq!(&bits.data[end..], &adler32(&out).to_be_bytes());
+        out
+    }
+
+    /// Returns bytes that hardly compress, from a xorshift generator.
+    pub(crate) fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn round_trip(data: &[u8]) {
+        for level in 0..=9 {
+            let mut out = Vec::new();
+            Deflater::new(level).compress(data, &mut out);
+            assert!(inflate(&out) == data, "level {level}, {} bytes", data.len());
+        }
+    }
+
+    #[test]
+    fn short_inputs() {
+        round_trip(&[]);
+        round_trip(&[42]);
+        round_trip(b"ab");
+        round_trip(b"abc");
+        round_trip(b"abcabcabcabcabc");
+    }
+
+    #[test]
+    fn runs_longer_than_a_match() {
+        round_trip(&[7; 258]);
+        round_trip(&[7; 259]);
+        round_trip(&[0; 100_000]);
+    }
+
+    #[test]
+    fn noise_spanning_several_blocks() {
+        // More than 64 KiB of literals needs several stored blocks, and more than `BLOCK_SYMBOLS` symbols.
+        round_trip(&noise(150_000));
+    }
+
+    #[test]
+    fn repeats_beyond_the_window() {
+        let pattern = noise(WINDOW + 1000);
+        let mut data = pattern.clone();
+        data.extend_from_slice(&b"some text in between".repeat(500));
+        data.extend_from_slice(&pattern);
+        round_trip(&data);
+    }
+
+    #[test]
+    fn mixed_content_uses_dynamic_blocks() {
+        let mut data = Vec::new();
+        for i in 0..20_000u32 {
+            data.extend_from_slice(format!("row {} of {}\n", i % 97, i / 13).as_bytes());
+        }
+        round_trip(&data);
+        let mut out = Vec::new();
+        Deflater::new(6).compress(&data, &mut out);
+        assert!(out.len() < data.len() / 4);
+    }
+
+    #[test]
+    fn deflater_can_be_reused() {
+        let mut deflater = Deflater::new(9);
+        for data in [noise(5000), vec![1; 5000], noise(40_000)] {
+            let mut out = Vec::new();
+            deflater.compress(&data, &mut out);
+            assert!(inflate(&out) == data);
+        }
+    }
+
+    #[test]
+    fn code_lengths_are_limited_and_complete() {
+        // Fibonacci frequencies make the unlimited Huffman code as deep as possible.
+        let mut freqs = vec![1u32, 1];
+        while freqs.len() < 30 {
+            freqs.push(freqs[freqs.len() - 1] + freqs[freqs.len() - 2]);
+        }
+        for limit in [7, 15] {
+            let lengths = code_lengths(&freqs, limit);
+            assert!(lengths.iter().all(|&len| (1..=limit).contains(&len)));
+            let kraft: f64 = lengths.iter().map(|&len| 0.5f64.powi(len as i32)).sum();
+            assert_eq!(kraft, 1.0);
+        }
+        // A single used symbol still gets a complete code of two symbols.
+        assert_eq!(code_lengths(&[0, 5, 0], 15), [1, 1, 0]);
+    }
+
+    #[test]
+    fn canonical_codes_match_the_rfc() {
+        // The example of RFC 1951, section 3.2.2, with the codes bit-reversed.
+        let codes = canonical_codes(&[3, 3, 3, 3, 3, 2, 4, 4]);
+        let expected = [0b010, 0b011, 0b100, 0b101, 0b110, 0b00, 0b1110, 0b1111];
+        let lengths = [3, 3, 3, 3, 3, 2, 4, 4];
+        for ((code, expected), len) in codes.into_iter().zip(expected).zip(lengths) {
+            assert_eq!(code, (expected as u16).reverse_bits() >> (16 - len));
+        }
+    }
+
+    #[test]
+    fn adler32_of_known_strings() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        // Long inputs need the sums reduced in between.
+        assert_eq!(adler32(&[255; 100_000]), 0x149A_302C);
+    }
+}
@@ -0,0 +1,268 @@
+//! Reading and writing of Netpbm files: PPM, PGM and PAM.
+//!
+//! These formats are little more than a text header followed by the pixels, which makes them easy to produce and
+//! inspect with other tools. [`encode_ppm`] writes color images as PPM and [`PixelFormat::Gray8`] images as PGM,
+//! dropping the alpha channel, [`encode_pam`] writes PAM files, which keep it. Pixels are converted from the BGR
+//! order of the captures to the RGB order of the files.
+//!
+//! [`decode`] reads all of them back, including the plain (ASCII) variants of PPM and PGM and files with more or
+//! fewer than 8 bits per sample. Pixels are converted back to BGR, so frames read from files look like captures,
+//! e.g. for [`Script::image_files`](crate::Script::image_files).
+//!
+//! # Examples
+//!
+//! ```
+//! use qshot::{pnm, Frame, PixelFormat};
+//!
+//! let frame = Frame::from_vec(vec![1, 2, 3, 255, 4, 5, 6, 128], 2, 1, PixelFormat::Bgra32);
+//!
+//! let mut ppm = Vec::new();
+//! pnm::encode_ppm(&frame, &mut ppm).unwrap();
+//! assert_eq!(ppm, b"P6\n2 1\n255\n\x03\x02\x01\x06\x05\x04");
+//! assert_eq!(pnm::decode(ppm.as_slice()).unwrap().bits(), &[1, 2, 3, 4, 5, 6]);
+//!
+//! let mut pam = Vec::new();
+//! pnm::encode_pam(&frame, &mut pam).unwrap();
+//! assert_eq!(pnm::decode(pam.as_slice()).unwrap().bits(), frame.bits());
+//! ```
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::convert::Converter;
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use crate::view::ImageView;
+
+/// Writes an image as a binary PPM file, or as a binary PGM file if it is [`PixelFormat::Gray8`], to `writer`.
+///
+/// The alpha channel is dropped.
+pub fn encode_ppm<'a>(image: impl Into<ImageView<'a>>, mut writer: impl Write) -> io::Result<()> {
+    let image = image.into();
+    let (magic, format) = match image.format() {
+        PixelFormat::Gray8 => ("P5", PixelFormat::Gray8),
+        _ => ("P6", PixelFormat::Rgb24),
+    };
+    write!(
+        writer,
+        "{magic}\n{} {}\n255\n",
+        image.width(),
+        image.height()
+    )?;
+    write_rows(image, format, writer)
+}
+
+/// Writes an image as a PAM file to `writer`, keeping the alpha channel.
+pub fn encode_pam<'a>(image: impl Into<ImageView<'a>>, mut writer: impl Write) -> io::Result<()> {
+    let image = image.into();
+    let (format, tuple_type) = match image.format() {
+        PixelFormat::Bgr24 | PixelFormat::Rgb24 => (PixelFormat::Rgb24, "RGB"),
+        PixelFormat::Bgra32 | PixelFormat::Rgba32 => (PixelFormat::Rgba32, "RGB_ALPHA"),
+        _ => (PixelFormat::Gray8, "GRAYSCALE"),
+    };
+    write!(
+        writer,
+        "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL 255\nTUPLTYPE {tuple_type}\nENDHDR\n",
+        image.width(),
+        image.height(),
+        format.bytes_per_pixel(),
+    )?;
+    write_rows(image, format, writer)
+}
+
+fn write_rows(image: ImageView, format: PixelFormat, mut writer: impl Write) -> io::Result<()> {
+    let converter = Converter::new();
+    let mut row = vec![0; image.width() * format.bytes_per_pixel()];
+    for src in image.rows() {
+        converter.convert_row(src, image.format(), &mut row, format);
+        writer.write_all(&row)?;
+    }
+    writer.flush()
+}
+
+/// Reads a PPM, PGM or PAM file from `reader`.
+///
+/// Color images are returned as [`PixelFormat::Bgr24`], grayscale images as [`PixelFormat::Gray8`] and images with
+/// an alpha channel as [`PixelFormat::Bgra32`], all tightly packed. Samples are scaled to 8 bits.
+///
+/// # Errors
+///
+/// Returns an [`io::ErrorKind::InvalidData`] error if the file is not one of these or is truncated, and any error
+/// `reader` returns. Bitmaps (PBM) are not supported.
+pub fn decode(mut reader: impl Read) -> io::Result<Frame> {
+    let mut file = Vec::new();
+    reader.read_to_end(&mut file)?;
+    parse(&file).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a supported PPM, PGM or PAM file",
+        )
+    })
+}
+
+/// Reads whitespace-separated tokens, skipping comments.
+struct Tokens<'a> {
+    file: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> Option<&'a [u8]> {
+        loop {
+            match self.file.get(self.pos)? {
+                b'#' => {
+                    while !matches!(self.file.get(self.pos), Some(b'\n' | b'\r') | None) {
+                        self.pos += 1;
+                    }
+                }
+                byte if byte.is_ascii_whitespace() => self.pos += 1,
+                _ => break,
+            }
+        }
+        let start = self.pos;
+        while self
+            .file
+            .get(self.pos)
+            .is_some_and(|byte| !byte.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+        Some(&self.file[start..self.pos])
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        std::str::from_utf8(self.next()?).ok()?.parse().ok()
+    }
+}
+
+fn parse(file: &[u8]) -> Option<Frame> {
+    let mut tokens = Tokens { file, pos: 0 };
+    let magic = tokens.next()?;
+    let (width, height, depth, maxval, plain) = match magic {
+        b"P2" | b"P3" | b"P5" | b"P6" => {
+            let depth = if matches!(magic, b"P2" | b"P5") { 1 } else { 3 };
+            let (width, height, maxval) = (tokens.number()?, tokens.number()?, tokens.number()?);
+            (width, height, depth, maxval, matches!(magic, b"P2" | b"P3"))
+        }
+        b"P7" => {
+            let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+            loop {
+                match tokens.next()? {
+                    b"WIDTH" => width = tokens.number(),
+                    b"HEIGHT" => height = tokens.number(),
+                    b"DEPTH" => depth = tokens.number(),
+                    b"MAXVAL" => maxval = tokens.number(),
+                    b"TUPLTYPE" => {
+                        tokens.next()?;
+                    }
+                    b"ENDHDR" => break,
+                    _ => return None,
+                }
+            }
+            (width?, height?, depth?, maxval?, false)
+        }
+        _ => return None,
+    };
+    if !(1..=4).contains(&depth) || !(1..=65535).contains(&maxval) {
+        return None;
+    }
+    let count = width.checked_mul(height)?.checked_mul(depth)?;
+
+    // The header can claim any size, so nothing is allocated before the file is known to be large enough.
+    let samples: Vec<u32> = if plain {
+        // Every sample takes at least a digit and the whitespace before it.
+        if count > (file.len() - tokens.pos) / 2 {
+            return None;
+        }
+        (0..count)
+            .map(|_| Some(tokens.number()?.min(maxval) as u32))
+            .collect::<Option<_>>()?
+    } else {
+        // A single whitespace character separates the header from the samples.
+        let start = tokens.pos + 1;
+        let wide = maxval > 255;
+        let len = count.checked_mul(1 + wide as usize)?;
+        let data = file.get(start..start.checked_add(len)?)?;
+        if wide {
+            data.chunks_exact(2)
+                .map(|s| u16::from_be_bytes([s[0], s[1]]) as u32)
+                .collect()
+        } else {
+            data.iter().map(|&s| s as u32).collect()
+        }
+    };
+    let maxval = maxval as u32;
+    let bytes: Vec<u8> = if maxval == 255 {
+        samples.iter().map(|&s| s as u8).collect()
+    } else {
+        samples
+            .iter()
+            .map(|&s| ((s.min(maxval) * 255 + maxval / 2) / maxval) as u8)
+            .collect()
+    };
+
+    let frame = match depth {
+        1 => Frame::from_vec(bytes, width, height, PixelFormat::Gray8),
+        2 => {
+            let bits = bytes
+                .chunks_exact(2)
+                .flat_map(|s| [s[0], s[0], s[0], s[1]])
+                .collect();
+            Frame::from_vec(bits, width, height, PixelFormat::Bgra32)
+        }
+        3 => {
+            Frame::from_vec(bytes, width, height, PixelFormat::Rgb24).to_format(PixelFormat::Bgr24)
+        }
+        _ => Frame::from_vec(bytes, width, height, PixelFormat::Rgba32)
+            .to_format(PixelFormat::Bgra32),
+    };
+    Some(frame)
+}
+
+/// Saves an image as a PPM or PGM file, replacing the file if it exists, see [`encode_ppm`].
+pub fn save_ppm<'a>(image: impl Into<ImageView<'a>>, path: impl AsRef<Path>) -> io::Result<()> {
+    encode_ppm(image, BufWriter::new(File::create(path)?))
+}
+
+/// Saves an image as a PAM file, replacing the file if it exists, see [`encode_pam`].
+pub fn save_pam<'a>(image: impl Into<ImageView<'a>>, path: impl AsRef<Path>) -> io::Result<()> {
+    encode_pam(image, BufWriter::new(File::create(path)?))
+}
+
+/// Loads a PPM, PGM or PAM file, see [`decode`].
+pub fn load(path: impl AsRef<Path>) -> io::Result<Frame> {
+    decode(File::open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::fixtures;
+
+    fn invalid(file: &[u8]) -> bool {
+        fixtures::fails_with(decode(file), io::ErrorKind::InvalidData)
+    }
+
+    #[test]
+    fn oversized_headers_are_rejected() {
+        assert!(invalid(b"P6\n100000 100000\n255\n"));
+        assert!(invalid(b"P5\n100000 100000\n65535\n\0\0"));
+        assert!(invalid(b"P3\n100000 100000\n255\n1 2 3"));
+        assert!(invalid(b"P2\n3 1\n255\n1 2"));
+        assert!(invalid(
+            b"P7\nWIDTH 4000000000\nHEIGHT 4000000000\nDEPTH 4\nMAXVAL 255\nENDHDR\n"
+        ));
+        assert!(invalid(
+            b"P7\nWIDTH 4000000000\nHEIGHT 1\nDEPTH 3\nMAXVAL 255\nENDHDR\n\0\0\0"
+        ));
+    }
+
+    #[test]
+    fn plain_samples_fit_exactly() {
+        let frame = decode(b"P2\n3 1\n255\n1 2 3".as_slice()).unwrap();
+        assert_eq!(frame.bits(), &[1, 2, 3]);
+        let frame = decode(b"P3 1 1 15 15 0 0".as_slice()).unwrap();
+        assert_eq!(frame.bits(), &[0, 0, 255]);
+    }
+}
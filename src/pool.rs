@@ -0,0 +1,118 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+
+struct Shared {
+    shape: Option<(usize, usize, PixelFormat)>,
+    capacity: usize,
+    frames: Vec<Frame>,
+}
+
+/// A pool of frames of one size and format, used to avoid allocating a new buffer for every capture.
+///
+/// The pool is a cheap handle that can be cloned and moved to other threads, so that frames can be
+/// [`recycle`](FramePool::recycle)d wherever they are consumed. Frames of a different size or format than the
+/// pool's are dropped instead of being recycled, as are frames returned while the pool is full.
+///
+/// Every [`CaptureManager`](crate::CaptureManager) owns a pool which is used by
+/// [`capture_pooled`](crate::CaptureManager::capture_pooled) and invalidated whenever the captured area or the
+/// format changes.
+///
+/// # Examples
+///
+/// ```
+/// use qshot::{FramePool, PixelFormat};
+///
+/// let pool = FramePool::new(2).with_shape(64, 48, PixelFormat::Bgra32);
+/// let frame = pool.get();
+/// let ptr = frame.bits().as_ptr();
+///
+/// pool.recycle(frame);
+/// assert_eq!(pool.len(), 1);
+/// assert_eq!(pool.get().bits().as_ptr(), ptr);
+/// ```
+#[derive(Clone)]
+pub struct FramePool {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl FramePool {
+    /// Creates an empty pool that keeps at most `capacity` frames.
+    ///
+    /// The pool has no shape yet, so nothing is recycled until [`set_shape`](FramePool::set_shape) is called.
+    pub fn new(capacity: usize) -> FramePool {
+        FramePool {
+            shared: Arc::new(Mutex::new(Shared {
+                shape: None,
+                capacity,
+                frames: Vec::new(),
+            })),
+        }
+    }
+
+    /// Sets the shape of the frames kept by the pool, see [`set_shape`](FramePool::set_shape).
+    pub fn with_shape(self, width: usize, height: usize, format: PixelFormat) -> FramePool {
+        self.set_shape(width, height, format);
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        // The shared state stays consistent even if a thread panicked while holding the lock.
+        self.shared.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Sets the size and format of the frames kept by the pool. Frames of the previous shape are dropped.
+    pub fn set_shape(&self, width: usize, height: usize, format: PixelFormat) {
+        let mut shared = self.lock();
+        if shared.shape != Some((width, height, format)) {
+            shared.shape = Some((width, height, format));
+            shared.frames.clear();
+        }
+    }
+
+    /// Returns the size and format of the frames kept by the pool.
+    pub fn shape(&self) -> Option<(usize, usize, PixelFormat)> {
+        self.lock().shape
+    }
+
+    /// Drops all frames and forgets the shape of the pool, frames recycled afterwards are dropped as well
+    /// until a new shape is set.
+    pub fn invalidate(&self) {
+        let mut shared = self.lock();
+        shared.shape = None;
+        shared.frames.clear();
+    }
+
+    /// Takes a frame out of the pool, or allocates a new one of the pool's shape if the pool is empty.
+    ///
+    /// The contents of a recycled frame are whatever they were when it was returned.
+    pub fn get(&self) -> Frame {
+        let mut shared = self.lock();
+        if let Some(frame) = shared.frames.pop() {
+            return frame;
+        }
+        let (width, height, format) = shared.shape.unwrap_or((0, 0, PixelFormat::Bgr24));
+        drop(shared);
+        Frame::new(width, height, format)
+    }
+
+    /// Returns a frame to the pool so that its buffer can be reused.
+    pub fn recycle(&self, frame: Frame) {
+        let mut shared = self.lock();
+        let shape = (frame.width(), frame.height(), frame.format());
+        if shared.shape == Some(shape) && shared.frames.len() < shared.capacity {
+            shared.frames.push(frame);
+        }
+    }
+
+    /// Returns the number of frames in the pool.
+    pub fn len(&self) -> usize {
+        self.lock().frames.len()
+    }
+
+    /// Returns `true` if the pool holds no frames.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
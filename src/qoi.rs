@@ -0,0 +1,543 @@
+//! Reading and writing of QOI ("Quite OK Image") files.
+//!
+//! QOI is a lossless format that encodes and decodes many times faster than PNG, while compressing screen contents
+//! nearly as well, which makes it a good fit for recording many frames. See <https://qoiformat.org> for the
+//! specification.
+//!
+//! A [`QoiEncoder`] reads the pixels straight from a capture in any format, so no converted copy is made, and
+//! writes the file in small pieces as it goes. A [`QoiDecoder`] can decode into an existing [`Frame`], reusing its
+//! buffer. Decoded images are [`PixelFormat::Bgr24`] or [`PixelFormat::Bgra32`], depending on the number of
+//! channels stored in the file, so they look like captures.
+//!
+//! # Examples
+//!
+//! ```
+//! use qshot::{qoi, CaptureManager, Rect, Script, SyntheticBackend};
+//!
+//! let script = Script::new((64, 48)).moving_rect([30, 30, 30], [0, 120, 255], (16, 16), (0, 0), (8, 4), 3);
+//! let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 64, 48)).unwrap();
+//!
+//! let mut encoder = qoi::QoiEncoder::new();
+//! let mut decoder = qoi::QoiDecoder::new();
+//! let mut frame = manager.capture_frame().unwrap();
+//! for _ in 0..3 {
+//!     let capture = manager.capture().unwrap();
+//!     let mut file = Vec::new();
+//!     encoder.encode(&capture, &mut file).unwrap();
+//!     assert!(file.len() < 64 * 48 * 3 / 10);
+//!
+//!     decoder.decode_into(file.as_slice(), &mut frame).unwrap();
+//!     assert_eq!(frame.bits(), capture.get_bits());
+//! }
+//! ```
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use crate::view::ImageView;
+
+const MAGIC: &[u8; 4] = b"qoif";
+const HEADER_SIZE: usize = 14;
+const END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+const OP_INDEX: u8 = 0x00;
+const OP_DIFF: u8 = 0x40;
+const OP_LUMA: u8 = 0x80;
+const OP_RUN: u8 = 0xC0;
+const OP_RGB: u8 = 0xFE;
+const OP_RGBA: u8 = 0xFF;
+const MASK: u8 = 0xC0;
+/// The longest run a single chunk can encode.
+const MAX_RUN: u8 = 62;
+
+/// The size of the output buffer of the encoder, which is written out whenever it is full.
+const BUFFER_SIZE: usize = 1 << 16;
+
+/// Returns the position of a pixel, given as \[R, G, B, A], in the index of recently seen pixels.
+fn index_position([r, g, b, a]: [u8; 4]) -> usize {
+    (r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64
+}
+
+/// Encodes images as QOI, reusing its output buffer from one image to the next.
+#[derive(Clone, Debug)]
+pub struct QoiEncoder {
+    buffer: Vec<u8>,
+}
+
+impl Default for QoiEncoder {
+    fn default() -> QoiEncoder {
+        QoiEncoder::new()
+    }
+}
+
+impl QoiEncoder {
+    /// Creates an encoder.
+    pub fn new() -> QoiEncoder {
+        QoiEncoder {
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+        }
+    }
+
+    /// Writes an image as a complete QOI file to `writer`.
+    ///
+    /// Images with alpha are stored with 4 channels, all others with 3. [`PixelFormat::Gray8`] images are stored as
+    /// RGB. The file is written in pieces of 64 KiB, so `writer` doesn't need to be buffered.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::ErrorKind::InvalidInput`] error if the image is too large for a QOI file, and any error
+    /// `writer` returns.
+    pub fn encode<'a>(
+        &mut self,
+        image: impl Into<ImageView<'a>>,
+        mut writer: impl Write,
+    ) -> io::Result<()> {
+        let image = image.into();
+        let (Ok(width), Ok(height)) = (u32::try_from(image.width()), u32::try_from(image.height()))
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "image is too large for a QOI file",
+            ));
+        };
+        self.buffer.clear();
+        self.buffer.extend_from_slice(MAGIC);
+        self.buffer.extend_from_slice(&width.to_be_bytes());
+        self.buffer.extend_from_slice(&height.to_be_bytes());
+        // The channels, followed by the sRGB color space.
+        self.buffer
+            .extend_from_slice(&[3 + image.format().has_alpha() as u8, 0]);
+
+        match image.format() {
+            PixelFormat::Bgr24 => self.chunks::<3>(image, &mut writer, |p| [p[2], p[1], p[0], 255]),
+            PixelFormat::Bgra32 => {
+                self.chunks::<4>(image, &mut writer, |p| [p[2], p[1], p[0], p[3]])
+            }
+            PixelFormat::Rgb24 => self.chunks::<3>(image, &mut writer, |p| [p[0], p[1], p[2], 255]),
+            PixelFormat::Rgba32 => {
+                self.chunks::<4>(image, &mut writer, |p| [p[0], p[1], p[2], p[3]])
+            }
+            PixelFormat::Gray8 => self.chunks::<1>(image, &mut writer, |p| [p[0], p[0], p[0], 255]),
+        }?;
+
+        self.buffer.extend_from_slice(&END_MARKER);
+        writer.write_all(&self.buffer)?;
+        self.buffer.clear();
+        writer.flush()
+    }
+
+    /// Encodes the pixels of all rows, reading each with `rgba`.
+    fn chunks<const BPP: usize>(
+        &mut self,
+        image: ImageView,
+        writer: &mut impl Write,
+        rgba: impl Fn(&[u8]) -> [u8; 4],
+    ) -> io::Result<()> {
+        let mut index = [[0u8; 4]; 64];
+        let mut prev = [0, 0, 0, 255];
+        let mut run = 0u8;
+        for row in image.rows() {
+            if self.buffer.len() + row.len() / BPP * 5 > BUFFER_SIZE {
+                writer.write_all(&self.buffer)?;
+                self.buffer.clear();
+            }
+            for pixel in row.chunks_exact(BPP) {
+                let pixel = rgba(pixel);
+                if pixel == prev {
+                    run += 1;
+                    if run == MAX_RUN {
+                        self.buffer.push(OP_RUN | (run - 1));
+                        run = 0;
+                    }
+                    continue;
+                }
+                if run > 0 {
+                    self.buffer.push(OP_RUN | (run - 1));
+                    run = 0;
+                }
+                let position = index_position(pixel);
+                if index[position] == pixel {
+                    self.buffer.push(OP_INDEX | position as u8);
+                } else {
+                    index[position] = pixel;
+                    self.push_color(pixel, prev);
+                }
+                prev = pixel;
+            }
+        }
+        if run > 0 {
+            self.buffer.push(OP_RUN | (run - 1));
+        }
+        Ok(())
+    }
+
+    /// Encodes a pixel that differs from the previous one and isn't in the index.
+    fn push_color(&mut self, pixel: [u8; 4], prev: [u8; 4]) {
+        if pixel[3] != prev[3] {
+            self.buffer.push(OP_RGBA);
+            self.buffer.extend_from_slice(&pixel);
+            return;
+        }
+        let dr = pixel[0].wrapping_sub(prev[0]) as i8;
+        let dg = pixel[1].wrapping_sub(prev[1]) as i8;
+        let db = pixel[2].wrapping_sub(prev[2]) as i8;
+        let (dr_dg, db_dg) = (dr.wrapping_sub(dg), db.wrapping_sub(dg));
+        if (-2..2).contains(&dr) && (-2..2).contains(&dg) && (-2..2).contains(&db) {
+            self.buffer
+                .push(OP_DIFF | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8);
+        } else if (-32..32).contains(&dg) && (-8..8).contains(&dr_dg) && (-8..8).contains(&db_dg) {
+            self.buffer.push(OP_LUMA | (dg + 32) as u8);
+            self.buffer
+                .push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+        } else {
+            self.buffer.push(OP_RGB);
+            self.buffer.extend_from_slice(&pixel[..3]);
+        }
+    }
+}
+
+/// Decodes QOI files, reusing its input buffer from one file to the next.
+///
+/// # Examples
+///
+/// ```
+/// use qshot::qoi::{self, QoiDecoder};
+/// use qshot::{Frame, PixelFormat};
+///
+/// // Two rows of two translucent pixels, each row padded to 12 bytes.
+/// let bits = [1, 2, 3, 4, 1, 2, 3, 4, 0, 0, 0, 0, 9, 8, 7, 6, 255, 255, 255, 0, 0, 0, 0, 0];
+/// let frame = Frame::from_vec_with_stride(bits.to_vec(), 2, 2, 12, PixelFormat::Bgra32);
+/// let mut file = Vec::new();
+/// qoi::encode(&frame, &mut file).unwrap();
+///
+/// let decoded = QoiDecoder::new().decode(file.as_slice()).unwrap();
+/// assert_eq!(decoded.format(), PixelFormat::Bgra32);
+/// assert!(decoded.rows().eq(frame.rows()));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct QoiDecoder {
+    input: Vec<u8>,
+}
+
+impl QoiDecoder {
+    /// Creates a decoder.
+    pub fn new() -> QoiDecoder {
+        QoiDecoder { input: Vec::new() }
+    }
+
+    /// Reads a QOI file from `reader`.
+    ///
+    /// Files with 3 channels are returned as [`PixelFormat::Bgr24`], files with 4 channels as
+    /// [`PixelFormat::Bgra32`], tightly packed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`io::ErrorKind::InvalidData`] error if the file is not a QOI file or is truncated, and any error
+    /// `reader` returns.
+    pub fn decode(&mut self, reader: impl Read) -> io::Result<Frame> {
+        let mut frame = Frame::new(0, 0, PixelFormat::Bgr24);
+        self.decode_into(reader, &mut frame)?;
+        Ok(frame)
+    }
+
+    /// Reads a QOI file from `reader` into `frame`, whose buffer is reused.
+    ///
+    /// `frame` is reshaped like the frames returned by [`decode`](QoiDecoder::decode) and timestamped with the
+    /// current time. Its contents are unspecified if decoding fails.
+    ///
+    /// # Errors
+    ///
+    /// See [`decode`](QoiDecoder::decode).
+    pub fn decode_into(&mut self, mut reader: impl Read, frame: &mut Frame) -> io::Result<()> {
+        self.input.clear();
+        reader.read_to_end(&mut self.input)?;
+        parse(&self.input, frame)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a valid QOI file"))
+    }
+}
+
+fn parse(file: &[u8], frame: &mut Frame) -> Option<()> {
+    if file.len() < HEADER_SIZE || &file[..4] != MAGIC {
+        return None;
+    }
+    let width = u32::from_be_bytes(file[4..8].try_into().unwrap()) as usize;
+    let height = u32::from_be_bytes(file[8..12].try_into().unwrap()) as usize;
+    let format = match file[12] {
+        3 => PixelFormat::Bgr24,
+        4 => PixelFormat::Bgra32,
+        _ => return None,
+    };
+    let pixels = width.checked_mul(height)?;
+    // A single byte encodes at most a run of 62 pixels, so the size of the file bounds the size of the image
+    // before anything is allocated for it.
+    if pixels / MAX_RUN as usize > file.len() {
+        return None;
+    }
+    let stride = width * format.bytes_per_pixel();
+    frame.reshape(width, height, stride, format);
+    let chunks = &file[HEADER_SIZE..];
+    match format {
+        PixelFormat::Bgr24 => pixels_into::<3>(chunks, frame.bits_mut()),
+        _ => pixels_into::<4>(chunks, frame.bits_mut()),
+    }
+}
+
+/// Decodes the chunks into `out`, storing the pixels as \[B, G, R] or \[B, G, R, A].
+fn pixels_into<const BPP: usize>(chunks: &[u8], out: &mut [u8]) -> Option<()> {
+    let mut index = [[0u8; 4]; 64];
+    let mut pixel = [0, 0, 0, 255];
+    let mut pos = 0;
+    let mut next = || {
+        let byte = *chunks.get(pos)?;
+        pos += 1;
+        Some(byte)
+    };
+    let mut pixels = out.chunks_exact_mut(BPP);
+    while let Some(first) = pixels.next() {
+        let tag = next()?;
+        let mut run = 0;
+        match tag {
+            OP_RGB => {
+                pixel[..3].copy_from_slice(&[next()?, next()?, next()?]);
+            }
+            OP_RGBA => {
+                pixel = [next()?, next()?, next()?, next()?];
+            }
+            _ => match tag & MASK {
+                OP_INDEX => pixel = index[tag as usize],
+                OP_DIFF => {
+                    pixel[0] = pixel[0].wrapping_add((tag >> 4 & 3).wrapping_sub(2));
+                    pixel[1] = pixel[1].wrapping_add((tag >> 2 & 3).wrapping_sub(2));
+                    pixel[2] = pixel[2].wrapping_add((tag & 3).wrapping_sub(2));
+                }
+                OP_LUMA => {
+                    let byte = next()?;
+                    let dg = (tag & 0x3F).wrapping_sub(32);
+                    pixel[0] = pixel[0].wrapping_add(dg.wrapping_add(byte >> 4).wrapping_sub(8));
+                    pixel[1] = pixel[1].wrapping_add(dg);
+                    pixel[2] = pixel[2].wrapping_add(dg.wrapping_add(byte & 0xF).wrapping_sub(8));
+                }
+                _ => run = (tag & 0x3F) as usize,
+            },
+        }
+        index[index_position(pixel)] = pixel;
+        let bgra = [pixel[2], pixel[1], pixel[0], pixel[3]];
+        first.copy_from_slice(&bgra[..BPP]);
+        for out in pixels.by_ref().take(run) {
+            out.copy_from_slice(&bgra[..BPP]);
+        }
+    }
+    Some(())
+}
+
+/// Writes an image as a QOI file to `writer`, see [`QoiEncoder::encode`].
+pub fn encode<'a>(image: impl Into<ImageView<'a>>, writer: impl Write) -> io::Result<()> {
+    QoiEncoder::new().encode(image, writer)
+}
+
+/// Reads a QOI file from `reader`, see [`QoiDecoder::decode`].
+pub fn decode(reader: impl Read) -> io::Result<Frame> {
+    QoiDecoder::new().decode(reader)
+}
+
+/// Saves an image as a QOI file, replacing the file if it exists, see [`QoiEncoder::encode`].
+pub fn save<'a>(image: impl Into<ImageView<'a>>, path: impl AsRef<Path>) -> io::Result<()> {
+    encode(image, File::create(path)?)
+}
+
+/// Loads a QOI file, see [`QoiDecoder::decode`].
+pub fn load(path: impl AsRef<Path>) -> io::Result<Frame> {
+    decode(File::open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::fixtures;
+
+    const FORMATS: [PixelFormat; 5] = [
+        PixelFormat::Bgr24,
+        PixelFormat::Bgra32,
+        PixelFormat::Rgb24,
+        PixelFormat::Rgba32,
+        PixelFormat::Gray8,
+    ];
+
+    /// Returns a frame whose rows are padded by `padding` bytes, filled with a mix of runs, small and large steps.
+    fn frame(width: usize, height: usize, padding: usize, format: PixelFormat) -> Frame {
+        let mut state = 0x9E37_79B9u32;
+        fixtures::padded(width, height, padding, format, |x, y| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let value = match (x / 3 + y) % 4 {
+                0 => 10,
+                1 => (x + y) as u8,
+                2 => (x * 40) as u8,
+                _ => state as u8,
+            };
+            std::array::from_fn(|c| value.wrapping_add((state >> (8 * c)) as u8 % 3 * 2))
+        })
+    }
+
+    fn encoded(image: &Frame) -> Vec<u8> {
+        fixtures::encoded(|file| encode(image, file))
+    }
+
+    /// Returns the chunks of a file, without the header and the end marker.
+    fn chunks(file: &[u8]) -> &[u8] {
+        assert_eq!(&file[file.len() - 8..], &END_MARKER);
+        &file[HEADER_SIZE..file.len() - 8]
+    }
+
+    fn assert_round_trip(image: &Frame) {
+        let decoded = decode(encoded(image).as_slice()).unwrap();
+        let expected = if image.format().has_alpha() {
+            PixelFormat::Bgra32
+        } else {
+            PixelFormat::Bgr24
+        };
+        assert_eq!(decoded.format(), expected);
+        assert_eq!(
+            (decoded.width(), decoded.height()),
+            (image.width(), image.height())
+        );
+        assert!(decoded.rows().eq(image.to_format(expected).rows()));
+    }
+
+    #[test]
+    fn round_trips_odd_sizes_and_padded_strides() {
+        for format in FORMATS {
+            for (width, height) in [(1, 1), (3, 5), (7, 1), (1, 7), (17, 13), (130, 3)] {
+                for padding in [0, 1, 5] {
+                    assert_round_trip(&frame(width, height, padding, format));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_empty_images() {
+        for format in FORMATS {
+            assert_round_trip(&Frame::new(0, 0, format));
+            assert_round_trip(&Frame::new(0, 4, format));
+        }
+    }
+
+    #[test]
+    fn runs_are_split_at_62_pixels() {
+        // Opaque black equals the initial previous pixel, so the whole row is a run.
+        for (len, expected) in [
+            (61, vec![OP_RUN | 60]),
+            (62, vec![OP_RUN | 61]),
+            (63, vec![OP_RUN | 61, OP_RUN]),
+            (124, vec![OP_RUN | 61, OP_RUN | 61]),
+            (125, vec![OP_RUN | 61, OP_RUN | 61, OP_RUN]),
+        ] {
+            let image = Frame::new(len, 1, PixelFormat::Bgr24);
+            let file = encoded(&image);
+            assert_eq!(chunks(&file), expected, "run of {len}");
+            assert_round_trip(&image);
+        }
+    }
+
+    #[test]
+    fn runs_continue_across_rows() {
+        let image = Frame::from_vec(vec![0; 31 * 2 * 3], 31, 2, PixelFormat::Bgr24);
+        assert_eq!(chunks(&encoded(&image)), [OP_RUN | 61]);
+    }
+
+    #[test]
+    fn alpha_changes_are_stored_as_rgba() {
+        let bits = [1, 2, 3, 255, 1, 2, 3, 254, 1, 2, 3, 254, 1, 2, 3, 0];
+        let image = Frame::from_vec(bits.to_vec(), 4, 1, PixelFormat::Bgra32);
+        let file = encoded(&image);
+        assert_eq!(file[12], 4);
+        let expected = [
+            [OP_LUMA | 34, 9 << 4 | 7].as_slice(),
+            &[OP_RGBA, 3, 2, 1, 254],
+            &[OP_RUN],
+            &[OP_RGBA, 3, 2, 1, 0],
+        ];
+        assert_eq!(chunks(&file), expected.concat());
+        assert_round_trip(&image);
+    }
+
+    #[test]
+    fn index_hits_and_initial_entries() {
+        let position = index_position([0, 0, 0, 0]);
+        // Transparent black is in the initial index, so the first pixel is a hit.
+        let image = Frame::from_vec(vec![0; 4], 1, 1, PixelFormat::Bgra32);
+        assert_eq!(chunks(&encoded(&image)), [OP_INDEX | position as u8]);
+        assert_round_trip(&image);
+
+        // Returning to an earlier color refers to the index instead of repeating it.
+        let (a, b) = ([200, 100, 50], [10, 20, 250]);
+        let image = Frame::from_vec([a, b, a, b].concat(), 4, 1, PixelFormat::Bgr24);
+        let hit = |[b, g, r]: [u8; 3]| OP_INDEX | index_position([r, g, b, 255]) as u8;
+        let file = encoded(&image);
+        assert_eq!(&chunks(&file)[8..], [hit(a), hit(b)]);
+        assert_round_trip(&image);
+
+        // Colors sharing a position replace each other.
+        let c = [0, 0, 0, 255];
+        let d = (1..=255u8)
+            .flat_map(|v| (0..=255u8).map(move |w| [v, w, 0, 255]))
+            .find(|&d| index_position(d) == index_position(c))
+            .unwrap();
+        let bgra = |[r, g, b, a]: [u8; 4]| [b, g, r, a];
+        let bits = [bgra(d), [9, 9, 9, 255], bgra(c), [9, 9, 9, 255], bgra(d)].concat();
+        assert_round_trip(&Frame::from_vec(bits, 5, 1, PixelFormat::Bgra32));
+    }
+
+    #[test]
+    fn differences_wrap_around() {
+        // Going from 0 to 255 is a difference of -1, and the LUMA steps reach the limits of its ranges.
+        let bits = [[255, 255, 255], [0, 0, 0], [29, 31, 30], [253, 255, 254]].concat();
+        let image = Frame::from_vec(bits, 4, 1, PixelFormat::Bgr24);
+        assert_eq!(
+            chunks(&encoded(&image)),
+            [
+                OP_DIFF | 1 << 4 | 1 << 2 | 1,
+                OP_DIFF | 3 << 4 | 3 << 2 | 3,
+                OP_LUMA | 63,
+                7 << 4 | 6,
+                OP_LUMA,
+                8 << 4 | 8,
+            ]
+        );
+        assert_round_trip(&image);
+    }
+
+    #[test]
+    fn decoder_reuses_the_frame() {
+        let mut decoder = QoiDecoder::new();
+        let mut out = Frame::new(17, 13, PixelFormat::Bgra32);
+        let ptr = out.bits().as_ptr();
+        let image = frame(17, 13, 0, PixelFormat::Bgra32);
+        decoder
+            .decode_into(encoded(&image).as_slice(), &mut out)
+            .unwrap();
+        assert_eq!(out.bits().as_ptr(), ptr);
+        assert_eq!(out.bits(), image.bits());
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let file = encoded(&frame(17, 13, 0, PixelFormat::Bgr24));
+        let invalid = |file: &[u8]| {
+            fixtures::fails_with(QoiDecoder::new().decode(file), io::ErrorKind::InvalidData)
+        };
+        assert!(invalid(&file[..HEADER_SIZE - 1]));
+        assert!(invalid(&file[..file.len() / 2]));
+        assert!(invalid(&[b"qoiF", &file[4..]].concat()));
+        assert!(invalid(&[&file[..12], &[5], &file[13..]].concat()));
+        // A huge header is rejected before anything is allocated for it.
+        let mut huge = file.clone();
+        huge[4..12].copy_from_slice(&[0xFF; 8]);
+        assert!(invalid(&huge));
+    }
+}
@@ -0,0 +1,264 @@
+use std::fmt;
+
+/// A rectangular area in pixels, described by its upper-left corner and its size.
+///
+/// Coordinates are relative to the capture target, e.g. a window or the screen. Captures of the entire screen on
+/// Windows use the coordinates of the virtual screen, in which monitors left of or above the primary monitor have
+/// negative coordinates.
+///
+/// A rectangle whose width or height is not positive is [empty](Rect::is_empty).
+///
+/// # Examples
+///
+/// ```
+/// use qshot::Rect;
+///
+/// let a = Rect::new(0, 0, 100, 100);
+/// let b = Rect::new(50, 80, 100, 100);
+/// assert_eq!(a.intersection(&b), Some(Rect::new(50, 80, 50, 20)));
+/// assert_eq!(a.union(&b), Rect::new(0, 0, 150, 180));
+///
+/// // A 100x100 area on a 150% (144 DPI) display.
+/// assert_eq!(a.to_physical(144), Rect::new(0, 0, 150, 150));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Rect {
+    /// The X coordinate of the left edge.
+    pub x: i32,
+    /// The Y coordinate of the top edge.
+    pub y: i32,
+    /// The width of the area.
+    pub width: i32,
+    /// The height of the area.
+    pub height: i32,
+}
+
+impl Rect {
+    /// Creates a rectangle from the coordinates of its upper-left corner and its size.
+    pub const fn new(x: i32, y: i32, width: i32, height: i32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Creates a rectangle from its upper-left corner and its width and height.
+    pub const fn from_parts(top_left: (i32, i32), wh: (i32, i32)) -> Rect {
+        Rect::new(top_left.0, top_left.1, wh.0, wh.1)
+    }
+
+    /// Creates the smallest rectangle containing both corners, which are given in any order.
+    ///
+    /// The width and height saturate at `i32::MAX` for corners further apart.
+    pub fn from_corners(a: (i32, i32), b: (i32, i32)) -> Rect {
+        let (left, right) = (a.0.min(b.0), a.0.max(b.0));
+        let (top, bottom) = (a.1.min(b.1), a.1.max(b.1));
+        Rect::new(
+            left,
+            top,
+            right.saturating_sub(left),
+            bottom.saturating_sub(top),
+        )
+    }
+
+    /// Returns the upper-left corner.
+    pub const fn top_left(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+
+    /// Returns the width and height.
+    pub const fn wh(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    /// Returns the X coordinate just past the right edge.
+    pub const fn right(&self) -> i32 {
+        self.x.saturating_add(self.width)
+    }
+
+    /// Returns the Y coordinate just past the bottom edge.
+    pub const fn bottom(&self) -> i32 {
+        self.y.saturating_add(self.height)
+    }
+
+    /// Returns `true` if the rectangle covers no pixels.
+    pub const fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    /// Returns the number of pixels covered by the rectangle.
+    pub fn area(&self) -> u64 {
+        if self.is_empty() {
+            0
+        } else {
+            self.width as u64 * self.height as u64
+        }
+    }
+
+    /// Returns `true` if the pixel at the given coordinates lies within the rectangle.
+    pub const fn contains_point(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    /// Returns `true` if `other` lies entirely within the rectangle. Empty rectangles are contained in any rectangle.
+    pub fn contains(&self, other: &Rect) -> bool {
+        other.is_empty()
+            || (other.x >= self.x
+                && other.y >= self.y
+                && other.right() <= self.right()
+                && other.bottom() <= self.bottom())
+    }
+
+    /// Returns the area covered by both rectangles, or `None` if they don't overlap.
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let left = self.x.max(other.x);
+        let top = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+        let rect = Rect::new(
+            left,
+            top,
+            right.saturating_sub(left),
+            bottom.saturating_sub(top),
+        );
+        (!rect.is_empty()).then_some(rect)
+    }
+
+    /// Returns `true` if the rectangles share at least one pixel.
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    /// Returns the smallest rectangle containing both rectangles. Empty rectangles are ignored.
+    pub fn union(&self, other: &Rect) -> Rect {
+        if other.is_empty() {
+            return *self;
+        }
+        if self.is_empty() {
+            return *other;
+        }
+        Rect::from_corners(
+            (self.x.min(other.x), self.y.min(other.y)),
+            (
+                self.right().max(other.right()),
+                self.bottom().max(other.bottom()),
+            ),
+        )
+    }
+
+    /// Returns the part of the rectangle that lies within `bounds`.
+    ///
+    /// Unlike [`intersection`](Rect::intersection), the result is always a rectangle within `bounds`, which is
+    /// empty if the rectangles don't overlap.
+    pub fn clamp(&self, bounds: &Rect) -> Rect {
+        self.intersection(bounds).unwrap_or_else(|| {
+            let x = self.x.clamp(bounds.x, bounds.right().max(bounds.x));
+            let y = self.y.clamp(bounds.y, bounds.bottom().max(bounds.y));
+            Rect::new(x, y, 0, 0)
+        })
+    }
+
+    /// Returns the rectangle moved by the given distances.
+    ///
+    /// Like [`right`](Rect::right) and [`bottom`](Rect::bottom), the coordinates saturate at the bounds of `i32`.
+    ///
+    /// ```
+    /// use qshot::Rect;
+    ///
+    /// assert_eq!(Rect::new(10, 20, 5, 5).offset(-15, 5), Rect::new(-5, 25, 5, 5));
+    /// assert_eq!(Rect::new(i32::MAX - 1, 0, 5, 5).offset(10, 0), Rect::new(i32::MAX, 0, 5, 5));
+    /// ```
+    pub const fn offset(&self, dx: i32, dy: i32) -> Rect {
+        Rect::new(
+            self.x.saturating_add(dx),
+            self.y.saturating_add(dy),
+            self.width,
+            self.height,
+        )
+    }
+
+    /// Returns the rectangle with its coordinates and size multiplied by `factor`.
+    ///
+    /// Edges that don't land on whole pixels are rounded outwards, so the result covers the entire scaled area.
+    pub fn scale(&self, factor: f64) -> Rect {
+        let left = (self.x as f64 * factor).floor() as i32;
+        let top = (self.y as f64 * factor).floor() as i32;
+        let right = (self.right() as f64 * factor).ceil() as i32;
+        let bottom = (self.bottom() as f64 * factor).ceil() as i32;
+        Rect::new(
+            left,
+            top,
+            right.saturating_sub(left),
+            bottom.saturating_sub(top),
+        )
+    }
+
+    /// Converts a rectangle in logical pixels to physical pixels of a display with the given DPI.
+    ///
+    /// A display of 96 DPI has a scale factor of 100%, so logical and physical pixels are the same.
+    pub fn to_physical(&self, dpi: u32) -> Rect {
+        self.scale(dpi as f64 / 96.0)
+    }
+
+    /// Converts a rectangle in physical pixels of a display with the given DPI to logical pixels.
+    ///
+    /// This is the inverse of [`to_physical`](Rect::to_physical), rounded outwards.
+    pub fn to_logical(&self, dpi: u32) -> Rect {
+        self.scale(96.0 / dpi as f64)
+    }
+}
+
+impl From<((i32, i32), (i32, i32))> for Rect {
+    fn from((top_left, wh): ((i32, i32), (i32, i32))) -> Rect {
+        Rect::from_parts(top_left, wh)
+    }
+}
+
+impl fmt::Display for Rect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{} at ({}, {})",
+            self.width, self.height, self.x, self.y
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extreme_coordinates_saturate() {
+        let (a, b) = (
+            Rect::new(-2_000_000_000, 0, 10, 10),
+            Rect::new(2_000_000_000, 0, 10, 10),
+        );
+        assert_eq!(a.union(&b), Rect::new(-2_000_000_000, 0, i32::MAX, 10));
+        assert_eq!(b.union(&a), a.union(&b));
+        assert_eq!(a.intersection(&b), None);
+        assert!(!a.intersects(&b));
+        assert_eq!(
+            Rect::from_corners((i32::MAX, i32::MIN), (i32::MIN, i32::MAX)),
+            Rect::new(i32::MIN, i32::MIN, i32::MAX, i32::MAX)
+        );
+        assert_eq!(
+            Rect::new(i32::MIN, i32::MIN, i32::MAX, i32::MAX)
+                .intersection(&Rect::new(-10, -10, 5, 5)),
+            Some(Rect::new(-10, -10, 5, 5))
+        );
+    }
+
+    #[test]
+    fn scaling_saturates() {
+        let wide = Rect::new(-2_000_000_000, -10, i32::MAX, 20);
+        assert_eq!(wide.scale(2.0), Rect::new(i32::MIN, -20, i32::MAX, 40));
+        assert_eq!(
+            Rect::new(1_000_000_000, 0, 10, 10).to_physical(960),
+            Rect::new(i32::MAX, 0, 0, 100)
+        );
+        assert_eq!(Rect::new(-3, -3, 5, 5).scale(0.5), Rect::new(-2, -2, 3, 3));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::backend::CaptureBackend;
+use crate::capture::CaptureManager;
+use crate::error::Error;
+use crate::format::OPAQUE_BLACK;
+use crate::frame::Frame;
+use crate::rect::Rect;
+
+/// Several regions of one capture packed into a single frame, created by
+/// [`CaptureManager::capture_atlas`].
+///
+/// The regions are packed in rows without overlapping, the parts of the frame not covered by any region are
+/// opaque black.
+#[derive(Clone, Debug)]
+pub struct Atlas {
+    frame: Frame,
+    placements: Vec<Rect>,
+}
+
+impl Atlas {
+    /// Returns the frame holding all regions.
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// Returns the frame holding all regions, dropping the placements.
+    pub fn into_frame(self) -> Frame {
+        self.frame
+    }
+
+    /// Returns where each region ended up in the frame, in the order the regions were given.
+    pub fn placements(&self) -> &[Rect] {
+        &self.placements
+    }
+
+    /// Returns a copy of the `index`-th region.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn region(&self, index: usize) -> Frame {
+        self.frame.crop(self.placements[index])
+    }
+}
+
+/// Packs rectangles of the given sizes into rows of a roughly square area.
+///
+/// Returns the size of the area and the position of every rectangle within it.
+fn pack(sizes: &[(i32, i32)]) -> ((i32, i32), Vec<Rect>) {
+    let total: i64 = sizes.iter().map(|&(w, h)| w as i64 * h as i64).sum();
+    let widest = sizes.iter().map(|&(w, _)| w).max().unwrap_or(0);
+    let width = widest.max((total as f64).sqrt().ceil() as i32);
+
+    // Placing the tallest rectangles first keeps the rows evenly filled.
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(sizes[i].1));
+
+    let mut placements = vec![Rect::default(); sizes.len()];
+    let (mut x, mut y, mut row_height) = (0, 0, 0);
+    for i in order {
+        let (w, h) = sizes[i];
+        if x + w > width {
+            y += row_height;
+            x = 0;
+            row_height = 0;
+        }
+        placements[i] = Rect::new(x, y, w, h);
+        x += w;
+        row_height = row_height.max(h);
+    }
+    ((width, y + row_height), placements)
+}
+
+impl<B: CaptureBackend> CaptureManager<B> {
+    /// Captures the smallest area containing all regions and returns its top-left corner.
+    fn capture_bounding(&mut self, regions: &[Rect]) -> Result<(i32, i32), Error> {
+        let mut union = Rect::default();
+        for region in regions {
+            Error::check_region(*region, None)?;
+            union = union.union(region);
+        }
+        let area = self.backend.area();
+        if area.contains(&union) {
+            self.backend.capture_into(&mut self.bounding)?;
+            return Ok(area.top_left());
+        }
+
+        Error::check_region(union, self.backend.bounds())?;
+        let res = self
+            .backend
+            .resize(union)
+            .and_then(|()| self.backend.capture_into(&mut self.bounding));
+        // The captured area is restored even if resizing or capturing failed.
+        let restored = self.backend.resize(area);
+        res.and(restored)?;
+        Ok(union.top_left())
+    }
+
+    /// Captures several regions of the target at once.
+    ///
+    /// The regions are given in the same coordinates as the captured [`area`](CaptureManager::area) and may
+    /// overlap. The smallest area containing all of them is captured once and every region is copied out of it,
+    /// converted into the manager's [`format`](CaptureManager::format) on the way. The returned frames are in the
+    /// order of the regions and share the same timestamp.
+    ///
+    /// If the regions lie within the captured area, it is captured as it is. Otherwise the backend is temporarily
+    /// resized to the area around the regions. The GDI and X11 backends keep the buffers of both sizes, so repeated
+    /// calls don't reallocate, but capturing is still cheapest with a manager whose area contains the regions.
+    ///
+    /// # Errors
+    ///
+    /// This method returns [`Error::InvalidRegion`] if one of the regions is empty and [`Error::OffScreen`] if all
+    /// of them lie outside of the target. Otherwise it fails in the same cases as
+    /// [`capture`](CaptureManager::capture).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qshot::{CaptureManager, Direction, Rect, Script, SyntheticBackend};
+    ///
+    /// let script = Script::new((100, 100)).gradient([0, 0, 0], [255, 255, 255], Direction::Horizontal, 1);
+    /// let mut manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 100, 100)).unwrap();
+    ///
+    /// let frames = manager
+    ///     .capture_regions(&[Rect::new(0, 0, 10, 10), Rect::new(90, 50, 10, 20)])
+    ///     .unwrap();
+    /// assert_eq!((frames[1].width(), frames[1].height()), (10, 20));
+    /// assert!(frames[0].row(0)[0] < frames[1].row(0)[0]);
+    /// ```
+    pub fn capture_regions(&mut self, regions: &[Rect]) -> Result<Vec<Frame>, Error> {
+        if regions.is_empty() {
+            return Ok(Vec::new());
+        }
+        let origin = self.capture_bounding(regions)?;
+        let format = self.format();
+        let frames = regions
+            .iter()
+            .map(|region| {
+                let (width, height) = (region.width as usize, region.height as usize);
+                let stride = width * format.bytes_per_pixel();
+                let bits = vec![0; stride * height];
+                let timestamp = self.bounding.timestamp();
+                let mut frame = Frame::from_parts(bits, width, height, stride, format, timestamp);
+                let area = region.offset(-origin.0, -origin.1);
+                self.bounding.copy_area(area, &mut frame, (0, 0));
+                frame
+            })
+            .collect();
+        Ok(frames)
+    }
+
+    /// Captures several regions of the target at once, like [`capture_regions`](CaptureManager::capture_regions),
+    /// but packs them into a single frame.
+    ///
+    /// # Errors
+    ///
+    /// This method fails in the same cases as [`capture_regions`](CaptureManager::capture_regions).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qshot::{CaptureManager, Rect, Script, SyntheticBackend};
+    ///
+    /// let script = Script::new((100, 100)).solid([255, 0, 0], 1);
+    /// let mut manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 100, 100)).unwrap();
+    ///
+    /// let regions = [Rect::new(0, 0, 30, 10), Rect::new(50, 50, 20, 20)];
+    /// let atlas = manager.capture_atlas(&regions).unwrap();
+    ///
+    /// let placed = atlas.placements()[0];
+    /// assert_eq!(placed.wh(), (30, 10));
+    /// assert!(!placed.intersects(&atlas.placements()[1]));
+    /// assert_eq!(&atlas.region(0).row(0)[..3], &[255, 0, 0]);
+    /// ```
+    pub fn capture_atlas(&mut self, regions: &[Rect]) -> Result<Atlas, Error> {
+        let format = self.format();
+        if regions.is_empty() {
+            let frame = Frame::new(0, 0, format);
+            return Ok(Atlas {
+                frame,
+                placements: Vec::new(),
+            });
+        }
+        let origin = self.capture_bounding(regions)?;
+        let sizes: Vec<(i32, i32)> = regions.iter().map(Rect::wh).collect();
+        let ((width, height), placements) = pack(&sizes);
+
+        let (width, height) = (width as usize, height as usize);
+        let bits = OPAQUE_BLACK[..format.bytes_per_pixel()].repeat(width * height);
+        let stride = width * format.bytes_per_pixel();
+        let timestamp = self.bounding.timestamp();
+        let mut frame = Frame::from_parts(bits, width, height, stride, format, timestamp);
+        for (region, placement) in regions.iter().zip(&placements) {
+            let area = region.offset(-origin.0, -origin.1);
+            let at = (placement.x as usize, placement.y as usize);
+            self.bounding.copy_area(area, &mut frame, at);
+        }
+        Ok(Atlas { frame, placements })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CaptureManager, Rect, Script, SyntheticBackend};
+
+    #[test]
+    fn area_is_restored_when_the_capture_fails() {
+        let script = Script::new((100, 100)).error("gone");
+        let area = Rect::new(0, 0, 10, 10);
+        let mut manager = CaptureManager::<SyntheticBackend>::open(script, area).unwrap();
+        assert!(manager
+            .capture_regions(&[Rect::new(50, 50, 20, 20)])
+            .is_err());
+        assert_eq!(manager.area(), area);
+    }
+}
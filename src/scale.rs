@@ -0,0 +1,459 @@
+//! Resampling of captured images to a different resolution.
+//!
+//! The [`Scaler`] works on 8-bit channels in fixed point. The weights of the source pixels are computed once per
+//! combination of source and destination size and reused as long as the sizes don't change, so scaling a stream
+//! of captures only costs the filtering itself. Images are filtered vertically first, which reads every source row
+//! once and keeps the inner loops simple enough to be vectorized by the compiler.
+//!
+//! [`CaptureManager::set_output_size`](crate::CaptureManager::set_output_size) scales every capture right after
+//! it was taken, which avoids keeping a full resolution copy around.
+
+#[cfg(target_arch = "x86_64")]
+use crate::convert::Isa;
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use crate::view::ImageView;
+
+/// The number of fractional bits of the filter weights.
+const SHIFT: u32 = 14;
+const ONE: u32 = 1 << SHIFT;
+/// The number of fractional bits kept between the vertical and the horizontal pass.
+const MID_SHIFT: u32 = 8;
+
+/// The way source pixels are combined into destination pixels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Filter {
+    /// Takes the source pixel closest to the center of every destination pixel. The fastest filter,
+    /// but thin lines and text may disappear when downscaling.
+    Nearest,
+    /// Interpolates between the four source pixels around the center of every destination pixel.
+    /// Smooth for upscaling and small reductions, but aliases when reducing by more than half.
+    Bilinear,
+    /// Averages all source pixels covered by every destination pixel, weighted by how much of them is covered.
+    /// The best choice for downscaling.
+    #[default]
+    Box,
+}
+
+/// The resolution images are scaled to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Resolution {
+    /// A fraction of the source size, e.g. `Fraction(2)` halves the width and height.
+    ///
+    /// Sizes are rounded down, but never below a single pixel.
+    Fraction(u32),
+    /// A fixed width and height in pixels, regardless of the source size.
+    Size(usize, usize),
+}
+
+impl Resolution {
+    /// Returns the size an image of the given size is scaled to.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the resolution is `Fraction(0)`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qshot::scale::Resolution;
+    ///
+    /// assert_eq!(Resolution::Fraction(4).resolve(1920, 1080), (480, 270));
+    /// assert_eq!(Resolution::Size(320, 180).resolve(1920, 1080), (320, 180));
+    /// ```
+    pub fn resolve(self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Resolution::Fraction(n) => {
+                assert!(n > 0, "fraction must not be zero");
+                let n = n as usize;
+                let reduce = |len: usize| if len == 0 { 0 } else { (len / n).max(1) };
+                (reduce(width), reduce(height))
+            }
+            Resolution::Size(width, height) => (width, height),
+        }
+    }
+}
+
+/// The source pixels contributing to every destination pixel along one axis.
+#[derive(Debug, Default)]
+struct Taps {
+    /// The first source pixel, the position of its weight in `weights` and the number of weights.
+    spans: Vec<(usize, usize, usize)>,
+    /// The weights of consecutive source pixels, those of every destination pixel sum up to `ONE`.
+    weights: Vec<u32>,
+}
+
+impl Taps {
+    fn build(filter: Filter, src: usize, dst: usize) -> Taps {
+        let mut taps = Taps::default();
+        let ratio = src as f64 / dst as f64;
+        let mut coverage = Vec::new();
+        for i in 0..dst {
+            coverage.clear();
+            let first = match filter {
+                Filter::Nearest => {
+                    coverage.push(1.0);
+                    (((i as f64 + 0.5) * ratio) as usize).min(src - 1)
+                }
+                Filter::Bilinear => {
+                    let center = ((i as f64 + 0.5) * ratio - 0.5).max(0.0);
+                    let first = (center as usize).min(src - 1);
+                    let fraction = center - first as f64;
+                    coverage.push(1.0 - fraction);
+                    if first + 1 < src && fraction > 0.0 {
+                        coverage.push(fraction);
+                    }
+                    first
+                }
+                Filter::Box => {
+                    let (start, end) = (i as f64 * ratio, (i + 1) as f64 * ratio);
+                    let first = (start as usize).min(src - 1);
+                    let last = (end.ceil() as usize).clamp(first + 1, src);
+                    for j in first..last {
+                        let overlap = end.min(j as f64 + 1.0) - start.max(j as f64);
+                        coverage.push(overlap.max(0.0) / ratio);
+                    }
+                    first
+                }
+            };
+
+            // Rounding the weights must not change their sum, or flat areas would change their color.
+            let start = taps.weights.len();
+            let total: f64 = coverage.iter().sum();
+            taps.weights.extend(
+                coverage
+                    .iter()
+                    .map(|c| (c / total * ONE as f64).round() as u32),
+            );
+            let weights = &mut taps.weights[start..];
+            let sum: u32 = weights.iter().sum();
+            let largest = (0..weights.len()).max_by_key(|&k| weights[k]).unwrap();
+            weights[largest] = (weights[largest] + ONE).wrapping_sub(sum);
+            taps.spans.push((first, start, weights.len()));
+        }
+        taps
+    }
+
+    fn weights(&self, i: usize) -> (usize, &[u32]) {
+        let (first, start, len) = self.spans[i];
+        (first, &self.weights[start..start + len])
+    }
+}
+
+/// A reusable image scaler.
+///
+/// # Examples
+///
+/// ```
+/// use qshot::scale::{Filter, Scaler};
+/// use qshot::{Frame, PixelFormat};
+///
+/// // Alternating black and white columns average out to gray.
+/// let bits: Vec<u8> = (0..4 * 2).flat_map(|i| [if i % 2 == 0 { 0 } else { 255 }; 3]).collect();
+/// let frame = Frame::from_vec(bits, 4, 2, PixelFormat::Bgr24);
+///
+/// let mut scaler = Scaler::new(Filter::Box);
+/// let small = scaler.scale(&frame, 2, 1);
+/// assert_eq!(small.bits(), &[128; 6]);
+/// ```
+#[derive(Debug)]
+pub struct Scaler {
+    filter: Filter,
+    /// The source and destination sizes the taps were built for.
+    shape: Option<(usize, usize, usize, usize)>,
+    xs: Taps,
+    ys: Taps,
+    /// Vertically filtered rows, before and after dropping the fractional bits the horizontal pass doesn't need.
+    acc: Vec<u32>,
+    mid: Vec<u16>,
+}
+
+impl Scaler {
+    /// Creates a scaler using the given filter.
+    pub fn new(filter: Filter) -> Scaler {
+        Scaler {
+            filter,
+            shape: None,
+            xs: Taps::default(),
+            ys: Taps::default(),
+            acc: Vec::new(),
+            mid: Vec::new(),
+        }
+    }
+
+    /// Returns the filter used by the scaler.
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    /// Scales an image to the given size, returning a new tightly packed frame in the same format.
+    pub fn scale<'a>(
+        &mut self,
+        src: impl Into<ImageView<'a>>,
+        width: usize,
+        height: usize,
+    ) -> Frame {
+        let mut frame = Frame::new(0, 0, PixelFormat::Bgr24);
+        self.scale_into(src, &mut frame, width, height);
+        frame
+    }
+
+    /// Scales an image to the given size, writing it into `dst` whose buffer is reused.
+    ///
+    /// `dst` ends up tightly packed and in the format of the source. An empty source scales to opaque black.
+    pub fn scale_into<'a>(
+        &mut self,
+        src: impl Into<ImageView<'a>>,
+        dst: &mut Frame,
+        width: usize,
+        height: usize,
+    ) {
+        let src = src.into();
+        let format = src.format();
+        let bpp = format.bytes_per_pixel();
+        if src.width() == 0 || src.height() == 0 {
+            dst.reset(width, height, format);
+            return;
+        }
+        dst.reshape(width, height, width * bpp, format);
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        let shape = (src.width(), src.height(), width, height);
+        if self.shape != Some(shape) {
+            self.xs = Taps::build(self.filter, src.width(), width);
+            self.ys = Taps::build(self.filter, src.height(), height);
+            self.shape = Some(shape);
+        }
+
+        let rows = dst.bits_mut().chunks_exact_mut(width * bpp);
+        if self.filter == Filter::Nearest {
+            for (y, out) in rows.enumerate() {
+                let row = src.row(self.ys.spans[y].0);
+                match bpp {
+                    1 => nearest::<1>(row, &self.xs, out),
+                    3 => nearest::<3>(row, &self.xs, out),
+                    4 => nearest::<4>(row, &self.xs, out),
+                    _ => unreachable!("unsupported pixel size"),
+                }
+            }
+            return;
+        }
+
+        self.acc.resize(src.width() * bpp, 0);
+        self.mid.resize(src.width() * bpp, 0);
+        for (y, out) in rows.enumerate() {
+            let (first, weights) = self.ys.weights(y);
+            vertical(&src, first, weights, &mut self.acc, &mut self.mid);
+            match bpp {
+                1 => horizontal::<1>(&self.mid, &self.xs, out),
+                3 => horizontal::<3>(&self.mid, &self.xs, out),
+                4 => horizontal::<4>(&self.mid, &self.xs, out),
+                _ => unreachable!("unsupported pixel size"),
+            }
+        }
+    }
+}
+
+/// Filters the source rows starting at `first` vertically into `mid`.
+fn vertical(src: &ImageView, first: usize, weights: &[u32], acc: &mut [u32], mid: &mut [u16]) {
+    // 32-bit multiplications are only vectorized well with AVX2.
+    #[cfg(target_arch = "x86_64")]
+    if Isa::detect() == Isa::Avx2 {
+        return unsafe { vertical_avx2(src, first, weights, acc, mid) };
+    }
+    vertical_generic(src, first, weights, acc, mid)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn vertical_avx2(
+    src: &ImageView,
+    first: usize,
+    weights: &[u32],
+    acc: &mut [u32],
+    mid: &mut [u16],
+) {
+    vertical_generic(src, first, weights, acc, mid)
+}
+
+#[inline(always)]
+fn vertical_generic(
+    src: &ImageView,
+    first: usize,
+    weights: &[u32],
+    acc: &mut [u32],
+    mid: &mut [u16],
+) {
+    for (acc, &s) in acc.iter_mut().zip(src.row(first)) {
+        *acc = s as u32 * weights[0];
+    }
+    for (k, &weight) in weights.iter().enumerate().skip(1) {
+        for (acc, &s) in acc.iter_mut().zip(src.row(first + k)) {
+            *acc += s as u32 * weight;
+        }
+    }
+    let round = 1 << (SHIFT - MID_SHIFT - 1);
+    for (mid, &acc) in mid.iter_mut().zip(acc.iter()) {
+        *mid = ((acc + round) >> (SHIFT - MID_SHIFT)) as u16;
+    }
+}
+
+/// Picks the pixels of a destination row out of a source row.
+fn nearest<const CH: usize>(row: &[u8], xs: &Taps, out: &mut [u8]) {
+    for (pixel, &(x, _, _)) in out.chunks_exact_mut(CH).zip(&xs.spans) {
+        pixel.copy_from_slice(&row[x * CH..(x + 1) * CH]);
+    }
+}
+
+/// Filters a vertically filtered row horizontally into a destination row.
+fn horizontal<const CH: usize>(mid: &[u16], xs: &Taps, out: &mut [u8]) {
+    const SHIFT_OUT: u32 = SHIFT + MID_SHIFT;
+    for (pixel, &(first, start, len)) in out.chunks_exact_mut(CH).zip(&xs.spans) {
+        let src = &mid[first * CH..(first + len) * CH];
+        let mut sum = [1u32 << (SHIFT_OUT - 1); CH];
+        for (px, &weight) in src.chunks_exact(CH).zip(&xs.weights[start..start + len]) {
+            for c in 0..CH {
+                sum[c] += px[c] as u32 * weight;
+            }
+        }
+        for c in 0..CH {
+            pixel[c] = (sum[c] >> SHIFT_OUT) as u8;
+        }
+    }
+}
+
+/// Scales an image to the given size with a new [`Scaler`].
+///
+/// # Examples
+///
+/// ```
+/// use qshot::scale::{self, Filter};
+/// use qshot::{Frame, PixelFormat};
+///
+/// let frame = Frame::new(1920, 1080, PixelFormat::Bgra32);
+/// let thumbnail = scale::scale(&frame, 320, 180, Filter::Box);
+/// assert_eq!((thumbnail.width(), thumbnail.height()), (320, 180));
+/// ```
+pub fn scale<'a>(
+    src: impl Into<ImageView<'a>>,
+    width: usize,
+    height: usize,
+    filter: Filter,
+) -> Frame {
+    Scaler::new(filter).scale(src, width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::fixtures;
+
+    const FILTERS: [Filter; 3] = [Filter::Nearest, Filter::Bilinear, Filter::Box];
+
+    fn gray(bits: &[u8], width: usize, height: usize) -> Frame {
+        Frame::from_vec(bits.to_vec(), width, height, PixelFormat::Gray8)
+    }
+
+    fn scaled(filter: Filter, src: &Frame, width: usize, height: usize) -> Vec<u8> {
+        let frame = scale(src, width, height, filter);
+        assert_eq!((frame.width(), frame.height()), (width, height));
+        assert_eq!(frame.format(), src.format());
+        frame.into_vec()
+    }
+
+    #[test]
+    fn identity_keeps_every_pixel() {
+        let src = fixtures::padded(7, 5, 3, PixelFormat::Bgra32, |x, y| {
+            [(x * 37) as u8, (y * 51) as u8, (x * y) as u8, 200]
+        });
+        let expected = src.to_format(PixelFormat::Bgra32).into_vec();
+        for filter in FILTERS {
+            assert_eq!(scaled(filter, &src, 7, 5), expected, "{filter:?}");
+        }
+    }
+
+    #[test]
+    fn halving() {
+        let row = gray(&[0, 100, 200, 255], 4, 1);
+        assert_eq!(scaled(Filter::Nearest, &row, 2, 1), [100, 255]);
+        assert_eq!(scaled(Filter::Bilinear, &row, 2, 1), [50, 228]);
+        assert_eq!(scaled(Filter::Box, &row, 2, 1), [50, 228]);
+
+        let square = gray(&[0, 100, 200, 255], 2, 2);
+        assert_eq!(scaled(Filter::Nearest, &square, 1, 1), [255]);
+        assert_eq!(scaled(Filter::Bilinear, &square, 1, 1), [139]);
+        assert_eq!(scaled(Filter::Box, &square, 1, 1), [139]);
+    }
+
+    #[test]
+    fn box_weights_partly_covered_pixels() {
+        let row = gray(&[0, 90, 180], 3, 1);
+        assert_eq!(scaled(Filter::Box, &row, 2, 1), [30, 150]);
+    }
+
+    #[test]
+    fn doubling() {
+        let row = gray(&[0, 200], 2, 1);
+        assert_eq!(scaled(Filter::Nearest, &row, 4, 1), [0, 0, 200, 200]);
+        assert_eq!(scaled(Filter::Bilinear, &row, 4, 1), [0, 50, 150, 200]);
+        assert_eq!(scaled(Filter::Box, &row, 4, 1), [0, 0, 200, 200]);
+    }
+
+    #[test]
+    fn single_columns() {
+        let column = gray(&[0, 100, 200, 255], 1, 4);
+        assert_eq!(scaled(Filter::Nearest, &column, 1, 2), [100, 255]);
+        assert_eq!(scaled(Filter::Bilinear, &column, 1, 2), [50, 228]);
+        assert_eq!(scaled(Filter::Box, &column, 1, 2), [50, 228]);
+
+        let column = gray(&[0, 200], 1, 2);
+        assert_eq!(
+            scaled(Filter::Bilinear, &column, 3, 4),
+            [0, 0, 0, 50, 50, 50, 150, 150, 150, 200, 200, 200]
+        );
+    }
+
+    #[test]
+    fn padding_is_ignored() {
+        for format in [PixelFormat::Gray8, PixelFormat::Bgr24, PixelFormat::Bgra32] {
+            let padded = fixtures::padded(9, 6, 7, format, |x, y| {
+                [(x * 29 + y) as u8, (y * 41) as u8, (x ^ y) as u8 * 16, 255]
+            });
+            let packed = padded.to_format(format);
+            for filter in FILTERS {
+                for (width, height) in [(4, 3), (13, 5), (1, 1)] {
+                    assert_eq!(
+                        scaled(filter, &padded, width, height),
+                        scaled(filter, &packed, width, height),
+                        "{format:?} {filter:?} {width}x{height}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flat_areas_keep_their_color() {
+        let src = fixtures::padded(7, 5, 2, PixelFormat::Bgra32, |_, _| [12, 34, 56, 78]);
+        for filter in FILTERS {
+            for (width, height) in [(3, 2), (13, 11), (1, 1), (7, 1)] {
+                let bits = scaled(filter, &src, width, height);
+                assert!(
+                    bits.chunks_exact(4).all(|pixel| pixel == [12, 34, 56, 78]),
+                    "{filter:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn empty_sources_scale_to_black() {
+        let src = Frame::new(0, 3, PixelFormat::Bgra32);
+        assert_eq!(
+            scaled(Filter::Box, &src, 2, 1),
+            [0, 0, 0, 255, 0, 0, 0, 255]
+        );
+    }
+}
@@ -0,0 +1,381 @@
+//! Searching images for pixels of a given color.
+//!
+//! Colors are given as \[B, G, R] triplets like everywhere else in the crate and are matched regardless of the pixel
+//! format of the image: for RGB(A) images they are swapped, for grayscale images their BT.601 luma is searched for.
+//! Alpha channels are ignored.
+//!
+//! [`find_pixel`] stops at the first match, while [`find_all`], [`count_matching`] and [`bounding_box_of`] look at
+//! every pixel. [`ColorSearch`] allows restricting the search to a part of the image. Rows are compared 16 or 32
+//! bytes at a time with SSE2 or AVX2 where available.
+
+use std::ops::ControlFlow;
+
+use crate::convert::{Converter, Isa};
+use crate::format::PixelFormat;
+use crate::rect::Rect;
+use crate::view::ImageView;
+
+#[cfg(target_arch = "x86_64")]
+mod x86;
+
+/// The bytes of a vector compared at once.
+#[cfg(target_arch = "x86_64")]
+#[derive(Clone, Copy, Debug)]
+struct Block {
+    /// `0xFF` for the bytes that never prevent a match, i.e. alpha channels and the bytes past the last whole pixel.
+    ignore: [u8; 32],
+    /// A bit for the first byte of every whole pixel.
+    firsts: u32,
+    /// The number of bytes of the whole pixels.
+    step: usize,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Block {
+    fn new(lanes: usize, bpp: usize, alpha: bool) -> Block {
+        let step = lanes / bpp * bpp;
+        let mut block = Block {
+            ignore: [0; 32],
+            firsts: 0,
+            step,
+        };
+        for (i, ignore) in block.ignore[..lanes].iter_mut().enumerate() {
+            if i >= step || (alpha && i % bpp == 3) {
+                *ignore = 0xFF;
+            }
+            if i < step && i % bpp == 0 {
+                block.firsts |= 1 << i;
+            }
+        }
+        block
+    }
+}
+
+/// A color in the layout of the searched image.
+#[derive(Clone, Copy, Debug)]
+struct Pattern {
+    bpp: usize,
+    tolerance: u8,
+    /// The color repeated to fill a vector, starting at the first byte.
+    bytes: [u8; 32],
+    alpha: bool,
+    #[cfg(target_arch = "x86_64")]
+    sse2: Block,
+    #[cfg(target_arch = "x86_64")]
+    avx2: Block,
+}
+
+impl Pattern {
+    fn new(color: [u8; 3], format: PixelFormat, tolerance: u8) -> Pattern {
+        let bpp = format.bytes_per_pixel();
+        let mut pixel = [0; 4];
+        Converter::new().convert_row(&color, PixelFormat::Bgr24, &mut pixel, format);
+        let mut bytes = [0; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = pixel[i % bpp];
+        }
+        let alpha = format.has_alpha();
+        Pattern {
+            bpp,
+            tolerance,
+            bytes,
+            alpha,
+            #[cfg(target_arch = "x86_64")]
+            sse2: Block::new(16, bpp, alpha),
+            #[cfg(target_arch = "x86_64")]
+            avx2: Block::new(32, bpp, alpha),
+        }
+    }
+
+    fn matches(&self, pixel: &[u8]) -> bool {
+        pixel
+            .iter()
+            .zip(&self.bytes)
+            .enumerate()
+            .all(|(i, (a, b))| (self.alpha && i == 3) || a.abs_diff(*b) <= self.tolerance)
+    }
+}
+
+/// Turns a mask with a bit for every matching byte into a mask with a bit for the first byte of every pixel whose
+/// bytes all match.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+fn pixel_hits(bytes: u32, bpp: usize, firsts: u32) -> u32 {
+    let mut hits = bytes;
+    for shift in 1..bpp {
+        hits &= bytes >> shift;
+    }
+    hits & firsts
+}
+
+/// Calls `found` with the index of every pixel in `hits`, a mask of pixels starting at byte `offset`.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+fn report(
+    mut hits: u32,
+    offset: usize,
+    bpp: usize,
+    found: &mut impl FnMut(usize) -> ControlFlow<()>,
+) -> ControlFlow<()> {
+    while hits != 0 {
+        found((offset + hits.trailing_zeros() as usize) / bpp)?;
+        hits &= hits - 1;
+    }
+    ControlFlow::Continue(())
+}
+
+/// A search for pixels of a color, optionally with a tolerance and within a part of the image.
+///
+/// A pixel matches if none of its channels differs from the color by more than the
+/// [`tolerance`](ColorSearch::tolerance). Pixels are found from left to right and top to bottom, their coordinates
+/// are relative to the whole image even if the search is restricted to a part of it.
+///
+/// # Examples
+///
+/// ```
+/// use qshot::search::ColorSearch;
+/// use qshot::{CaptureManager, Rect, Script, SyntheticBackend};
+///
+/// // A red 20x10 rectangle at (30, 40) on a gray background.
+/// let script = Script::new((100, 100)).moving_rect([50; 3], [0, 0, 250], (20, 10), (30, 40), (0, 0), 1);
+/// let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 100, 100)).unwrap();
+/// let frame = manager.capture().unwrap();
+///
+/// let red = ColorSearch::new([0, 0, 255]).tolerance(8);
+/// assert_eq!(red.find(&frame), Some((30, 40)));
+/// assert_eq!(red.count(&frame), 200);
+/// assert_eq!(red.bounding_box(&frame), Some(Rect::new(30, 40, 20, 10)));
+///
+/// let right_half = red.within(Rect::new(40, 0, 60, 100));
+/// assert_eq!(right_half.find(&frame), Some((40, 40)));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ColorSearch {
+    color: [u8; 3],
+    tolerance: u8,
+    area: Option<Rect>,
+    isa: Isa,
+}
+
+impl ColorSearch {
+    /// Creates a search for pixels of exactly the given color in the whole image.
+    pub fn new(color: [u8; 3]) -> ColorSearch {
+        ColorSearch {
+            color,
+            tolerance: 0,
+            area: None,
+            isa: Isa::detect(),
+        }
+    }
+
+    /// Sets the largest difference of a single channel for which pixels still match.
+    pub fn tolerance(mut self, tolerance: u8) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Restricts the search to the part of the image within `area`.
+    pub fn within(mut self, area: Rect) -> Self {
+        self.area = Some(area);
+        self
+    }
+
+    /// Makes the search use the given instruction set, or returns `None` if the CPU does not support it.
+    pub fn with_isa(mut self, isa: Isa) -> Option<Self> {
+        self.isa = isa;
+        isa.is_available().then_some(self)
+    }
+
+    /// Returns the coordinates of the first matching pixel, stopping the search there.
+    pub fn find<'a>(&self, image: impl Into<ImageView<'a>>) -> Option<(usize, usize)> {
+        let mut first = None;
+        self.scan(image.into(), |x, y| {
+            first = Some((x, y));
+            ControlFlow::Break(())
+        });
+        first
+    }
+
+    /// Returns the coordinates of all matching pixels.
+    pub fn find_all<'a>(&self, image: impl Into<ImageView<'a>>) -> Vec<(usize, usize)> {
+        let mut all = Vec::new();
+        self.scan(image.into(), |x, y| {
+            all.push((x, y));
+            ControlFlow::Continue(())
+        });
+        all
+    }
+
+    /// Returns the number of matching pixels.
+    pub fn count<'a>(&self, image: impl Into<ImageView<'a>>) -> usize {
+        let mut count = 0;
+        self.scan(image.into(), |_, _| {
+            count += 1;
+            ControlFlow::Continue(())
+        });
+        count
+    }
+
+    /// Returns the smallest rectangle containing all matching pixels, or `None` if there are none.
+    pub fn bounding_box<'a>(&self, image: impl Into<ImageView<'a>>) -> Option<Rect> {
+        let mut bounds: Option<(usize, usize, usize, usize)> = None;
+        self.scan(image.into(), |x, y| {
+            bounds = Some(match bounds {
+                Some((left, top, right, _)) => (left.min(x), top, right.max(x), y),
+                None => (x, y, x, y),
+            });
+            ControlFlow::Continue(())
+        });
+        bounds.map(|(left, top, right, bottom)| {
+            Rect::from_corners(
+                (left as i32, top as i32),
+                (right as i32 + 1, bottom as i32 + 1),
+            )
+        })
+    }
+
+    /// Calls `found` with the coordinates of every matching pixel until it breaks.
+    fn scan(&self, image: ImageView, mut found: impl FnMut(usize, usize) -> ControlFlow<()>) {
+        let bounds = image.bounds();
+        let area = self.area.map_or(bounds, |area| area.clamp(&bounds));
+        let view = image.sub_view(area);
+        let pattern = Pattern::new(self.color, image.format(), self.tolerance);
+        let (left, top) = (area.x as usize, area.y as usize);
+        for (y, row) in view.rows().enumerate() {
+            let flow = self.scan_row(&pattern, row, &mut |x| found(left + x, top + y));
+            if flow.is_break() {
+                return;
+            }
+        }
+    }
+
+    fn scan_row(
+        &self,
+        pattern: &Pattern,
+        row: &[u8],
+        found: &mut impl FnMut(usize) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        let start = match self.isa {
+            #[cfg(target_arch = "x86_64")]
+            Isa::Avx2 => unsafe { x86::scan_avx2(row, pattern, found)? },
+            #[cfg(target_arch = "x86_64")]
+            Isa::Sse2 | Isa::Ssse3 => unsafe { x86::scan_sse2(row, pattern, found)? },
+            _ => 0,
+        };
+        let first = start / pattern.bpp;
+        for (x, pixel) in row[start..].chunks_exact(pattern.bpp).enumerate() {
+            if pattern.matches(pixel) {
+                found(first + x)?;
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+/// Returns the coordinates of the first pixel of the given color, see [`ColorSearch::find`].
+///
+/// # Examples
+///
+/// ```
+/// use qshot::{search, Frame, PixelFormat};
+///
+/// let mut frame = Frame::new(64, 64, PixelFormat::Bgra32);
+/// frame.bits_mut()[(10 * 64 + 20) * 4..][..3].copy_from_slice(&[200, 100, 50]);
+///
+/// assert_eq!(search::find_pixel(&frame, [198, 101, 50], 2), Some((20, 10)));
+/// assert_eq!(search::find_pixel(&frame, [198, 101, 50], 1), None);
+/// ```
+pub fn find_pixel<'a>(
+    image: impl Into<ImageView<'a>>,
+    color: [u8; 3],
+    tolerance: u8,
+) -> Option<(usize, usize)> {
+    ColorSearch::new(color).tolerance(tolerance).find(image)
+}
+
+/// Returns the coordinates of all pixels of the given color, see [`ColorSearch::find_all`].
+pub fn find_all<'a>(
+    image: impl Into<ImageView<'a>>,
+    color: [u8; 3],
+    tolerance: u8,
+) -> Vec<(usize, usize)> {
+    ColorSearch::new(color).tolerance(tolerance).find_all(image)
+}
+
+/// Returns the number of pixels of the given color, see [`ColorSearch::count`].
+pub fn count_matching<'a>(image: impl Into<ImageView<'a>>, color: [u8; 3], tolerance: u8) -> usize {
+    ColorSearch::new(color).tolerance(tolerance).count(image)
+}
+
+/// Returns the smallest rectangle containing all pixels of the given color, see [`ColorSearch::bounding_box`].
+pub fn bounding_box_of<'a>(
+    image: impl Into<ImageView<'a>>,
+    color: [u8; 3],
+    tolerance: u8,
+) -> Option<Rect> {
+    ColorSearch::new(color)
+        .tolerance(tolerance)
+        .bounding_box(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns bytes between 0 and 3, so that colors near `[1, 2, 3]` are frequent.
+    fn random_bytes(len: usize) -> Vec<u8> {
+        let mut seed = 0x2545_f491_u32;
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                (seed % 4) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_isa_finds_the_same_pixels() {
+        let data = random_bytes(4096);
+        let formats = [
+            PixelFormat::Bgr24,
+            PixelFormat::Bgra32,
+            PixelFormat::Rgb24,
+            PixelFormat::Rgba32,
+            PixelFormat::Gray8,
+        ];
+        for tolerance in [0, 1] {
+            let scalar = ColorSearch::new([1, 2, 3])
+                .tolerance(tolerance)
+                .with_isa(Isa::Scalar)
+                .unwrap();
+            for format in formats {
+                for width in [1, 5, 13, 64, 333] {
+                    let stride = width * format.bytes_per_pixel() + 1;
+                    let view = ImageView::new(&data, width, data.len() / stride, stride, format);
+                    let expected = scalar.find_all(view);
+                    for isa in Isa::available() {
+                        let simd = scalar.with_isa(isa).unwrap();
+                        let context = format!("{format:?} {width} {tolerance} {isa:?}");
+                        assert_eq!(simd.find_all(view), expected, "{context}");
+                        assert_eq!(simd.find(view), expected.first().copied(), "{context}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn alpha_is_ignored() {
+        // Long enough for whole vectors, with every alpha value on the color and one off-color pixel.
+        let mut data: Vec<u8> = (0..40).flat_map(|i| [3, 2, 1, i * 6]).collect();
+        data[7 * 4 + 2] = 2;
+        let view = ImageView::new(&data, 40, 1, 160, PixelFormat::Bgra32);
+        for isa in Isa::available() {
+            let search = ColorSearch::new([3, 2, 1]).with_isa(isa).unwrap();
+            assert_eq!(search.count(view), 39, "{isa:?}");
+            assert_eq!(search.bounding_box(view), Some(Rect::new(0, 0, 40, 1)));
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::arch::x86_64::*;
+use std::ops::ControlFlow;
+
+use super::{pixel_hits, report, Pattern};
+
+#[inline(always)]
+unsafe fn load128(src: &[u8], i: usize) -> __m128i {
+    debug_assert!(i + 16 <= src.len());
+    _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i)
+}
+
+#[inline(always)]
+unsafe fn load256(src: &[u8], i: usize) -> __m256i {
+    debug_assert!(i + 32 <= src.len());
+    _mm256_loadu_si256(src.as_ptr().add(i) as *const __m256i)
+}
+
+/// Reports the matching pixels of all whole vectors of the row and returns the offset of the first pixel left.
+#[target_feature(enable = "sse2")]
+pub(super) unsafe fn scan_sse2(
+    row: &[u8],
+    pattern: &Pattern,
+    found: &mut impl FnMut(usize) -> ControlFlow<()>,
+) -> ControlFlow<(), usize> {
+    let block = &pattern.sse2;
+    let color = load128(&pattern.bytes, 0);
+    let ignore = load128(&block.ignore, 0);
+    let tolerance = _mm_set1_epi8(pattern.tolerance as i8);
+    let mut i = 0;
+    while i + 16 <= row.len() {
+        let v = load128(row, i);
+        let diff = _mm_or_si128(_mm_subs_epu8(v, color), _mm_subs_epu8(color, v));
+        let close = _mm_cmpeq_epi8(_mm_subs_epu8(diff, tolerance), _mm_setzero_si128());
+        let bytes = _mm_movemask_epi8(_mm_or_si128(close, ignore)) as u32;
+        let hits = pixel_hits(bytes, pattern.bpp, block.firsts);
+        if hits != 0 {
+            report(hits, i, pattern.bpp, found)?;
+        }
+        i += block.step;
+    }
+    ControlFlow::Continue(i)
+}
+
+#[target_feature(enable = "avx2")]
+pub(super) unsafe fn scan_avx2(
+    row: &[u8],
+    pattern: &Pattern,
+    found: &mut impl FnMut(usize) -> ControlFlow<()>,
+) -> ControlFlow<(), usize> {
+    let block = &pattern.avx2;
+    let color = load256(&pattern.bytes, 0);
+    let ignore = load256(&block.ignore, 0);
+    let tolerance = _mm256_set1_epi8(pattern.tolerance as i8);
+    let mut i = 0;
+    while i + 32 <= row.len() {
+        let v = load256(row, i);
+        let diff = _mm256_or_si256(_mm256_subs_epu8(v, color), _mm256_subs_epu8(color, v));
+        let close = _mm256_cmpeq_epi8(_mm256_subs_epu8(diff, tolerance), _mm256_setzero_si256());
+        let bytes = _mm256_movemask_epi8(_mm256_or_si256(close, ignore)) as u32;
+        let hits = pixel_hits(bytes, pattern.bpp, block.firsts);
+        if hits != 0 {
+            report(hits, i, pattern.bpp, found)?;
+        }
+        i += block.step;
+    }
+    ControlFlow::Continue(i)
+}
@@ -0,0 +1,393 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+use crate::backend::CaptureBackend;
+use crate::capture::CaptureManager;
+use crate::capture_loop::{CaptureLoop, Delivery, LoopHandle};
+use crate::error::Error;
+use crate::frame::Frame;
+use crate::pool::FramePool;
+
+/// What a [`FrameStream`] does with a new frame while its buffer is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Backpressure {
+    /// Drops the oldest buffered frame, so the consumer always gets the most recent frames.
+    #[default]
+    DropOldest,
+    /// Drops the new frame, so the consumer gets the frames that were buffered first.
+    DropNewest,
+    /// Waits for the consumer, which slows the capture loop down. Frames missed while waiting count as dropped.
+    Block,
+}
+
+struct Queue {
+    frames: VecDeque<Frame>,
+    error: Option<Error>,
+    finished: bool,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    /// Signalled when a frame is taken out of the queue or the stream is dropped.
+    space: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        self.queue.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Ends the stream, optionally with an error, and wakes up the consumer.
+    fn finish(&self, error: Option<Error>) {
+        let mut queue = self.lock();
+        queue.error = error;
+        queue.finished = true;
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// An asynchronous stream of frames captured at a fixed rate on a dedicated thread.
+///
+/// It is created by [`CaptureManager::stream`], and starts capturing when it is first polled.
+/// The stream ends after the first capture error, which is its last item.
+///
+/// The stream implements `futures_core::Stream`, and frames can also be awaited with [`next`](FrameStream::next)
+/// without any other crate. Frames can be handed back through [`pool`](FrameStream::pool) once they are no longer
+/// needed, which saves the capture thread from allocating new ones.
+///
+/// Backends that can't be moved to another thread are opened on the capture thread with
+/// [`CaptureManager::stream_with`].
+///
+/// # Examples
+///
+/// ```
+/// # use std::future::Future;
+/// # use std::sync::Arc;
+/// # use std::task::{Context, Poll, Wake, Waker};
+/// # struct Unpark(std::thread::Thread);
+/// # impl Wake for Unpark {
+/// #     fn wake(self: Arc<Self>) {
+/// #         self.0.unpark();
+/// #     }
+/// # }
+/// # fn block_on<F: Future>(future: F) -> F::Output {
+/// #     let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+/// #     let mut future = std::pin::pin!(future);
+/// #     loop {
+/// #         if let Poll::Ready(out) = future.as_mut().poll(&mut Context::from_waker(&waker)) {
+/// #             return out;
+/// #         }
+/// #         std::thread::park();
+/// #     }
+/// # }
+/// use qshot::{Backpressure, CaptureManager, Rect, Script, SyntheticBackend};
+///
+/// let script = Script::new((100, 100)).solid([255, 0, 0], 1).error("gone");
+/// let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 10, 10)).unwrap();
+/// let mut stream = manager.stream(240.0).backpressure(Backpressure::Block);
+///
+/// block_on(async {
+///     let frame = stream.next().await.unwrap().unwrap();
+///     assert_eq!(&frame.bits()[..3], &[255, 0, 0]);
+///     stream.pool().recycle(frame);
+///
+///     // The script fails on its second frame, which ends the stream.
+///     assert!(stream.next().await.unwrap().is_err());
+///     assert!(stream.next().await.is_none());
+/// });
+/// ```
+pub struct FrameStream<B: CaptureBackend> {
+    shared: Arc<Shared>,
+    open: Option<Open<B>>,
+    capture_loop: Option<CaptureLoop>,
+    backpressure: Backpressure,
+    capacity: usize,
+    pool: FramePool,
+    handle: Option<LoopHandle>,
+}
+
+/// Opens the manager of a [`FrameStream`] on its capture thread.
+type Open<B> = Box<dyn FnOnce() -> Result<CaptureManager<B>, Error> + Send>;
+
+impl<B: CaptureBackend + 'static> FrameStream<B> {
+    fn new(open: Open<B>, pool: FramePool, fps: f64) -> FrameStream<B> {
+        FrameStream {
+            shared: Arc::new(Shared {
+                queue: Mutex::new(Queue {
+                    frames: VecDeque::new(),
+                    error: None,
+                    finished: false,
+                    closed: false,
+                    waker: None,
+                }),
+                space: Condvar::new(),
+            }),
+            pool,
+            open: Some(open),
+            capture_loop: Some(CaptureLoop::new(fps)),
+            backpressure: Backpressure::default(),
+            capacity: 2,
+            handle: None,
+        }
+    }
+
+    /// Sets what happens to new frames while the buffer is full, [`Backpressure::DropOldest`] by default.
+    ///
+    /// Has no effect once the stream was polled.
+    pub fn backpressure(mut self, backpressure: Backpressure) -> FrameStream<B> {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// Sets how many frames are buffered for the consumer, 2 by default.
+    ///
+    /// Has no effect once the stream was polled.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn capacity(mut self, capacity: usize) -> FrameStream<B> {
+        assert!(capacity > 0, "capacity must not be zero");
+        self.capacity = capacity;
+        self
+    }
+
+    /// Returns the pool the frames are taken from.
+    pub fn pool(&self) -> &FramePool {
+        &self.pool
+    }
+
+    /// Returns a handle to the capture loop, e.g. to pause it or to look at its statistics.
+    ///
+    /// Returns `None` until the stream was polled.
+    pub fn handle(&self) -> Option<&LoopHandle> {
+        self.handle.as_ref()
+    }
+
+    fn start(&mut self, open: Open<B>) {
+        let capture_loop = self.capture_loop.take().unwrap();
+        self.handle = Some(capture_loop.handle());
+        let shared = self.shared.clone();
+        let (backpressure, capacity) = (self.backpressure, self.capacity);
+        let pool = self.pool.clone();
+
+        std::thread::spawn(move || {
+            let mut manager = match open() {
+                Ok(manager) => manager,
+                Err(err) => return shared.finish(Some(err)),
+            };
+            manager.set_pool(pool);
+            let res = capture_loop.drive(|| {
+                let frame = manager.capture_pooled()?;
+                let mut queue = shared.lock();
+                if backpressure == Backpressure::Block {
+                    while queue.frames.len() >= capacity && !queue.closed {
+                        queue = shared
+                            .space
+                            .wait(queue)
+                            .unwrap_or_else(|err| err.into_inner());
+                    }
+                }
+                if queue.closed {
+                    return Ok(Delivery::Closed);
+                }
+                if queue.frames.len() >= capacity {
+                    match backpressure {
+                        Backpressure::DropNewest => {
+                            manager.pool().recycle(frame);
+                            return Ok(Delivery::Dropped);
+                        }
+                        _ => {
+                            let oldest = queue.frames.pop_front().unwrap();
+                            manager.pool().recycle(oldest);
+                        }
+                    }
+                }
+                queue.frames.push_back(frame);
+                if let Some(waker) = queue.waker.take() {
+                    waker.wake();
+                }
+                Ok(Delivery::Delivered)
+            });
+            shared.finish(res.err());
+        });
+    }
+
+    /// Polls for the next frame, `None` once the stream has ended.
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Frame, Error>>> {
+        if let Some(open) = self.open.take() {
+            self.start(open);
+        }
+        let mut queue = self.shared.lock();
+        if let Some(frame) = queue.frames.pop_front() {
+            self.shared.space.notify_one();
+            return Poll::Ready(Some(Ok(frame)));
+        }
+        if let Some(err) = queue.error.take() {
+            return Poll::Ready(Some(Err(err)));
+        }
+        if queue.finished {
+            return Poll::Ready(None);
+        }
+        queue.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Waits for the next frame, `None` once the stream has ended.
+    // Named after `StreamExt::next` of the futures crate, which it mirrors.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Next<'_, B> {
+        Next { stream: self }
+    }
+}
+
+impl<B: CaptureBackend> Drop for FrameStream<B> {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.space.notify_all();
+        if let Some(handle) = &self.handle {
+            handle.stop();
+        }
+    }
+}
+
+/// The future returned by [`FrameStream::next`].
+pub struct Next<'a, B: CaptureBackend> {
+    stream: &'a mut FrameStream<B>,
+}
+
+impl<B: CaptureBackend + 'static> Future for Next<'_, B> {
+    type Output = Option<Result<Frame, Error>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.stream.poll_next(cx)
+    }
+}
+
+impl<B: CaptureBackend + 'static> futures_core::Stream for FrameStream<B> {
+    type Item = Result<Frame, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        FrameStream::poll_next(self.get_mut(), cx)
+    }
+}
+
+impl<B> CaptureManager<B>
+where
+    B: CaptureBackend + Send + 'static,
+{
+    /// Turns the manager into an asynchronous stream of frames captured at the given rate on a dedicated thread.
+    ///
+    /// See [`FrameStream`] for details.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`CaptureLoop::new`].
+    pub fn stream(self, fps: f64) -> FrameStream<B> {
+        let pool = self.pool().clone();
+        FrameStream::new(Box::new(move || Ok(self)), pool, fps)
+    }
+}
+
+impl<B: CaptureBackend + 'static> CaptureManager<B> {
+    /// Creates an asynchronous stream of frames captured at the given rate by the manager `open` returns.
+    ///
+    /// Unlike [`stream`](CaptureManager::stream), the manager is opened on the capture thread, so the backend
+    /// doesn't have to be [`Send`]. An error returned by `open` is the only item of the stream.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`CaptureLoop::new`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qshot::{CaptureManager, Rect, Script, SyntheticBackend};
+    ///
+    /// let script = Script::new((100, 100));
+    /// let mut stream = CaptureManager::stream_with(
+    ///     move || CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 10, 10)),
+    ///     30.0,
+    /// );
+    /// ```
+    pub fn stream_with<F>(open: F, fps: f64) -> FrameStream<B>
+    where
+        F: FnOnce() -> Result<CaptureManager<B>, Error> + Send + 'static,
+    {
+        FrameStream::new(Box::new(open), FramePool::new(4), fps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+
+    use super::*;
+    use crate::{Rect, Script, SyntheticBackend};
+
+    struct Unpark(std::thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn next<B: CaptureBackend + 'static>(
+        stream: &mut FrameStream<B>,
+    ) -> Option<Result<Frame, Error>> {
+        let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match futures_core::Stream::poll_next(Pin::new(&mut *stream), &mut cx) {
+                Poll::Ready(item) => return item,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn opens_on_the_capture_thread() {
+        let caller = std::thread::current().id();
+        let mut stream = CaptureManager::stream_with(
+            move || {
+                assert_ne!(std::thread::current().id(), caller);
+                CaptureManager::<SyntheticBackend>::open(
+                    Script::new((100, 100)),
+                    Rect::new(0, 0, 10, 10),
+                )
+            },
+            240.0,
+        );
+        let frame = next(&mut stream).unwrap().unwrap();
+        assert_eq!((frame.width(), frame.height()), (10, 10));
+        stream.pool().recycle(frame);
+        assert!(next(&mut stream).unwrap().is_ok());
+    }
+
+    #[test]
+    fn open_error_ends_the_stream() {
+        let mut stream = CaptureManager::stream_with(
+            || {
+                CaptureManager::<SyntheticBackend>::open(
+                    Script::new((100, 100)),
+                    Rect::new(0, 0, 0, 0),
+                )
+            },
+            240.0,
+        );
+        assert!(matches!(
+            next(&mut stream),
+            Some(Err(Error::InvalidRegion(_)))
+        ));
+        assert!(next(&mut stream).is_none());
+    }
+}
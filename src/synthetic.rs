@@ -0,0 +1,467 @@
+use std::cell::Cell;
+use std::fmt;
+use std::path::Path;
+
+use crate::backend::CaptureBackend;
+use crate::bmp;
+use crate::capture::CaptureData;
+use crate::convert::Converter;
+use crate::error::Error;
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use crate::pnm;
+use crate::rect::Rect;
+use crate::view::ImageView;
+
+/// The direction in which a gradient changes its color.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// From the left edge of the screen to the right edge.
+    Horizontal,
+    /// From the top edge of the screen to the bottom edge.
+    Vertical,
+}
+
+#[derive(Clone, Debug)]
+enum Step {
+    Solid {
+        color: [u8; 3],
+    },
+    Gradient {
+        from: [u8; 3],
+        to: [u8; 3],
+        direction: Direction,
+    },
+    MovingRect {
+        background: [u8; 3],
+        color: [u8; 3],
+        size: (i32, i32),
+        start: (i32, i32),
+        velocity: (i32, i32),
+    },
+    Image {
+        bits: Vec<u8>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// A deterministic sequence of frames played back by the [`SyntheticBackend`].
+///
+/// A script describes a virtual screen of a fixed size. Each call to [`Script::solid`], [`Script::gradient`] etc.
+/// appends a number of frames to the script, which are then returned by consecutive captures.
+/// Once the last frame is reached, the script starts over from the beginning.
+///
+/// All colors are given as \[B, G, R] triplets, the same layout that [`CaptureData::get_bits`] uses.
+///
+/// # Examples
+///
+/// ```
+/// use qshot::{CaptureManager, Direction, Rect, Script, SyntheticBackend};
+///
+/// let script = Script::new((640, 480))
+///     .solid([255, 0, 0], 2)
+///     .gradient([0, 0, 0], [255, 255, 255], Direction::Horizontal, 1)
+///     .error("window closed");
+///
+/// let manager = CaptureManager::<SyntheticBackend>::open(script, Rect::new(0, 0, 640, 480)).unwrap();
+///
+/// assert_eq!(&manager.capture().unwrap().get_bits()[..3], &[255, 0, 0]);
+/// assert_eq!(&manager.capture().unwrap().get_bits()[..3], &[255, 0, 0]);
+/// assert_eq!(&manager.capture().unwrap().get_bits()[..3], &[0, 0, 0]);
+/// assert!(manager.capture().is_err());
+/// ```
+#[derive(Clone, Debug)]
+pub struct Script {
+    screen: (i32, i32),
+    steps: Vec<(Step, usize)>,
+}
+
+impl Script {
+    /// Creates an empty script for a virtual screen of the given width and height.
+    ///
+    /// An empty script produces black frames.
+    pub fn new(screen: (i32, i32)) -> Script {
+        Script {
+            screen,
+            steps: Vec::new(),
+        }
+    }
+
+    /// Returns the width and height of the virtual screen.
+    pub fn screen(&self) -> (i32, i32) {
+        self.screen
+    }
+
+    /// Returns the number of frames in the script.
+    pub fn len(&self) -> usize {
+        self.steps.iter().map(|(_, frames)| frames).sum()
+    }
+
+    /// Returns `true` if the script contains no frames.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn push(mut self, step: Step, frames: usize) -> Script {
+        if frames > 0 {
+            self.steps.push((step, frames));
+        }
+        self
+    }
+
+    /// Appends `frames` frames in which the whole screen has a single color.
+    pub fn solid(self, color: [u8; 3], frames: usize) -> Script {
+        self.push(Step::Solid { color }, frames)
+    }
+
+    /// Appends `frames` frames containing a linear gradient spanning the whole screen.
+    pub fn gradient(
+        self,
+        from: [u8; 3],
+        to: [u8; 3],
+        direction: Direction,
+        frames: usize,
+    ) -> Script {
+        self.push(
+            Step::Gradient {
+                from,
+                to,
+                direction,
+            },
+            frames,
+        )
+    }
+
+    /// Appends `frames` frames containing a rectangle that moves by `velocity` pixels every frame.
+    ///
+    /// The rectangle starts with its upper-left corner at `start` and wraps around the edges of the screen.
+    pub fn moving_rect(
+        self,
+        background: [u8; 3],
+        color: [u8; 3],
+        size: (i32, i32),
+        start: (i32, i32),
+        velocity: (i32, i32),
+        frames: usize,
+    ) -> Script {
+        self.push(
+            Step::MovingRect {
+                background,
+                color,
+                size,
+                start,
+                velocity,
+            },
+            frames,
+        )
+    }
+
+    /// Appends a single frame containing the given bits.
+    ///
+    /// The bits must cover the whole screen, 3 bytes per pixel without any padding.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of `bits` does not match the size of the screen.
+    pub fn frame(self, bits: Vec<u8>) -> Script {
+        assert_eq!(
+            bits.len(),
+            self.screen_len(),
+            "frame size does not match the screen size"
+        );
+        self.push(Step::Image { bits }, 1)
+    }
+
+    /// Appends one frame for every file in `paths`.
+    ///
+    /// Every file must contain a raw dump of the whole screen, 3 bytes per pixel without any padding.
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if a file can't be read or its size does not match the size of the screen.
+    pub fn raw_files<I, P>(mut self, paths: I) -> std::io::Result<Script>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        for path in paths {
+            let bits = std::fs::read(path)?;
+            if bits.len() != self.screen_len() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "frame size does not match the screen size",
+                ));
+            }
+            self = self.push(Step::Image { bits }, 1);
+        }
+        Ok(self)
+    }
+
+    /// Appends a single frame containing the given image, converted to \[B, G, R] pixels.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size of the image does not match the size of the screen.
+    pub fn image<'a>(self, image: impl Into<ImageView<'a>>) -> Script {
+        let image = image.into();
+        assert!(
+            self.fits(&image),
+            "image size does not match the screen size"
+        );
+        self.push(
+            Step::Image {
+                bits: packed_bgr(image),
+            },
+            1,
+        )
+    }
+
+    /// Appends one frame for every image file in `paths`, e.g. captures saved with [`bmp::save`] or
+    /// [`pnm::save_ppm`].
+    ///
+    /// The files may be BMP files or PPM, PGM and PAM files, see [`bmp::decode`] and [`pnm::decode`].
+    ///
+    /// # Errors
+    ///
+    /// This method will return an error if a file can't be read or decoded or the size of its image does not match
+    /// the size of the screen.
+    pub fn image_files<I, P>(mut self, paths: I) -> std::io::Result<Script>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        for path in paths {
+            let file = std::fs::read(path)?;
+            let frame = if file.starts_with(b"BM") {
+                bmp::decode(file.as_slice())?
+            } else {
+                pnm::decode(file.as_slice())?
+            };
+            if !self.fits(&(&frame).into()) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "image size does not match the screen size",
+                ));
+            }
+            self = self.push(
+                Step::Image {
+                    bits: packed_bgr((&frame).into()),
+                },
+                1,
+            );
+        }
+        Ok(self)
+    }
+
+    /// Appends a capture that fails with an [`Error::Platform`] caused by [`SyntheticError::Injected`].
+    pub fn error(self, message: impl Into<String>) -> Script {
+        self.push(
+            Step::Error {
+                message: message.into(),
+            },
+            1,
+        )
+    }
+
+    fn fits(&self, image: &ImageView) -> bool {
+        (image.width(), image.height())
+            == (self.screen.0.max(0) as usize, self.screen.1.max(0) as usize)
+    }
+
+    fn screen_len(&self) -> usize {
+        self.screen.0.max(0) as usize * self.screen.1.max(0) as usize * 3
+    }
+
+    /// Finds the step for the given frame, together with the index of the frame within the step.
+    fn step(&self, frame: usize) -> Option<(&Step, usize)> {
+        let len = self.len();
+        if len == 0 {
+            return None;
+        }
+        let mut frame = frame % len;
+        for (step, frames) in &self.steps {
+            if frame < *frames {
+                return Some((step, frame));
+            }
+            frame -= frames;
+        }
+        None
+    }
+}
+
+/// Returns the pixels of an image as tightly packed \[B, G, R] bytes.
+fn packed_bgr(image: ImageView) -> Vec<u8> {
+    let converter = Converter::new();
+    let stride = image.width() * 3;
+    let mut bits = vec![0; stride * image.height()];
+    if stride > 0 {
+        for (src, dst) in image.rows().zip(bits.chunks_exact_mut(stride)) {
+            converter.convert_row(src, image.format(), dst, PixelFormat::Bgr24);
+        }
+    }
+    bits
+}
+
+/// The cause of the errors returned by the [`SyntheticBackend`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyntheticError {
+    /// The capture failed because the script said so.
+    Injected(String),
+}
+
+impl fmt::Display for SyntheticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyntheticError::Injected(message) => write!(f, "injected error: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for SyntheticError {}
+
+/// A capture backend that plays back a [`Script`] instead of capturing a real screen.
+///
+/// It behaves like the other backends, i.e. it returns the part of the virtual screen described by the captured area.
+/// Pixels outside of the virtual screen are black.
+pub struct SyntheticBackend {
+    script: Script,
+    top_left: (i32, i32),
+    wh: (i32, i32),
+    frame: Cell<usize>,
+}
+
+impl SyntheticBackend {
+    /// Returns the script played back by this backend.
+    pub fn script(&self) -> &Script {
+        &self.script
+    }
+
+    /// Returns the index of the frame that will be returned by the next capture.
+    pub fn frame_index(&self) -> usize {
+        self.frame.get()
+    }
+
+    /// Rewinds the script so that the next capture returns the given frame.
+    pub fn seek(&self, frame: usize) {
+        self.frame.set(frame);
+    }
+
+    /// Renders the next frame of the script into a tightly packed \[B, G, R] buffer.
+    fn render(&self, bits: &mut [u8]) -> Result<(), SyntheticError> {
+        let frame = self.frame.get();
+        self.frame.set(frame + 1);
+
+        let (w, h) = (self.wh.0.max(0), self.wh.1.max(0));
+        match self.script.step(frame) {
+            Some((Step::Error { message }, _)) => Err(SyntheticError::Injected(message.clone())),
+            Some((step, index)) => {
+                let mut pixels = bits.chunks_exact_mut(3);
+                for y in 0..h {
+                    for x in 0..w {
+                        let pixel =
+                            self.pixel(step, index, self.top_left.0 + x, self.top_left.1 + y);
+                        pixels.next().unwrap().copy_from_slice(&pixel);
+                    }
+                }
+                Ok(())
+            }
+            None => {
+                bits.fill(0);
+                Ok(())
+            }
+        }
+    }
+
+    fn pixel(&self, step: &Step, index: usize, x: i32, y: i32) -> [u8; 3] {
+        let (sw, sh) = self.script.screen;
+        if x < 0 || y < 0 || x >= sw || y >= sh {
+            return [0, 0, 0];
+        }
+        match step {
+            Step::Solid { color } => *color,
+            Step::Gradient {
+                from,
+                to,
+                direction,
+            } => {
+                let (pos, span) = match direction {
+                    Direction::Horizontal => (x, sw),
+                    Direction::Vertical => (y, sh),
+                };
+                let span = (span - 1).max(1) as i64;
+                let mut out = [0; 3];
+                for c in 0..3 {
+                    let (a, b) = (from[c] as i64, to[c] as i64);
+                    out[c] = (a + (b - a) * pos as i64 / span) as u8;
+                }
+                out
+            }
+            Step::MovingRect {
+                background,
+                color,
+                size,
+                start,
+                velocity,
+            } => {
+                let index = index as i64;
+                let left = (start.0 as i64 + velocity.0 as i64 * index).rem_euclid(sw as i64);
+                let top = (start.1 as i64 + velocity.1 as i64 * index).rem_euclid(sh as i64);
+                let dx = (x as i64 - left).rem_euclid(sw as i64);
+                let dy = (y as i64 - top).rem_euclid(sh as i64);
+                if dx < size.0 as i64 && dy < size.1 as i64 {
+                    *color
+                } else {
+                    *background
+                }
+            }
+            Step::Image { bits } => {
+                let i = (y as usize * sw as usize + x as usize) * 3;
+                [bits[i], bits[i + 1], bits[i + 2]]
+            }
+            Step::Error { .. } => [0, 0, 0],
+        }
+    }
+}
+
+impl CaptureBackend for SyntheticBackend {
+    type Target = Script;
+    fn open(script: Script, area: Rect) -> Result<Self, Error> {
+        let (top_left, wh) = (area.top_left(), area.wh());
+        Ok(SyntheticBackend {
+            script,
+            top_left,
+            wh,
+            frame: Cell::new(0),
+        })
+    }
+
+    fn capture(&self) -> Result<CaptureData, Error> {
+        let (w, h) = (self.wh.0.max(0) as usize, self.wh.1.max(0) as usize);
+        let mut bits = vec![0; w * h * 3];
+        self.render(&mut bits).map_err(Error::platform)?;
+        Ok(CaptureData::from_vec(bits, w, h, PixelFormat::Bgr24))
+    }
+
+    fn capture_into(&self, frame: &mut Frame) -> Result<(), Error> {
+        let (w, h) = (self.wh.0.max(0) as usize, self.wh.1.max(0) as usize);
+        frame.reshape(w, h, w * 3, PixelFormat::Bgr24);
+        self.render(frame.bits_mut()).map_err(Error::platform)
+    }
+
+    fn resize(&mut self, area: Rect) -> Result<(), Error> {
+        let (top_left, wh) = (area.top_left(), area.wh());
+        self.top_left = top_left;
+        self.wh = wh;
+        Ok(())
+    }
+
+    fn area(&self) -> Rect {
+        Rect::from_parts(self.top_left, self.wh)
+    }
+
+    fn bounds(&self) -> Option<Rect> {
+        Some(Rect::from_parts((0, 0), self.script.screen))
+    }
+}
@@ -0,0 +1,153 @@
+use crate::capture::CaptureData;
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use crate::rect::Rect;
+
+/// A borrowed image laid out in rows, like the bits of a [`CaptureData`] or a [`Frame`].
+///
+/// Functions working on pixels take `impl Into<ImageView>`, so they accept both without copying.
+///
+/// # Examples
+///
+/// ```
+/// use qshot::{ImageView, PixelFormat, Rect};
+///
+/// // Two rows of two BGR pixels, each row padded to 8 bytes.
+/// let bits = [1, 2, 3, 4, 5, 6, 0, 0, 7, 8, 9, 10, 11, 12, 0, 0];
+/// let view = ImageView::new(&bits, 2, 2, 8, PixelFormat::Bgr24);
+/// assert_eq!(view.pixel(1, 1), &[10, 11, 12]);
+///
+/// let part = view.sub_view(Rect::new(1, 0, 5, 5));
+/// assert_eq!((part.width(), part.height()), (1, 2));
+/// assert_eq!(part.row(1), &[10, 11, 12]);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct ImageView<'a> {
+    bits: &'a [u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    format: PixelFormat,
+}
+
+impl<'a> ImageView<'a> {
+    /// Creates a view of bits in which every row is `stride` bytes long.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `stride` is smaller than a row of pixels or `bits` is too short to hold `height` rows.
+    pub fn new(
+        bits: &'a [u8],
+        width: usize,
+        height: usize,
+        stride: usize,
+        format: PixelFormat,
+    ) -> ImageView<'a> {
+        let row = width * format.bytes_per_pixel();
+        assert!(stride >= row, "stride is too small");
+        assert!(
+            height == 0 || bits.len() >= stride * (height - 1) + row,
+            "buffer is too small"
+        );
+        ImageView {
+            bits,
+            width,
+            height,
+            stride,
+            format,
+        }
+    }
+
+    /// Returns the bits of the image, including any padding.
+    pub fn bits(&self) -> &'a [u8] {
+        self.bits
+    }
+
+    /// Returns the width of the image in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of the image in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the distance between the starts of two consecutive rows in bytes.
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Returns the layout of a single pixel.
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Returns the area covered by the image, with its upper-left corner at the origin.
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width as i32, self.height as i32)
+    }
+
+    /// Returns the pixels of the `y`-th row, without the padding.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `y` is not smaller than the height.
+    pub fn row(&self, y: usize) -> &'a [u8] {
+        assert!(y < self.height, "row out of bounds");
+        let start = y * self.stride;
+        &self.bits[start..start + self.width * self.format.bytes_per_pixel()]
+    }
+
+    /// Returns an iterator over the rows, without the padding.
+    pub fn rows(&self) -> impl Iterator<Item = &'a [u8]> {
+        let view = *self;
+        (0..self.height).map(move |y| view.row(y))
+    }
+
+    /// Returns the bytes of the pixel at the given coordinates.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coordinates lie outside of the image.
+    pub fn pixel(&self, x: usize, y: usize) -> &'a [u8] {
+        assert!(x < self.width, "pixel out of bounds");
+        let bpp = self.format.bytes_per_pixel();
+        &self.row(y)[x * bpp..(x + 1) * bpp]
+    }
+
+    /// Returns a view of the part of the image within `area`, which is clamped to the image first.
+    pub fn sub_view(&self, area: Rect) -> ImageView<'a> {
+        let area = area.clamp(&self.bounds());
+        let start = area.y as usize * self.stride + area.x as usize * self.format.bytes_per_pixel();
+        let (width, height) = (area.width as usize, area.height as usize);
+        if width == 0 || height == 0 {
+            return ImageView::new(&[], 0, 0, 0, self.format);
+        }
+        ImageView::new(&self.bits[start..], width, height, self.stride, self.format)
+    }
+}
+
+impl<'a> From<&'a CaptureData> for ImageView<'a> {
+    fn from(data: &'a CaptureData) -> ImageView<'a> {
+        ImageView::new(
+            data.get_bits(),
+            data.width(),
+            data.height(),
+            data.stride(),
+            data.format(),
+        )
+    }
+}
+
+impl<'a> From<&'a Frame> for ImageView<'a> {
+    fn from(frame: &'a Frame) -> ImageView<'a> {
+        ImageView::new(
+            frame.bits(),
+            frame.width(),
+            frame.height(),
+            frame.stride(),
+            frame.format(),
+        )
+    }
+}
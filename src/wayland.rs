@@ -0,0 +1,1304 @@
+use std::cell::RefCell;
+use std::ffi::{c_int, c_void};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::backend::CaptureBackend;
+use crate::capture::CaptureData;
+use crate::error::Error;
+use crate::format::{PixelFormat, OPAQUE_BLACK};
+use crate::frame::Frame;
+use crate::rect::Rect;
+
+mod sys {
+    use std::ffi::{c_char, c_int, c_uint, c_void};
+
+    pub const SOL_SOCKET: c_int = 1;
+    pub const SCM_RIGHTS: c_int = 1;
+    pub const MFD_CLOEXEC: c_uint = 1;
+    pub const MSG_NOSIGNAL: c_int = 0x4000;
+
+    #[repr(C)]
+    pub struct IoVec {
+        pub base: *const c_void,
+        pub len: usize,
+    }
+
+    #[repr(C)]
+    pub struct MsgHdr {
+        pub name: *mut c_void,
+        pub namelen: u32,
+        pub iov: *const IoVec,
+        pub iovlen: usize,
+        pub control: *mut c_void,
+        pub controllen: usize,
+        pub flags: c_int,
+    }
+
+    #[repr(C)]
+    pub struct CmsgHdr {
+        pub len: usize,
+        pub level: c_int,
+        pub kind: c_int,
+    }
+
+    extern "C" {
+        pub fn sendmsg(fd: c_int, msg: *const MsgHdr, flags: c_int) -> isize;
+        pub fn memfd_create(name: *const c_char, flags: c_uint) -> c_int;
+    }
+}
+
+/// Object ids and opcodes of the few protocol objects we use.
+mod proto {
+    pub const DISPLAY: u32 = 1;
+    pub const DISPLAY_SYNC: u16 = 0;
+    pub const DISPLAY_GET_REGISTRY: u16 = 1;
+    pub const DISPLAY_EV_ERROR: u16 = 0;
+    pub const DISPLAY_EV_DELETE_ID: u16 = 1;
+
+    pub const REGISTRY_BIND: u16 = 0;
+    pub const REGISTRY_EV_GLOBAL: u16 = 0;
+
+    pub const CALLBACK_EV_DONE: u16 = 0;
+
+    pub const SHM_CREATE_POOL: u16 = 0;
+    pub const SHM_POOL_CREATE_BUFFER: u16 = 0;
+    pub const SHM_POOL_DESTROY: u16 = 1;
+    pub const BUFFER_DESTROY: u16 = 0;
+
+    pub const OUTPUT_EV_MODE: u16 = 1;
+    pub const OUTPUT_EV_SCALE: u16 = 3;
+    pub const OUTPUT_EV_NAME: u16 = 4;
+    pub const OUTPUT_MODE_CURRENT: u32 = 1;
+
+    pub const SCREENCOPY_CAPTURE_OUTPUT: u16 = 0;
+    pub const SCREENCOPY_CAPTURE_OUTPUT_REGION: u16 = 1;
+    pub const SCREENCOPY_DESTROY: u16 = 2;
+
+    pub const FRAME_COPY: u16 = 0;
+    pub const FRAME_DESTROY: u16 = 1;
+    pub const FRAME_EV_BUFFER: u16 = 0;
+    pub const FRAME_EV_FLAGS: u16 = 1;
+    pub const FRAME_EV_READY: u16 = 2;
+    pub const FRAME_EV_FAILED: u16 = 3;
+    pub const FRAME_EV_BUFFER_DONE: u16 = 6;
+    pub const FRAME_FLAG_Y_INVERT: u32 = 1;
+
+    pub const SOURCE_DESTROY: u16 = 0;
+    pub const OUTPUT_SOURCE_MANAGER_CREATE_SOURCE: u16 = 0;
+    pub const OUTPUT_SOURCE_MANAGER_DESTROY: u16 = 1;
+
+    pub const COPY_MANAGER_CREATE_SESSION: u16 = 0;
+    pub const COPY_MANAGER_DESTROY: u16 = 2;
+    pub const COPY_OPTION_PAINT_CURSORS: u32 = 1;
+
+    pub const SESSION_CREATE_FRAME: u16 = 0;
+    pub const SESSION_DESTROY: u16 = 1;
+    pub const SESSION_EV_BUFFER_SIZE: u16 = 0;
+    pub const SESSION_EV_SHM_FORMAT: u16 = 1;
+    pub const SESSION_EV_DONE: u16 = 4;
+    pub const SESSION_EV_STOPPED: u16 = 5;
+
+    pub const COPY_FRAME_DESTROY: u16 = 0;
+    pub const COPY_FRAME_ATTACH_BUFFER: u16 = 1;
+    pub const COPY_FRAME_DAMAGE_BUFFER: u16 = 2;
+    pub const COPY_FRAME_CAPTURE: u16 = 3;
+    pub const COPY_FRAME_EV_READY: u16 = 3;
+    pub const COPY_FRAME_EV_FAILED: u16 = 4;
+    pub const COPY_FRAME_FAILURE_BUFFER_CONSTRAINTS: u32 = 1;
+    pub const COPY_FRAME_FAILURE_STOPPED: u32 = 2;
+}
+
+/// `wl_shm` formats we know how to convert, i.e. little-endian DRM fourcc codes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ShmFormat {
+    /// `ARGB8888` and `XRGB8888`, stored as \[B, G, R, X].
+    Xrgb8888(u32),
+    /// `ABGR8888` and `XBGR8888`, stored as \[R, G, B, X].
+    Xbgr8888(u32),
+    /// `RGB888`, stored as \[B, G, R].
+    Rgb888,
+    /// `BGR888`, stored as \[R, G, B].
+    Bgr888,
+}
+
+impl ShmFormat {
+    fn from_code(code: u32) -> Option<ShmFormat> {
+        match code {
+            0 | 1 => Some(ShmFormat::Xrgb8888(code)),
+            0x3432_4241 | 0x3432_4258 => Some(ShmFormat::Xbgr8888(code)),
+            0x3432_4752 => Some(ShmFormat::Rgb888),
+            0x3432_4742 => Some(ShmFormat::Bgr888),
+            _ => None,
+        }
+    }
+
+    fn code(self) -> u32 {
+        match self {
+            ShmFormat::Xrgb8888(code) | ShmFormat::Xbgr8888(code) => code,
+            ShmFormat::Rgb888 => 0x3432_4752,
+            ShmFormat::Bgr888 => 0x3432_4742,
+        }
+    }
+
+    fn bytes_per_pixel(self) -> usize {
+        match self {
+            ShmFormat::Xrgb8888(_) | ShmFormat::Xbgr8888(_) => 4,
+            ShmFormat::Rgb888 | ShmFormat::Bgr888 => 3,
+        }
+    }
+
+    /// Returns the \[B, G, R] values of a single pixel.
+    fn bgr(self, pixel: &[u8]) -> [u8; 3] {
+        match self {
+            ShmFormat::Xrgb8888(_) | ShmFormat::Rgb888 => [pixel[0], pixel[1], pixel[2]],
+            ShmFormat::Xbgr8888(_) | ShmFormat::Bgr888 => [pixel[2], pixel[1], pixel[0]],
+        }
+    }
+}
+
+/// Selects the output captured by the [`WaylandBackend`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OutputSelector {
+    /// The n-th output advertised by the compositor.
+    Index(usize),
+    /// The output with the given name, e.g. `"HEADLESS-1"` (requires `wl_output` version 4).
+    Name(String),
+}
+
+/// Describes the Wayland display and output that should be captured.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WaylandTarget {
+    display: Option<String>,
+    output: OutputSelector,
+    cursor: bool,
+}
+
+impl Default for WaylandTarget {
+    fn default() -> WaylandTarget {
+        WaylandTarget {
+            display: None,
+            output: OutputSelector::Index(0),
+            cursor: false,
+        }
+    }
+}
+
+impl WaylandTarget {
+    /// Targets the first output of the compositor named by the `WAYLAND_DISPLAY` environment variable.
+    pub fn new() -> WaylandTarget {
+        WaylandTarget::default()
+    }
+
+    /// Selects the output that should be captured.
+    pub fn output(mut self, output: OutputSelector) -> WaylandTarget {
+        self.output = output;
+        self
+    }
+
+    /// Connects to the given display (a socket name or an absolute path) instead of `WAYLAND_DISPLAY`.
+    pub fn display(mut self, name: impl Into<String>) -> WaylandTarget {
+        self.display = Some(name.into());
+        self
+    }
+
+    /// Includes the cursor in the captured frames.
+    pub fn cursor(mut self, cursor: bool) -> WaylandTarget {
+        self.cursor = cursor;
+        self
+    }
+}
+
+/// The cause of the errors returned by the [`WaylandBackend`].
+#[derive(Debug)]
+pub enum WaylandError {
+    /// Communicating with the compositor failed.
+    Io(io::Error),
+    /// The compositor reported a protocol error.
+    Protocol(String),
+    /// The compositor does not support the named global, e.g. `wl_shm`, or none of the capture protocols.
+    Unsupported(&'static str),
+    /// The selected output does not exist.
+    NoSuchOutput,
+    /// The compositor failed to copy the frame.
+    CopyFailed,
+    /// None of the buffer formats offered by the compositor are supported.
+    UnsupportedFormat,
+}
+
+impl fmt::Display for WaylandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaylandError::Io(err) => write!(f, "wayland connection error: {err}"),
+            WaylandError::Protocol(message) => write!(f, "wayland protocol error: {message}"),
+            WaylandError::Unsupported(global) => write!(f, "compositor does not support {global}"),
+            WaylandError::NoSuchOutput => write!(f, "the selected output does not exist"),
+            WaylandError::CopyFailed => write!(f, "the compositor failed to copy the frame"),
+            WaylandError::UnsupportedFormat => write!(f, "unsupported buffer format"),
+        }
+    }
+}
+
+impl std::error::Error for WaylandError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WaylandError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WaylandError {
+    fn from(err: io::Error) -> WaylandError {
+        WaylandError::Io(err)
+    }
+}
+
+impl From<WaylandError> for Error {
+    fn from(err: WaylandError) -> Error {
+        match err {
+            WaylandError::Io(err) => Error::Io(err),
+            WaylandError::Unsupported(_) => Error::unavailable(err),
+            WaylandError::UnsupportedFormat => Error::UnsupportedFormat(err.to_string()),
+            _ => Error::platform(err),
+        }
+    }
+}
+
+/// A request being serialized into the wire format.
+struct Request {
+    buf: Vec<u8>,
+}
+
+impl Request {
+    fn new(object: u32, opcode: u16) -> Request {
+        let mut buf = Vec::with_capacity(32);
+        buf.extend_from_slice(&object.to_ne_bytes());
+        buf.extend_from_slice(&(opcode as u32).to_ne_bytes());
+        Request { buf }
+    }
+
+    fn uint(mut self, value: u32) -> Request {
+        self.buf.extend_from_slice(&value.to_ne_bytes());
+        self
+    }
+
+    fn int(self, value: i32) -> Request {
+        self.uint(value as u32)
+    }
+
+    fn string(mut self, value: &str) -> Request {
+        self = self.uint(value.len() as u32 + 1);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+        while self.buf.len() % 4 != 0 {
+            self.buf.push(0);
+        }
+        self
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let size = self.buf.len() as u32;
+        let opcode = u32::from_ne_bytes(self.buf[4..8].try_into().unwrap());
+        self.buf[4..8].copy_from_slice(&(size << 16 | opcode).to_ne_bytes());
+        self.buf
+    }
+}
+
+/// An event received from the compositor.
+struct Event {
+    object: u32,
+    opcode: u16,
+    args: Vec<u8>,
+    pos: usize,
+}
+
+impl Event {
+    fn uint(&mut self) -> u32 {
+        let value = self
+            .args
+            .get(self.pos..self.pos + 4)
+            .map_or(0, |b| u32::from_ne_bytes(b.try_into().unwrap()));
+        self.pos += 4;
+        value
+    }
+
+    fn int(&mut self) -> i32 {
+        self.uint() as i32
+    }
+
+    fn string(&mut self) -> String {
+        let len = self.uint() as usize;
+        let end = (self.pos + len).min(self.args.len());
+        let bytes = &self.args[self.pos.min(end)..end];
+        let value = String::from_utf8_lossy(bytes.strip_suffix(&[0]).unwrap_or(bytes)).into_owned();
+        self.pos += (len + 3) & !3;
+        value
+    }
+}
+
+struct Connection {
+    stream: UnixStream,
+    reader: BufReader<UnixStream>,
+    next_id: u32,
+    /// Ids released by the compositor with `wl_display.delete_id`, handed out again before new ones.
+    free_ids: Vec<u32>,
+}
+
+impl Connection {
+    fn connect(display: Option<&str>) -> Result<Connection, WaylandError> {
+        let name = display
+            .map(str::to_owned)
+            .or_else(|| std::env::var("WAYLAND_DISPLAY").ok())
+            .unwrap_or_else(|| "wayland-0".to_owned());
+        let mut path = PathBuf::from(&name);
+        if path.is_relative() {
+            let runtime = std::env::var_os("XDG_RUNTIME_DIR").ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "XDG_RUNTIME_DIR is not set")
+            })?;
+            path = PathBuf::from(runtime).join(name);
+        }
+        Ok(Connection::from_stream(UnixStream::connect(path)?)?)
+    }
+
+    fn from_stream(stream: UnixStream) -> io::Result<Connection> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        Ok(Connection {
+            reader: BufReader::new(stream.try_clone()?),
+            stream,
+            next_id: 2,
+            free_ids: Vec::new(),
+        })
+    }
+
+    fn new_id(&mut self) -> u32 {
+        self.free_ids.pop().unwrap_or_else(|| {
+            let id = self.next_id;
+            self.next_id += 1;
+            id
+        })
+    }
+
+    fn send(&mut self, request: Request) -> Result<(), WaylandError> {
+        self.stream.write_all(&request.finish())?;
+        Ok(())
+    }
+
+    /// Sends a request carrying a file descriptor.
+    fn send_with_fd(&mut self, request: Request, fd: RawFd) -> Result<(), WaylandError> {
+        let msg = request.finish();
+        let header = (std::mem::size_of::<sys::CmsgHdr>() + std::mem::size_of::<usize>() - 1)
+            & !(std::mem::size_of::<usize>() - 1);
+        let fd_space = (std::mem::size_of::<c_int>() + std::mem::size_of::<usize>() - 1)
+            & !(std::mem::size_of::<usize>() - 1);
+        let mut control = vec![0usize; (header + fd_space) / std::mem::size_of::<usize>()];
+        unsafe {
+            let cmsg = control.as_mut_ptr() as *mut sys::CmsgHdr;
+            (*cmsg).len = header + std::mem::size_of::<c_int>();
+            (*cmsg).level = sys::SOL_SOCKET;
+            (*cmsg).kind = sys::SCM_RIGHTS;
+            std::ptr::write_unaligned((cmsg as *mut u8).add(header) as *mut c_int, fd);
+        }
+        let iov = sys::IoVec {
+            base: msg.as_ptr() as *const c_void,
+            len: msg.len(),
+        };
+        let hdr = sys::MsgHdr {
+            name: std::ptr::null_mut(),
+            namelen: 0,
+            iov: &iov,
+            iovlen: 1,
+            control: control.as_mut_ptr() as *mut c_void,
+            controllen: header + fd_space,
+            flags: 0,
+        };
+        let sent = unsafe { sys::sendmsg(self.stream.as_raw_fd(), &hdr, sys::MSG_NOSIGNAL) };
+        if sent < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        // The descriptor went out with the first byte, the rest can be written normally.
+        self.stream.write_all(&msg[sent as usize..])?;
+        Ok(())
+    }
+
+    /// Reads the next event, handling the events of `wl_display` itself.
+    fn read_event(&mut self) -> Result<Event, WaylandError> {
+        loop {
+            let mut header = [0; 8];
+            self.reader.read_exact(&mut header)?;
+            let object = u32::from_ne_bytes(header[..4].try_into().unwrap());
+            let word = u32::from_ne_bytes(header[4..].try_into().unwrap());
+            let size = (word >> 16) as usize;
+            if size < 8 {
+                return Err(WaylandError::Protocol("malformed event".to_owned()));
+            }
+            let mut args = vec![0; size - 8];
+            self.reader.read_exact(&mut args)?;
+            let mut event = Event {
+                object,
+                opcode: word as u16,
+                args,
+                pos: 0,
+            };
+            if event.object != proto::DISPLAY {
+                return Ok(event);
+            }
+            match event.opcode {
+                proto::DISPLAY_EV_ERROR => {
+                    let (object, code) = (event.uint(), event.uint());
+                    let message = event.string();
+                    return Err(WaylandError::Protocol(format!(
+                        "object {object}, code {code}: {message}"
+                    )));
+                }
+                proto::DISPLAY_EV_DELETE_ID => self.free_ids.push(event.uint()),
+                _ => {}
+            }
+        }
+    }
+
+    /// Sends `wl_display.sync` and returns the id of the callback that signals its completion.
+    fn sync(&mut self) -> Result<u32, WaylandError> {
+        let callback = self.new_id();
+        self.send(Request::new(proto::DISPLAY, proto::DISPLAY_SYNC).uint(callback))?;
+        Ok(callback)
+    }
+}
+
+#[derive(Default)]
+struct Output {
+    id: u32,
+    name: Option<String>,
+    size: Option<(i32, i32)>,
+    scale: i32,
+}
+
+/// A shared memory buffer the compositor copies frames into.
+struct ShmBuffer {
+    file: File,
+    pool: u32,
+    buffer: u32,
+    format: ShmFormat,
+    width: u32,
+    height: u32,
+    stride: u32,
+}
+
+/// The protocol used to capture the output.
+enum Method {
+    /// `zwlr_screencopy_manager_v1`, which copies only the captured region.
+    Screencopy { manager: u32, version: u32 },
+    /// `ext_image_copy_capture_manager_v1`, which copies the whole output through a session created up front.
+    ImageCopy(Session),
+}
+
+/// An `ext_image_copy_capture_session_v1` and the objects it was created from.
+struct Session {
+    manager: u32,
+    source_manager: u32,
+    source: u32,
+    session: u32,
+    /// The buffer format and size the compositor currently accepts, `None` if we support none of its formats.
+    constraints: Option<(ShmFormat, u32, u32)>,
+    /// The constraints sent since the last `done` event.
+    pending: (Option<ShmFormat>, Option<(u32, u32)>),
+}
+
+impl Session {
+    /// Creates a session capturing `output` and waits for its buffer constraints.
+    fn create(
+        conn: &mut Connection,
+        manager: u32,
+        source_manager: u32,
+        output: u32,
+        cursor: bool,
+    ) -> Result<Session, WaylandError> {
+        let source = conn.new_id();
+        let request = Request::new(source_manager, proto::OUTPUT_SOURCE_MANAGER_CREATE_SOURCE)
+            .uint(source)
+            .uint(output);
+        conn.send(request)?;
+        let session = conn.new_id();
+        let options = if cursor {
+            proto::COPY_OPTION_PAINT_CURSORS
+        } else {
+            0
+        };
+        let request = Request::new(manager, proto::COPY_MANAGER_CREATE_SESSION)
+            .uint(session)
+            .uint(source)
+            .uint(options);
+        conn.send(request)?;
+
+        let mut session = Session {
+            manager,
+            source_manager,
+            source,
+            session,
+            constraints: None,
+            pending: (None, None),
+        };
+        loop {
+            let mut event = conn.read_event()?;
+            if event.object == session.session && session.handle_event(&mut event)? {
+                return Ok(session);
+            }
+        }
+    }
+
+    /// Handles an event of the session, returns `true` once the current constraints are known.
+    fn handle_event(&mut self, event: &mut Event) -> Result<bool, WaylandError> {
+        match event.opcode {
+            proto::SESSION_EV_BUFFER_SIZE => self.pending.1 = Some((event.uint(), event.uint())),
+            // The first format we support wins.
+            proto::SESSION_EV_SHM_FORMAT if self.pending.0.is_none() => {
+                self.pending.0 = ShmFormat::from_code(event.uint());
+            }
+            proto::SESSION_EV_DONE => {
+                let (format, size) = std::mem::take(&mut self.pending);
+                self.constraints = format.zip(size).map(|(format, (w, h))| (format, w, h));
+                return Ok(true);
+            }
+            proto::SESSION_EV_STOPPED => return Err(WaylandError::NoSuchOutput),
+            _ => {}
+        }
+        Ok(false)
+    }
+}
+
+struct State {
+    conn: Connection,
+    shm: u32,
+    /// The capture protocol, `None` once the backend is closed.
+    method: Option<Method>,
+    buffer: Option<ShmBuffer>,
+    /// The contents of the buffer, kept around so that they don't have to be reallocated for every capture.
+    data: Vec<u8>,
+}
+
+/// A capture backend for Wayland compositors.
+///
+/// It uses the `wlr-screencopy-unstable-v1` protocol of wlroots based compositors (sway, Hyprland, labwc, ...) and
+/// falls back to the `ext-image-copy-capture-v1` protocol with `ext-image-capture-source-v1` output sources on
+/// other compositors, always with shared memory buffers. The latter copies the whole output for every capture, so
+/// small areas are cheaper to capture through screencopy. Compositors offering neither, like weston releases
+/// without `ext-image-copy-capture-v1`, are reported as unavailable.
+/// Both [`PixelFormat::Bgr24`] and [`PixelFormat::Bgra32`] are produced natively.
+/// Parts of the captured area lying outside of the selected output are black.
+///
+/// # Examples
+///
+/// ```no_run
+/// use qshot::{CaptureManager, OutputSelector, Rect, WaylandBackend, WaylandTarget};
+///
+/// let target = WaylandTarget::new().output(OutputSelector::Name("HEADLESS-1".to_owned()));
+/// let manager = CaptureManager::<WaylandBackend>::open(target, Rect::new(0, 0, 640, 480)).unwrap();
+/// let res = manager.capture().unwrap();
+/// assert_eq!(res.get_bits().len(), 640 * 480 * 3);
+/// ```
+pub struct WaylandBackend {
+    state: RefCell<State>,
+    output: Output,
+    cursor: bool,
+    top_left: (i32, i32),
+    wh: (i32, i32),
+    format: PixelFormat,
+}
+
+impl WaylandBackend {
+    /// Returns the name of the captured output, if the compositor advertises output names.
+    pub fn output_name(&self) -> Option<&str> {
+        self.output.name.as_deref()
+    }
+
+    /// Returns the size of the captured output in pixels, if known.
+    pub fn output_size(&self) -> Option<(i32, i32)> {
+        self.output.size
+    }
+
+    /// Returns the part of the captured area that can be requested with `capture_output_region`.
+    fn region(&self) -> Option<(i32, i32, i32, i32)> {
+        let (ow, oh) = self.output.size?;
+        // Regions are given in logical coordinates, so only unscaled outputs map 1:1 to pixels.
+        if self.output.scale != 1 {
+            return None;
+        }
+        let left = self.top_left.0.max(0);
+        let top = self.top_left.1.max(0);
+        let right = (self.top_left.0 + self.wh.0).min(ow);
+        let bottom = (self.top_left.1 + self.wh.1).min(oh);
+        (right > left && bottom > top).then_some((left, top, right - left, bottom - top))
+    }
+
+    fn grab(&self, bits: &mut [u8]) -> Result<(), WaylandError> {
+        let state = &mut *self.state.borrow_mut();
+        let (origin, y_invert) = match &state.method {
+            Some(Method::Screencopy { manager, version }) => {
+                let (manager, version) = (*manager, *version);
+                self.screencopy(state, manager, version)?
+            }
+            Some(Method::ImageCopy(_)) => {
+                Self::copy_session(state)?;
+                ((0, 0), false)
+            }
+            None => return Err(WaylandError::Io(io::ErrorKind::NotConnected.into())),
+        };
+        self.read_buffer(state, bits, origin, y_invert)
+    }
+
+    /// Copies the captured region through wlr-screencopy, returns the origin of the copied region and whether it
+    /// is upside down.
+    fn screencopy(
+        &self,
+        state: &mut State,
+        manager: u32,
+        version: u32,
+    ) -> Result<((i32, i32), bool), WaylandError> {
+        let frame = state.conn.new_id();
+        let origin = match self.region() {
+            Some((x, y, w, h)) => {
+                let request = Request::new(manager, proto::SCREENCOPY_CAPTURE_OUTPUT_REGION)
+                    .uint(frame)
+                    .int(self.cursor as i32)
+                    .uint(self.output.id)
+                    .int(x)
+                    .int(y)
+                    .int(w)
+                    .int(h);
+                state.conn.send(request)?;
+                (x, y)
+            }
+            None => {
+                let request = Request::new(manager, proto::SCREENCOPY_CAPTURE_OUTPUT)
+                    .uint(frame)
+                    .int(self.cursor as i32)
+                    .uint(self.output.id);
+                state.conn.send(request)?;
+                (0, 0)
+            }
+        };
+
+        let res = Self::copy_frame(state, frame, version);
+        state.conn.send(Request::new(frame, proto::FRAME_DESTROY))?;
+        Ok((origin, res?))
+    }
+
+    /// Copies the whole output through the image copy session, retrying once if the buffer constraints changed.
+    fn copy_session(state: &mut State) -> Result<(), WaylandError> {
+        for _ in 0..2 {
+            let Some(Method::ImageCopy(session)) = &state.method else {
+                unreachable!()
+            };
+            let (format, width, height) =
+                session.constraints.ok_or(WaylandError::UnsupportedFormat)?;
+            let session = session.session;
+            let stride = width * format.bytes_per_pixel() as u32;
+            let buffer = Self::shm_buffer(state, format, width, height, stride)?;
+
+            let frame = state.conn.new_id();
+            state
+                .conn
+                .send(Request::new(session, proto::SESSION_CREATE_FRAME).uint(frame))?;
+            state
+                .conn
+                .send(Request::new(frame, proto::COPY_FRAME_ATTACH_BUFFER).uint(buffer))?;
+            let damage = Request::new(frame, proto::COPY_FRAME_DAMAGE_BUFFER)
+                .int(0)
+                .int(0)
+                .int(width as i32)
+                .int(height as i32);
+            state.conn.send(damage)?;
+            state
+                .conn
+                .send(Request::new(frame, proto::COPY_FRAME_CAPTURE))?;
+
+            let res = Self::wait_session_frame(state, session, frame);
+            state
+                .conn
+                .send(Request::new(frame, proto::COPY_FRAME_DESTROY))?;
+            if res? {
+                return Ok(());
+            }
+        }
+        Err(WaylandError::CopyFailed)
+    }
+
+    /// Waits until a frame of the session is copied, returns `false` if the buffer no longer fits the constraints.
+    fn wait_session_frame(
+        state: &mut State,
+        session: u32,
+        frame: u32,
+    ) -> Result<bool, WaylandError> {
+        loop {
+            let mut event = state.conn.read_event()?;
+            if event.object == session {
+                if let Some(Method::ImageCopy(session)) = &mut state.method {
+                    session.handle_event(&mut event)?;
+                }
+                continue;
+            }
+            if event.object != frame {
+                continue;
+            }
+            match event.opcode {
+                proto::COPY_FRAME_EV_READY => return Ok(true),
+                proto::COPY_FRAME_EV_FAILED => {
+                    return match event.uint() {
+                        proto::COPY_FRAME_FAILURE_BUFFER_CONSTRAINTS => Ok(false),
+                        proto::COPY_FRAME_FAILURE_STOPPED => Err(WaylandError::NoSuchOutput),
+                        _ => Err(WaylandError::CopyFailed),
+                    };
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Reads the frame the compositor copied into the shared memory buffer into `bits`.
+    fn read_buffer(
+        &self,
+        state: &mut State,
+        bits: &mut [u8],
+        origin: (i32, i32),
+        y_invert: bool,
+    ) -> Result<(), WaylandError> {
+        let buffer = state.buffer.as_ref().unwrap();
+        let size = buffer.stride as usize * buffer.height as usize;
+        state.data.resize(size, 0);
+        buffer.file.read_exact_at(&mut state.data, 0)?;
+        let data = &state.data;
+
+        let bpp = buffer.format.bytes_per_pixel();
+        let out_bpp = self.format.bytes_per_pixel();
+        let out_stride = self.wh.0 as usize * out_bpp;
+        for dy in 0..self.wh.1 {
+            let sy = self.top_left.1 + dy - origin.1;
+            if sy < 0 || sy >= buffer.height as i32 {
+                continue;
+            }
+            let sy = if y_invert {
+                buffer.height as i32 - 1 - sy
+            } else {
+                sy
+            };
+            let row = &data[sy as usize * buffer.stride as usize..];
+            for dx in 0..self.wh.0 {
+                let sx = self.top_left.0 + dx - origin.0;
+                if sx < 0 || sx >= buffer.width as i32 {
+                    continue;
+                }
+                let pixel = buffer.format.bgr(&row[sx as usize * bpp..]);
+                let out = dy as usize * out_stride + dx as usize * out_bpp;
+                bits[out..out + 3].copy_from_slice(&pixel);
+            }
+        }
+        Ok(())
+    }
+
+    /// Negotiates a buffer for the frame and waits until it is copied, returns whether the frame is upside down.
+    fn copy_frame(state: &mut State, frame: u32, version: u32) -> Result<bool, WaylandError> {
+        let mut offered = None;
+        let mut y_invert = false;
+        loop {
+            let mut event = state.conn.read_event()?;
+            if event.object != frame {
+                continue;
+            }
+            match event.opcode {
+                proto::FRAME_EV_BUFFER => {
+                    let (code, width, height, stride) =
+                        (event.uint(), event.uint(), event.uint(), event.uint());
+                    if offered.is_none() {
+                        offered = ShmFormat::from_code(code).map(|f| (f, width, height, stride));
+                    }
+                    if version < 3 {
+                        Self::copy(state, frame, offered)?;
+                    }
+                }
+                proto::FRAME_EV_BUFFER_DONE => Self::copy(state, frame, offered)?,
+                proto::FRAME_EV_FLAGS => y_invert = event.uint() & proto::FRAME_FLAG_Y_INVERT != 0,
+                proto::FRAME_EV_READY => return Ok(y_invert),
+                proto::FRAME_EV_FAILED => return Err(WaylandError::CopyFailed),
+                _ => {}
+            }
+        }
+    }
+
+    fn copy(
+        state: &mut State,
+        frame: u32,
+        offered: Option<(ShmFormat, u32, u32, u32)>,
+    ) -> Result<(), WaylandError> {
+        let (format, width, height, stride) = offered.ok_or(WaylandError::UnsupportedFormat)?;
+        let buffer = Self::shm_buffer(state, format, width, height, stride)?;
+        state
+            .conn
+            .send(Request::new(frame, proto::FRAME_COPY).uint(buffer))
+    }
+
+    /// Returns a `wl_buffer` with the given layout, reusing the previous one if it matches.
+    fn shm_buffer(
+        state: &mut State,
+        format: ShmFormat,
+        width: u32,
+        height: u32,
+        stride: u32,
+    ) -> Result<u32, WaylandError> {
+        let reusable = state.buffer.as_ref().is_some_and(|b| {
+            (b.format, b.width, b.height, b.stride) == (format, width, height, stride)
+        });
+        if !reusable {
+            Self::destroy_buffer(state)?;
+            let size = stride as usize * height as usize;
+            let fd = unsafe { sys::memfd_create(c"qshot".as_ptr(), sys::MFD_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            let file = unsafe { File::from_raw_fd(fd) };
+            file.set_len(size as u64)?;
+
+            let pool = state.conn.new_id();
+            let request = Request::new(state.shm, proto::SHM_CREATE_POOL)
+                .uint(pool)
+                .int(size as i32);
+            state.conn.send_with_fd(request, file.as_raw_fd())?;
+            let buffer = state.conn.new_id();
+            let request = Request::new(pool, proto::SHM_POOL_CREATE_BUFFER)
+                .uint(buffer)
+                .int(0)
+                .int(width as i32)
+                .int(height as i32)
+                .int(stride as i32)
+                .uint(format.code());
+            state.conn.send(request)?;
+            state.buffer = Some(ShmBuffer {
+                file,
+                pool,
+                buffer,
+                format,
+                width,
+                height,
+                stride,
+            });
+        }
+        Ok(state.buffer.as_ref().unwrap().buffer)
+    }
+
+    fn destroy_buffer(state: &mut State) -> Result<(), WaylandError> {
+        if let Some(buffer) = state.buffer.take() {
+            state
+                .conn
+                .send(Request::new(buffer.buffer, proto::BUFFER_DESTROY))?;
+            state
+                .conn
+                .send(Request::new(buffer.pool, proto::SHM_POOL_DESTROY))?;
+        }
+        Ok(())
+    }
+}
+
+impl CaptureBackend for WaylandBackend {
+    type Target = WaylandTarget;
+    fn open(target: WaylandTarget, area: Rect) -> Result<Self, Error> {
+        let (top_left, wh) = (area.top_left(), area.wh());
+        let mut conn =
+            Connection::connect(target.display.as_deref()).map_err(Error::unavailable)?;
+
+        let registry = conn.new_id();
+        conn.send(Request::new(proto::DISPLAY, proto::DISPLAY_GET_REGISTRY).uint(registry))?;
+        let done = conn.sync()?;
+        let mut globals = Vec::new();
+        loop {
+            let mut event = conn.read_event()?;
+            if event.object == done && event.opcode == proto::CALLBACK_EV_DONE {
+                break;
+            }
+            if event.object == registry && event.opcode == proto::REGISTRY_EV_GLOBAL {
+                let name = event.uint();
+                let interface = event.string();
+                let version = event.uint();
+                globals.push((name, interface, version));
+            }
+        }
+
+        let mut bind =
+            |interface: &'static str, max_version: u32| -> Result<Vec<(u32, u32)>, WaylandError> {
+                let mut ids = Vec::new();
+                for (name, _, version) in globals.iter().filter(|(_, i, _)| i == interface) {
+                    let version = (*version).min(max_version);
+                    let id = conn.new_id();
+                    let request = Request::new(registry, proto::REGISTRY_BIND)
+                        .uint(*name)
+                        .string(interface)
+                        .uint(version)
+                        .uint(id);
+                    conn.send(request)?;
+                    ids.push((id, version));
+                }
+                Ok(ids)
+            };
+        let shm = bind("wl_shm", 1)?
+            .first()
+            .map(|(id, _)| *id)
+            .ok_or(WaylandError::Unsupported("wl_shm"))?;
+        let screencopy = bind("zwlr_screencopy_manager_v1", 3)?.first().copied();
+        let image_copy = match screencopy {
+            Some(_) => None,
+            None => {
+                let manager = bind("ext_image_copy_capture_manager_v1", 1)?;
+                let source_manager = bind("ext_output_image_capture_source_manager_v1", 1)?;
+                manager
+                    .first()
+                    .zip(source_manager.first())
+                    .map(|(m, s)| (m.0, s.0))
+            }
+        };
+        if screencopy.is_none() && image_copy.is_none() {
+            return Err(WaylandError::Unsupported(
+                "zwlr_screencopy_manager_v1 or ext_image_copy_capture_manager_v1",
+            )
+            .into());
+        }
+        let mut outputs: Vec<Output> = bind("wl_output", 4)?
+            .into_iter()
+            .map(|(id, _)| Output {
+                id,
+                scale: 1,
+                ..Default::default()
+            })
+            .collect();
+
+        let done = conn.sync()?;
+        loop {
+            let mut event = conn.read_event()?;
+            if event.object == done && event.opcode == proto::CALLBACK_EV_DONE {
+                break;
+            }
+            let Some(output) = outputs.iter_mut().find(|o| o.id == event.object) else {
+                continue;
+            };
+            match event.opcode {
+                proto::OUTPUT_EV_MODE => {
+                    let (flags, width, height) = (event.uint(), event.int(), event.int());
+                    if flags & proto::OUTPUT_MODE_CURRENT != 0 {
+                        output.size = Some((width, height));
+                    }
+                }
+                proto::OUTPUT_EV_SCALE => output.scale = event.int(),
+                proto::OUTPUT_EV_NAME => output.name = Some(event.string()),
+                _ => {}
+            }
+        }
+
+        let index = match &target.output {
+            OutputSelector::Index(index) => Some(*index).filter(|i| *i < outputs.len()),
+            OutputSelector::Name(name) => outputs
+                .iter()
+                .position(|o| o.name.as_deref() == Some(name.as_str())),
+        };
+        let output = outputs.swap_remove(index.ok_or(WaylandError::NoSuchOutput)?);
+        let method = match (screencopy, image_copy) {
+            (Some((manager, version)), _) => Method::Screencopy { manager, version },
+            (None, Some((manager, source_manager))) => Method::ImageCopy(Session::create(
+                &mut conn,
+                manager,
+                source_manager,
+                output.id,
+                target.cursor,
+            )?),
+            (None, None) => unreachable!(),
+        };
+
+        Ok(WaylandBackend {
+            state: RefCell::new(State {
+                conn,
+                shm,
+                method: Some(method),
+                buffer: None,
+                data: Vec::new(),
+            }),
+            output,
+            cursor: target.cursor,
+            top_left,
+            wh,
+            format: PixelFormat::Bgr24,
+        })
+    }
+
+    fn capture(&self) -> Result<CaptureData, Error> {
+        let (width, height) = (self.wh.0.max(0) as usize, self.wh.1.max(0) as usize);
+        let mut bits = OPAQUE_BLACK[..self.format.bytes_per_pixel()].repeat(width * height);
+        self.grab(&mut bits)?;
+        Ok(CaptureData::from_vec(bits, width, height, self.format))
+    }
+
+    fn capture_into(&self, frame: &mut Frame) -> Result<(), Error> {
+        let (width, height) = (self.wh.0.max(0) as usize, self.wh.1.max(0) as usize);
+        frame.reset(width, height, self.format);
+        self.grab(frame.bits_mut()).map_err(Error::from)
+    }
+
+    fn set_format(&mut self, format: PixelFormat) -> bool {
+        if matches!(format, PixelFormat::Bgr24 | PixelFormat::Bgra32) {
+            self.format = format;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn resize(&mut self, area: Rect) -> Result<(), Error> {
+        let (top_left, wh) = (area.top_left(), area.wh());
+        self.top_left = top_left;
+        self.wh = wh;
+        Ok(())
+    }
+
+    fn area(&self) -> Rect {
+        Rect::from_parts(self.top_left, self.wh)
+    }
+
+    fn bounds(&self) -> Option<Rect> {
+        self.output.size.map(|wh| Rect::from_parts((0, 0), wh))
+    }
+
+    fn close(&mut self) {
+        let state = self.state.get_mut();
+        if let Some(method) = state.method.take() {
+            // The connection is going away anyway, so errors don't matter here.
+            let _ = Self::destroy_buffer(state);
+            let requests = match method {
+                Method::Screencopy { manager, .. } => {
+                    vec![Request::new(manager, proto::SCREENCOPY_DESTROY)]
+                }
+                Method::ImageCopy(session) => vec![
+                    Request::new(session.session, proto::SESSION_DESTROY),
+                    Request::new(session.source, proto::SOURCE_DESTROY),
+                    Request::new(session.manager, proto::COPY_MANAGER_DESTROY),
+                    Request::new(session.source_manager, proto::OUTPUT_SOURCE_MANAGER_DESTROY),
+                ],
+            };
+            for request in requests {
+                let _ = state.conn.send(request);
+            }
+        }
+    }
+}
+
+impl Drop for WaylandBackend {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::CaptureManager;
+
+    // The ignored tests need a compositor, e.g. `WLR_BACKENDS=headless sway &` or
+    // `weston --backend=headless --width=320 --height=240 &`, then `cargo test --features wayland -- --ignored wayland`.
+
+    /// Returns the words of a message, in native byte order.
+    fn words(buf: &[u8]) -> Vec<u32> {
+        buf.chunks_exact(4)
+            .map(|word| u32::from_ne_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    fn event(object: u32, opcode: u16, args: &[u32]) -> Vec<u8> {
+        let size = 8 + 4 * args.len() as u32;
+        [object, size << 16 | opcode as u32]
+            .iter()
+            .chain(args)
+            .flat_map(|word| word.to_ne_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn strings_are_padded_to_words() {
+        for (value, padded) in [
+            ("", 4),
+            ("abc", 4),
+            ("abcd", 8),
+            ("wl_shm", 8),
+            ("wl_output", 12),
+        ] {
+            let buf = Request::new(3, 0).string(value).buf;
+            assert_eq!(buf.len(), 8 + 4 + padded, "{value:?}");
+            assert_eq!(words(&buf)[2], value.len() as u32 + 1);
+            assert_eq!(&buf[12..12 + value.len()], value.as_bytes());
+            assert!(buf[12 + value.len()..].iter().all(|&byte| byte == 0));
+        }
+    }
+
+    #[test]
+    fn finish_writes_the_size_and_opcode() {
+        let msg = Request::new(7, 3).uint(9).int(-2).string("ab").finish();
+        assert_eq!(
+            words(&msg),
+            [
+                7,
+                24 << 16 | 3,
+                9,
+                -2i32 as u32,
+                3,
+                u32::from_ne_bytes(*b"ab\0\0")
+            ]
+        );
+        assert_eq!(words(&Request::new(1, 0).finish()), [1, 8 << 16]);
+    }
+
+    #[test]
+    fn event_arguments_are_parsed_in_order() {
+        let mut args = words(&Request::new(0, 0).uint(42).int(-5).string("HEADLESS-1").buf[8..]);
+        args.push(7);
+        let mut event = Event {
+            object: 4,
+            opcode: 0,
+            args: event(4, 0, &args)[8..].to_vec(),
+            pos: 0,
+        };
+        assert_eq!(event.uint(), 42);
+        assert_eq!(event.int(), -5);
+        assert_eq!(event.string(), "HEADLESS-1");
+        assert_eq!(event.uint(), 7);
+        // Missing arguments read as zero instead of panicking.
+        assert_eq!(event.uint(), 0);
+        assert_eq!(event.string(), "");
+    }
+
+    #[test]
+    fn truncated_strings_are_cut() {
+        let mut event = Event {
+            object: 4,
+            opcode: 0,
+            args: [&10u32.to_ne_bytes()[..], b"abc"].concat(),
+            pos: 0,
+        };
+        assert_eq!(event.string(), "abc");
+    }
+
+    #[test]
+    fn shm_formats() {
+        let pixel = [1, 2, 3, 4];
+        for (code, bpp, bgr) in [
+            (0, 4, [1, 2, 3]),
+            (1, 4, [1, 2, 3]),
+            (0x3432_4241, 4, [3, 2, 1]),
+            (0x3432_4258, 4, [3, 2, 1]),
+            (0x3432_4752, 3, [1, 2, 3]),
+            (0x3432_4742, 3, [3, 2, 1]),
+        ] {
+            let format = ShmFormat::from_code(code).unwrap();
+            assert_eq!(format.code(), code);
+            assert_eq!(format.bytes_per_pixel(), bpp);
+            assert_eq!(format.bgr(&pixel), bgr, "{format:?}");
+        }
+        // RGB565 and the 10-bit formats.
+        for code in [0x3631_4752, 0x3033_5241, 0x3033_5258] {
+            assert_eq!(ShmFormat::from_code(code), None);
+        }
+    }
+
+    #[test]
+    fn deleted_ids_are_reused() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut conn = Connection::from_stream(client).unwrap();
+        assert_eq!((conn.new_id(), conn.new_id(), conn.new_id()), (2, 3, 4));
+
+        let delete_id = |id| event(proto::DISPLAY, proto::DISPLAY_EV_DELETE_ID, &[id]);
+        server.write_all(&delete_id(3)).unwrap();
+        server.write_all(&delete_id(2)).unwrap();
+        server.write_all(&event(9, 1, &[])).unwrap();
+        let event = conn.read_event().unwrap();
+        assert_eq!((event.object, event.opcode), (9, 1));
+
+        assert_eq!((conn.new_id(), conn.new_id(), conn.new_id()), (2, 3, 5));
+    }
+
+    #[test]
+    fn display_errors_are_reported() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut conn = Connection::from_stream(client).unwrap();
+        let mut args = vec![5, 2];
+        args.extend(words(&Request::new(0, 0).string("bad size").buf[8..]));
+        let error = event(proto::DISPLAY, proto::DISPLAY_EV_ERROR, &args);
+        server.write_all(&error).unwrap();
+        let err = conn.read_event().err().unwrap();
+        assert!(
+            matches!(err, WaylandError::Protocol(message) if message == "object 5, code 2: bad size")
+        );
+
+        server.write_all(&[0; 8]).unwrap();
+        assert!(matches!(conn.read_event(), Err(WaylandError::Protocol(_))));
+    }
+
+    fn open(area: Rect) -> WaylandBackend {
+        WaylandBackend::open(WaylandTarget::new(), area).unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs a Wayland compositor"]
+    fn captures_first_output() {
+        let mut backend = open(Rect::new(0, 0, 64, 48));
+        assert!(backend.output_size().is_some());
+        let capture = backend.capture().unwrap();
+        assert_eq!((capture.width(), capture.height()), (64, 48));
+        assert_eq!(capture.get_bits().len(), 64 * 48 * 3);
+
+        assert!(backend.set_format(PixelFormat::Bgra32));
+        assert!(!backend.set_format(PixelFormat::Gray8));
+        let mut frame = Frame::new(1, 1, PixelFormat::Gray8);
+        backend.capture_into(&mut frame).unwrap();
+        assert_eq!(frame.format(), PixelFormat::Bgra32);
+        assert!(frame.bits().chunks_exact(4).all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    #[ignore = "needs a Wayland compositor"]
+    fn area_outside_the_output_is_black() {
+        let mut backend = open(Rect::new(-8, -8, 16, 16));
+        let capture = backend.capture().unwrap();
+        assert_eq!(capture.row(0), &[0; 16 * 3][..]);
+
+        let bounds = backend.bounds().unwrap();
+        backend
+            .resize(Rect::new(bounds.wh().0 - 4, 0, 8, 8))
+            .unwrap();
+        let capture = backend.capture().unwrap();
+        assert!(capture.rows().all(|row| row[12..] == [0; 12]));
+    }
+
+    #[test]
+    #[ignore = "needs a Wayland compositor"]
+    fn ids_are_recycled_between_captures() {
+        let backend = open(Rect::new(0, 0, 16, 16));
+        backend.capture().unwrap();
+        let next_id = backend.state.borrow().conn.next_id;
+        for _ in 0..50 {
+            backend.capture().unwrap();
+        }
+        // Every capture creates a frame and callbacks, which must not use up new ids.
+        assert!(backend.state.borrow().conn.next_id <= next_id + 4);
+    }
+
+    #[test]
+    #[ignore = "needs a Wayland compositor"]
+    fn missing_output_is_reported() {
+        let target = WaylandTarget::new().output(OutputSelector::Name("no such output".to_owned()));
+        let res = CaptureManager::<WaylandBackend>::open(target, Rect::new(0, 0, 8, 8));
+        assert!(matches!(res, Err(Error::Platform(_))));
+        let res = WaylandBackend::open(
+            WaylandTarget::new().output(OutputSelector::Index(99)),
+            Rect::new(0, 0, 8, 8),
+        );
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,802 @@
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_uint, c_ulong, c_void, CString};
+use std::fmt;
+use std::ptr;
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+
+use crate::backend::CaptureBackend;
+use crate::capture::CaptureData;
+use crate::error::Error;
+use crate::format::{PixelFormat, OPAQUE_BLACK};
+use crate::frame::Frame;
+use crate::rect::Rect;
+
+mod ffi {
+    use std::ffi::{c_char, c_int, c_long, c_uint, c_ulong, c_void};
+
+    pub type Display = c_void;
+    pub type Visual = c_void;
+    pub type Window = c_ulong;
+    pub type Bool = c_int;
+
+    pub const ZPIXMAP: c_int = 2;
+    pub const LSB_FIRST: c_int = 0;
+    pub const ALL_PLANES: c_ulong = !0;
+    pub const BAD_WINDOW: u8 = 3;
+    pub const BAD_DRAWABLE: u8 = 9;
+
+    pub const IPC_PRIVATE: c_int = 0;
+    pub const IPC_CREAT: c_int = 0o1000;
+    pub const IPC_RMID: c_int = 0;
+
+    #[repr(C)]
+    pub struct XErrorEvent {
+        pub kind: c_int,
+        pub display: *mut Display,
+        pub resourceid: c_ulong,
+        pub serial: c_ulong,
+        pub error_code: u8,
+        pub request_code: u8,
+        pub minor_code: u8,
+    }
+
+    #[repr(C)]
+    pub struct XWindowAttributes {
+        pub x: c_int,
+        pub y: c_int,
+        pub width: c_int,
+        pub height: c_int,
+        pub border_width: c_int,
+        pub depth: c_int,
+        pub visual: *mut Visual,
+        pub root: Window,
+        pub class: c_int,
+        pub bit_gravity: c_int,
+        pub win_gravity: c_int,
+        pub backing_store: c_int,
+        pub backing_planes: c_ulong,
+        pub backing_pixel: c_ulong,
+        pub save_under: Bool,
+        pub colormap: c_ulong,
+        pub map_installed: Bool,
+        pub map_state: c_int,
+        pub all_event_masks: c_long,
+        pub your_event_mask: c_long,
+        pub do_not_propagate_mask: c_long,
+        pub override_redirect: Bool,
+        pub screen: *mut c_void,
+    }
+
+    #[repr(C)]
+    pub struct ImageFns {
+        pub create_image: *mut c_void,
+        pub destroy_image: unsafe extern "C" fn(*mut XImage) -> c_int,
+        pub get_pixel: *mut c_void,
+        pub put_pixel: *mut c_void,
+        pub sub_image: *mut c_void,
+        pub add_pixel: *mut c_void,
+    }
+
+    #[repr(C)]
+    pub struct XImage {
+        pub width: c_int,
+        pub height: c_int,
+        pub xoffset: c_int,
+        pub format: c_int,
+        pub data: *mut c_char,
+        pub byte_order: c_int,
+        pub bitmap_unit: c_int,
+        pub bitmap_bit_order: c_int,
+        pub bitmap_pad: c_int,
+        pub depth: c_int,
+        pub bytes_per_line: c_int,
+        pub bits_per_pixel: c_int,
+        pub red_mask: c_ulong,
+        pub green_mask: c_ulong,
+        pub blue_mask: c_ulong,
+        pub obdata: *mut c_char,
+        pub f: ImageFns,
+    }
+
+    #[repr(C)]
+    pub struct XShmSegmentInfo {
+        pub shmseg: c_ulong,
+        pub shmid: c_int,
+        pub shmaddr: *mut c_char,
+        pub read_only: Bool,
+    }
+
+    pub type XErrorHandler = Option<unsafe extern "C" fn(*mut Display, *mut XErrorEvent) -> c_int>;
+
+    #[link(name = "X11")]
+    extern "C" {
+        pub fn XOpenDisplay(name: *const c_char) -> *mut Display;
+        pub fn XCloseDisplay(display: *mut Display) -> c_int;
+        pub fn XDefaultRootWindow(display: *mut Display) -> Window;
+        pub fn XGetWindowAttributes(
+            display: *mut Display,
+            window: Window,
+            attributes: *mut XWindowAttributes,
+        ) -> c_int;
+        pub fn XGetImage(
+            display: *mut Display,
+            drawable: Window,
+            x: c_int,
+            y: c_int,
+            width: c_uint,
+            height: c_uint,
+            plane_mask: c_ulong,
+            format: c_int,
+        ) -> *mut XImage;
+        pub fn XSync(display: *mut Display, discard: Bool) -> c_int;
+        pub fn XSetErrorHandler(handler: XErrorHandler) -> XErrorHandler;
+        pub fn XNextRequest(display: *mut Display) -> c_ulong;
+    }
+
+    #[link(name = "Xext")]
+    extern "C" {
+        pub fn XShmQueryExtension(display: *mut Display) -> Bool;
+        pub fn XShmCreateImage(
+            display: *mut Display,
+            visual: *mut Visual,
+            depth: c_uint,
+            format: c_int,
+            data: *mut c_char,
+            info: *mut XShmSegmentInfo,
+            width: c_uint,
+            height: c_uint,
+        ) -> *mut XImage;
+        pub fn XShmAttach(display: *mut Display, info: *mut XShmSegmentInfo) -> Bool;
+        pub fn XShmDetach(display: *mut Display, info: *mut XShmSegmentInfo) -> Bool;
+        pub fn XShmGetImage(
+            display: *mut Display,
+            drawable: Window,
+            image: *mut XImage,
+            x: c_int,
+            y: c_int,
+            plane_mask: c_ulong,
+        ) -> Bool;
+    }
+
+    extern "C" {
+        pub fn shmget(key: c_int, size: usize, flags: c_int) -> c_int;
+        pub fn shmat(id: c_int, addr: *const c_void, flags: c_int) -> *mut c_void;
+        pub fn shmdt(addr: *const c_void) -> c_int;
+        pub fn shmctl(id: c_int, cmd: c_int, buf: *mut c_void) -> c_int;
+    }
+}
+
+/// The displays opened by [`X11Backend`]s, with the errors trapped on each of them.
+static DISPLAYS: Mutex<Vec<DisplayErrors>> = Mutex::new(Vec::new());
+/// The error handler that was installed before ours, errors on other displays are passed on to it.
+static PREVIOUS_HANDLER: OnceLock<ffi::XErrorHandler> = OnceLock::new();
+
+struct DisplayErrors {
+    display: usize,
+    /// The serial of the first request of the active [`ErrorTrap`], if any.
+    trap_from: Option<c_ulong>,
+    /// The code of the first error caused by a request of the active trap.
+    error: u8,
+}
+
+fn displays() -> MutexGuard<'static, Vec<DisplayErrors>> {
+    DISPLAYS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+unsafe extern "C" fn error_handler(
+    display: *mut ffi::Display,
+    event: *mut ffi::XErrorEvent,
+) -> c_int {
+    let event = &*event;
+    if let Some(entry) = displays()
+        .iter_mut()
+        .find(|entry| entry.display == display as usize)
+    {
+        // Errors on our own connections never reach the application, those outside a trap are ignored.
+        if entry.trap_from.is_some_and(|from| event.serial >= from) && entry.error == 0 {
+            entry.error = event.error_code;
+        }
+        return 0;
+    }
+    match PREVIOUS_HANDLER.get().copied().flatten() {
+        Some(previous) => previous(display, event as *const _ as *mut _),
+        None => 0,
+    }
+}
+
+/// Routes the errors of `display` to the traps of the backend instead of the default Xlib error handler, which
+/// terminates the process.
+///
+/// Our handler is installed once per process. It passes errors on other displays on to the handler it replaced.
+fn register_display(display: *mut ffi::Display) {
+    PREVIOUS_HANDLER.get_or_init(|| unsafe { ffi::XSetErrorHandler(Some(error_handler)) });
+    displays().push(DisplayErrors {
+        display: display as usize,
+        trap_from: None,
+        error: 0,
+    });
+}
+
+/// Stops routing the errors of a closed display.
+///
+/// Another thread may already have opened a display at the same address, so only the oldest entry is removed.
+fn unregister_display(display: *mut ffi::Display) {
+    let mut displays = displays();
+    if let Some(index) = displays
+        .iter()
+        .position(|entry| entry.display == display as usize)
+    {
+        displays.remove(index);
+    }
+}
+
+/// Collects the first error caused by the requests made on a display while the trap is alive.
+struct ErrorTrap {
+    display: *mut ffi::Display,
+}
+
+impl ErrorTrap {
+    fn new(display: *mut ffi::Display) -> ErrorTrap {
+        let from = unsafe { ffi::XNextRequest(display) };
+        if let Some(entry) = displays()
+            .iter_mut()
+            .find(|entry| entry.display == display as usize)
+        {
+            entry.trap_from = Some(from);
+            entry.error = 0;
+        }
+        ErrorTrap { display }
+    }
+
+    /// Waits for the X server to process the requests made so far and returns the first error code, or 0.
+    fn sync(&self) -> u8 {
+        unsafe { ffi::XSync(self.display, 0) };
+        self.error()
+    }
+
+    /// Returns the first error code received so far, or 0.
+    fn error(&self) -> u8 {
+        displays()
+            .iter()
+            .find(|entry| entry.display == self.display as usize)
+            .map_or(0, |entry| entry.error)
+    }
+}
+
+impl Drop for ErrorTrap {
+    fn drop(&mut self) {
+        if let Some(entry) = displays()
+            .iter_mut()
+            .find(|entry| entry.display == self.display as usize)
+        {
+            entry.trap_from = None;
+        }
+    }
+}
+
+/// Describes the X11 display and window that should be captured.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct X11Target {
+    display: Option<String>,
+    window: Option<u64>,
+}
+
+impl X11Target {
+    /// Targets the root window of the display named by the `DISPLAY` environment variable.
+    pub fn root() -> X11Target {
+        X11Target::default()
+    }
+
+    /// Targets the window with the given XID.
+    pub fn window(xid: u64) -> X11Target {
+        X11Target {
+            display: None,
+            window: Some(xid),
+        }
+    }
+
+    /// Connects to the given display (e.g. `":1"`) instead of the one named by the `DISPLAY` environment variable.
+    pub fn display(mut self, name: impl Into<String>) -> X11Target {
+        self.display = Some(name.into());
+        self
+    }
+}
+
+/// The cause of the errors returned by the [`X11Backend`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum X11Error {
+    /// The connection to the X server could not be established.
+    OpenDisplay,
+    /// The targeted window does not exist (anymore).
+    BadWindow,
+    /// The X server refused to return the image, the error code is included if known.
+    GetImage(u8),
+    /// The image returned by the X server uses a pixel layout that is not supported.
+    UnsupportedVisual,
+}
+
+impl fmt::Display for X11Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            X11Error::OpenDisplay => write!(f, "failed to open the X display"),
+            X11Error::BadWindow => write!(f, "the targeted window does not exist"),
+            X11Error::GetImage(code) => write!(f, "failed to get the image (X error {code})"),
+            X11Error::UnsupportedVisual => write!(f, "unsupported pixel layout"),
+        }
+    }
+}
+
+impl std::error::Error for X11Error {}
+
+impl From<X11Error> for Error {
+    fn from(err: X11Error) -> Error {
+        match err {
+            X11Error::OpenDisplay => Error::unavailable(err),
+            X11Error::BadWindow => Error::WindowGone,
+            X11Error::UnsupportedVisual => Error::UnsupportedFormat(err.to_string()),
+            X11Error::GetImage(_) => Error::platform(err),
+        }
+    }
+}
+
+/// An XImage living in a shared memory segment attached to the X server.
+struct ShmImage {
+    image: *mut ffi::XImage,
+    info: Box<ffi::XShmSegmentInfo>,
+}
+
+/// A capture backend for X11 displays.
+///
+/// It uses the MIT-SHM extension when the X server supports it and falls back to plain `XGetImage` otherwise.
+/// Both [`PixelFormat::Bgr24`] and [`PixelFormat::Bgra32`] are produced natively.
+/// Parts of the captured area lying outside of the target window are black.
+///
+/// Xlib reports errors through a single handler per process. The first backend installs one that keeps the errors
+/// of its connections to itself and passes the others on to the handler installed before it.
+///
+/// # Examples
+///
+/// ```no_run
+/// use qshot::{CaptureManager, Rect, X11Backend, X11Target};
+///
+/// let manager = CaptureManager::<X11Backend>::open(X11Target::root(), Rect::new(0, 0, 640, 480)).unwrap();
+/// let res = manager.capture().unwrap();
+/// assert_eq!(res.get_bits().len(), 640 * 480 * 3);
+/// ```
+pub struct X11Backend {
+    display: *mut ffi::Display,
+    window: ffi::Window,
+    top_left: (i32, i32),
+    wh: (i32, i32),
+    format: PixelFormat,
+    /// The part of the captured area that lies within the window, relative to the window.
+    visible: (i32, i32, i32, i32),
+    /// The size of the window when the captured area was last configured.
+    window_size: (i32, i32),
+    depth: c_int,
+    visual: *mut ffi::Visual,
+    shm_available: bool,
+    shm: RefCell<Option<ShmImage>>,
+    /// The shared memory image of the previous size, so that switching back and forth between two sizes doesn't
+    /// reallocate, e.g. for [`CaptureManager::capture_regions`](crate::CaptureManager::capture_regions).
+    spare: Option<ShmImage>,
+}
+
+impl X11Backend {
+    /// Returns `true` if captures go through the MIT-SHM extension.
+    pub fn uses_shm(&self) -> bool {
+        self.shm.borrow().is_some()
+    }
+
+    /// Queries the window geometry and recreates the shared memory image to match the captured area, unless the image
+    /// of the previous size fits.
+    fn configure(&mut self) -> Result<(), X11Error> {
+        let previous = self.shm.get_mut().take();
+        let spare = std::mem::replace(&mut self.spare, previous);
+
+        let mut attributes = std::mem::MaybeUninit::<ffi::XWindowAttributes>::uninit();
+        let status = unsafe {
+            ffi::XGetWindowAttributes(self.display, self.window, attributes.as_mut_ptr())
+        };
+        if status == 0 {
+            if let Some(shm) = spare {
+                unsafe { self.free_shm(shm) };
+            }
+            return Err(X11Error::BadWindow);
+        }
+        let attributes = unsafe { attributes.assume_init() };
+        self.depth = attributes.depth;
+        self.visual = attributes.visual;
+        self.window_size = (attributes.width, attributes.height);
+
+        let left = self.top_left.0.max(0);
+        let top = self.top_left.1.max(0);
+        let right = (self.top_left.0 + self.wh.0).min(attributes.width);
+        let bottom = (self.top_left.1 + self.wh.1).min(attributes.height);
+        self.visible = (left, top, (right - left).max(0), (bottom - top).max(0));
+
+        let size = (self.visible.2, self.visible.3);
+        match spare {
+            Some(shm) if unsafe { ((*shm.image).width, (*shm.image).height) } == size => {
+                *self.shm.get_mut() = Some(shm);
+            }
+            spare => {
+                if let Some(shm) = spare {
+                    unsafe { self.free_shm(shm) };
+                }
+                if self.shm_available && size.0 > 0 && size.1 > 0 {
+                    // Falling back to XGetImage is always possible, so a failure here is not an error.
+                    *self.shm.get_mut() = unsafe { self.create_shm() };
+                }
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn create_shm(&self) -> Option<ShmImage> {
+        let mut info = Box::new(ffi::XShmSegmentInfo {
+            shmseg: 0,
+            shmid: -1,
+            shmaddr: ptr::null_mut(),
+            read_only: 0,
+        });
+        let image = ffi::XShmCreateImage(
+            self.display,
+            self.visual,
+            self.depth as c_uint,
+            ffi::ZPIXMAP,
+            ptr::null_mut(),
+            &mut *info,
+            self.visible.2 as c_uint,
+            self.visible.3 as c_uint,
+        );
+        if image.is_null() {
+            return None;
+        }
+        let size = (*image).bytes_per_line as usize * (*image).height as usize;
+        info.shmid = ffi::shmget(ffi::IPC_PRIVATE, size, ffi::IPC_CREAT | 0o600);
+        if info.shmid < 0 {
+            ((*image).f.destroy_image)(image);
+            return None;
+        }
+        info.shmaddr = ffi::shmat(info.shmid, ptr::null(), 0) as *mut c_char;
+        if info.shmaddr as isize == -1 {
+            ffi::shmctl(info.shmid, ffi::IPC_RMID, ptr::null_mut());
+            ((*image).f.destroy_image)(image);
+            return None;
+        }
+        (*image).data = info.shmaddr;
+
+        let trap = ErrorTrap::new(self.display);
+        let attached = ffi::XShmAttach(self.display, &mut *info) != 0;
+        let error = trap.sync();
+        // The segment goes away as soon as both we and the X server detach from it.
+        ffi::shmctl(info.shmid, ffi::IPC_RMID, ptr::null_mut());
+        if !attached || error != 0 {
+            ffi::shmdt(info.shmaddr as *const c_void);
+            (*image).data = ptr::null_mut();
+            ((*image).f.destroy_image)(image);
+            return None;
+        }
+        Some(ShmImage { image, info })
+    }
+
+    unsafe fn free_shm(&self, mut shm: ShmImage) {
+        ffi::XShmDetach(self.display, &mut *shm.info);
+        ffi::XSync(self.display, 0);
+        ffi::shmdt(shm.info.shmaddr as *const c_void);
+        (*shm.image).data = ptr::null_mut();
+        ((*shm.image).f.destroy_image)(shm.image);
+    }
+
+    fn destroy_shm(&mut self) {
+        for shm in [self.shm.get_mut().take(), self.spare.take()]
+            .into_iter()
+            .flatten()
+        {
+            unsafe { self.free_shm(shm) };
+        }
+    }
+
+    fn grab(&self, bits: &mut [u8]) -> Result<(), X11Error> {
+        let (x, y, w, h) = self.visible;
+        if w == 0 || h == 0 {
+            return Ok(());
+        }
+        let dst_x = (x - self.top_left.0) as usize;
+        let dst_y = (y - self.top_left.1) as usize;
+
+        let trap = ErrorTrap::new(self.display);
+        if let Some(shm) = &*self.shm.borrow() {
+            let ok = unsafe {
+                ffi::XShmGetImage(self.display, self.window, shm.image, x, y, ffi::ALL_PLANES)
+            };
+            if ok != 0 {
+                return unsafe { self.copy_image(&*shm.image, bits, dst_x, dst_y) };
+            }
+        }
+
+        let image = unsafe {
+            ffi::XGetImage(
+                self.display,
+                self.window,
+                x,
+                y,
+                w as c_uint,
+                h as c_uint,
+                ffi::ALL_PLANES,
+                ffi::ZPIXMAP,
+            )
+        };
+        if image.is_null() {
+            return match trap.sync() {
+                ffi::BAD_WINDOW | ffi::BAD_DRAWABLE => Err(X11Error::BadWindow),
+                code => Err(X11Error::GetImage(code)),
+            };
+        }
+        let res = unsafe { self.copy_image(&*image, bits, dst_x, dst_y) };
+        unsafe { ((*image).f.destroy_image)(image) };
+        res
+    }
+
+    /// Copies an XImage into the \[B, G, R] output buffer at the given offset.
+    unsafe fn copy_image(
+        &self,
+        image: &ffi::XImage,
+        bits: &mut [u8],
+        dst_x: usize,
+        dst_y: usize,
+    ) -> Result<(), X11Error> {
+        let out_bpp = self.format.bytes_per_pixel();
+        let out_stride = self.wh.0 as usize * out_bpp;
+        let width = image.width as usize;
+        let bpp = image.bits_per_pixel as usize;
+        if !matches!(bpp, 16 | 24 | 32) {
+            return Err(X11Error::UnsupportedVisual);
+        }
+        let masks = [image.blue_mask, image.green_mask, image.red_mask].map(Mask::new);
+        let fast = bpp == 32
+            && image.byte_order == ffi::LSB_FIRST
+            && image.red_mask == 0xff0000
+            && image.green_mask == 0xff00
+            && image.blue_mask == 0xff;
+
+        for row in 0..image.height as usize {
+            let src = std::slice::from_raw_parts(
+                (image.data as *const u8).add(row * image.bytes_per_line as usize),
+                width * bpp / 8,
+            );
+            let start = (dst_y + row) * out_stride + dst_x * out_bpp;
+            let dst = &mut bits[start..start + width * out_bpp];
+            if fast {
+                for (d, s) in dst.chunks_exact_mut(out_bpp).zip(src.chunks_exact(4)) {
+                    d[..3].copy_from_slice(&s[..3]);
+                }
+                continue;
+            }
+            for (d, s) in dst.chunks_exact_mut(out_bpp).zip(src.chunks_exact(bpp / 8)) {
+                let mut pixel: c_ulong = 0;
+                for (i, &b) in s.iter().enumerate() {
+                    pixel |= if image.byte_order == ffi::LSB_FIRST {
+                        (b as c_ulong) << (8 * i)
+                    } else {
+                        (b as c_ulong) << (8 * (s.len() - 1 - i))
+                    };
+                }
+                for (c, mask) in masks.iter().enumerate() {
+                    d[c] = mask.extract(pixel);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A channel mask of an XImage.
+#[derive(Clone, Copy)]
+struct Mask {
+    mask: c_ulong,
+    shift: u32,
+    bits: u32,
+}
+
+impl Mask {
+    fn new(mask: c_ulong) -> Mask {
+        Mask {
+            mask,
+            shift: mask.trailing_zeros().min(c_ulong::BITS - 1),
+            bits: mask.count_ones(),
+        }
+    }
+
+    /// Extracts the channel from a pixel value and scales it to 8 bits.
+    fn extract(self, pixel: c_ulong) -> u8 {
+        if self.bits == 0 {
+            return 0;
+        }
+        let value = (pixel & self.mask) >> self.shift;
+        if self.bits >= 8 {
+            (value >> (self.bits - 8)) as u8
+        } else {
+            (value * 255 / ((1 << self.bits) - 1)) as u8
+        }
+    }
+}
+
+impl CaptureBackend for X11Backend {
+    type Target = X11Target;
+    fn open(target: X11Target, area: Rect) -> Result<Self, Error> {
+        let (top_left, wh) = (area.top_left(), area.wh());
+        let name = match target.display {
+            Some(name) => Some(CString::new(name).map_err(|_| X11Error::OpenDisplay)?),
+            None => None,
+        };
+        let display =
+            unsafe { ffi::XOpenDisplay(name.as_ref().map_or(ptr::null(), |name| name.as_ptr())) };
+        if display.is_null() {
+            return Err(X11Error::OpenDisplay.into());
+        }
+        register_display(display);
+        let window = match target.window {
+            Some(xid) => xid as ffi::Window,
+            None => unsafe { ffi::XDefaultRootWindow(display) },
+        };
+
+        let mut backend = X11Backend {
+            display,
+            window,
+            top_left,
+            wh,
+            format: PixelFormat::Bgr24,
+            visible: (0, 0, 0, 0),
+            window_size: (0, 0),
+            depth: 0,
+            visual: ptr::null_mut(),
+            shm_available: unsafe { ffi::XShmQueryExtension(display) } != 0,
+            shm: RefCell::new(None),
+            spare: None,
+        };
+        backend.configure()?;
+        Ok(backend)
+    }
+
+    fn capture(&self) -> Result<CaptureData, Error> {
+        let (width, height) = (self.wh.0.max(0) as usize, self.wh.1.max(0) as usize);
+        let mut bits = OPAQUE_BLACK[..self.format.bytes_per_pixel()].repeat(width * height);
+        self.grab(&mut bits)?;
+        Ok(CaptureData::from_vec(bits, width, height, self.format))
+    }
+
+    fn capture_into(&self, frame: &mut Frame) -> Result<(), Error> {
+        let (width, height) = (self.wh.0.max(0) as usize, self.wh.1.max(0) as usize);
+        frame.reset(width, height, self.format);
+        self.grab(frame.bits_mut()).map_err(Error::from)
+    }
+
+    fn resize(&mut self, area: Rect) -> Result<(), Error> {
+        let (top_left, wh) = (area.top_left(), area.wh());
+        self.top_left = top_left;
+        self.wh = wh;
+        self.configure().map_err(Error::from)
+    }
+
+    fn area(&self) -> Rect {
+        Rect::from_parts(self.top_left, self.wh)
+    }
+
+    fn bounds(&self) -> Option<Rect> {
+        Some(Rect::from_parts((0, 0), self.window_size))
+    }
+
+    fn set_format(&mut self, format: PixelFormat) -> bool {
+        if matches!(format, PixelFormat::Bgr24 | PixelFormat::Bgra32) {
+            self.format = format;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn close(&mut self) {
+        if !self.display.is_null() {
+            self.destroy_shm();
+            unsafe { ffi::XCloseDisplay(self.display) };
+            unregister_display(self.display);
+            self.display = ptr::null_mut();
+        }
+    }
+}
+
+impl Drop for X11Backend {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Once;
+
+    // These tests need an X server, e.g. `Xvfb :99 -screen 0 320x240x24 & DISPLAY=:99 cargo test --features x11 --
+    // --ignored x11`.
+
+    static FOREIGN_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn foreign_handler(_: *mut ffi::Display, _: *mut ffi::XErrorEvent) -> c_int {
+        FOREIGN_ERRORS.fetch_add(1, Ordering::SeqCst);
+        0
+    }
+
+    /// Installs an application error handler before any backend replaces it.
+    fn setup() {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| unsafe {
+            ffi::XSetErrorHandler(Some(foreign_handler));
+        });
+    }
+
+    fn open(area: Rect) -> X11Backend {
+        setup();
+        X11Backend::open(X11Target::root(), area).unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs an X server"]
+    fn captures_root_window() {
+        let mut backend = open(Rect::new(0, 0, 64, 48));
+        let capture = backend.capture().unwrap();
+        assert_eq!((capture.width(), capture.height()), (64, 48));
+        assert_eq!(capture.get_bits().len(), 64 * 48 * 3);
+
+        assert!(backend.set_format(PixelFormat::Bgra32));
+        let mut frame = Frame::new(1, 1, PixelFormat::Gray8);
+        backend.capture_into(&mut frame).unwrap();
+        assert_eq!(frame.format(), PixelFormat::Bgra32);
+        assert!(frame.bits().chunks_exact(4).all(|pixel| pixel[3] == 255));
+    }
+
+    #[test]
+    #[ignore = "needs an X server"]
+    fn area_outside_the_window_is_black() {
+        let mut backend = open(Rect::new(-8, -8, 16, 16));
+        let capture = backend.capture().unwrap();
+        assert_eq!(capture.row(0), &[0; 16 * 3][..]);
+
+        let bounds = backend.bounds().unwrap();
+        backend
+            .resize(Rect::new(bounds.wh().0 - 4, 0, 8, 8))
+            .unwrap();
+        let capture = backend.capture().unwrap();
+        assert!(capture.rows().all(|row| row[12..] == [0; 12]));
+    }
+
+    #[test]
+    #[ignore = "needs an X server"]
+    fn missing_window_is_reported() {
+        setup();
+        let res = X11Backend::open(X11Target::window(0x7ff_fff0), Rect::new(0, 0, 8, 8));
+        assert!(matches!(res, Err(Error::WindowGone)));
+    }
+
+    #[test]
+    #[ignore = "needs an X server"]
+    fn errors_on_other_displays_reach_their_handler() {
+        let backend = open(Rect::new(0, 0, 8, 8));
+        let foreign = unsafe { ffi::XOpenDisplay(ptr::null()) };
+        assert!(!foreign.is_null());
+
+        let before = FOREIGN_ERRORS.load(Ordering::SeqCst);
+        let trap = ErrorTrap::new(backend.display);
+        let mut attributes = std::mem::MaybeUninit::<ffi::XWindowAttributes>::uninit();
+        unsafe { ffi::XGetWindowAttributes(foreign, 0x7ff_fff0, attributes.as_mut_ptr()) };
+        // The error belongs to the application's connection, not to the backend.
+        assert_eq!(trap.sync(), 0);
+        assert_eq!(FOREIGN_ERRORS.load(Ordering::SeqCst), before + 1);
+        drop(trap);
+
+        backend.capture().unwrap();
+        unsafe { ffi::XCloseDisplay(foreign) };
+    }
+}
@@ -0,0 +1,415 @@
+//! Conversions into the YUV 4:2:0 layouts video encoders expect.
+
+use crate::view::ImageView;
+
+/// The matrix used to derive the luma and chroma components from RGB.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ColorMatrix {
+    /// ITU-R BT.601, the usual choice for SD content.
+    #[default]
+    Bt601,
+    /// ITU-R BT.709, the usual choice for HD content.
+    Bt709,
+}
+
+/// The range of values the components are mapped to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Range {
+    /// Luma in 16..=235 and chroma in 16..=240, which most encoders assume.
+    #[default]
+    Limited,
+    /// All components use 0..=255.
+    Full,
+}
+
+/// The horizontal position of a chroma sample relative to the two luma samples it covers.
+///
+/// Vertically, chroma samples always sit between the two rows they cover.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ChromaSiting {
+    /// Co-sited with the left luma sample, as in MPEG-2, H.264 and HEVC.
+    #[default]
+    Left,
+    /// Centered between both luma samples, as in JPEG and MPEG-1.
+    Center,
+}
+
+/// A planar 4:2:0 image: a full resolution Y plane followed by quarter resolution U and V planes.
+///
+/// The planes are tightly packed, chroma planes are `(width + 1) / 2` by `(height + 1) / 2` samples.
+/// The buffers are kept when the image is converted into again, so reusing an `I420` avoids allocations.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct I420 {
+    width: usize,
+    height: usize,
+    y: Vec<u8>,
+    u: Vec<u8>,
+    v: Vec<u8>,
+}
+
+impl I420 {
+    /// Creates an empty image, its buffers are allocated by the first conversion.
+    pub fn new() -> I420 {
+        I420::default()
+    }
+
+    /// Returns the width of the image in pixels, which is also the length of a row of the luma plane.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of the image in pixels, the chroma planes have half as many rows, rounded up.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the luma plane, `width` bytes per row.
+    pub fn y(&self) -> &[u8] {
+        &self.y
+    }
+
+    /// Returns the blue difference plane, `(width + 1) / 2` bytes per row.
+    pub fn u(&self) -> &[u8] {
+        &self.u
+    }
+
+    /// Returns the red difference plane, `(width + 1) / 2` bytes per row.
+    pub fn v(&self) -> &[u8] {
+        &self.v
+    }
+
+    /// Returns all three planes as one contiguous buffer, which is how most encoders take them.
+    pub fn to_vec(&self) -> Vec<u8> {
+        [&self.y[..], &self.u, &self.v].concat()
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        let (cw, ch) = chroma_size(width, height);
+        self.width = width;
+        self.height = height;
+        self.y.resize(width * height, 0);
+        self.u.resize(cw * ch, 0);
+        self.v.resize(cw * ch, 0);
+    }
+}
+
+/// A semi-planar 4:2:0 image: a full resolution Y plane followed by a plane of interleaved U and V samples.
+///
+/// The planes are tightly packed, the UV plane has `(height + 1) / 2` rows of `(width + 1) / 2` sample pairs.
+/// The buffers are kept when the image is converted into again, so reusing an `Nv12` avoids allocations.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Nv12 {
+    width: usize,
+    height: usize,
+    y: Vec<u8>,
+    uv: Vec<u8>,
+}
+
+impl Nv12 {
+    /// Creates an empty image, its buffers are allocated by the first conversion.
+    pub fn new() -> Nv12 {
+        Nv12::default()
+    }
+
+    /// Returns the width of the image in pixels, which is also the length of a row of the luma plane.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the height of the image in pixels, the chroma plane has half as many rows, rounded up.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the luma plane, `width` bytes per row.
+    pub fn y(&self) -> &[u8] {
+        &self.y
+    }
+
+    /// Returns the interleaved chroma plane, `(width + 1) / 2 * 2` bytes per row.
+    pub fn uv(&self) -> &[u8] {
+        &self.uv
+    }
+
+    /// Returns both planes as one contiguous buffer, which is how most encoders take them.
+    pub fn to_vec(&self) -> Vec<u8> {
+        [&self.y[..], &self.uv].concat()
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        let (cw, ch) = chroma_size(width, height);
+        self.width = width;
+        self.height = height;
+        self.y.resize(width * height, 0);
+        self.uv.resize(cw * 2 * ch, 0);
+    }
+}
+
+fn chroma_size(width: usize, height: usize) -> (usize, usize) {
+    (width.div_ceil(2), height.div_ceil(2))
+}
+
+/// Converts images, e.g. captures, into [`I420`] or [`Nv12`] images.
+///
+/// By default BT.601 with limited range and left chroma siting is used.
+///
+/// # Examples
+///
+/// ```
+/// use qshot::yuv::{ColorMatrix, I420, Range, YuvConverter};
+/// use qshot::{CaptureData, PixelFormat};
+///
+/// // A white 3x3 capture, the chroma planes round up to 2x2.
+/// let data = CaptureData::from_vec(vec![255; 3 * 3 * 3], 3, 3, PixelFormat::Bgr24);
+///
+/// let mut image = I420::new();
+/// YuvConverter::new().convert_i420(&data, &mut image);
+/// assert_eq!(image.y(), &[235; 9]);
+/// assert_eq!((image.u(), image.v()), (&[128; 4][..], &[128; 4][..]));
+///
+/// let converter = YuvConverter::new().matrix(ColorMatrix::Bt709).range(Range::Full);
+/// converter.convert_i420(&data, &mut image);
+/// assert_eq!(image.y(), &[255; 9]);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct YuvConverter {
+    matrix: ColorMatrix,
+    range: Range,
+    siting: ChromaSiting,
+}
+
+/// Fixed point coefficients with 16 fractional bits.
+struct Coefficients {
+    y: [i32; 3],
+    u: [i32; 3],
+    v: [i32; 3],
+    y_offset: i32,
+}
+
+impl YuvConverter {
+    /// Creates a converter using BT.601 coefficients, limited range and chroma samples co-sited on the left.
+    pub fn new() -> YuvConverter {
+        YuvConverter::default()
+    }
+
+    /// Sets the matrix used to compute luma and chroma from RGB.
+    pub fn matrix(mut self, matrix: ColorMatrix) -> YuvConverter {
+        self.matrix = matrix;
+        self
+    }
+
+    /// Sets whether the samples use the full 0 to 255 range or the limited (video) range.
+    pub fn range(mut self, range: Range) -> YuvConverter {
+        self.range = range;
+        self
+    }
+
+    /// Sets where the chroma samples are located relative to the luma samples they cover.
+    pub fn siting(mut self, siting: ChromaSiting) -> YuvConverter {
+        self.siting = siting;
+        self
+    }
+
+    fn coefficients(&self) -> Coefficients {
+        let (kr, kb) = match self.matrix {
+            ColorMatrix::Bt601 => (0.299, 0.114),
+            ColorMatrix::Bt709 => (0.2126, 0.0722),
+        };
+        let (y_scale, c_scale, y_offset) = match self.range {
+            Range::Full => (1.0, 1.0, 0),
+            Range::Limited => (219.0 / 255.0, 224.0 / 255.0, 16),
+        };
+        let fixed = |c: f64| (c * 65536.0).round() as i32;
+
+        // The green coefficients are derived from the others, so that grays map exactly to neutral chroma.
+        let (yr, yb) = (fixed(kr * y_scale), fixed(kb * y_scale));
+        let (ur, ub) = (
+            fixed(-kr / (2.0 * (1.0 - kb)) * c_scale),
+            fixed(0.5 * c_scale),
+        );
+        let (vr, vb) = (
+            fixed(0.5 * c_scale),
+            fixed(-kb / (2.0 * (1.0 - kr)) * c_scale),
+        );
+        Coefficients {
+            y: [yr, fixed(y_scale) - yr - yb, yb],
+            u: [ur, -ur - ub, ub],
+            v: [vr, -vr - vb, vb],
+            y_offset,
+        }
+    }
+
+    /// Converts an image into an [`I420`] image, reusing its buffers.
+    pub fn convert_i420<'a>(&self, image: impl Into<ImageView<'a>>, out: &mut I420) {
+        let image = image.into();
+        out.resize(image.width(), image.height());
+        let I420 { y, u, v, .. } = out;
+        self.convert(image, y, |i, cb, cr| {
+            u[i] = cb;
+            v[i] = cr;
+        });
+    }
+
+    /// Converts an image into an [`Nv12`] image, reusing its buffers.
+    pub fn convert_nv12<'a>(&self, image: impl Into<ImageView<'a>>, out: &mut Nv12) {
+        let image = image.into();
+        out.resize(image.width(), image.height());
+        let Nv12 { y, uv, .. } = out;
+        self.convert(image, y, |i, cb, cr| {
+            uv[i * 2] = cb;
+            uv[i * 2 + 1] = cr;
+        });
+    }
+
+    /// Converts an image into a new [`I420`] image.
+    pub fn i420<'a>(&self, image: impl Into<ImageView<'a>>) -> I420 {
+        let mut out = I420::new();
+        self.convert_i420(image, &mut out);
+        out
+    }
+
+    /// Converts an image into a new [`Nv12`] image.
+    pub fn nv12<'a>(&self, image: impl Into<ImageView<'a>>) -> Nv12 {
+        let mut out = Nv12::new();
+        self.convert_nv12(image, &mut out);
+        out
+    }
+
+    /// Writes the luma plane into `luma` and hands every chroma sample, by index, to `chroma`.
+    fn convert(&self, image: ImageView, luma: &mut [u8], mut chroma: impl FnMut(usize, u8, u8)) {
+        let (width, height) = (image.width(), image.height());
+        if width == 0 || height == 0 {
+            return;
+        }
+        let k = self.coefficients();
+        let (bpp, [r, g, b]) = (
+            image.format().bytes_per_pixel(),
+            image.format().rgb_offsets(),
+        );
+        let rgb = |row: &[u8], x: usize| {
+            let p = &row[x * bpp..];
+            [p[r] as i32, p[g] as i32, p[b] as i32]
+        };
+        let dot = |c: &[i32; 3], p: [i32; 3]| c[0] * p[0] + c[1] * p[1] + c[2] * p[2];
+
+        for (y, row) in image.rows().enumerate() {
+            for (x, out) in luma[y * width..(y + 1) * width].iter_mut().enumerate() {
+                let value = (dot(&k.y, rgb(row, x)) + (k.y_offset << 16) + (1 << 15)) >> 16;
+                *out = value.clamp(0, 255) as u8;
+            }
+        }
+
+        // Every chroma sample is the average of 8 weighted samples, 2 rows of either [1, 2, 1] or [2, 2].
+        // Samples beyond the edges of odd sized captures are replaced by the nearest ones.
+        let (cw, ch) = chroma_size(width, height);
+        let taps: &[(isize, i32)] = match self.siting {
+            ChromaSiting::Left => &[(-1, 1), (0, 2), (1, 1)],
+            ChromaSiting::Center => &[(0, 2), (1, 2)],
+        };
+        for cy in 0..ch {
+            let rows = [image.row(cy * 2), image.row((cy * 2 + 1).min(height - 1))];
+            for cx in 0..cw {
+                let mut sum = [0; 3];
+                for row in rows {
+                    for &(dx, weight) in taps {
+                        let x = (cx as isize * 2 + dx).clamp(0, width as isize - 1) as usize;
+                        let p = rgb(row, x);
+                        for c in 0..3 {
+                            sum[c] += p[c] * weight;
+                        }
+                    }
+                }
+                let round = (128 << 19) + (1 << 18);
+                let cb = ((dot(&k.u, sum) + round) >> 19).clamp(0, 255) as u8;
+                let cr = ((dot(&k.v, sum) + round) >> 19).clamp(0, 255) as u8;
+                chroma(cy * cw + cx, cb, cr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::PixelFormat;
+    use crate::frame::{fixtures, Frame};
+
+    /// A 3x3 image without green, whose blue changes by column and whose red changes by row, in rows padded by 5
+    /// bytes.
+    fn image(format: PixelFormat) -> Frame {
+        fixtures::padded(3, 3, 5, format, |x, y| {
+            let (blue, red) = ([0, 48, 200][x], [20, 100, 250][y]);
+            match format {
+                PixelFormat::Rgb24 | PixelFormat::Rgba32 => [red, 0, blue, 255],
+                _ => [blue, 0, red, 255],
+            }
+        })
+    }
+
+    fn full_range() -> YuvConverter {
+        YuvConverter::new().range(Range::Full)
+    }
+
+    #[test]
+    fn luma_of_every_pixel() {
+        // Y = 0.299 R + 0.114 B, or 16 + 219/255 of that in limited range.
+        let image = image(PixelFormat::Bgr24);
+        assert_eq!(
+            full_range().i420(&image).y(),
+            &[6, 11, 29, 30, 35, 53, 75, 80, 98]
+        );
+        assert_eq!(
+            YuvConverter::new().i420(&image).y(),
+            &[21, 26, 41, 42, 46, 61, 80, 85, 100]
+        );
+    }
+
+    #[test]
+    fn chroma_co_sited_on_the_left() {
+        // Averaged with weights [1, 2, 1], the blue of the columns is 12 and 162 after repeating the edges, the
+        // red of the rows 60 and 250. Cb = 128 - 0.168736 R + 0.5 B and Cr = 128 + 0.5 R - 0.081312 B.
+        let i420 = full_range().i420(&image(PixelFormat::Bgr24));
+        assert_eq!((i420.width(), i420.height()), (3, 3));
+        assert_eq!(i420.u(), &[124, 199, 92, 167]);
+        assert_eq!(i420.v(), &[157, 145, 252, 240]);
+    }
+
+    #[test]
+    fn chroma_centered() {
+        // Averaged with weights [2, 2], the blue of the columns is 24 and 200.
+        let converter = full_range().siting(ChromaSiting::Center);
+        let i420 = converter.i420(&image(PixelFormat::Bgr24));
+        assert_eq!(i420.u(), &[130, 218, 98, 186]);
+        assert_eq!(i420.v(), &[156, 142, 251, 237]);
+    }
+
+    #[test]
+    fn every_format_converts_alike() {
+        for siting in [ChromaSiting::Left, ChromaSiting::Center] {
+            let converter = YuvConverter::new().siting(siting);
+            let expected = converter.i420(&image(PixelFormat::Bgr24));
+            for format in [PixelFormat::Bgra32, PixelFormat::Rgb24, PixelFormat::Rgba32] {
+                assert_eq!(converter.i420(&image(format)), expected, "{format:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn nv12_interleaves_the_chroma() {
+        let image = image(PixelFormat::Bgra32);
+        let nv12 = full_range().nv12(&image);
+        assert_eq!((nv12.width(), nv12.height()), (3, 3));
+        assert_eq!(nv12.y(), full_range().i420(&image).y());
+        assert_eq!(nv12.uv(), &[124, 157, 199, 145, 92, 252, 167, 240]);
+        assert_eq!(nv12.to_vec().len(), 9 + 8);
+    }
+
+    #[test]
+    fn buffers_follow_the_size() {
+        let mut i420 = full_range().i420(&image(PixelFormat::Bgr24));
+        full_range().convert_i420(&Frame::new(5, 1, PixelFormat::Bgr24), &mut i420);
+        assert_eq!((i420.y().len(), i420.u().len(), i420.v().len()), (5, 3, 3));
+        full_range().convert_i420(&Frame::new(0, 0, PixelFormat::Bgr24), &mut i420);
+        assert!(i420.to_vec().is_empty());
+    }
+}